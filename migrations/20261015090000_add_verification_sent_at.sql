@@ -0,0 +1,2 @@
+-- Last time a verification email was sent, used to rate limit re-signups
+ALTER TABLE users ADD COLUMN verification_sent_at DATETIME;
@@ -0,0 +1,3 @@
+-- Short code alternative to the verification link, for mail gateways that pre-click links
+ALTER TABLE users ADD COLUMN verification_code TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN verification_code_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Server side sessions for logged in pages, in the layout tower-sessions' SQLite store expects
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    data BLOB NOT NULL,
+    expiry_date INTEGER NOT NULL
+);
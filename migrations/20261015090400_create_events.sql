@@ -0,0 +1,13 @@
+-- Subscriber lifecycle history. Users are deleted when they unsubscribe, so
+-- events keep their own copy of the email and don't reference users.
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    event_type TEXT NOT NULL CHECK( event_type IN ('signup', 'verification', 'unsubscribe', 'resubscribe', 'bounce', 'suppression') ),
+    source TEXT NOT NULL CHECK( source IN ('web', 'api', 'cli', 'webhook') ),
+    detail TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS events_email ON events (email COLLATE NOCASE);
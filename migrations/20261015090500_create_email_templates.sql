@@ -0,0 +1,8 @@
+-- Admin edited overrides of the compiled email templates
+CREATE TABLE IF NOT EXISTS email_templates (
+    name TEXT PRIMARY KEY NOT NULL CHECK( name IN ('verification', 'notification') ),
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
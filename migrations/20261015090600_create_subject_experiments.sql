@@ -0,0 +1,15 @@
+-- Subject line A/B tests for notification emails, at most one running at a time
+CREATE TABLE IF NOT EXISTS subject_experiments (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT UNIQUE NOT NULL,
+    started_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS subject_variants (
+    id TEXT PRIMARY KEY NOT NULL,
+    experiment_id TEXT NOT NULL REFERENCES subject_experiments (id),
+    label TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    UNIQUE (experiment_id, label)
+);
@@ -0,0 +1,13 @@
+-- Every notification email sent, one row per recipient per send
+CREATE TABLE IF NOT EXISTS notification_log (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    subject_variant_id TEXT REFERENCES subject_variants (id),
+    sent_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    opened_at DATETIME,
+    clicked_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS notification_log_variant ON notification_log (subject_variant_id);
@@ -0,0 +1,2 @@
+-- Where a subscriber signed up from, e.g. a QR code or social post
+ALTER TABLE users ADD COLUMN signup_source TEXT;
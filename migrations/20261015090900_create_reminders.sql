@@ -0,0 +1,10 @@
+-- Opt-in reminders sent shortly before each predicted flood
+ALTER TABLE users ADD COLUMN wants_reminders BOOLEAN NOT NULL DEFAULT 0;
+
+-- One row per flood a subscriber was reminded about, so each flood gets at most one reminder
+CREATE TABLE IF NOT EXISTS reminder_log (
+    user_id TEXT NOT NULL,
+    prediction_time DATETIME NOT NULL,
+    sent_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, prediction_time)
+);
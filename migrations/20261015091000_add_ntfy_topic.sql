@@ -0,0 +1,2 @@
+-- ntfy topic URL a subscriber gets push notifications on, alongside email
+ALTER TABLE users ADD COLUMN ntfy_topic TEXT;
@@ -0,0 +1,2 @@
+-- Pushover user key a subscriber gets push notifications on, alongside email
+ALTER TABLE users ADD COLUMN pushover_user_key TEXT;
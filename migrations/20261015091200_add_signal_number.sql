@@ -0,0 +1,2 @@
+-- Phone number a subscriber gets Signal messages on, alongside email
+ALTER TABLE users ADD COLUMN signal_number TEXT;
@@ -0,0 +1,4 @@
+-- When a subscriber was last sent a notification, and the span of floods it covered
+ALTER TABLE users ADD COLUMN last_notified_at DATETIME;
+ALTER TABLE users ADD COLUMN last_window_start DATETIME;
+ALTER TABLE users ADD COLUMN last_window_end DATETIME;
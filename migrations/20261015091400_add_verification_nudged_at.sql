@@ -0,0 +1,2 @@
+-- When an unverified signup was sent its one reminder to verify
+ALTER TABLE users ADD COLUMN verification_nudged_at DATETIME;
@@ -0,0 +1,9 @@
+-- Jobs triggered through the admin API, so their outcome can be checked later
+CREATE TABLE IF NOT EXISTS job_runs (
+    id TEXT PRIMARY KEY NOT NULL,
+    job TEXT NOT NULL CHECK( job IN ('sync', 'notify') ),
+    status TEXT NOT NULL CHECK( status IN ('running', 'succeeded', 'failed') ),
+    error TEXT,
+    started_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at DATETIME
+);
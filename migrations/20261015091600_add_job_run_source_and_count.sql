@@ -0,0 +1,4 @@
+-- Runs from the CLI and dashboard are recorded too, along with how much each did:
+-- tide predictions stored by a sync, notifications sent by a notify
+ALTER TABLE job_runs ADD COLUMN source TEXT NOT NULL DEFAULT 'api';
+ALTER TABLE job_runs ADD COLUMN count INTEGER;
@@ -0,0 +1,24 @@
+-- Runs can be queued for the worker in `serve`, which starts them once run_at has passed
+-- and retries failures up to max_attempts. Runs started straight away have no run_at.
+CREATE TABLE job_runs_new (
+    id TEXT PRIMARY KEY NOT NULL,
+    job TEXT NOT NULL CHECK( job IN ('sync', 'notify') ),
+    status TEXT NOT NULL CHECK( status IN ('queued', 'running', 'succeeded', 'failed') ),
+    source TEXT NOT NULL DEFAULT 'api',
+    payload TEXT NOT NULL DEFAULT '{}',
+    run_at DATETIME,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 1,
+    count INTEGER,
+    error TEXT,
+    started_at DATETIME,
+    finished_at DATETIME
+);
+
+INSERT INTO job_runs_new (id, job, status, source, attempts, count, error, started_at, finished_at)
+    SELECT id, job, status, source, 1, count, error, started_at, finished_at FROM job_runs;
+
+DROP TABLE job_runs;
+ALTER TABLE job_runs_new RENAME TO job_runs;
+
+CREATE INDEX IF NOT EXISTS job_runs_due ON job_runs (status, run_at);
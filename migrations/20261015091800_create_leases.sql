@@ -0,0 +1,6 @@
+-- Leases let one of several replicas claim a role, like running queued jobs
+CREATE TABLE IF NOT EXISTS leases (
+    name TEXT PRIMARY KEY NOT NULL,
+    holder TEXT NOT NULL,
+    expires_at DATETIME NOT NULL
+);
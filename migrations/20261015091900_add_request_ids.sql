@@ -0,0 +1,5 @@
+-- The X-Request-Id of the request behind each row, so one request can be followed across the
+-- subscriber history, notification log and job runs. Rows from the CLI have none.
+ALTER TABLE events ADD COLUMN request_id TEXT;
+ALTER TABLE notification_log ADD COLUMN request_id TEXT;
+ALTER TABLE job_runs ADD COLUMN request_id TEXT;
@@ -0,0 +1,43 @@
+-- Tide times were stored as naive Pacific times, which repeat or skip an hour at the
+-- daylight saving changes. Convert them to UTC using the US rules (since 2007): PDT
+-- from 2AM on the second Sunday in March to 2AM on the first Sunday in November.
+-- Times in the repeated hour in November are taken as PDT, the earlier of the two.
+
+-- Rebuilt rather than updated in place, so a converted time can't briefly clash with
+-- the primary key of a row not yet converted
+CREATE TABLE tides_utc (
+    prediction_time DATETIME PRIMARY KEY NOT NULL,
+    height_ft REAL NOT NULL,
+    tide_type TEXT CHECK( tide_type IN ('High', 'Low') ),
+    last_updated DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO tides_utc (prediction_time, height_ft, tide_type, last_updated)
+SELECT
+    datetime(prediction_time, CASE
+        WHEN prediction_time >= datetime(strftime('%Y', prediction_time) || '-03-01', 'weekday 0', '+7 days', '+2 hours')
+            AND prediction_time < datetime(strftime('%Y', prediction_time) || '-11-01', 'weekday 0', '+2 hours')
+        THEN '+7 hours' ELSE '+8 hours' END),
+    height_ft,
+    tide_type,
+    last_updated
+FROM tides;
+
+DROP TABLE tides;
+ALTER TABLE tides_utc RENAME TO tides;
+
+UPDATE reminder_log SET prediction_time = datetime(prediction_time, CASE
+    WHEN prediction_time >= datetime(strftime('%Y', prediction_time) || '-03-01', 'weekday 0', '+7 days', '+2 hours')
+        AND prediction_time < datetime(strftime('%Y', prediction_time) || '-11-01', 'weekday 0', '+2 hours')
+    THEN '+7 hours' ELSE '+8 hours' END);
+
+UPDATE users SET
+    last_window_start = datetime(last_window_start, CASE
+        WHEN last_window_start >= datetime(strftime('%Y', last_window_start) || '-03-01', 'weekday 0', '+7 days', '+2 hours')
+            AND last_window_start < datetime(strftime('%Y', last_window_start) || '-11-01', 'weekday 0', '+2 hours')
+        THEN '+7 hours' ELSE '+8 hours' END),
+    last_window_end = datetime(last_window_end, CASE
+        WHEN last_window_end >= datetime(strftime('%Y', last_window_end) || '-03-01', 'weekday 0', '+7 days', '+2 hours')
+            AND last_window_end < datetime(strftime('%Y', last_window_end) || '-11-01', 'weekday 0', '+2 hours')
+        THEN '+7 hours' ELSE '+8 hours' END)
+WHERE last_window_start IS NOT NULL;
@@ -0,0 +1,33 @@
+-- Whether people saw the path flooded, for calibrating the flood threshold
+CREATE TABLE IF NOT EXISTS flood_reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    station_id TEXT NOT NULL,
+    -- UTC, when they saw it
+    observed_at DATETIME NOT NULL,
+    flooded BOOLEAN NOT NULL,
+    source TEXT NOT NULL,
+    request_id TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_flood_reports_station_observed_at
+    ON flood_reports (station_id, observed_at);
+
+-- Water levels NOAA measured at the station, every 6 minutes
+CREATE TABLE IF NOT EXISTS water_levels (
+    station_id TEXT NOT NULL,
+    observed_time DATETIME NOT NULL,
+    height_ft REAL NOT NULL,
+    PRIMARY KEY (station_id, observed_time)
+);
+
+-- The latest threshold suggested for each station by `calibrate`
+CREATE TABLE IF NOT EXISTS threshold_calibrations (
+    station_id TEXT PRIMARY KEY NOT NULL,
+    threshold_ft REAL NOT NULL,
+    path_elevation_ft REAL NOT NULL,
+    bias_ft REAL NOT NULL,
+    reports INTEGER NOT NULL,
+    misclassified INTEGER NOT NULL,
+    calibrated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
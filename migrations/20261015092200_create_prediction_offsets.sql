@@ -0,0 +1,8 @@
+-- The latest observed minus predicted offset for each station, from `bias`
+CREATE TABLE IF NOT EXISTS prediction_offsets (
+    station_id TEXT PRIMARY KEY NOT NULL,
+    offset_ft REAL NOT NULL,
+    samples INTEGER NOT NULL,
+    days INTEGER NOT NULL,
+    computed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,10 @@
+-- Location specific settings for a station, overriding the environment
+-- without a deploy. Unset columns keep the environment's value.
+CREATE TABLE IF NOT EXISTS station_settings (
+    station_id TEXT PRIMARY KEY NOT NULL,
+    threshold_ft REAL,
+    reminder_window_hours INTEGER,
+    location_description TEXT,
+    alternate_route TEXT,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
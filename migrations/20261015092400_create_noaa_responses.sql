@@ -0,0 +1,11 @@
+-- The latest raw response NOAA sent for each station and product, used by
+-- `sync` when NOAA can't be reached
+CREATE TABLE IF NOT EXISTS noaa_responses (
+    station_id TEXT NOT NULL,
+    product TEXT NOT NULL,
+    begin_date DATE NOT NULL,
+    end_date DATE NOT NULL,
+    body TEXT NOT NULL,
+    fetched_at DATETIME NOT NULL,
+    PRIMARY KEY (station_id, product)
+);
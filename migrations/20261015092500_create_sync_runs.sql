@@ -0,0 +1,19 @@
+-- Each sync of a station's predictions, for the freshness banner, the status
+-- endpoint and the admin dashboard
+CREATE TABLE IF NOT EXISTS sync_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    station_id TEXT NOT NULL,
+    window_start DATETIME NOT NULL,
+    window_end DATETIME NOT NULL,
+    inserted INTEGER NOT NULL DEFAULT 0,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    duration_ms INTEGER NOT NULL,
+    -- `api`, or `cache` when NOAA couldn't be reached, NULL when nothing came back
+    source TEXT,
+    -- When NOAA sent the predictions, earlier than finished_at for the cache
+    fetched_at DATETIME,
+    error TEXT,
+    finished_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sync_runs_station ON sync_runs (station_id, finished_at);
@@ -0,0 +1,32 @@
+-- Addresses are stored lowercased, with the key they're deduplicated by,
+-- which also drops the local part's +tag when DEDUP_PLUS_ADDRESSES is on
+ALTER TABLE users ADD COLUMN email_key TEXT;
+
+-- One row per address ignoring case: verified subscribers win, then the
+-- latest signup
+CREATE TEMP TABLE duplicate_users AS
+SELECT id, kept_id FROM (
+    SELECT id, FIRST_VALUE(id) OVER (
+        PARTITION BY lower(trim(email))
+        ORDER BY is_verified DESC, is_subscribed DESC, created_at DESC, id DESC
+    ) AS kept_id
+    FROM users
+)
+WHERE id != kept_id;
+
+UPDATE notification_log
+SET user_id = (SELECT kept_id FROM duplicate_users WHERE id = notification_log.user_id)
+WHERE user_id IN (SELECT id FROM duplicate_users);
+
+-- Reminders the kept row already has for the same tide are dropped
+UPDATE OR IGNORE reminder_log
+SET user_id = (SELECT kept_id FROM duplicate_users WHERE id = reminder_log.user_id)
+WHERE user_id IN (SELECT id FROM duplicate_users);
+DELETE FROM reminder_log WHERE user_id IN (SELECT id FROM duplicate_users);
+
+DELETE FROM users WHERE id IN (SELECT id FROM duplicate_users);
+DROP TABLE duplicate_users;
+
+UPDATE users SET email = lower(trim(email)), email_key = lower(trim(email));
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_key ON users (email_key);
@@ -0,0 +1,7 @@
+-- Unsubscribes and suppressions are matched by the same canonical key as
+-- users, so signing up again under a variant still counts as a resubscribe
+ALTER TABLE events ADD COLUMN email_key TEXT;
+
+UPDATE events SET email_key = lower(trim(email));
+
+CREATE INDEX IF NOT EXISTS idx_events_email_key ON events (email_key);
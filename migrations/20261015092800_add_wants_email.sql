@@ -0,0 +1,2 @@
+-- Subscribers with another channel can stop just the emails
+ALTER TABLE users ADD COLUMN wants_email BOOLEAN NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+-- Subscribers who'd rather get each flood as a calendar invite
+ALTER TABLE users ADD COLUMN wants_invites BOOLEAN NOT NULL DEFAULT 0;
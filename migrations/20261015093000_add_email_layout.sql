@@ -0,0 +1,2 @@
+-- The email layout a subscriber chose, NULL for the site's EMAIL_LAYOUT
+ALTER TABLE users ADD COLUMN email_layout TEXT;
@@ -0,0 +1,4 @@
+-- How flood times in emails are written: the language signed up in, and a
+-- 12 or 24 hour clock when chosen over the language's usual
+ALTER TABLE users ADD COLUMN lang TEXT;
+ALTER TABLE users ADD COLUMN clock_24h BOOLEAN;
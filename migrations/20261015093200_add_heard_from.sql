@@ -0,0 +1,2 @@
+-- The subscriber's answer to "How did you hear about us?" on the signup form
+ALTER TABLE users ADD COLUMN heard_from TEXT;
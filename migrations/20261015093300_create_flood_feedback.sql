@@ -0,0 +1,12 @@
+-- Subscribers' answers to whether the path flooded at a predicted flood,
+-- from the feedback link in notification emails. One per flood each, the
+-- latest answer wins.
+CREATE TABLE IF NOT EXISTS flood_feedback (
+    user_id TEXT NOT NULL,
+    prediction_time DATETIME NOT NULL,
+    flooded BOOLEAN NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, prediction_time)
+);
+
+CREATE INDEX IF NOT EXISTS flood_feedback_prediction_time ON flood_feedback (prediction_time);
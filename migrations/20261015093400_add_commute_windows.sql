@@ -0,0 +1,3 @@
+-- When the subscriber is on the path, e.g. "weekdays 7:00-9:00, weekdays 16:00-18:00".
+-- Only floods overlapping one are notified. NULL for every flood.
+ALTER TABLE users ADD COLUMN commute_windows TEXT;
@@ -0,0 +1,5 @@
+-- Unverified rows from before verification codes have an empty code. Give them a random one
+-- they were never sent, so an empty code can't verify them.
+UPDATE users
+SET verification_code = printf('%06d', abs(random() % 1000000))
+WHERE verification_code = '';
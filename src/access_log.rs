@@ -0,0 +1,163 @@
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::USER_AGENT;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, LineWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::rate_limit::{self, RateLimitConfig};
+use crate::request_id;
+
+/// Where `ACCESS_LOG` sends one JSON line per request: `stdout`, or a file
+/// path that's appended to. Off when unset.
+pub struct AccessLog {
+    out: Mutex<Box<dyn Write + Send>>,
+    trust_proxy_headers: bool,
+}
+
+impl AccessLog {
+    pub fn from_env() -> io::Result<Option<Arc<Self>>> {
+        let Some(target) = env::var("ACCESS_LOG")
+            .ok()
+            .filter(|target| !target.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let out: Box<dyn Write + Send> = match target.trim() {
+            "stdout" => Box::new(io::stdout()),
+            path => Box::new(LineWriter::new(open(path)?)),
+        };
+        println!("Logging requests to {}", target.trim());
+        Ok(Some(Arc::new(AccessLog {
+            out: Mutex::new(out),
+            trust_proxy_headers: RateLimitConfig::from_env().trust_proxy_headers,
+        })))
+    }
+
+    fn write(&self, entry: &Entry) {
+        let line = serde_json::to_string(entry).expect("access log entries serialize");
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = writeln!(out, "{}", line) {
+            eprintln!("Error writing the access log: {}", e);
+        }
+    }
+}
+
+/// Opens `path` for appending, for the access log and the startup checks.
+pub fn open(path: &str) -> io::Result<std::fs::File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Can't open {}: {}", path, e)))
+}
+
+/// One request. The query string is left out, it can carry tokens.
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    time: String,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    latency_ms: f64,
+    ip: Option<IpAddr>,
+    user_agent: Option<&'a str>,
+    request_id: Option<String>,
+}
+
+/// Middleware writing each request to the access log, timed until the
+/// response headers are ready.
+pub async fn middleware(
+    State(access_log): State<Option<Arc<AccessLog>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(access_log) = access_log else {
+        return next.run(req).await;
+    };
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let peer_ip = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let ip = rate_limit::client_ip(req.headers(), peer_ip, access_log.trust_proxy_headers);
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let started = Instant::now();
+    let response = next.run(req).await;
+    access_log.write(&Entry {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        method: method.as_str(),
+        path: &path,
+        status: response.status().as_u16(),
+        latency_ms: (started.elapsed().as_secs_f64() * 1000.0 * 100.0).round() / 100.0,
+        ip,
+        user_agent: user_agent.as_deref(),
+        request_id: request_id::current(),
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    /// Collects what's written so the test can read it back.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_log() {
+        let buffer = Buffer::default();
+        let access_log = Arc::new(AccessLog {
+            out: Mutex::new(Box::new(buffer.clone())),
+            trust_proxy_headers: true,
+        });
+        let app = Router::new()
+            .route("/tides", get(|| async { "ok" }))
+            .layer(from_fn_with_state(Some(access_log), middleware));
+
+        let req = Request::builder()
+            .uri("/tides?token=secret")
+            .header(USER_AGENT, "curl/8.5.0")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap();
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(text.trim_end()).unwrap();
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/tides");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["ip"], "203.0.113.7");
+        assert_eq!(entry["user_agent"], "curl/8.5.0");
+        assert!(entry["latency_ms"].is_f64());
+        assert!(!text.contains("secret"));
+    }
+}
@@ -0,0 +1,611 @@
+use argon2::Argon2;
+use argon2::password_hash::phc::PasswordHash;
+use argon2::password_hash::{PasswordHasher, PasswordVerifier};
+use askama::Template;
+use axum::Form;
+use axum::Router;
+use axum::extract::{Path, Request, State};
+use axum::http::{
+    HeaderMap, Method, StatusCode, header::AUTHORIZATION, header::HOST, header::ORIGIN,
+    header::REFERER, header::WWW_AUTHENTICATE,
+};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum_extra::extract::cookie::SignedCookieJar;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::Url;
+use serde::Deserialize;
+use std::env;
+use std::sync::Arc;
+use tower_sessions::{Session, session};
+
+use crate::AppState;
+use crate::attribution::{self, HeardFromCount, SourceCount};
+use crate::database;
+use crate::email_templates::{self, EmailKind, EmailTemplate};
+use crate::error::AppError;
+use crate::events::EventSource;
+use crate::flash::Flash;
+use crate::funnel::DEFAULT_FUNNEL_WEEKS;
+use crate::growth::{self, GrowthPeriod, Interval};
+use crate::jobs::{self, Job};
+use crate::notification_log::{self, LastNotified};
+use crate::oidc;
+use crate::sync_runs::{self, SyncRun};
+use crate::tides::{FORECAST_DAYS, get_flood_tides};
+
+/// Operator login for the admin area, configured with `ADMIN_USERNAME` and
+/// an argon2 `ADMIN_PASSWORD_HASH` (see the `hash-password` command).
+pub struct AdminCredentials {
+    username: String,
+    password_hash: String,
+}
+
+impl AdminCredentials {
+    /// Returns the credentials when both variables are set, otherwise the
+    /// admin area is disabled.
+    pub fn from_env() -> Option<Self> {
+        let username = env::var("ADMIN_USERNAME").ok().filter(|v| !v.is_empty())?;
+        let password_hash = env::var("ADMIN_PASSWORD_HASH")
+            .ok()
+            .filter(|v| !v.is_empty())?;
+        PasswordHash::new(&password_hash).expect("ADMIN_PASSWORD_HASH must be an argon2 hash");
+        Some(AdminCredentials {
+            username,
+            password_hash,
+        })
+    }
+
+    fn verify(&self, username: &str, password: &str) -> bool {
+        // Always check the password so a wrong username takes as long as a wrong password
+        let password_ok = Argon2::default()
+            .verify_password(password.as_bytes(), self.password_hash.as_str())
+            .is_ok();
+        password_ok && username == self.username
+    }
+}
+
+/// Hashes a password for use as `ADMIN_PASSWORD_HASH`.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let hash: PasswordHash = Argon2::default()
+        .hash_password(password.as_bytes())
+        .map_err(|e| AppError::Internal(format!("Can't hash the password: {}", e)))?;
+    Ok(hash.to_string())
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Browsers resend basic auth credentials and the session cookie
+/// automatically, so state changing requests must come from our own pages.
+/// Checks `Origin`, else `Referer`, and refuses requests with neither. Pages
+/// served on an internal listener post from its own address rather than
+/// `BASE_URL`'s, so the request's `Host` counts as our origin too.
+fn is_same_origin(headers: &HeaderMap, base_url: &str) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let Some(url) = header(ORIGIN)
+        .or_else(|| header(REFERER))
+        .and_then(|url| Url::parse(url).ok())
+    else {
+        return false;
+    };
+    let host_url =
+        header(HOST).and_then(|host| Url::parse(&format!("{}://{}", url.scheme(), host)).ok());
+    [Url::parse(base_url).ok(), host_url]
+        .into_iter()
+        .flatten()
+        .any(|ours| ours.origin() == url.origin())
+}
+
+const ADMIN_EMAIL_KEY: &str = "admin_email";
+
+/// Starts an admin session for someone who logged in through OIDC.
+pub async fn start_session(session: &Session, email: &str) -> Result<(), session::Error> {
+    // A new id on login stops a session id planted before login from being reused
+    session.cycle_id().await?;
+    session.insert(ADMIN_EMAIL_KEY, email).await
+}
+
+/// Returns who is signed in, if anyone. Sessions of addresses removed
+/// from `ADMIN_EMAILS` stop working straight away.
+async fn signed_in_as(state: &AppState, session: &Session) -> Option<String> {
+    let oidc = state.oidc.as_ref()?;
+    let email: String = session.get(ADMIN_EMAIL_KEY).await.ok()??;
+    oidc.is_admin(&email).then_some(email)
+}
+
+/// Middleware requiring either an OIDC admin session or the admin's basic
+/// auth credentials.
+async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    session: Session,
+    req: Request,
+    next: Next,
+) -> Response {
+    if state.admin_credentials.is_none() && state.oidc.is_none() {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    }
+
+    let authorized = signed_in_as(&state, &session).await.is_some()
+        || basic_credentials(req.headers()).is_some_and(|(username, password)| {
+            state
+                .admin_credentials
+                .as_ref()
+                .is_some_and(|credentials| credentials.verify(&username, &password))
+        });
+    if !authorized {
+        if state.oidc.is_some()
+            && req.method() == Method::GET
+            && !req.headers().contains_key(AUTHORIZATION)
+        {
+            return Redirect::to("/admin/login").into_response();
+        }
+        if state.admin_credentials.is_some() {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Basic realm=\"admin\", charset=\"UTF-8\"")],
+                "Unauthorized",
+            )
+                .into_response();
+        }
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    if req.method() != Method::GET && !is_same_origin(req.headers(), &state.base_url) {
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Routes nested under `/admin`, all behind [`require_admin`] apart from
+/// the OIDC login itself.
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(dashboard_handler))
+        .route("/sync", post(sync_handler))
+        .route("/notify", post(notify_handler))
+        .route("/api/sync", post(jobs::sync_job_handler))
+        .route("/api/notify", post(jobs::notify_job_handler))
+        .route("/api/runs", get(jobs::job_runs_handler))
+        .route("/api/checkpoint", post(database::checkpoint_handler))
+        .route("/api/runs/{id}", get(jobs::job_run_handler))
+        .route("/logout", post(logout_handler))
+        .route("/templates", get(email_templates_handler))
+        .route(
+            "/templates/{name}",
+            get(edit_email_template_handler).post(save_email_template_handler),
+        )
+        .route(
+            "/templates/{name}/reset",
+            post(reset_email_template_handler),
+        )
+        .layer(middleware::from_fn_with_state(state, require_admin))
+        .route("/login", get(oidc::login_handler))
+        .route(
+            oidc::CALLBACK_PATH.trim_start_matches("/admin"),
+            get(oidc::callback_handler),
+        )
+}
+
+/// Subscribers listed under "Recently notified" on the dashboard.
+const RECENTLY_NOTIFIED_LIMIT: i64 = 20;
+const RECENT_SYNCS_LIMIT: i64 = 10;
+
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate {
+    flash: Option<Flash>,
+    signed_in_as: Option<String>,
+    total_users: i64,
+    subscribed: i64,
+    unverified: i64,
+    stored_tides: i64,
+    upcoming_floods: usize,
+    active_api_keys: i64,
+    sources: Vec<SourceCount>,
+    heard_from: Vec<HeardFromCount>,
+    /// Weekly, oldest first
+    growth: Vec<GrowthPeriod>,
+    recently_notified: Vec<LastNotified>,
+    recent_syncs: Vec<SyncRun>,
+}
+
+async fn dashboard_template(
+    state: &AppState,
+    flash: Option<Flash>,
+    signed_in_as: Option<String>,
+) -> Result<DashboardTemplate, AppError> {
+    let users = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) AS "total!: i64",
+            COALESCE(SUM(is_verified = 1 AND is_subscribed = 1), 0) AS "subscribed!: i64",
+            COALESCE(SUM(is_verified = 0), 0) AS "unverified!: i64"
+        FROM users
+        "#
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    let stored_tides = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM tides"#)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let active_api_keys = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM api_keys WHERE revoked_at IS NULL"#
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    let upcoming_floods = get_flood_tides(&state.pool, FORECAST_DAYS).await?.len();
+    let sources = attribution::signups_by_source(&state.pool).await?;
+    let heard_from = attribution::signups_by_heard_from(&state.pool).await?;
+    let growth = growth::subscriber_growth(
+        &state.pool,
+        Interval::Week,
+        DEFAULT_FUNNEL_WEEKS,
+        chrono::Utc::now().date_naive(),
+    )
+    .await?;
+    let recently_notified =
+        notification_log::recently_notified(&state.pool, RECENTLY_NOTIFIED_LIMIT).await?;
+    let recent_syncs = sync_runs::recent(&state.pool, RECENT_SYNCS_LIMIT).await?;
+
+    Ok(DashboardTemplate {
+        flash,
+        signed_in_as,
+        total_users: users.total,
+        subscribed: users.subscribed,
+        unverified: users.unverified,
+        stored_tides,
+        upcoming_floods,
+        active_api_keys,
+        sources,
+        heard_from,
+        growth,
+        recently_notified,
+        recent_syncs,
+    })
+}
+
+async fn dashboard_handler(
+    State(state): State<Arc<AppState>>,
+    session: Session,
+    headers: HeaderMap,
+) -> Response {
+    let (jar, flash) = Flash::take(SignedCookieJar::from_headers(
+        &headers,
+        state.cookie_key.clone(),
+    ));
+
+    let signed_in_as = signed_in_as(&state, &session).await;
+    let template = match dashboard_template(&state, flash, signed_in_as).await {
+        Ok(template) => template,
+        Err(e) => {
+            eprintln!("Error loading admin dashboard: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response();
+        }
+    };
+
+    match template.render() {
+        Ok(html) => (jar, Html(html)).into_response(),
+        Err(e) => AppError::from(e).into_response(),
+    }
+}
+
+fn redirect_with_flash(state: &AppState, to: &str, flash: Flash) -> Response {
+    let jar = flash.set(SignedCookieJar::new(state.cookie_key.clone()));
+    (jar, Redirect::to(to)).into_response()
+}
+
+async fn logout_handler(session: Session) -> Response {
+    if let Err(e) = session.flush().await {
+        eprintln!("Error ending admin session: {}", e);
+    }
+    Redirect::to("/").into_response()
+}
+
+/// Runs the same tide sync as the `sync` command.
+async fn sync_handler(State(state): State<Arc<AppState>>) -> Response {
+    let result = jobs::run(&state.pool, Job::Sync, EventSource::Web)
+        .await
+        .map_err(|e| e.to_string());
+    let flash = match result {
+        Ok(count) => Flash::success(format!("Synced {} tide predictions.", count)),
+        Err(e) => {
+            eprintln!("Admin sync failed: {}", e);
+            Flash::error(format!("Sync failed: {}", e))
+        }
+    };
+    redirect_with_flash(&state, "/admin", flash)
+}
+
+/// Runs the same notification check as the `notify` command.
+async fn notify_handler(State(state): State<Arc<AppState>>) -> Response {
+    let result = jobs::run(&state.pool, Job::Notify, EventSource::Web)
+        .await
+        .map_err(|e| e.to_string());
+    let flash = match result {
+        Ok(count) => Flash::success(format!(
+            "Notification check finished, {} notifications sent.",
+            count
+        )),
+        Err(e) => {
+            eprintln!("Admin notify failed: {}", e);
+            Flash::error(format!("Notify failed: {}", e))
+        }
+    };
+    redirect_with_flash(&state, "/admin", flash)
+}
+
+struct EmailTemplateRow {
+    name: &'static str,
+    title: &'static str,
+    updated_at: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/email_templates.html")]
+struct EmailTemplatesTemplate {
+    flash: Option<Flash>,
+    templates: Vec<EmailTemplateRow>,
+}
+
+async fn email_templates_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    let (jar, flash) = Flash::take(SignedCookieJar::from_headers(
+        &headers,
+        state.cookie_key.clone(),
+    ));
+
+    let overridden = match email_templates::overridden_at(&state.pool).await {
+        Ok(overridden) => overridden,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response();
+        }
+    };
+    let templates = EmailKind::ALL
+        .into_iter()
+        .map(|kind| EmailTemplateRow {
+            name: kind.as_str(),
+            title: kind.title(),
+            updated_at: overridden
+                .iter()
+                .find(|(overridden_kind, _)| *overridden_kind == kind)
+                .map(|(_, updated_at)| updated_at.to_string()),
+        })
+        .collect();
+
+    match (EmailTemplatesTemplate { flash, templates }).render() {
+        Ok(html) => (jar, Html(html)).into_response(),
+        Err(e) => AppError::from(e).into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/email_template_edit.html")]
+struct EmailTemplateEditTemplate {
+    name: &'static str,
+    title: &'static str,
+    variables: &'static [&'static str],
+    template: EmailTemplate,
+    overridden: bool,
+    error: Option<String>,
+}
+
+impl EmailTemplateEditTemplate {
+    fn new(kind: EmailKind, template: EmailTemplate, overridden: bool) -> Self {
+        EmailTemplateEditTemplate {
+            name: kind.as_str(),
+            title: kind.title(),
+            variables: kind.variables(),
+            template,
+            overridden,
+            error: None,
+        }
+    }
+
+    fn into_response(self, status: StatusCode) -> Response {
+        match self.render() {
+            Ok(html) => (status, Html(html)).into_response(),
+            Err(e) => AppError::from(e).into_response(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EmailTemplateForm {
+    subject: String,
+    html_body: String,
+    text_body: String,
+}
+
+/// Shows the current override, or the compiled template to start one from.
+async fn edit_email_template_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response {
+    let Some(kind) = EmailKind::parse(&name) else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    match email_templates::load_override(&state.pool, kind).await {
+        Ok(Some(template)) => {
+            EmailTemplateEditTemplate::new(kind, template, true).into_response(StatusCode::OK)
+        }
+        Ok(None) => EmailTemplateEditTemplate::new(kind, kind.default_template(), false)
+            .into_response(StatusCode::OK),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+        }
+    }
+}
+
+async fn save_email_template_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Form(form): Form<EmailTemplateForm>,
+) -> Response {
+    let Some(kind) = EmailKind::parse(&name) else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    // Browsers submit textarea line breaks as CRLF
+    let template = EmailTemplate {
+        subject: form.subject.trim().to_string(),
+        html_body: form.html_body.replace("\r\n", "\n"),
+        text_body: form.text_body.replace("\r\n", "\n"),
+    };
+    let unknown = template.unknown_variables(kind);
+    let error = if template.subject.is_empty() {
+        Some("The subject can't be empty.".to_string())
+    } else if !unknown.is_empty() {
+        Some(format!(
+            "Unknown variables: {}. Only the listed variables can be used.",
+            unknown.join(", ")
+        ))
+    } else {
+        None
+    };
+    if error.is_some() {
+        // Show the edits again rather than losing them
+        let overridden = matches!(
+            email_templates::load_override(&state.pool, kind).await,
+            Ok(Some(_))
+        );
+        return EmailTemplateEditTemplate {
+            error,
+            ..EmailTemplateEditTemplate::new(kind, template, overridden)
+        }
+        .into_response(StatusCode::BAD_REQUEST);
+    }
+
+    let flash = match email_templates::save_override(&state.pool, kind, &template).await {
+        Ok(()) => Flash::success(format!("Saved the {}.", kind.title().to_lowercase())),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            Flash::error("Couldn't save the template, please try again.")
+        }
+    };
+    redirect_with_flash(&state, "/admin/templates", flash)
+}
+
+/// Goes back to the compiled template.
+async fn reset_email_template_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response {
+    let Some(kind) = EmailKind::parse(&name) else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    let flash = match email_templates::delete_override(&state.pool, kind).await {
+        Ok(()) => Flash::success(format!(
+            "The {} is back to the default.",
+            kind.title().to_lowercase()
+        )),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            Flash::error("Couldn't reset the template, please try again.")
+        }
+    };
+    redirect_with_flash(&state, "/admin/templates", flash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn credentials(password: &str) -> AdminCredentials {
+        AdminCredentials {
+            username: "admin".to_string(),
+            password_hash: hash_password(password).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_verify_credentials() {
+        let credentials = credentials("correct horse");
+        assert!(credentials.verify("admin", "correct horse"));
+        assert!(!credentials.verify("admin", "wrong"));
+        assert!(!credentials.verify("someone", "correct horse"));
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(basic_credentials(&headers), None);
+
+        // "admin:pa:ss", passwords may contain colons
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic YWRtaW46cGE6c3M="),
+        );
+        assert_eq!(
+            basic_credentials(&headers),
+            Some(("admin".to_string(), "pa:ss".to_string()))
+        );
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer mvf_abc"));
+        assert_eq!(basic_credentials(&headers), None);
+    }
+
+    #[test]
+    fn test_is_same_origin() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, HeaderValue::from_static("https://example.com"));
+        assert!(is_same_origin(&headers, "https://example.com/"));
+
+        headers.insert(ORIGIN, HeaderValue::from_static("https://evil.example"));
+        assert!(!is_same_origin(&headers, "https://example.com"));
+        headers.insert(ORIGIN, HeaderValue::from_static("null"));
+        assert!(!is_same_origin(&headers, "https://example.com"));
+    }
+
+    #[test]
+    fn test_is_same_origin_without_origin() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_same_origin(&headers, "https://example.com"));
+
+        headers.insert(
+            REFERER,
+            HeaderValue::from_static("https://example.com/admin/templates"),
+        );
+        assert!(is_same_origin(&headers, "https://example.com"));
+        headers.insert(
+            REFERER,
+            HeaderValue::from_static("https://example.com.evil.example/admin"),
+        );
+        assert!(!is_same_origin(&headers, "https://example.com"));
+    }
+
+    #[test]
+    fn test_is_same_origin_on_internal_listener() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, HeaderValue::from_static("http://127.0.0.1:3001"));
+        headers.insert(HOST, HeaderValue::from_static("127.0.0.1:3001"));
+        assert!(is_same_origin(&headers, "https://example.com"));
+
+        headers.insert(HOST, HeaderValue::from_static("127.0.0.1:3000"));
+        assert!(!is_same_origin(&headers, "https://example.com"));
+        headers.insert(ORIGIN, HeaderValue::from_static("http://evil.example"));
+        headers.insert(HOST, HeaderValue::from_static("example.com"));
+        assert!(!is_same_origin(&headers, "https://example.com"));
+    }
+}
@@ -0,0 +1,114 @@
+use reqwest::Url;
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use std::env;
+
+use crate::AppState;
+use crate::jobs::JobRun;
+use crate::site;
+
+/// Where failed job runs are reported, enabled by setting `ALERT_EMAIL`,
+/// `ALERT_WEBHOOK_URL` or both.
+pub struct AlertConfig {
+    email: Option<String>,
+    webhook_url: Option<Url>,
+}
+
+impl AlertConfig {
+    pub fn from_env() -> Option<Self> {
+        let var = |key| env::var(key).ok().filter(|value| !value.trim().is_empty());
+        let email = var("ALERT_EMAIL");
+        let webhook_url = var("ALERT_WEBHOOK_URL").and_then(|url| Url::parse(&url).ok());
+        (email.is_some() || webhook_url.is_some()).then_some(AlertConfig { email, webhook_url })
+    }
+}
+
+/// Subject and plain text body of the alert for a failed run.
+fn message(run: &JobRun, base_url: Option<&str>) -> (String, String) {
+    let subject = format!("The {} job failed", run.job);
+    let mut text = format!(
+        "{}.\n\nError: {}\nRun: {}\nStarted from: {}\nAttempt: {} of {}\nStarted: {} UTC",
+        subject,
+        run.error.as_deref().unwrap_or("unknown"),
+        run.id,
+        run.source,
+        run.attempts,
+        run.max_attempts,
+        run.started_at
+            .map(|time| time.to_string())
+            .unwrap_or_default()
+    );
+    if let Some(base_url) = base_url {
+        text.push_str(&format!("\n\n{}/admin/api/runs/{}", base_url, run.id));
+    }
+    (subject, text)
+}
+
+/// Alerts the operator to a failed run by email and webhook, whichever are
+/// set. Errors are logged, there's nowhere else to report them.
+pub async fn job_failed(pool: &SqlitePool, run: &JobRun) {
+    let Some(config) = AlertConfig::from_env() else {
+        return;
+    };
+    let base_url = site::var("BASE_URL");
+    let (subject, text) = message(run, base_url.as_deref());
+
+    if let Some(to) = &config.email {
+        let state = AppState::from_pool(pool.clone());
+        match state.mailer.send_alert(to, &subject, &text).await {
+            Ok(()) => println!("Emailed the {} failure alert to {}", run.job, to),
+            Err(e) => eprintln!("Failed to email the {} failure alert: {:?}", run.job, e),
+        }
+    }
+    if let Some(url) = &config.webhook_url {
+        // `text` is what Slack and Mattermost incoming webhooks post
+        let body = json!({ "text": text, "run": run });
+        let result = reqwest::Client::new()
+            .post(url.clone())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => println!("Posted the {} failure alert to the webhook", run.job),
+            Err(e) => eprintln!("Error posting the {} failure alert: {}", run.job, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_message() {
+        let run = JobRun {
+            id: "0190b6a0-0000-7000-8000-000000000000".to_string(),
+            job: "sync".to_string(),
+            status: "failed".to_string(),
+            source: "api".to_string(),
+            payload: "{}".to_string(),
+            run_at: None,
+            attempts: 3,
+            max_attempts: 3,
+            count: None,
+            error: Some(
+                "error sending request for url (https://api.tidesandcurrents.noaa.gov)".to_string(),
+            ),
+            started_at: NaiveDate::from_ymd_opt(2026, 1, 1)
+                .unwrap()
+                .and_hms_opt(8, 0, 0),
+            finished_at: None,
+        };
+        let (subject, text) = message(&run, Some("https://example.com"));
+        assert_eq!(subject, "The sync job failed");
+        assert!(text.contains("Error: error sending request"));
+        assert!(text.contains("Attempt: 3 of 3\nStarted: 2026-01-01 08:00:00 UTC"));
+        assert!(
+            text.ends_with(
+                "https://example.com/admin/api/runs/0190b6a0-0000-7000-8000-000000000000"
+            )
+        );
+    }
+}
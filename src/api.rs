@@ -0,0 +1,717 @@
+use axum::extract::{Query, Request, State};
+use axum::http::header::{
+    ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    LINK, RETRY_AFTER,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use mill_valley_sausalito_bikepath_flood_alert::api_types::{
+    EventsResponse, FloodDayEntry, FloodEventEntry, FunnelEntry, GrowthEntry, HeardFromEntry,
+    MessageResponse, PoolStatus, Prediction, PredictionsResponse, SensorResponse, StatsResponse,
+    StatusResponse, TideEntry, TidesResponse,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_governor::GovernorLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::AppState;
+use crate::api_keys::require_api_key;
+use crate::attribution::signups_by_heard_from;
+use crate::calibration::{FloodReportRequest, record_report};
+use crate::database::{self, PoolProbe};
+use crate::error::AppError;
+use crate::events::EventSource;
+use crate::extract::ValidatedJson;
+use crate::floods::{FLOOD_MARGIN_MINUTES, group_flood_events, next_flood};
+use crate::funnel::{DEFAULT_FUNNEL_WEEKS, verification_funnel};
+use crate::growth::{DEFAULT_GROWTH_DAYS, GrowthPeriod, Interval, subscriber_growth};
+use crate::handlers::sign_up;
+use crate::models::SignUpRequest;
+use crate::rate_limit::{IpRateLimitConfig, SharedLimit, shared_limit};
+use crate::request_id;
+use crate::snapshot::{self, Stale, Validators};
+use crate::sync_runs::{self, SyncRun};
+use crate::tides::{
+    self, FORECAST_DAYS, FloodTide, flood_threshold, get_flood_tides_between, station_id,
+};
+
+/// API versions this server can respond with, newest last.
+pub const SUPPORTED_VERSIONS: &[u32] = &[1];
+const API_VERSION_HEADER: &str = "api-version";
+const VENDOR_MEDIA_TYPE_PREFIX: &str = "application/vnd.mvflood.v";
+/// Set when a response was served from the in-memory snapshot because the
+/// database couldn't be read, to when the snapshot was taken.
+const DATA_STALE_SINCE_HEADER: &str = "data-stale-since";
+
+/// Routes nested under `/api`. Each version lives under its own prefix so a
+/// breaking change to a response shape goes into a new version instead.
+pub fn router(
+    state: Arc<AppState>,
+    signup_limit: Arc<IpRateLimitConfig>,
+    shared_signup: SharedLimit,
+) -> Router<Arc<AppState>> {
+    // Bulk data endpoints need an API key so heavy consumers can be identified
+    // and limited individually
+    let keyed = Router::new()
+        .route(
+            "/tides",
+            get(tides_handler).layer(middleware::from_fn_with_state(state.clone(), conditional)),
+        )
+        .route("/stats", get(stats_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_api_key,
+        ));
+
+    let v1 = Router::new()
+        .route(
+            "/predictions",
+            get(predictions_handler)
+                .layer(middleware::from_fn_with_state(state.clone(), conditional)),
+        )
+        .route(
+            "/events",
+            get(events_handler).layer(middleware::from_fn_with_state(state, conditional)),
+        )
+        .route("/sensor", get(sensor_handler))
+        .route("/status", get(status_handler))
+        .merge(keyed)
+        .route(
+            "/signup",
+            post(signup_handler).layer((
+                GovernorLayer::new(signup_limit.clone()),
+                middleware::from_fn_with_state(shared_signup.clone(), shared_limit),
+            )),
+        )
+        .route(
+            "/reports",
+            post(report_handler).layer((
+                GovernorLayer::new(signup_limit),
+                middleware::from_fn_with_state(shared_signup, shared_limit),
+            )),
+        )
+        .layer(middleware::from_fn(|req, next| {
+            negotiate_version(1, req, next)
+        }));
+
+    let api = Router::new().nest("/v1", v1);
+    match cors_layer() {
+        Some(cors) => api.layer(cors),
+        None => api,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AllowedOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+/// Parses a comma separated list of origins, where `*` allows any origin.
+fn parse_allowed_origins(value: &str) -> Option<AllowedOrigins> {
+    let mut origins = Vec::new();
+    for origin in value.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        if origin == "*" {
+            return Some(AllowedOrigins::Any);
+        }
+        match HeaderValue::from_str(origin.trim_end_matches('/')) {
+            Ok(origin) => origins.push(origin),
+            Err(_) => eprintln!("Ignoring invalid CORS origin: {}", origin),
+        }
+    }
+    (!origins.is_empty()).then_some(AllowedOrigins::List(origins))
+}
+
+/// Lets browser apps on the origins in `CORS_ALLOWED_ORIGINS`, like the
+/// embeddable widget or a third party dashboard, call the API directly.
+/// Without it browsers only allow same origin requests.
+fn cors_layer() -> Option<CorsLayer> {
+    let origins = parse_allowed_origins(&env::var("CORS_ALLOWED_ORIGINS").ok()?)?;
+    let allow_origin = match origins {
+        AllowedOrigins::Any => AllowOrigin::any(),
+        AllowedOrigins::List(origins) => AllowOrigin::list(origins),
+    };
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([
+                AUTHORIZATION,
+                CONTENT_TYPE,
+                IF_MODIFIED_SINCE,
+                IF_NONE_MATCH,
+                HeaderName::from_static(API_VERSION_HEADER),
+            ])
+            .expose_headers([
+                HeaderName::from_static(API_VERSION_HEADER),
+                ETAG,
+                HeaderName::from_static(DATA_STALE_SINCE_HEADER),
+                HeaderName::from_static("deprecation"),
+                HeaderName::from_static("sunset"),
+                LINK,
+                RETRY_AFTER,
+                request_id::X_REQUEST_ID,
+            ])
+            .max_age(Duration::from_secs(60 * 60)),
+    )
+}
+
+/// Rejects requests asking for a different version than the route serves,
+/// via either an `API-Version` header or a vendor media type in `Accept`
+/// (`application/vnd.mvflood.v1+json`), and labels the response's version.
+async fn negotiate_version(version: u32, req: Request, next: Next) -> Response {
+    if let Some(requested) = requested_version(req.headers())
+        && requested != version
+    {
+        return (
+            StatusCode::NOT_ACCEPTABLE,
+            Json(VersionError {
+                error: format!("API version {} is not served at this path", requested),
+                supported_versions: SUPPORTED_VERSIONS,
+            }),
+        )
+            .into_response();
+    }
+
+    let mut response = next.run(req).await;
+    response
+        .headers_mut()
+        .insert(API_VERSION_HEADER, HeaderValue::from(version));
+    response
+}
+
+fn requested_version(headers: &HeaderMap) -> Option<u32> {
+    if let Some(version) = headers
+        .get(API_VERSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+    {
+        return Some(version);
+    }
+
+    headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())?
+        .split(',')
+        .find_map(|media_type| {
+            media_type
+                .trim()
+                .strip_prefix(VENDOR_MEDIA_TYPE_PREFIX)?
+                .split_once('+')?
+                .0
+                .parse()
+                .ok()
+        })
+}
+
+/// Marks an endpoint as deprecated using the `Deprecation` (RFC 9745) and
+/// `Sunset` (RFC 8594) headers, pointing clients at its replacement.
+pub struct Deprecation {
+    /// Unix timestamp of when the endpoint was deprecated
+    pub since: i64,
+    /// HTTP date after which the endpoint may be removed
+    pub sunset: Option<&'static str>,
+    pub successor: &'static str,
+}
+
+impl Deprecation {
+    pub fn apply(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        headers.insert(
+            "deprecation",
+            HeaderValue::from_str(&format!("@{}", self.since)).unwrap(),
+        );
+        if let Some(sunset) = self.sunset {
+            headers.insert("sunset", HeaderValue::from_static(sunset));
+        }
+        headers.insert(
+            "link",
+            HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", self.successor))
+                .unwrap(),
+        );
+    }
+}
+
+/// JSON requests to `/signup` predate the versioned API.
+pub const LEGACY_JSON_SIGNUP: Deprecation = Deprecation {
+    since: 1_792_022_400,
+    sunset: None,
+    successor: "/api/v1/signup",
+};
+
+#[derive(Serialize)]
+struct VersionError {
+    error: String,
+    supported_versions: &'static [u32],
+}
+
+/// The `/api/v1/predictions` body, also written by `render`.
+pub fn predictions_response(tides: Vec<FloodTide>) -> PredictionsResponse {
+    PredictionsResponse {
+        station_id: station_id().to_string(),
+        flood_threshold_ft: flood_threshold(),
+        forecast_days: FORECAST_DAYS,
+        predictions: tides.into_iter().map(prediction).collect(),
+        adjustment_ft: tides::prediction_offset(),
+    }
+}
+
+fn prediction(tide: FloodTide) -> Prediction {
+    Prediction {
+        time: tides::local(tide.prediction_time),
+        height_ft: tide.height_ft,
+    }
+}
+
+/// The `/api/v1/events` body, also written by `render`.
+pub fn events_response(tides: Vec<FloodTide>) -> EventsResponse {
+    EventsResponse {
+        station_id: station_id().to_string(),
+        flood_threshold_ft: flood_threshold(),
+        forecast_days: FORECAST_DAYS,
+        events: group_flood_events(tides)
+            .into_iter()
+            .map(|event| FloodEventEntry {
+                start: tides::local(event.start),
+                end: tides::local(event.end),
+                peak_time: tides::local(event.peak_time),
+                peak_height_ft: event.peak_height_ft,
+                severity: event.severity.as_str().to_string(),
+                days: event
+                    .days
+                    .into_iter()
+                    .map(|day| FloodDayEntry {
+                        date: day.date,
+                        peak_height_ft: day.peak_height_ft,
+                        tides: day.tides.into_iter().map(prediction).collect(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        adjustment_ft: tides::prediction_offset(),
+    }
+}
+
+/// Answers `If-None-Match` and `If-Modified-Since` on the forecast endpoints
+/// with a 304 from the snapshot's validators, without querying the database,
+/// and labels full responses with them. Endpoints that change with the
+/// clock, like the sensor, can't use it.
+async fn conditional(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(validators) = state.tide_snapshot.validators(Utc::now()) else {
+        return next.run(req).await;
+    };
+    let mut response = if not_modified(req.headers(), &validators) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        next.run(req).await
+    };
+    if matches!(response.status(), StatusCode::OK | StatusCode::NOT_MODIFIED) {
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&validators.etag) {
+            headers.insert(ETAG, etag);
+        }
+        let last_modified = validators
+            .last_modified
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        if let Ok(last_modified) = HeaderValue::from_str(&last_modified) {
+            headers.insert(LAST_MODIFIED, last_modified);
+        }
+    }
+    response
+}
+
+/// Whether the client's copy is current. `If-None-Match` takes precedence
+/// over `If-Modified-Since` when both are sent.
+fn not_modified(headers: &HeaderMap, validators: &Validators) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|etag| etag == "*" || etag.trim_start_matches("W/") == validators.etag)
+        });
+    }
+    headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| since.timestamp() >= validators.last_modified.timestamp())
+}
+
+/// Labels a response served from the snapshot.
+fn mark_stale(mut response: Response, stale: Option<Stale>) -> Response {
+    if let Some(value) = stale.and_then(|stale| HeaderValue::from_str(&stale.header_value()).ok()) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(DATA_STALE_SINCE_HEADER), value);
+    }
+    response
+}
+
+async fn predictions_handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (tides, stale) = snapshot::flood_tides(&state, FORECAST_DAYS).await?;
+    Ok(mark_stale(
+        Json(predictions_response(tides)).into_response(),
+        stale,
+    ))
+}
+
+fn sensor_response(tides: &[FloodTide], now: DateTime<Utc>) -> SensorResponse {
+    let (status, flood) = next_flood(tides, now);
+    let margin = chrono::Duration::minutes(FLOOD_MARGIN_MINUTES);
+    SensorResponse {
+        status: status.as_str().to_string(),
+        next_flood_time: flood.map(|flood| {
+            flood
+                .prediction_time
+                .with_timezone(&tides::LOCAL_TZ)
+                .fixed_offset()
+        }),
+        next_flood_height: flood.map(|flood| flood.height_ft),
+        seconds_until_flood: flood
+            .map(|flood| (flood.prediction_time - margin - now).num_seconds().max(0)),
+        adjustment_ft: tides::prediction_offset(),
+    }
+}
+
+/// The sensor state at the moment. Includes a flood that peaked in the last
+/// hour, since the path is still under water.
+pub async fn current_sensor(pool: &SqlitePool) -> Result<SensorResponse, AppError> {
+    let now = Utc::now();
+    let margin = chrono::Duration::minutes(FLOOD_MARGIN_MINUTES);
+    let tides = get_flood_tides_between(
+        pool,
+        now - margin,
+        now + chrono::Duration::days(FORECAST_DAYS),
+    )
+    .await?;
+    Ok(sensor_response(&tides, now))
+}
+
+/// The next flood for home dashboards.
+async fn sensor_handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let now = Utc::now();
+    let margin = chrono::Duration::minutes(FLOOD_MARGIN_MINUTES);
+    let (tides, stale) = snapshot::flood_tides_between(
+        &state,
+        now - margin,
+        now + chrono::Duration::days(FORECAST_DAYS),
+    )
+    .await?;
+    Ok(mark_stale(
+        Json(sensor_response(&tides, now)).into_response(),
+        stale,
+    ))
+}
+
+fn status_response(
+    last_sync: Option<SyncRun>,
+    fetched_at: Option<DateTime<Utc>>,
+    pool: PoolProbe,
+    now: DateTime<Utc>,
+) -> StatusResponse {
+    StatusResponse {
+        station_id: station_id().to_string(),
+        last_sync: last_sync.as_ref().map(SyncRun::entry),
+        predictions_fetched_at: fetched_at.map(|fetched_at| fetched_at.naive_utc()),
+        outdated: fetched_at.is_some_and(|fetched_at| sync_runs::is_outdated(fetched_at, now)),
+        db_pool: PoolStatus {
+            size: pool.size,
+            idle: pool.idle,
+            max_connections: pool.max_connections,
+            last_acquire_wait_ms: pool.last_wait.map(|wait| wait.as_millis() as u64),
+            acquire_timeouts: pool.timeouts,
+        },
+    }
+}
+
+/// The latest sync, how fresh the predictions are and the database pool,
+/// without a query so it still answers while the database is down or its
+/// connections are used up.
+async fn status_handler(State(state): State<Arc<AppState>>) -> Json<StatusResponse> {
+    Json(status_response(
+        state.tide_snapshot.last_sync(),
+        state.tide_snapshot.fetched_at(),
+        database::pool_probe(&state.pool),
+        Utc::now(),
+    ))
+}
+
+/// Flood tides grouped into events of consecutive flooding days.
+async fn events_handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (tides, stale) = snapshot::flood_tides(&state, FORECAST_DAYS).await?;
+    Ok(mark_stale(
+        Json(events_response(tides)).into_response(),
+        stale,
+    ))
+}
+
+async fn tides_handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (tides, stale) = snapshot::tides(&state, FORECAST_DAYS).await?;
+    Ok(mark_stale(
+        Json(TidesResponse {
+            station_id: station_id().to_string(),
+            forecast_days: FORECAST_DAYS,
+            tides: tides
+                .into_iter()
+                .map(|tide| TideEntry {
+                    time: tides::local(tide.prediction_time),
+                    height_ft: tide.height_ft,
+                    tide_type: tide.tide_type,
+                })
+                .collect(),
+            adjustment_ft: tides::prediction_offset(),
+        })
+        .into_response(),
+        stale,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    weeks: Option<i64>,
+    days: Option<i64>,
+}
+
+fn growth_entries(growth: Vec<GrowthPeriod>) -> Vec<GrowthEntry> {
+    growth
+        .into_iter()
+        .map(|period| GrowthEntry {
+            net: period.net(),
+            start: period.start,
+            gained: period.gained,
+            lost: period.lost,
+        })
+        .collect()
+}
+
+async fn stats_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<StatsResponse>, AppError> {
+    let weeks = params.weeks.unwrap_or(DEFAULT_FUNNEL_WEEKS).clamp(1, 104);
+    let days = params.days.unwrap_or(DEFAULT_GROWTH_DAYS).clamp(1, 366);
+    let funnel = verification_funnel(&state.pool, weeks).await?;
+    let today = Utc::now().date_naive();
+    let daily = subscriber_growth(&state.pool, Interval::Day, days, today).await?;
+    let weekly = subscriber_growth(&state.pool, Interval::Week, weeks, today).await?;
+    let heard_from = signups_by_heard_from(&state.pool).await?;
+    Ok(Json(StatsResponse {
+        verification_funnel: funnel
+            .into_iter()
+            .map(|week| FunnelEntry {
+                verified_24h_rate: week.rate_24h(),
+                verified_7d_rate: week.rate_7d(),
+                week: week.week,
+                signups: week.signups,
+                verified_24h: week.verified_24h,
+                verified_7d: week.verified_7d,
+            })
+            .collect(),
+        daily_growth: growth_entries(daily),
+        weekly_growth: growth_entries(weekly),
+        heard_from: heard_from
+            .into_iter()
+            .map(|answer| HeardFromEntry {
+                answer: answer.answer,
+                signups: answer.signups,
+                verified: answer.verified,
+            })
+            .collect(),
+    }))
+}
+
+async fn signup_handler(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<SignUpRequest>,
+) -> Response {
+    match sign_up(&state, payload, EventSource::Api).await {
+        Ok(message) => Json(MessageResponse { message }).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Records whether someone saw the path flooded, for `calibrate`.
+async fn report_handler(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(report): ValidatedJson<FloodReportRequest>,
+) -> Response {
+    match record_report(&state.pool, &report, EventSource::Api).await {
+        Ok(()) => (
+            StatusCode::CREATED,
+            Json(MessageResponse {
+                message: "Thanks for the report.".to_string(),
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            eprintln!("Database error recording a flood report: {:?}", e);
+            AppError::Internal("Internal server error".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensor_response() {
+        let tides = [FloodTide {
+            prediction_time: "2026-12-13T16:50:00Z".parse().unwrap(),
+            height_ft: 7.1,
+        }];
+        // 5:50 PST, two hours before the path starts flooding
+        let now = "2026-12-13T13:50:00Z".parse::<DateTime<Utc>>().unwrap();
+        let json = serde_json::to_value(sensor_response(&tides, now)).unwrap();
+        assert_eq!(json["status"], "upcoming");
+        assert_eq!(json["next_flood_time"], "2026-12-13T08:50:00-08:00");
+        assert_eq!(json["next_flood_height"], 7.1);
+        assert_eq!(json["seconds_until_flood"], 7200);
+
+        let json = serde_json::to_value(sensor_response(&[], now)).unwrap();
+        assert_eq!(json["status"], "clear");
+        assert!(json["next_flood_time"].is_null());
+    }
+
+    #[test]
+    fn test_status_response() {
+        let now = "2026-12-13T13:50:00Z".parse::<DateTime<Utc>>().unwrap();
+        let fetched_at = "2026-12-11T20:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let run = SyncRun {
+            station_id: station_id().to_string(),
+            window_start: fetched_at.naive_utc(),
+            window_end: now.naive_utc(),
+            inserted: 124,
+            deleted: 122,
+            duration_ms: 850,
+            source: Some("cache".to_string()),
+            fetched_at: Some(fetched_at.naive_utc()),
+            error: None,
+            finished_at: now.naive_utc(),
+        };
+        let pool = PoolProbe {
+            size: 5,
+            max_connections: 5,
+            last_wait: Some(std::time::Duration::from_millis(1200)),
+            timeouts: 2,
+            ..PoolProbe::default()
+        };
+        let json =
+            serde_json::to_value(status_response(Some(run), Some(fetched_at), pool, now)).unwrap();
+        assert_eq!(json["db_pool"]["idle"], 0);
+        assert_eq!(json["db_pool"]["last_acquire_wait_ms"], 1200);
+        assert_eq!(json["db_pool"]["acquire_timeouts"], 2);
+        assert_eq!(json["last_sync"]["source"], "cache");
+        assert_eq!(json["last_sync"]["inserted"], 124);
+        assert_eq!(json["predictions_fetched_at"], "2026-12-11T20:00:00");
+        assert_eq!(json["outdated"], true);
+
+        let json =
+            serde_json::to_value(status_response(None, None, PoolProbe::default(), now)).unwrap();
+        assert!(json["last_sync"].is_null());
+        assert_eq!(json["outdated"], false);
+    }
+
+    #[test]
+    fn test_requested_version_from_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(requested_version(&headers), None);
+
+        headers.insert(API_VERSION_HEADER, HeaderValue::from_static("2"));
+        assert_eq!(requested_version(&headers), Some(2));
+    }
+
+    #[test]
+    fn test_requested_version_from_accept() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html, application/vnd.mvflood.v1+json"),
+        );
+        assert_eq!(requested_version(&headers), Some(1));
+
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        assert_eq!(requested_version(&headers), None);
+    }
+
+    #[test]
+    fn test_parse_allowed_origins() {
+        assert_eq!(
+            parse_allowed_origins("https://a.example, https://b.example/ ,"),
+            Some(AllowedOrigins::List(vec![
+                HeaderValue::from_static("https://a.example"),
+                HeaderValue::from_static("https://b.example"),
+            ]))
+        );
+        assert_eq!(
+            parse_allowed_origins("https://a.example,*"),
+            Some(AllowedOrigins::Any)
+        );
+        assert_eq!(parse_allowed_origins(" "), None);
+    }
+
+    #[test]
+    fn test_deprecation_headers() {
+        let mut response = StatusCode::OK.into_response();
+        Deprecation {
+            since: 1_700_000_000,
+            sunset: Some("Fri, 01 Jan 2027 00:00:00 GMT"),
+            successor: "/api/v1/signup",
+        }
+        .apply(&mut response);
+
+        let headers = response.headers();
+        assert_eq!(headers["deprecation"], "@1700000000");
+        assert_eq!(headers["sunset"], "Fri, 01 Jan 2027 00:00:00 GMT");
+        assert_eq!(
+            headers["link"],
+            "</api/v1/signup>; rel=\"successor-version\""
+        );
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let validators = Validators {
+            etag: "\"1760000000-202610151230-202611142250\"".to_string(),
+            last_modified: DateTime::parse_from_rfc3339("2025-10-09T08:53:20Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        };
+        let mut headers = HeaderMap::new();
+        assert!(!not_modified(&headers, &validators));
+
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 09 Oct 2025 08:53:20 GMT"),
+        );
+        assert!(not_modified(&headers, &validators));
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 09 Oct 2025 08:53:19 GMT"),
+        );
+        assert!(!not_modified(&headers, &validators));
+
+        // A stale ETag wins over a current date
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 09 Oct 2025 09:00:00 GMT"),
+        );
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("\"1-none-none\""));
+        assert!(!not_modified(&headers, &validators));
+        headers.insert(
+            IF_NONE_MATCH,
+            HeaderValue::from_static("\"1-none-none\", W/\"1760000000-202610151230-202611142250\""),
+        );
+        assert!(not_modified(&headers, &validators));
+    }
+}
@@ -0,0 +1,233 @@
+use axum::extract::{Request, State};
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::AppState;
+use crate::error::AppError;
+
+const KEY_PREFIX: &str = "mvf_";
+
+/// Generates a new random API key. Only its hash is ever stored.
+pub fn generate_key() -> String {
+    format!(
+        "{}{}{}",
+        KEY_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// In-memory rate limiters, one per API key with that key's own quota.
+#[derive(Default)]
+pub struct ApiKeyLimiters {
+    limiters: Mutex<HashMap<String, Arc<DefaultDirectRateLimiter>>>,
+}
+
+impl ApiKeyLimiters {
+    /// Returns how long the caller has to wait if the key is over its limit.
+    fn check(&self, key_id: &str, per_minute: u32) -> Result<(), std::time::Duration> {
+        let limiter = self
+            .limiters
+            .lock()
+            .unwrap()
+            .entry(key_id.to_string())
+            .or_insert_with(|| {
+                let per_minute = NonZeroU32::new(per_minute).unwrap_or(NonZeroU32::MIN);
+                Arc::new(RateLimiter::direct(Quota::per_minute(per_minute)))
+            })
+            .clone();
+
+        limiter
+            .check()
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    AppError::Unauthorized(message.to_string()).into_response()
+}
+
+/// Middleware requiring a valid, unrevoked `Authorization: Bearer <key>`
+/// header, and applying that key's per-minute rate limit.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = bearer_token(req.headers()) else {
+        return unauthorized("An API key is required for this endpoint");
+    };
+    let key_hash = hash_key(key);
+
+    let result = sqlx::query!(
+        r#"
+        SELECT id, rate_limit_per_minute FROM api_keys
+        WHERE key_hash = ? AND revoked_at IS NULL
+        "#,
+        key_hash
+    )
+    .fetch_optional(&state.pool)
+    .await;
+
+    let api_key = match result {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => return unauthorized("Invalid or revoked API key"),
+        Err(e) => {
+            return AppError::Database(e).into_response();
+        }
+    };
+
+    let per_minute = u32::try_from(api_key.rate_limit_per_minute).unwrap_or(0);
+    if let Err(wait) = state.api_key_limiters.check(&api_key.id, per_minute) {
+        return AppError::TooManyRequests {
+            message: "API key rate limit exceeded".to_string(),
+            retry_after_secs: Some(wait.as_secs().max(1)),
+        }
+        .into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Creates a key and prints it, this is the only time the key is shown.
+pub async fn mint(
+    pool: &SqlitePool,
+    name: &str,
+    rate_limit_per_minute: u32,
+) -> Result<(), AppError> {
+    let id = Uuid::new_v7(Timestamp::now(NoContext)).to_string();
+    let key = generate_key();
+    let key_hash = hash_key(&key);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO api_keys (id, name, key_hash, rate_limit_per_minute)
+        VALUES (?, ?, ?, ?);
+        "#,
+        id,
+        name,
+        key_hash,
+        rate_limit_per_minute
+    )
+    .execute(pool)
+    .await?;
+
+    println!("Created API key {} for {}", id, name);
+    println!("Key (store it now, it can't be shown again): {}", key);
+    Ok(())
+}
+
+pub async fn revoke(pool: &SqlitePool, id: &str) -> Result<(), AppError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND revoked_at IS NULL;
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        println!("Revoked API key {}", id);
+    } else {
+        println!("No active API key with id {}", id);
+    }
+    Ok(())
+}
+
+pub async fn list(pool: &SqlitePool) -> Result<(), AppError> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT id, name, rate_limit_per_minute,
+            created_at AS "created_at: chrono::NaiveDateTime",
+            revoked_at AS "revoked_at: chrono::NaiveDateTime"
+        FROM api_keys
+        ORDER BY created_at ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for key in keys {
+        println!(
+            "{}  {}  {}/min  created {}  {}",
+            key.id,
+            key.name,
+            key.rate_limit_per_minute,
+            key.created_at.map(|t| t.to_string()).unwrap_or_default(),
+            key.revoked_at
+                .map(|t| format!("revoked {}", t))
+                .unwrap_or_else(|| "active".to_string())
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_key() {
+        let key = generate_key();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + 64);
+        assert_ne!(key, generate_key(), "Keys should be random");
+    }
+
+    #[test]
+    fn test_hash_key() {
+        let key = generate_key();
+        assert_eq!(hash_key(&key), hash_key(&key));
+        assert_ne!(hash_key(&key), key);
+        assert_eq!(hash_key(&key).len(), 64);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let req = Request::builder()
+            .header(AUTHORIZATION, "Bearer mvf_abc")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(bearer_token(req.headers()), Some("mvf_abc"));
+
+        let req = Request::builder()
+            .header(AUTHORIZATION, "Basic dXNlcjpwYXNz")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(bearer_token(req.headers()), None);
+    }
+
+    #[test]
+    fn test_limiters_are_per_key() {
+        let limiters = ApiKeyLimiters::default();
+        assert!(limiters.check("a", 2).is_ok());
+        assert!(limiters.check("a", 2).is_ok());
+        assert!(limiters.check("a", 2).is_err());
+        assert!(limiters.check("b", 2).is_ok());
+    }
+}
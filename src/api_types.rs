@@ -0,0 +1,222 @@
+//! Response bodies of the `/api/v1` endpoints, shared by the server and the
+//! `client` module.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Body of every error response, an RFC 7807 problem served as
+/// `application/problem+json`. `error` repeats `detail` for clients that
+/// predate the problem members.
+pub struct ErrorResponse {
+    #[serde(rename = "type", default = "about_blank")]
+    pub problem_type: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub status: u16,
+    #[serde(default)]
+    pub detail: String,
+    pub error: String,
+    /// Messages for each invalid field of a request body, by field name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Vec<String>>,
+    /// The response's `X-Request-Id`, to quote when reporting a problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+fn about_blank() -> String {
+    "about:blank".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A predicted high tide at or above the flood threshold.
+pub struct Prediction {
+    pub time: NaiveDateTime,
+    pub height_ft: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PredictionsResponse {
+    pub station_id: String,
+    pub flood_threshold_ft: f64,
+    pub forecast_days: i64,
+    pub predictions: Vec<Prediction>,
+    /// Added to NOAA's predicted heights for recently measured water levels,
+    /// when a bias correction is applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adjustment_ft: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FloodDayEntry {
+    pub date: NaiveDate,
+    pub peak_height_ft: f64,
+    pub tides: Vec<Prediction>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FloodEventEntry {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub peak_time: NaiveDateTime,
+    pub peak_height_ft: f64,
+    /// `minor`, `moderate` or `major`
+    pub severity: String,
+    pub days: Vec<FloodDayEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventsResponse {
+    pub station_id: String,
+    pub flood_threshold_ft: f64,
+    pub forecast_days: i64,
+    pub events: Vec<FloodEventEntry>,
+    /// Added to NOAA's predicted heights for recently measured water levels,
+    /// when a bias correction is applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adjustment_ft: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// The next flood as flat fields, shaped for a Home Assistant REST sensor.
+pub struct SensorResponse {
+    /// `flooding`, `upcoming` when a flood starts within a day, or `clear`
+    pub status: String,
+    /// Peak of the flood in progress or the next one, with its UTC offset
+    pub next_flood_time: Option<DateTime<FixedOffset>>,
+    pub next_flood_height: Option<f64>,
+    /// Until the path is expected to start flooding, 0 while it is
+    pub seconds_until_flood: Option<i64>,
+    /// Added to NOAA's predicted heights for recently measured water levels,
+    /// when a bias correction is applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adjustment_ft: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A predicted high or low tide.
+pub struct TideEntry {
+    pub time: NaiveDateTime,
+    pub height_ft: f64,
+    /// `High` or `Low`
+    pub tide_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TidesResponse {
+    pub station_id: String,
+    pub forecast_days: i64,
+    pub tides: Vec<TideEntry>,
+    /// Added to NOAA's predicted heights for recently measured water levels,
+    /// when a bias correction is applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adjustment_ft: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FunnelEntry {
+    /// Monday the week starts on
+    pub week: String,
+    pub signups: i64,
+    pub verified_24h: i64,
+    pub verified_7d: i64,
+    pub verified_24h_rate: Option<f64>,
+    pub verified_7d_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Subscribers gained and lost in a day or week.
+pub struct GrowthEntry {
+    /// First day of the period, or the Monday of the week
+    pub start: NaiveDate,
+    /// Addresses that verified
+    pub gained: i64,
+    /// Unsubscribes and suppressions of verified addresses
+    pub lost: i64,
+    pub net: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    pub verification_funnel: Vec<FunnelEntry>,
+    /// Oldest first
+    #[serde(default)]
+    pub daily_growth: Vec<GrowthEntry>,
+    /// Oldest first, over the same weeks as the funnel
+    #[serde(default)]
+    pub weekly_growth: Vec<GrowthEntry>,
+    /// Answers to "How did you hear about us?" from subscribers still on the
+    /// list, most common first
+    #[serde(default)]
+    pub heard_from: Vec<HeardFromEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeardFromEntry {
+    /// e.g. `signage` or `nextdoor`
+    pub answer: String,
+    pub signups: i64,
+    pub verified: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// One sync of the station's predictions, with times in UTC.
+pub struct SyncRunEntry {
+    pub finished_at: NaiveDateTime,
+    pub window_start: NaiveDateTime,
+    pub window_end: NaiveDateTime,
+    pub inserted: i64,
+    pub deleted: i64,
+    pub duration_ms: i64,
+    /// `api`, or `cache` when NOAA couldn't be reached
+    pub source: Option<String>,
+    /// When NOAA sent the predictions
+    pub fetched_at: Option<NaiveDateTime>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub station_id: String,
+    pub last_sync: Option<SyncRunEntry>,
+    /// When NOAA sent the predictions being served, in UTC
+    pub predictions_fetched_at: Option<NaiveDateTime>,
+    /// Whether those predictions are old enough that NOAA's latest may be
+    /// missing from the forecast
+    pub outdated: bool,
+    pub db_pool: PoolStatus,
+}
+
+/// The database connection pool, for telling when it runs out under load.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+    /// How long the server's latest probe waited for a connection
+    pub last_acquire_wait_ms: Option<u64>,
+    /// Probes that timed out waiting for a connection since it started
+    pub acquire_timeouts: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_response_round_trip() {
+        let json = r#"{"station_id":"9414819","flood_threshold_ft":6.4,"forecast_days":30,"events":[{"start":"2026-12-13T08:50:00","end":"2026-12-13T21:20:00","peak_time":"2026-12-13T08:50:00","peak_height_ft":7.1,"severity":"major","days":[{"date":"2026-12-13","peak_height_ft":7.1,"tides":[{"time":"2026-12-13T08:50:00","height_ft":7.1},{"time":"2026-12-13T21:20:00","height_ft":6.6}]}]}]}"#;
+        let response: EventsResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(response.events[0].severity, "major");
+        assert_eq!(response.events[0].days[0].tides.len(), 2);
+        assert_eq!(serde_json::to_string(&response).unwrap(), json);
+    }
+}
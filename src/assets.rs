@@ -0,0 +1,148 @@
+use axum::Router;
+use axum::extract::Request;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderValue, Uri};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::LazyLock;
+use tower_http::services::ServeDir;
+
+const ASSETS_DIR: &str = "assets";
+/// Hex digits of the content hash put in fingerprinted names.
+const FINGERPRINT_LEN: usize = 8;
+/// A fingerprinted URL always serves the same bytes, so it can be cached for good.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+static MANIFEST: LazyLock<AssetManifest> =
+    LazyLock::new(|| match AssetManifest::load(Path::new(ASSETS_DIR)) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Error fingerprinting assets: {}", e);
+            AssetManifest::default()
+        }
+    });
+
+/// Fingerprinted names of the files in `assets/`, with a hash of their
+/// contents before the extension, e.g. `img/favicon.png` is served as
+/// `img/favicon-1a2b3c4d.png`.
+#[derive(Debug, Default)]
+pub struct AssetManifest {
+    fingerprinted: HashMap<String, String>,
+    originals: HashMap<String, String>,
+}
+
+impl AssetManifest {
+    fn load(dir: &Path) -> io::Result<Self> {
+        let mut manifest = AssetManifest::default();
+        manifest.add_dir(dir, "")?;
+        Ok(manifest)
+    }
+
+    fn add_dir(&mut self, dir: &Path, prefix: &str) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.add_dir(&entry.path(), &format!("{}/", name))?;
+            } else {
+                let fingerprinted = fingerprint(&name, &fs::read(entry.path())?);
+                self.originals.insert(fingerprinted.clone(), name.clone());
+                self.fingerprinted.insert(name, fingerprinted);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.fingerprinted.len()
+    }
+
+    /// Pairs of each asset's path and its fingerprinted path.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fingerprinted
+            .iter()
+            .map(|(name, fingerprinted)| (name.as_str(), fingerprinted.as_str()))
+    }
+}
+
+fn fingerprint(name: &str, contents: &[u8]) -> String {
+    let hash = hex::encode(Sha256::digest(contents));
+    let hash = &hash[..FINGERPRINT_LEN];
+    let file_start = name.rfind('/').map_or(0, |slash| slash + 1);
+    match name[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = name.split_at(file_start + dot);
+            format!("{}-{}{}", stem, hash, extension)
+        }
+        _ => format!("{}-{}", name, hash),
+    }
+}
+
+/// Hashes the assets now rather than on the first page load.
+pub fn manifest() -> &'static AssetManifest {
+    &MANIFEST
+}
+
+/// URL of a file in `assets/` for templates, fingerprinted so it can be
+/// cached forever: `asset("img/favicon.png")` is
+/// `/assets/img/favicon-1a2b3c4d.png`. Unknown files keep their name.
+pub fn asset(path: &str) -> String {
+    match MANIFEST.fingerprinted.get(path) {
+        Some(fingerprinted) => format!("/{}/{}", ASSETS_DIR, fingerprinted),
+        None => format!("/{}/{}", ASSETS_DIR, path),
+    }
+}
+
+/// Serves `assets/`, answering fingerprinted names with the original file.
+pub fn router() -> Router {
+    Router::new()
+        .fallback_service(ServeDir::new(ASSETS_DIR))
+        .layer(middleware::from_fn(serve_fingerprinted))
+}
+
+async fn serve_fingerprinted(mut req: Request, next: Next) -> Response {
+    let original = MANIFEST
+        .originals
+        .get(req.uri().path().trim_start_matches('/'));
+    let Some(uri) = original.and_then(|original| format!("/{}", original).parse::<Uri>().ok())
+    else {
+        return next.run(req).await;
+    };
+    *req.uri_mut() = uri;
+    let mut response = next.run(req).await;
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint("main.css", b"body {}"), "main-62368a1a.css");
+        assert_eq!(fingerprint("js/app.min.js", b""), "js/app.min-e3b0c442.js");
+        assert_eq!(fingerprint("v1.0/LICENSE", b""), "v1.0/LICENSE-e3b0c442");
+        assert_eq!(fingerprint(".well-known", b""), ".well-known-e3b0c442");
+
+        let manifest = manifest();
+        assert!(manifest.len() > 0);
+        let favicon = asset("img/favicon.png");
+        assert!(favicon.starts_with("/assets/img/favicon-"));
+        assert!(favicon.ends_with(".png"));
+        assert_eq!(
+            manifest.originals[favicon.trim_start_matches("/assets/")],
+            "img/favicon.png"
+        );
+        assert_eq!(asset("missing.css"), "/assets/missing.css");
+    }
+}
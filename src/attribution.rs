@@ -0,0 +1,140 @@
+use sqlx::sqlite::SqlitePool;
+
+use crate::error::AppError;
+
+const MAX_SOURCE_LEN: usize = 64;
+
+/// Cleans up a `source` or `utm_source` value so the same campaign isn't
+/// counted under slightly different spellings. Returns None for values
+/// that are empty once cleaned.
+pub fn normalize_source(raw: &str) -> Option<String> {
+    let source: String = raw
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .take(MAX_SOURCE_LEN)
+        .collect();
+    (!source.is_empty()).then_some(source)
+}
+
+/// Answers to "How did you hear about us?" on the signup form, in the
+/// order they're offered. Labels are in each language's strings.
+pub const HEARD_FROM: [&str; 6] = [
+    "signage",
+    "nextdoor",
+    "bike-coalition",
+    "friend",
+    "search",
+    "other",
+];
+
+/// One of the `HEARD_FROM` answers, or None for no answer or one that
+/// isn't offered.
+pub fn normalize_heard_from(raw: &str) -> Option<String> {
+    let answer = raw.trim().to_lowercase();
+    HEARD_FROM.contains(&answer.as_str()).then_some(answer)
+}
+
+pub struct SourceCount {
+    pub source: String,
+    pub signups: i64,
+    pub verified: i64,
+}
+
+/// Signups still on the list, or awaiting verification, by where they came
+/// from. Unsubscribed addresses are deleted so don't count.
+pub async fn signups_by_source(pool: &SqlitePool) -> Result<Vec<SourceCount>, sqlx::Error> {
+    sqlx::query_as!(
+        SourceCount,
+        r#"
+        SELECT
+            COALESCE(signup_source, 'direct') AS "source!: String",
+            COUNT(*) AS "signups!: i64",
+            COALESCE(SUM(is_verified = 1), 0) AS "verified!: i64"
+        FROM users
+        GROUP BY signup_source
+        ORDER BY COUNT(*) DESC, signup_source ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub struct HeardFromCount {
+    pub answer: String,
+    pub signups: i64,
+    pub verified: i64,
+}
+
+/// Signups still on the list by how they heard about the site, leaving out
+/// ones that didn't say.
+pub async fn signups_by_heard_from(pool: &SqlitePool) -> Result<Vec<HeardFromCount>, sqlx::Error> {
+    sqlx::query_as!(
+        HeardFromCount,
+        r#"
+        SELECT
+            heard_from AS "answer!: String",
+            COUNT(*) AS "signups!: i64",
+            COALESCE(SUM(is_verified = 1), 0) AS "verified!: i64"
+        FROM users
+        WHERE heard_from IS NOT NULL
+        GROUP BY heard_from
+        ORDER BY COUNT(*) DESC, heard_from ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn print_report(pool: &SqlitePool) -> Result<(), AppError> {
+    let sources = signups_by_source(pool).await?;
+    if sources.is_empty() {
+        println!("No signups yet");
+    }
+    for source in sources {
+        println!(
+            "{:<24}  signups {:>5}  verified {:>5}",
+            source.source, source.signups, source.verified
+        );
+    }
+    let heard_from = signups_by_heard_from(pool).await?;
+    if !heard_from.is_empty() {
+        println!("\nHow they heard about us");
+    }
+    for answer in heard_from {
+        println!(
+            "{:<24}  signups {:>5}  verified {:>5}",
+            answer.answer, answer.signups, answer.verified
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_source() {
+        assert_eq!(normalize_source(" QR Gate "), Some("qr-gate".to_string()));
+        assert_eq!(
+            normalize_source("instagram_2026.10"),
+            Some("instagram_2026.10".to_string())
+        );
+        assert_eq!(
+            normalize_source("<script>x</script>"),
+            Some("scriptxscript".to_string())
+        );
+        assert_eq!(normalize_source("  "), None);
+        assert_eq!(normalize_source(&"a".repeat(100)).unwrap().len(), 64);
+
+        assert_eq!(
+            normalize_heard_from(" Nextdoor"),
+            Some("nextdoor".to_string())
+        );
+        assert_eq!(normalize_heard_from(""), None);
+        assert_eq!(normalize_heard_from("billboard"), None);
+    }
+}
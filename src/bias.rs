@@ -0,0 +1,213 @@
+use chrono::{Duration, Utc};
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use tracing::instrument;
+
+use crate::calibration;
+use crate::error::AppError;
+use crate::tides::{self, station_id};
+
+pub const DEFAULT_BIAS_DAYS: i64 = 7;
+/// Fewer tides than this with a measured water level aren't enough to go on,
+/// about two days' worth.
+const MIN_SAMPLES: usize = 8;
+/// A tide is compared with the average water level measured this close to it.
+const LEVEL_MINUTES: i64 = 6;
+/// Offsets bigger than this are more likely a broken gauge than the weather,
+/// so they're only reported.
+const MAX_APPLIED_OFFSET_FT: f64 = 1.0;
+
+/// Whether `PREDICTION_BIAS_CORRECTION` is on, so predicted heights are
+/// adjusted by the offset instead of it only being reported.
+pub fn correction_enabled() -> bool {
+    env::var("PREDICTION_BIAS_CORRECTION").is_ok_and(|value| value.trim() == "true")
+}
+
+/// How far measured water levels ran from NOAA's predictions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offset {
+    /// The mean of measured less predicted, at each high and low tide
+    pub offset_ft: f64,
+    pub samples: usize,
+}
+
+/// The offset over the high and low tides of the last `days`, from the
+/// water levels already stored. None without enough of them.
+#[instrument(level = "debug", skip(pool))]
+pub async fn compute(pool: &SqlitePool, days: i64) -> Result<Option<Offset>, sqlx::Error> {
+    let station_id = station_id();
+    let now = Utc::now();
+    let since = (now - Duration::days(days)).naive_utc();
+    let now = now.naive_utc();
+    let before = format!("-{} minutes", LEVEL_MINUTES);
+    let after = format!("+{} minutes", LEVEL_MINUTES);
+    let tides = sqlx::query!(
+        r#"
+        SELECT
+            t.height_ft,
+            (
+                SELECT AVG(w.height_ft) FROM water_levels w
+                WHERE w.station_id = ?
+                    AND w.observed_time >= datetime(t.prediction_time, ?)
+                    AND w.observed_time <= datetime(t.prediction_time, ?)
+            ) AS "observed_ft: f64"
+        FROM tides t
+        WHERE t.prediction_time >= ? AND t.prediction_time <= ?
+        "#,
+        station_id,
+        before,
+        after,
+        since,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+    let differences: Vec<f64> = tides
+        .into_iter()
+        .filter_map(|tide| Some(tide.observed_ft? - tide.height_ft))
+        .collect();
+    if differences.len() < MIN_SAMPLES {
+        return Ok(None);
+    }
+    Ok(Some(Offset {
+        offset_ft: differences.iter().sum::<f64>() / differences.len() as f64,
+        samples: differences.len(),
+    }))
+}
+
+#[instrument(level = "debug", skip_all)]
+async fn save(pool: &SqlitePool, offset: &Offset, days: i64) -> Result<(), sqlx::Error> {
+    let station_id = station_id();
+    let samples = offset.samples as i64;
+    sqlx::query!(
+        r#"
+        INSERT INTO prediction_offsets (station_id, offset_ft, samples, days)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (station_id) DO UPDATE SET
+            offset_ft = excluded.offset_ft,
+            samples = excluded.samples,
+            days = excluded.days,
+            computed_at = CURRENT_TIMESTAMP;
+        "#,
+        station_id,
+        offset.offset_ft,
+        samples,
+        days
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Adjusts predicted heights by the station's saved offset, when
+/// `PREDICTION_BIAS_CORRECTION` is on and it's small enough to trust.
+pub async fn load_applied(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let station_id = station_id();
+    if !correction_enabled() {
+        return Ok(());
+    }
+    let offset = sqlx::query_scalar!(
+        "SELECT offset_ft FROM prediction_offsets WHERE station_id = ?",
+        station_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .filter(|offset| offset.abs() <= MAX_APPLIED_OFFSET_FT);
+    tides::apply_prediction_offset(offset);
+    Ok(())
+}
+
+/// Fetches the water levels of the last `days` unless `fetch` is off,
+/// computes and saves the offset, and applies it if that's on.
+pub async fn run(pool: &SqlitePool, days: i64, fetch: bool) -> Result<(), AppError> {
+    if fetch {
+        let today = Utc::now().date_naive();
+        let stored =
+            calibration::fetch_water_levels(pool, today - Duration::days(days), today).await?;
+        println!("Stored {} measured water levels", stored);
+    }
+    let Some(offset) = compute(pool, days).await? else {
+        println!(
+            "Not enough measured tides in the last {} days for an offset, it takes {}",
+            days, MIN_SAMPLES
+        );
+        return Ok(());
+    };
+    save(pool, &offset, days).await?;
+    println!(
+        "Measured water levels ran {:+.2} ft from predictions over {} tides in the last {} days",
+        offset.offset_ft, offset.samples, days
+    );
+    if correction_enabled() {
+        load_applied(pool).await?;
+        if offset.offset_ft.abs() <= MAX_APPLIED_OFFSET_FT {
+            println!("Applied, as PREDICTION_BIAS_CORRECTION is on");
+        } else {
+            println!(
+                "Not applied, it's more than {:.1} ft",
+                MAX_APPLIED_OFFSET_FT
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Recomputes the offset after a sync when the correction is on, so it
+/// rolls forward with the latest measurements. Failures are logged, the
+/// sync already succeeded.
+pub async fn refresh(pool: &SqlitePool) {
+    if !correction_enabled() {
+        return;
+    }
+    if let Err(e) = run(pool, DEFAULT_BIAS_DAYS, true).await {
+        eprintln!("Error computing the prediction offset: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compute_offset() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let now = Utc::now();
+        // Three days of tides, measured 0.3 ft above their predictions on
+        // average, the first without a measurement
+        for (i, predicted) in [5.8, 1.2, 6.1, 0.4, 5.9, 1.0, 6.3, 0.2, 6.0, 0.9]
+            .into_iter()
+            .enumerate()
+        {
+            let time = (now - Duration::hours(72) + Duration::hours(6 * i as i64)).naive_utc();
+            sqlx::query(
+                "INSERT INTO tides (prediction_time, height_ft, tide_type) VALUES (?, ?, ?)",
+            )
+            .bind(time)
+            .bind(predicted)
+            .bind(if i % 2 == 0 { "High" } else { "Low" })
+            .execute(&pool)
+            .await
+            .unwrap();
+            if i == 0 {
+                continue;
+            }
+            let measured = predicted + if i % 2 == 0 { 0.2 } else { 0.4 };
+            sqlx::query(
+                "INSERT INTO water_levels (station_id, observed_time, height_ft) VALUES (?, ?, ?)",
+            )
+            .bind(station_id())
+            .bind(time)
+            .bind(measured)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let offset = compute(&pool, DEFAULT_BIAS_DAYS).await.unwrap().unwrap();
+        assert_eq!(offset.samples, 9);
+        // Five at +0.4 and four at +0.2
+        assert!((offset.offset_ft - 2.8 / 9.0).abs() < 1e-9);
+        assert!(compute(&pool, 1).await.unwrap().is_none());
+    }
+}
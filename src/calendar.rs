@@ -0,0 +1,183 @@
+use axum::extract::State;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+
+use crate::AppState;
+use crate::error::AppError;
+use crate::floods::FLOOD_MARGIN_MINUTES;
+use crate::site::site;
+use crate::snapshot;
+use crate::tides::{self, FORECAST_DAYS, FloodTide};
+
+/// How long before a flood an invite's alarm goes off.
+const INVITE_ALARM_HOURS: i64 = 1;
+
+fn format_utc(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The VEVENT's properties for `tide`, without its BEGIN and END, so the
+/// feed and invites give a flood the same UID.
+fn event_lines(tide: &FloodTide, generated_at: DateTime<Utc>) -> Vec<String> {
+    let margin = Duration::minutes(FLOOD_MARGIN_MINUTES);
+    let peak = tide.prediction_time;
+    vec![
+        format!("UID:{}@mv-sausalito-floods", format_utc(peak)),
+        format!("DTSTAMP:{}", format_utc(generated_at)),
+        format!("DTSTART:{}", format_utc(peak - margin)),
+        format!("DTEND:{}", format_utc(peak + margin)),
+        format!(
+            "SUMMARY:Bike path flooding ({:.2} ft high tide)",
+            tide.height_ft
+        ),
+        format!(
+            "DESCRIPTION:High tide of {:.2} ft at {}. Plan a different route.",
+            tide.height_ft,
+            tides::local(tide.prediction_time).format("%-I:%M%p")
+        ),
+        "TRANSP:TRANSPARENT".to_string(),
+    ]
+}
+
+/// iCalendar lines end in CRLF
+fn join_lines(lines: Vec<String>) -> String {
+    let mut calendar = lines.join("\r\n");
+    calendar.push_str("\r\n");
+    calendar
+}
+
+/// An iCalendar feed with an event for each flood tide, for subscribing to
+/// from a calendar app.
+pub fn flood_calendar(tides: &[FloodTide], generated_at: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//mill-valley-sausalito-bikepath-flood-alert//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{} Floods", site().name),
+    ];
+    for tide in tides {
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.extend(event_lines(tide, generated_at));
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+    join_lines(lines)
+}
+
+/// An invite to `attendee` for one flood, which calendars add on their own
+/// with an alarm before it. Sending it again for the same flood updates the
+/// event, the sequence going up with `generated_at`.
+pub fn flood_invite(
+    tide: &FloodTide,
+    organizer: &str,
+    attendee: &str,
+    generated_at: DateTime<Utc>,
+) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//mill-valley-sausalito-bikepath-flood-alert//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:REQUEST".to_string(),
+        "BEGIN:VEVENT".to_string(),
+    ];
+    lines.extend(event_lines(tide, generated_at));
+    lines.extend([
+        format!("SEQUENCE:{}", generated_at.timestamp()),
+        "STATUS:CONFIRMED".to_string(),
+        format!("ORGANIZER:mailto:{}", organizer),
+        format!(
+            "ATTENDEE;ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION;RSVP=FALSE:mailto:{}",
+            attendee
+        ),
+        "BEGIN:VALARM".to_string(),
+        "ACTION:DISPLAY".to_string(),
+        "DESCRIPTION:Bike path flooding soon".to_string(),
+        format!("TRIGGER:-PT{}H", INVITE_ALARM_HOURS),
+        "END:VALARM".to_string(),
+        "END:VEVENT".to_string(),
+        "END:VCALENDAR".to_string(),
+    ]);
+    join_lines(lines)
+}
+
+/// Serves the flood calendar at `/calendar.ics`.
+pub async fn calendar_handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let (tides, _) = snapshot::flood_tides(&state, FORECAST_DAYS).await?;
+    Ok((
+        [
+            (CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (CACHE_CONTROL, "public, max-age=3600"),
+        ],
+        flood_calendar(&tides, Utc::now()),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn test_flood_calendar() {
+        let tide = FloodTide {
+            prediction_time: tides::from_local(
+                NaiveDateTime::parse_from_str("2026-12-13 08:50", "%Y-%m-%d %H:%M").unwrap(),
+            ),
+            height_ft: 7.1,
+        };
+        let generated_at = tide.prediction_time;
+        let calendar = flood_calendar(&[tide], generated_at);
+
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        // 8:50 PST is 16:50 UTC
+        assert!(calendar.contains("DTSTART:20261213T155000Z\r\n"));
+        assert!(calendar.contains("DTEND:20261213T175000Z\r\n"));
+        assert!(calendar.contains("SUMMARY:Bike path flooding (7.10 ft high tide)"));
+        assert!(calendar.contains("at 8:50AM."));
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 1);
+
+        let empty = flood_calendar(&[], generated_at);
+        assert!(!empty.contains("VEVENT"));
+    }
+
+    #[test]
+    fn test_flood_invite() {
+        let tide = FloodTide {
+            prediction_time: tides::from_local(
+                NaiveDateTime::parse_from_str("2026-12-13 08:50", "%Y-%m-%d %H:%M").unwrap(),
+            ),
+            height_ft: 7.1,
+        };
+        let generated_at = tide.prediction_time - Duration::days(2);
+        let invite = flood_invite(&tide, "alerts@example.com", "a@example.com", generated_at);
+
+        assert!(invite.contains("METHOD:REQUEST\r\n"));
+        assert!(invite.contains("UID:20261213T165000Z@mv-sausalito-floods\r\n"));
+        assert!(invite.contains("ORGANIZER:mailto:alerts@example.com\r\n"));
+        assert!(invite.contains("RSVP=FALSE:mailto:a@example.com\r\n"));
+        assert!(invite.contains("TRIGGER:-PT1H\r\n"));
+        assert_eq!(invite.matches("BEGIN:VEVENT").count(), 1);
+        let later = flood_invite(
+            &tide,
+            "alerts@example.com",
+            "a@example.com",
+            generated_at + Duration::hours(6),
+        );
+        let sequence = |invite: &str| {
+            invite
+                .lines()
+                .find_map(|line| line.strip_prefix("SEQUENCE:"))
+                .unwrap()
+                .parse::<i64>()
+                .unwrap()
+        };
+        assert!(sequence(&later) > sequence(&invite));
+    }
+}
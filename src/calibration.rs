@@ -0,0 +1,508 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, SubsecRound, Utc};
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use tracing::{Instrument, Span, info_span, instrument};
+use validator::{Validate, ValidationError};
+
+use crate::error::AppError;
+use crate::events::EventSource;
+use crate::request_id;
+use crate::tides::{self, station_id};
+
+pub const DEFAULT_CALIBRATION_DAYS: i64 = 30;
+/// Fewer reports than this with a measured water level aren't enough to go on.
+const MIN_REPORTS: usize = 5;
+/// A report is matched to the average water level measured this close to it.
+const REPORT_LEVEL_MINUTES: i64 = 6;
+/// A high tide's observed peak is the highest level this close to it.
+const PEAK_MINUTES: i64 = 60;
+/// Reports can be sent for this long after seeing the path.
+const MAX_REPORT_AGE_HOURS: i64 = 24;
+/// A calibration further than this from the configured threshold is only
+/// suggested, even when applying them is on.
+const MAX_APPLIED_CHANGE_FT: f64 = 1.0;
+/// NOAA serves at most 31 days of 6 minute water levels per request.
+const FETCH_CHUNK_DAYS: i64 = 30;
+pub const NOAA_DATA_URL: &str = "https://api.tidesandcurrents.noaa.gov/api/prod/datagetter";
+
+/// Someone saying whether the path is flooded, from `POST /api/v1/reports`.
+#[derive(Debug, Deserialize, Validate)]
+pub struct FloodReportRequest {
+    pub flooded: bool,
+    /// When they saw it, now if not given
+    #[serde(default)]
+    #[validate(custom(
+        function = "recent",
+        message = "observed_at must be within the last 24 hours."
+    ))]
+    pub observed_at: Option<DateTime<Utc>>,
+}
+
+fn recent(time: &DateTime<Utc>) -> Result<(), ValidationError> {
+    let now = Utc::now();
+    if *time > now + Duration::minutes(5) || *time < now - Duration::hours(MAX_REPORT_AGE_HOURS) {
+        return Err(ValidationError::new("recent"));
+    }
+    Ok(())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn record_report(
+    pool: &SqlitePool,
+    report: &FloodReportRequest,
+    source: EventSource,
+) -> Result<(), sqlx::Error> {
+    let station_id = station_id();
+    let observed_at = report
+        .observed_at
+        .unwrap_or_else(Utc::now)
+        .trunc_subsecs(0)
+        .naive_utc();
+    let source = source.as_str();
+    let request_id = request_id::current();
+    sqlx::query!(
+        r#"
+        INSERT INTO flood_reports (station_id, observed_at, flooded, source, request_id)
+        VALUES (?, ?, ?, ?, ?);
+        "#,
+        station_id,
+        observed_at,
+        report.flooded,
+        source,
+        request_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Whether `APPLY_CALIBRATED_THRESHOLD` is on, so calibrations replace the
+/// configured threshold instead of only being suggested.
+pub fn apply_enabled() -> bool {
+    env::var("APPLY_CALIBRATED_THRESHOLD").is_ok_and(|value| value.trim() == "true")
+}
+
+#[derive(Debug, Deserialize)]
+struct WaterLevelResponse {
+    #[serde(default)]
+    data: Vec<WaterLevel>,
+    error: Option<NoaaError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WaterLevel {
+    /// UTC, like `2026-10-15 08:06`
+    t: String,
+    /// Feet above MLLW, empty when the gauge missed it
+    v: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct NoaaError {
+    message: String,
+}
+
+fn parse_water_levels(json: &str) -> Result<Vec<(NaiveDateTime, f64)>, AppError> {
+    let response: WaterLevelResponse =
+        serde_json::from_str(json).map_err(|e| AppError::Noaa(e.to_string()))?;
+    if let Some(error) = response.error {
+        return Err(AppError::Noaa(format!(
+            "NOAA water levels: {}",
+            error.message
+        )));
+    }
+    Ok(response
+        .data
+        .into_iter()
+        .filter_map(|level| {
+            let time = NaiveDateTime::parse_from_str(&level.t, "%Y-%m-%d %H:%M").ok()?;
+            Some((time, level.v.trim().parse().ok()?))
+        })
+        .collect())
+}
+
+/// Stores the water levels NOAA measured at the station from `begin` to
+/// `end`. Returns how many were stored.
+#[instrument(skip(pool), fields(stored))]
+pub async fn fetch_water_levels(
+    pool: &SqlitePool,
+    begin: NaiveDate,
+    end: NaiveDate,
+) -> Result<usize, AppError> {
+    let station_id = station_id();
+    let http = reqwest::Client::new();
+    let mut stored = 0;
+    let mut chunk_begin = begin;
+    while chunk_begin <= end {
+        let chunk_end = end.min(chunk_begin + Duration::days(FETCH_CHUNK_DAYS - 1));
+        let url = Url::parse_with_params(
+            NOAA_DATA_URL,
+            [
+                ("product", "water_level"),
+                ("application", "mv-sausalito-bikepath-flood-alert"),
+                ("station", station_id),
+                ("begin_date", &chunk_begin.format("%Y%m%d").to_string()),
+                ("end_date", &chunk_end.format("%Y%m%d").to_string()),
+                ("datum", "MLLW"),
+                ("time_zone", "gmt"),
+                ("units", "english"),
+                ("format", "json"),
+            ],
+        )
+        .map_err(|e| AppError::Config(e.to_string()))?;
+        let json = async { http.get(url).send().await?.error_for_status()?.text().await }
+            .instrument(info_span!(
+                "noaa_fetch",
+                product = "water_level",
+                begin_date = %chunk_begin,
+                end_date = %chunk_end
+            ))
+            .await
+            .map_err(|e| AppError::Noaa(e.to_string()))?;
+        let levels = parse_water_levels(&json)?;
+
+        let mut tx = pool.begin().await?;
+        for (observed_time, height_ft) in &levels {
+            sqlx::query!(
+                r#"
+                INSERT INTO water_levels (station_id, observed_time, height_ft) VALUES (?, ?, ?)
+                ON CONFLICT (station_id, observed_time) DO UPDATE SET height_ft = excluded.height_ft;
+                "#,
+                station_id,
+                observed_time,
+                height_ft
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        stored += levels.len();
+        chunk_begin = chunk_end + Duration::days(1);
+    }
+    Span::current().record("stored", stored);
+    Ok(stored)
+}
+
+/// A threshold fitted to the reports and water levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    /// For predicted high tides, the path elevation less the bias, plus
+    /// the offset from `bias` when one is applied since it's already in them
+    pub threshold_ft: f64,
+    /// The lowest measured water level that best separates the flooded
+    /// reports from the dry ones
+    pub path_elevation_ft: f64,
+    /// How far measured high tides ran above their predictions, the median
+    pub bias_ft: f64,
+    pub reports: usize,
+    /// Reports on the wrong side of the path elevation
+    pub misclassified: usize,
+}
+
+/// The water level from which the path floods, fitted to `(level, flooded)`
+/// samples, and how many samples disagree with it. Of the levels that
+/// disagree with the fewest, the lowest, so it errs towards alerting. None
+/// without both flooded and dry samples.
+fn fit_path_elevation(samples: &[(f64, bool)]) -> Option<(f64, usize)> {
+    if !samples.iter().any(|(_, flooded)| *flooded) || samples.iter().all(|(_, flooded)| *flooded) {
+        return None;
+    }
+    let misclassified = |elevation: f64| {
+        samples
+            .iter()
+            .filter(|(level, flooded)| (*level >= elevation) != *flooded)
+            .count()
+    };
+    samples
+        .iter()
+        .map(|(level, _)| *level)
+        .map(|elevation| (elevation, misclassified(elevation)))
+        .min_by(|a, b| a.1.cmp(&b.1).then(a.0.total_cmp(&b.0)))
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    })
+}
+
+/// Fits a threshold to the reports of the last `days`, from the water levels
+/// already stored. None without enough reports to go on.
+#[instrument(level = "debug", skip(pool))]
+pub async fn calibrate(pool: &SqlitePool, days: i64) -> Result<Option<Calibration>, AppError> {
+    let station_id = station_id();
+    let now = Utc::now();
+    let since = (now - Duration::days(days)).naive_utc();
+    let before = format!("-{} minutes", REPORT_LEVEL_MINUTES);
+    let after = format!("+{} minutes", REPORT_LEVEL_MINUTES);
+    let reports = sqlx::query!(
+        r#"
+        SELECT
+            r.flooded AS "flooded: bool",
+            (
+                SELECT AVG(w.height_ft) FROM water_levels w
+                WHERE w.station_id = r.station_id
+                    AND w.observed_time >= datetime(r.observed_at, ?)
+                    AND w.observed_time <= datetime(r.observed_at, ?)
+            ) AS "height_ft: f64"
+        FROM flood_reports r
+        WHERE r.station_id = ? AND r.observed_at >= ?
+        "#,
+        before,
+        after,
+        station_id,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+    let samples: Vec<(f64, bool)> = reports
+        .into_iter()
+        .filter_map(|report| Some((report.height_ft?, report.flooded)))
+        .collect();
+    if samples.len() < MIN_REPORTS {
+        return Ok(None);
+    }
+    let Some((path_elevation_ft, misclassified)) = fit_path_elevation(&samples) else {
+        return Ok(None);
+    };
+
+    let before = format!("-{} minutes", PEAK_MINUTES);
+    let after = format!("+{} minutes", PEAK_MINUTES);
+    let now = now.naive_utc();
+    let highs = sqlx::query!(
+        r#"
+        SELECT
+            t.height_ft,
+            (
+                SELECT MAX(w.height_ft) FROM water_levels w
+                WHERE w.station_id = ?
+                    AND w.observed_time >= datetime(t.prediction_time, ?)
+                    AND w.observed_time <= datetime(t.prediction_time, ?)
+            ) AS "observed_ft: f64"
+        FROM tides t
+        WHERE t.tide_type = 'High' AND t.prediction_time >= ? AND t.prediction_time <= ?
+        "#,
+        station_id,
+        before,
+        after,
+        since,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+    let bias_ft = median(
+        highs
+            .into_iter()
+            .filter_map(|high| Some(high.observed_ft? - high.height_ft))
+            .collect(),
+    )
+    .unwrap_or(0.0);
+
+    Ok(Some(Calibration {
+        threshold_ft: path_elevation_ft - bias_ft + tides::prediction_offset().unwrap_or(0.0),
+        path_elevation_ft,
+        bias_ft,
+        reports: samples.len(),
+        misclassified,
+    }))
+}
+
+#[instrument(level = "debug", skip_all)]
+async fn save(pool: &SqlitePool, calibration: &Calibration) -> Result<(), sqlx::Error> {
+    let station_id = station_id();
+    let reports = calibration.reports as i64;
+    let misclassified = calibration.misclassified as i64;
+    sqlx::query!(
+        r#"
+        INSERT INTO threshold_calibrations
+            (station_id, threshold_ft, path_elevation_ft, bias_ft, reports, misclassified)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT (station_id) DO UPDATE SET
+            threshold_ft = excluded.threshold_ft,
+            path_elevation_ft = excluded.path_elevation_ft,
+            bias_ft = excluded.bias_ft,
+            reports = excluded.reports,
+            misclassified = excluded.misclassified,
+            calibrated_at = CURRENT_TIMESTAMP;
+        "#,
+        station_id,
+        calibration.threshold_ft,
+        calibration.path_elevation_ft,
+        calibration.bias_ft,
+        reports,
+        misclassified
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+fn within_applied_limit(threshold_ft: f64) -> bool {
+    (threshold_ft - tides::configured_flood_threshold()).abs() <= MAX_APPLIED_CHANGE_FT
+}
+
+/// Uses the station's saved calibration as the flood threshold, when
+/// `APPLY_CALIBRATED_THRESHOLD` is on and it's close enough to the
+/// configured threshold to trust.
+pub async fn load_applied(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let station_id = station_id();
+    if !apply_enabled() {
+        return Ok(());
+    }
+    let threshold = sqlx::query_scalar!(
+        "SELECT threshold_ft FROM threshold_calibrations WHERE station_id = ?",
+        station_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .filter(|threshold| within_applied_limit(*threshold));
+    tides::apply_calibrated_threshold(threshold);
+    Ok(())
+}
+
+/// Fetches the water levels of the last `days` unless `fetch` is off,
+/// calibrates and saves the result, and applies it if that's on.
+pub async fn run(pool: &SqlitePool, days: i64, fetch: bool) -> Result<(), AppError> {
+    if fetch {
+        let today = Utc::now().date_naive();
+        let stored = fetch_water_levels(pool, today - Duration::days(days), today).await?;
+        println!("Stored {} measured water levels", stored);
+    }
+    let Some(calibration) = calibrate(pool, days).await? else {
+        println!(
+            "Not enough reports in the last {} days to calibrate, it takes {} with a measured water level and both flooded and dry ones",
+            days, MIN_REPORTS
+        );
+        return Ok(());
+    };
+    save(pool, &calibration).await?;
+    println!(
+        "The path floods from a measured {:.2} ft ({} of {} reports disagree), and high tides ran {:+.2} ft from predictions",
+        calibration.path_elevation_ft,
+        calibration.misclassified,
+        calibration.reports,
+        calibration.bias_ft
+    );
+    println!(
+        "Suggested threshold {:.2} ft, configured {:.2} ft",
+        calibration.threshold_ft,
+        tides::configured_flood_threshold()
+    );
+    if apply_enabled() {
+        load_applied(pool).await?;
+        if within_applied_limit(calibration.threshold_ft) {
+            println!("Applied, as APPLY_CALIBRATED_THRESHOLD is on");
+        } else {
+            println!(
+                "Not applied, it's more than {:.1} ft from the configured threshold",
+                MAX_APPLIED_CHANGE_FT
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Recalibrates after a sync when applying calibrations is on, so the
+/// threshold follows new reports without anyone running `calibrate`.
+/// Failures are logged, the sync already succeeded.
+pub async fn recalibrate(pool: &SqlitePool) {
+    if !apply_enabled() {
+        return;
+    }
+    if let Err(e) = run(pool, DEFAULT_CALIBRATION_DAYS, true).await {
+        eprintln!("Error calibrating the flood threshold: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_path_elevation() {
+        let samples = [
+            (6.2, false),
+            (6.5, false),
+            (6.7, true),
+            (6.9, true),
+            // Someone who only saw a puddle
+            (7.0, false),
+        ];
+        assert_eq!(fit_path_elevation(&samples), Some((6.7, 1)));
+        assert_eq!(fit_path_elevation(&[(6.2, false), (6.5, false)]), None);
+        assert_eq!(median(vec![0.3, 0.1, 0.2, 0.4]), Some(0.25));
+    }
+
+    #[test]
+    fn test_parse_water_levels() {
+        let json = r#"{"metadata": {"id": "9414819"}, "data": [
+            {"t": "2026-10-15 08:00", "v": "6.812", "s": "0.010", "f": "0,0,0,0", "q": "p"},
+            {"t": "2026-10-15 08:06", "v": "", "s": "", "f": "0,0,0,0", "q": "p"}
+        ]}"#;
+        let levels = parse_water_levels(json).unwrap();
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].1, 6.812);
+
+        let error = r#"{"error": {"message": "No data was found."}}"#;
+        assert!(parse_water_levels(error).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_calibrate() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let now = Utc::now();
+        // A day of high tides running 0.2 ft above their predictions, with
+        // the path flooding from a measured 6.8 ft
+        for (hours_ago, predicted, measured, flooded) in [
+            (24, 6.0, 6.2, false),
+            (36, 6.3, 6.5, false),
+            (48, 6.6, 6.8, true),
+            (60, 6.8, 7.0, true),
+            (72, 6.4, 6.6, false),
+        ] {
+            let time = (now - Duration::hours(hours_ago)).naive_utc();
+            sqlx::query(
+                "INSERT INTO tides (prediction_time, height_ft, tide_type) VALUES (?, ?, 'High')",
+            )
+            .bind(time)
+            .bind(predicted)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO water_levels (station_id, observed_time, height_ft) VALUES (?, ?, ?)",
+            )
+            .bind(station_id())
+            .bind(time)
+            .bind(measured)
+            .execute(&pool)
+            .await
+            .unwrap();
+            let report = FloodReportRequest {
+                flooded,
+                observed_at: Some(now - Duration::hours(hours_ago) + Duration::minutes(4)),
+            };
+            record_report(&pool, &report, EventSource::Api)
+                .await
+                .unwrap();
+        }
+
+        let calibration = calibrate(&pool, 30).await.unwrap().unwrap();
+        assert_eq!(calibration.reports, 5);
+        assert_eq!(calibration.misclassified, 0);
+        assert_eq!(calibration.path_elevation_ft, 6.8);
+        assert!((calibration.bias_ft - 0.2).abs() < 1e-9);
+        assert!((calibration.threshold_ft - 6.6).abs() < 1e-9);
+        assert!(calibrate(&pool, 1).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,523 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use std::env;
+use thiserror::Error;
+
+use crate::error::AppError;
+use crate::floods::{FloodStatus, Severity, next_flood};
+use crate::metrics;
+use crate::models::FloodDisplay;
+use crate::notification_log;
+use crate::notify::{Notifier, NotifyRun, Subscriber};
+use crate::site::site;
+use crate::tides::FloodTide;
+
+const DEFAULT_NTFY_SERVER: &str = "https://ntfy.sh";
+const PUSHOVER_MESSAGES_URL: &str = "https://api.pushover.net/1/messages.json";
+/// Emergency priority Pushover alerts repeat every `retry` seconds until
+/// acknowledged, for at most `expire` seconds.
+const PUSHOVER_RETRY_SECONDS: u32 = 300;
+const PUSHOVER_EXPIRE_SECONDS: u32 = 3600;
+
+pub const INVALID_NTFY_TOPIC: &str = "That isn't an ntfy topic URL on a supported server.";
+pub const INVALID_PUSHOVER_KEY: &str =
+    "That isn't a Pushover user key, it's the 30 characters on your Pushover dashboard.";
+pub const INVALID_SIGNAL_NUMBER: &str =
+    "That isn't a phone number, please include the country code, e.g. +1 415 555 0100.";
+/// Floods listed in a push before the rest are summarized.
+const MAX_PUSH_FLOODS: usize = 5;
+
+/// A short alert for push channels, sent alongside the email.
+pub struct PushMessage {
+    pub title: String,
+    pub body: String,
+    pub priority: Priority,
+    /// Opened when the notification is tapped
+    pub click_url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    High,
+    /// The path is flooded right now
+    Urgent,
+}
+
+#[derive(Error, Debug)]
+pub enum ChannelError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+/// A push channel a subscriber has set up besides email.
+pub enum Channel {
+    Ntfy {
+        topic_url: String,
+    },
+    Pushover {
+        app_token: String,
+        user_key: String,
+    },
+    /// Phone numbers or `group.` ids, sent through a signal-cli REST gateway
+    Signal {
+        gateway: SignalGateway,
+        recipients: Vec<String>,
+    },
+}
+
+/// A [signal-cli REST API](https://github.com/bbernhard/signal-cli-rest-api)
+/// gateway and the number registered with it, from `SIGNAL_API_URL` and
+/// `SIGNAL_NUMBER`.
+#[derive(Clone)]
+pub struct SignalGateway {
+    url: String,
+    number: String,
+}
+
+impl SignalGateway {
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("SIGNAL_API_URL")
+            .ok()
+            .filter(|url| !url.is_empty())?;
+        let number = env::var("SIGNAL_NUMBER")
+            .ok()
+            .filter(|number| !number.is_empty())?;
+        Some(SignalGateway {
+            url: url.trim_end_matches('/').to_string(),
+            number,
+        })
+    }
+}
+
+/// Signal groups every flood notification is also sent to, from the comma
+/// separated `SIGNAL_GROUP_IDS`.
+fn signal_groups() -> Vec<String> {
+    env::var("SIGNAL_GROUP_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|group| group.trim().to_string())
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+/// Normalizes a phone number to E.164, the format Signal uses.
+pub fn parse_signal_number(input: &str) -> Option<String> {
+    let number: String = input
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.'))
+        .collect();
+    let digits = number.strip_prefix('+')?;
+    (digits.len() >= 8 && digits.len() <= 15 && digits.chars().all(|c| c.is_ascii_digit()))
+        .then_some(number)
+}
+
+impl Channel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Ntfy { .. } => "ntfy",
+            Channel::Pushover { .. } => "Pushover",
+            Channel::Signal { .. } => "Signal",
+        }
+    }
+
+    /// The channel's label in the delivery metrics.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Channel::Ntfy { .. } => "ntfy",
+            Channel::Pushover { .. } => "pushover",
+            Channel::Signal { .. } => "signal",
+        }
+    }
+
+    pub async fn send(
+        &self,
+        http: &reqwest::Client,
+        message: &PushMessage,
+    ) -> Result<(), ChannelError> {
+        match self {
+            Channel::Ntfy { topic_url } => {
+                let priority = match message.priority {
+                    Priority::Normal => "default",
+                    Priority::High => "high",
+                    Priority::Urgent => "urgent",
+                };
+                http.post(topic_url)
+                    .header("Title", &message.title)
+                    .header("Priority", priority)
+                    .header("Click", &message.click_url)
+                    .header("Tags", "warning,bike")
+                    .body(message.body.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Channel::Pushover {
+                app_token,
+                user_key,
+            } => {
+                http.post(PUSHOVER_MESSAGES_URL)
+                    .form(&pushover_params(app_token, user_key, message))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Channel::Signal {
+                gateway,
+                recipients,
+            } => {
+                http.post(format!("{}/v2/send", gateway.url))
+                    .json(&serde_json::json!({
+                        "number": gateway.number,
+                        "recipients": recipients,
+                        "message": signal_text(message),
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Form fields of a Pushover message. Urgent messages use emergency
+/// priority, which keeps alerting until it's acknowledged.
+fn pushover_params(
+    app_token: &str,
+    user_key: &str,
+    message: &PushMessage,
+) -> Vec<(&'static str, String)> {
+    let priority = match message.priority {
+        Priority::Normal => 0,
+        Priority::High => 1,
+        Priority::Urgent => 2,
+    };
+    let mut params = vec![
+        ("token", app_token.to_string()),
+        ("user", user_key.to_string()),
+        ("title", message.title.clone()),
+        ("message", message.body.clone()),
+        ("priority", priority.to_string()),
+        ("url", message.click_url.clone()),
+        ("url_title", "Flood forecast".to_string()),
+    ];
+    if message.priority == Priority::Urgent {
+        params.push(("retry", PUSHOVER_RETRY_SECONDS.to_string()));
+        params.push(("expire", PUSHOVER_EXPIRE_SECONDS.to_string()));
+    }
+    params
+}
+
+/// Signal has no title or link fields, so they go in the text.
+fn signal_text(message: &PushMessage) -> String {
+    format!(
+        "{}\n\n{}\n\n{}",
+        message.title, message.body, message.click_url
+    )
+}
+
+/// The Pushover application token from `PUSHOVER_APP_TOKEN`. Subscribers
+/// can only add their user key when it's set.
+pub fn pushover_app_token() -> Option<String> {
+    env::var("PUSHOVER_APP_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Pushover user and group keys are 30 letters and digits.
+pub fn parse_pushover_key(input: &str) -> Option<String> {
+    let key = input.trim();
+    (key.len() == 30 && key.chars().all(|c| c.is_ascii_alphanumeric())).then(|| key.to_string())
+}
+
+/// ntfy servers topics can be on, from the comma separated `NTFY_SERVERS`.
+/// Only these are allowed so the server can't be made to post anywhere else.
+pub fn ntfy_servers() -> Vec<String> {
+    let servers: Vec<String> = env::var("NTFY_SERVERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|server| server.trim().trim_end_matches('/').to_string())
+        .filter(|server| !server.is_empty())
+        .collect();
+    if servers.is_empty() {
+        vec![DEFAULT_NTFY_SERVER.to_string()]
+    } else {
+        servers
+    }
+}
+
+/// Normalizes a pasted topic URL, or a bare topic name on the first server.
+/// Returns None unless it's a topic on one of `servers`.
+pub fn parse_ntfy_topic(input: &str, servers: &[String]) -> Option<String> {
+    let input = input.trim();
+    let url = if input.contains("://") {
+        input.to_string()
+    } else {
+        format!("{}/{}", servers.first()?, input)
+    };
+    let url = Url::parse(&url).ok()?;
+    if url.query().is_some() || url.fragment().is_some() {
+        return None;
+    }
+    let topic = url.path().strip_prefix('/')?;
+    let valid_topic = !topic.is_empty()
+        && topic.len() <= 64
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_topic {
+        return None;
+    }
+
+    let origin = url.origin().ascii_serialization();
+    servers
+        .iter()
+        .find(|server| Url::parse(server).is_ok_and(|s| s.origin().ascii_serialization() == origin))
+        .map(|server| format!("{}/{}", server, topic))
+}
+
+/// Push version of the notification email. High priority when a flood is
+/// major, and urgent when the path is flooded as of `now`.
+pub fn flood_push(floods: &[FloodTide], homepage: &str, now: DateTime<Utc>) -> PushMessage {
+    let mut lines: Vec<String> = floods
+        .iter()
+        .take(MAX_PUSH_FLOODS)
+        .map(|flood| {
+            let display = FloodDisplay::new(flood.prediction_time, flood.height_ft);
+            format!("{}: {} ft", display.datetime, display.height)
+        })
+        .collect();
+    if floods.len() > MAX_PUSH_FLOODS {
+        lines.push(format!("and {} more", floods.len() - MAX_PUSH_FLOODS));
+    }
+    let major = floods
+        .iter()
+        .any(|flood| Severity::from_height(flood.height_ft) == Severity::Major);
+    let priority = if next_flood(floods, now).0 == FloodStatus::Flooding {
+        Priority::Urgent
+    } else if major {
+        Priority::High
+    } else {
+        Priority::Normal
+    };
+
+    PushMessage {
+        title: format!("{} Flooding Predicted", site().name),
+        body: lines.join("\n"),
+        priority,
+        click_url: homepage.to_string(),
+    }
+}
+
+/// Pushes the floods to each subscriber's push channels, and to the Signal
+/// groups. Failures are logged per subscriber, a dead topic shouldn't stop
+/// the rest.
+#[derive(Default)]
+pub struct PushNotifier {
+    message: Option<PushMessage>,
+    app_token: Option<String>,
+    signal: Option<SignalGateway>,
+    http: reqwest::Client,
+}
+
+#[async_trait]
+impl Notifier for PushNotifier {
+    fn name(&self) -> &'static str {
+        "push"
+    }
+
+    async fn prepare(&mut self, run: &NotifyRun<'_>) -> Result<bool, AppError> {
+        if run.floods.is_empty() {
+            return Ok(false);
+        }
+        self.message = Some(flood_push(&run.floods, run.base_url, Utc::now()));
+        self.app_token = pushover_app_token();
+        self.signal = SignalGateway::from_env();
+        Ok(true)
+    }
+
+    async fn deliver(
+        &mut self,
+        run: &NotifyRun<'_>,
+        subscriber: &Subscriber,
+    ) -> Result<bool, AppError> {
+        let Some(message) = &self.message else {
+            return Ok(false);
+        };
+        let narrowed;
+        let message = if subscriber.floods.len() == run.floods.len() {
+            message
+        } else {
+            narrowed = flood_push(&subscriber.floods, run.base_url, Utc::now());
+            &narrowed
+        };
+        let mut channels = Vec::new();
+        if let Some(topic_url) = &subscriber.ntfy_topic {
+            channels.push(Channel::Ntfy {
+                topic_url: topic_url.clone(),
+            });
+        }
+        if let (Some(app_token), Some(user_key)) = (&self.app_token, &subscriber.pushover_user_key)
+        {
+            channels.push(Channel::Pushover {
+                app_token: app_token.clone(),
+                user_key: user_key.clone(),
+            });
+        }
+        if let (Some(gateway), Some(number)) = (&self.signal, &subscriber.signal_number) {
+            channels.push(Channel::Signal {
+                gateway: gateway.clone(),
+                recipients: vec![number.clone()],
+            });
+        }
+        let email = &subscriber.user.email;
+        let mut sent = false;
+        for channel in channels {
+            match metrics::track(
+                channel.label(),
+                "notification",
+                channel.send(&self.http, message),
+            )
+            .await
+            {
+                Ok(()) => {
+                    println!("Sent a {} push to {}", channel.name(), email);
+                    sent = true;
+                }
+                Err(e) => eprintln!("{} push to {} failed: {}", channel.name(), email, e),
+            }
+        }
+        if sent {
+            notification_log::mark_notified(run.pool, &subscriber.user, &subscriber.floods).await;
+        }
+        Ok(sent)
+    }
+
+    async fn finish(&mut self, _run: &NotifyRun<'_>) -> Result<(), AppError> {
+        let groups = signal_groups();
+        if let (Some(message), Some(gateway)) = (&self.message, self.signal.take())
+            && !groups.is_empty()
+        {
+            let channel = Channel::Signal {
+                gateway,
+                recipients: groups,
+            };
+            match metrics::track(
+                channel.label(),
+                "notification",
+                channel.send(&self.http, message),
+            )
+            .await
+            {
+                Ok(()) => println!("Sent the notification to the Signal groups"),
+                Err(e) => eprintln!("Signal group notification failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ntfy_topic() {
+        let servers = vec![
+            "https://ntfy.sh".to_string(),
+            "https://push.example.com".to_string(),
+        ];
+        assert_eq!(
+            parse_ntfy_topic(" https://ntfy.sh/bike-path_floods ", &servers).as_deref(),
+            Some("https://ntfy.sh/bike-path_floods")
+        );
+        assert_eq!(
+            parse_ntfy_topic("my-topic", &servers).as_deref(),
+            Some("https://ntfy.sh/my-topic")
+        );
+        assert_eq!(
+            parse_ntfy_topic("https://push.example.com/floods", &servers).as_deref(),
+            Some("https://push.example.com/floods")
+        );
+
+        for invalid in [
+            "https://evil.example/topic",
+            "http://ntfy.sh/topic",
+            "https://ntfy.sh/",
+            "https://ntfy.sh/a/b",
+            "https://ntfy.sh/topic?auth=x",
+            "not a topic",
+        ] {
+            assert_eq!(parse_ntfy_topic(invalid, &servers), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_flood_push() {
+        let at = |time| {
+            crate::tides::from_local(
+                chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(),
+            )
+        };
+        let flood = |height_ft| FloodTide {
+            prediction_time: at("2026-12-13 08:50"),
+            height_ft,
+        };
+        let day_before = at("2026-12-12 08:00");
+        let push = flood_push(&[flood(6.5)], "https://example.com", day_before);
+        assert_eq!(push.body, "Sunday, December 13 at 8:50AM: 6.50 ft");
+        assert_eq!(push.priority, Priority::Normal);
+        assert_eq!(push.click_url, "https://example.com");
+
+        let push = flood_push(&vec![flood(7.2); 7], "https://example.com", day_before);
+        assert_eq!(push.priority, Priority::High);
+        assert!(push.body.ends_with("\nand 2 more"));
+
+        let push = flood_push(&[flood(6.5)], "https://example.com", at("2026-12-13 08:30"));
+        assert_eq!(push.priority, Priority::Urgent);
+    }
+
+    #[test]
+    fn test_pushover_params() {
+        let mut message = PushMessage {
+            title: "Flooding".to_string(),
+            body: "Now".to_string(),
+            priority: Priority::High,
+            click_url: "https://example.com".to_string(),
+        };
+        let params = pushover_params("app", "user", &message);
+        assert!(params.contains(&("priority", "1".to_string())));
+        assert!(!params.iter().any(|(name, _)| *name == "retry"));
+
+        message.priority = Priority::Urgent;
+        let params = pushover_params("app", "user", &message);
+        assert!(params.contains(&("priority", "2".to_string())));
+        assert!(params.contains(&("retry", "300".to_string())));
+        assert!(params.contains(&("expire", "3600".to_string())));
+    }
+
+    #[test]
+    fn test_parse_signal_number() {
+        assert_eq!(
+            parse_signal_number(" +1 (415) 555-0100 ").as_deref(),
+            Some("+14155550100")
+        );
+        assert_eq!(parse_signal_number("415 555 0100"), None);
+        assert_eq!(parse_signal_number("+1415"), None);
+        assert_eq!(parse_signal_number("+1415555abcd"), None);
+    }
+
+    #[test]
+    fn test_parse_pushover_key() {
+        let key = "uQiRzpo4DXghDmr9QzzfQu27cmVRsG";
+        assert_eq!(
+            parse_pushover_key(&format!(" {} ", key)).as_deref(),
+            Some(key)
+        );
+        assert_eq!(parse_pushover_key("too-short"), None);
+        assert_eq!(parse_pushover_key(&format!("{}!", &key[..29])), None);
+    }
+}
@@ -0,0 +1,234 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+use std::env;
+
+use crate::AppState;
+use crate::email_templates::{self, EmailKind};
+use crate::error::AppError;
+use crate::events::{self, EventSource, EventType};
+use crate::models::User;
+
+/// Hours after the verification email before an unverified signup gets its
+/// one reminder.
+pub const DEFAULT_NUDGE_AFTER_HOURS: i64 = 48;
+/// Days after the verification email before an unverified signup is deleted.
+pub const DEFAULT_DELETE_AFTER_DAYS: i64 = 7;
+/// Days an unverified signup is kept at most, whether or not `users cleanup`
+/// runs.
+const DEFAULT_UNVERIFIED_RETENTION_DAYS: i64 = 30;
+
+/// From `UNVERIFIED_RETENTION_DAYS`, where 0 turns the purge off.
+pub fn unverified_retention_days() -> Option<i64> {
+    let days = env::var("UNVERIFIED_RETENTION_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_UNVERIFIED_RETENTION_DAYS);
+    (days > 0).then_some(days)
+}
+
+/// Deletes signups still unverified since before `cutoff`, recording each
+/// as a suppression. Returns the emails deleted.
+async fn delete_unverified(
+    pool: &SqlitePool,
+    cutoff: NaiveDateTime,
+    source: EventSource,
+) -> Result<Vec<String>, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE is_verified = 0 AND COALESCE(verification_sent_at, created_at) <= ?
+        RETURNING id, email
+        "#,
+        cutoff
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut emails = Vec::new();
+    for user in deleted {
+        events::record(
+            pool,
+            &user.id,
+            &user.email,
+            EventType::Suppression,
+            source,
+            Some("Removed after never verifying"),
+        )
+        .await;
+        emails.push(user.email);
+    }
+    Ok(emails)
+}
+
+/// Deletes signups unverified for longer than `UNVERIFIED_RETENTION_DAYS`,
+/// so addresses that were never confirmed aren't kept. Runs with every
+/// `notify`, and errors are logged rather than stopping it.
+pub async fn purge_unverified(pool: &SqlitePool, source: EventSource) {
+    let Some(days) = unverified_retention_days() else {
+        return;
+    };
+    let cutoff = Utc::now().naive_utc() - Duration::days(days);
+    match delete_unverified(pool, cutoff, source).await {
+        Ok(deleted) if deleted.is_empty() => {}
+        Ok(deleted) => println!(
+            "Purged {} signups unverified for over {} days",
+            deleted.len(),
+            days
+        ),
+        Err(e) => eprintln!("Error purging unverified signups: {}", e),
+    }
+}
+
+/// Unverified signups sent their verification email before `cutoff` that
+/// haven't had a reminder yet.
+async fn due_for_nudge(pool: &SqlitePool, cutoff: NaiveDateTime) -> Result<Vec<User>, sqlx::Error> {
+    let users = sqlx::query!(
+        r#"
+        SELECT id, email, verification_token, verification_code FROM users
+        WHERE is_verified = 0 AND verification_nudged_at IS NULL
+            AND verification_sent_at <= ?
+        "#,
+        cutoff
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|record| User {
+        id: record.id,
+        email: record.email,
+        verification_token: record.verification_token,
+        verification_code: record.verification_code,
+        ..Default::default()
+    })
+    .collect();
+    Ok(users)
+}
+
+/// Deletes signups unverified after `delete_after_days`, then resends the
+/// verification email once to those unverified after `nudge_after_hours`.
+/// Deleting first means no one is reminded just before being removed.
+pub async fn cleanup_unverified(
+    pool: SqlitePool,
+    nudge_after_hours: i64,
+    delete_after_days: i64,
+) -> Result<(), AppError> {
+    let now = Utc::now().naive_utc();
+    let deleted = delete_unverified(
+        &pool,
+        now - Duration::days(delete_after_days),
+        EventSource::Cli,
+    )
+    .await?;
+    println!(
+        "Deleted {} signups unverified after {} days",
+        deleted.len(),
+        delete_after_days
+    );
+
+    let users = due_for_nudge(&pool, now - Duration::hours(nudge_after_hours)).await?;
+    if users.is_empty() {
+        println!("No unverified signups to remind.");
+        return Ok(());
+    }
+
+    let app_state = AppState::from_pool(pool);
+    let pool = &app_state.pool;
+    let template = email_templates::load_override(pool, EmailKind::Verification).await?;
+    for user in &users {
+        let verification_link = format!(
+            "{}/verify?token={}",
+            app_state.base_url, user.verification_token
+        );
+        let unsubscribe_link = format!(
+            "{}/unsubscribe?id={}&token={}",
+            app_state.base_url,
+            user.id,
+            user.generate_unsubscribe_token(&app_state.unsubscribe_secret)
+        );
+        // A failed send is retried on the next run
+        if let Err(e) = app_state
+            .mailer
+            .send_verification_email(
+                user,
+                &verification_link,
+                &unsubscribe_link,
+                template.as_ref(),
+            )
+            .await
+        {
+            eprintln!("Verification reminder to {} failed: {}", user.email, e);
+            continue;
+        }
+        sqlx::query!(
+            "UPDATE users SET verification_nudged_at = ? WHERE id = ?;",
+            now,
+            user.id
+        )
+        .execute(pool)
+        .await?;
+        println!("Reminded {} to verify", user.email);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_user(pool: &SqlitePool, id: &str, verified: bool, sent_hours_ago: i64) {
+        let sent_at = Utc::now().naive_utc() - Duration::hours(sent_hours_ago);
+        sqlx::query(
+            "INSERT INTO users (id, email, verification_token, is_verified, verification_sent_at)
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(format!("{}@example.com", id))
+        .bind(id)
+        .bind(verified)
+        .bind(sent_at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_unverified() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        insert_user(&pool, "fresh", false, 2).await;
+        insert_user(&pool, "waiting", false, 72).await;
+        insert_user(&pool, "stale", false, 24 * 10).await;
+        insert_user(&pool, "verified", true, 24 * 10).await;
+
+        let now = Utc::now().naive_utc();
+        let deleted = delete_unverified(
+            &pool,
+            now - Duration::days(DEFAULT_DELETE_AFTER_DAYS),
+            EventSource::Cli,
+        )
+        .await
+        .unwrap();
+        assert_eq!(deleted, ["stale@example.com"]);
+        assert!(
+            events::has_left_before(&pool, "stale@example.com")
+                .await
+                .unwrap()
+        );
+
+        let due = due_for_nudge(&pool, now - Duration::hours(DEFAULT_NUDGE_AFTER_HOURS))
+            .await
+            .unwrap();
+        let emails: Vec<&str> = due.iter().map(|user| user.email.as_str()).collect();
+        assert_eq!(emails, ["waiting@example.com"]);
+
+        sqlx::query("UPDATE users SET verification_nudged_at = ? WHERE id = 'waiting'")
+            .bind(now)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let due = due_for_nudge(&pool, now - Duration::hours(DEFAULT_NUDGE_AFTER_HOURS))
+            .await
+            .unwrap();
+        assert!(due.is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::api_types::{
+    ErrorResponse, EventsResponse, MessageResponse, PredictionsResponse, SensorResponse,
+    StatusResponse, TidesResponse,
+};
+
+/// API version the client speaks.
+const API_VERSION: &str = "1";
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("API error ({status}): {error}")]
+    Api { status: StatusCode, error: String },
+}
+
+/// Client for the `/api/v1` endpoints.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), mill_valley_sausalito_bikepath_flood_alert::client::ClientError> {
+/// use mill_valley_sausalito_bikepath_flood_alert::client::FloodAlertClient;
+///
+/// let client = FloodAlertClient::new("https://example.com");
+/// for flood in client.predictions().await?.predictions {
+///     println!("{}: {} ft", flood.time, flood.height_ft);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct FloodAlertClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl FloodAlertClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        FloodAlertClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    /// Sets the API key, needed for [`FloodAlertClient::tides`].
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v1{}", self.base_url, path)
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, ClientError> {
+        let mut request = request.header("API-Version", API_VERSION);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+        let error = match response.json::<ErrorResponse>().await {
+            Ok(body) => body.error,
+            Err(_) => status.canonical_reason().unwrap_or_default().to_string(),
+        };
+        Err(ClientError::Api { status, error })
+    }
+
+    /// Predicted floods for the forecast window.
+    pub async fn predictions(&self) -> Result<PredictionsResponse, ClientError> {
+        self.send(self.http.get(self.url("/predictions"))).await
+    }
+
+    /// Predicted floods grouped into events.
+    pub async fn events(&self) -> Result<EventsResponse, ClientError> {
+        self.send(self.http.get(self.url("/events"))).await
+    }
+
+    /// The status of the path and the next flood.
+    pub async fn sensor(&self) -> Result<SensorResponse, ClientError> {
+        self.send(self.http.get(self.url("/sensor"))).await
+    }
+
+    /// The latest sync and whether the forecast is up to date.
+    pub async fn status(&self) -> Result<StatusResponse, ClientError> {
+        self.send(self.http.get(self.url("/status"))).await
+    }
+
+    /// Every predicted high and low tide. Needs an API key.
+    pub async fn tides(&self) -> Result<TidesResponse, ClientError> {
+        self.send(self.http.get(self.url("/tides"))).await
+    }
+
+    /// Signs up an email address, which then gets a verification email.
+    pub async fn signup(&self, email: &str) -> Result<MessageResponse, ClientError> {
+        #[derive(Serialize)]
+        struct Signup<'a> {
+            email: &'a str,
+        }
+        self.send(self.http.post(self.url("/signup")).json(&Signup { email }))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let client = FloodAlertClient::new("https://example.com/");
+        assert_eq!(
+            client.url("/predictions"),
+            "https://example.com/api/v1/predictions"
+        );
+    }
+}
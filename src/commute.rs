@@ -0,0 +1,196 @@
+use chrono::{Datelike, Duration, NaiveTime, Weekday};
+use std::fmt;
+
+use crate::floods::FLOOD_MARGIN_MINUTES;
+use crate::tides::{self, FloodTide};
+
+pub const INVALID_COMMUTE_WINDOWS: &str = "Those commute times weren't understood, please write them like \
+                                           weekdays 7:00-9:00, weekdays 16:00-18:00.";
+
+/// Part of a week a subscriber is on the path, in local time, e.g. weekdays
+/// 7:00-9:00. Days run from `first` to `last`, wrapping past Sunday.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommuteWindow {
+    first: Weekday,
+    last: Weekday,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl CommuteWindow {
+    fn includes(&self, day: Weekday) -> bool {
+        let (first, last, day) = (
+            self.first.num_days_from_monday(),
+            self.last.num_days_from_monday(),
+            day.num_days_from_monday(),
+        );
+        if first <= last {
+            first <= day && day <= last
+        } else {
+            day >= first || day <= last
+        }
+    }
+
+    /// Whether the path is flooded at any time in the window, taking each
+    /// flood to last `FLOOD_MARGIN_MINUTES` either side of high tide.
+    fn overlaps(&self, flood: &FloodTide) -> bool {
+        let margin = Duration::minutes(FLOOD_MARGIN_MINUTES);
+        let flood_start = tides::local(flood.prediction_time - margin);
+        let flood_end = tides::local(flood.prediction_time + margin);
+        // A flood around midnight falls on two days
+        [flood_start.date(), flood_end.date()]
+            .into_iter()
+            .any(|day| {
+                self.includes(day.weekday())
+                    && day.and_time(self.start) < flood_end
+                    && flood_start < day.and_time(self.end)
+            })
+    }
+}
+
+impl fmt::Display for CommuteWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = match (self.first, self.last) {
+            (Weekday::Mon, Weekday::Sun) => "daily".to_string(),
+            (Weekday::Mon, Weekday::Fri) => "weekdays".to_string(),
+            (Weekday::Sat, Weekday::Sun) => "weekends".to_string(),
+            (first, last) if first == last => day_name(first).to_string(),
+            (first, last) => format!("{}-{}", day_name(first), day_name(last)),
+        };
+        write!(
+            f,
+            "{} {}-{}",
+            days,
+            self.start.format("%-H:%M"),
+            self.end.format("%-H:%M")
+        )
+    }
+}
+
+fn day_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "mon",
+        Weekday::Tue => "tue",
+        Weekday::Wed => "wed",
+        Weekday::Thu => "thu",
+        Weekday::Fri => "fri",
+        Weekday::Sat => "sat",
+        Weekday::Sun => "sun",
+    }
+}
+
+/// `daily`, `weekdays`, `weekends`, a day like `mon` or a range like `mon-thu`.
+fn parse_days(input: &str) -> Option<(Weekday, Weekday)> {
+    match input {
+        "daily" => Some((Weekday::Mon, Weekday::Sun)),
+        "weekdays" => Some((Weekday::Mon, Weekday::Fri)),
+        "weekends" => Some((Weekday::Sat, Weekday::Sun)),
+        _ => match input.split_once('-') {
+            Some((first, last)) => Some((first.parse().ok()?, last.parse().ok()?)),
+            None => {
+                let day = input.parse().ok()?;
+                Some((day, day))
+            }
+        },
+    }
+}
+
+/// `7`, `7:30` or `16:00`, on a 24 hour clock.
+fn parse_time(input: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(input, "%H:%M")
+        .ok()
+        .or_else(|| NaiveTime::from_hms_opt(input.parse().ok()?, 0, 0))
+}
+
+/// Parses comma separated windows like `weekdays 7:00-9:00, sat 8-12`.
+/// Empty means none. Windows can't run past midnight.
+pub fn parse_windows(input: &str) -> Option<Vec<CommuteWindow>> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|window| !window.is_empty())
+        .map(|window| {
+            let window = window.to_lowercase();
+            let (days, times) = window.split_once(char::is_whitespace)?;
+            let (first, last) = parse_days(days)?;
+            let (start, end) = times.trim().split_once('-')?;
+            let (start, end) = (parse_time(start.trim())?, parse_time(end.trim())?);
+            (start < end).then_some(CommuteWindow {
+                first,
+                last,
+                start,
+                end,
+            })
+        })
+        .collect()
+}
+
+/// The windows as `parse_windows` reads them, for storing. None when empty.
+pub fn normalize_windows(input: &str) -> Option<Option<String>> {
+    let windows = parse_windows(input)?;
+    if windows.is_empty() {
+        return Some(None);
+    }
+    let windows: Vec<String> = windows.iter().map(ToString::to_string).collect();
+    Some(Some(windows.join(", ")))
+}
+
+/// The floods overlapping one of the windows. Subscribers without windows
+/// want every flood.
+pub fn wanted(windows: &[CommuteWindow], floods: &[FloodTide]) -> Vec<FloodTide> {
+    floods
+        .iter()
+        .filter(|flood| windows.is_empty() || windows.iter().any(|window| window.overlaps(flood)))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    fn flood(time: &str) -> FloodTide {
+        FloodTide {
+            prediction_time: time.parse::<DateTime<Utc>>().unwrap(),
+            height_ft: 6.8,
+        }
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        assert_eq!(
+            normalize_windows(" Weekdays 7-9,sat-sun 16:30-18:00 , fri-mon 6:00-7:00"),
+            Some(Some(
+                "weekdays 7:00-9:00, weekends 16:30-18:00, fri-mon 6:00-7:00".to_string()
+            ))
+        );
+        assert_eq!(normalize_windows("  "), Some(None));
+        assert_eq!(normalize_windows("weekdays 9-7"), None);
+        assert_eq!(normalize_windows("mornings"), None);
+        assert_eq!(normalize_windows("weekdays 7:00-25:00"), None);
+    }
+
+    #[test]
+    fn test_wanted() {
+        let times = |floods: Vec<FloodTide>| -> Vec<DateTime<Utc>> {
+            floods.iter().map(|flood| flood.prediction_time).collect()
+        };
+        let windows = parse_windows("weekdays 7-9, weekdays 16-18").unwrap();
+        // Thursday 11pm in Pacific time
+        let late = flood("2026-10-16T06:00:00Z");
+        assert!(wanted(&windows, std::slice::from_ref(&late)).is_empty());
+        // Thursday 9:45am, flooding from 8:45
+        let morning = flood("2026-10-15T16:45:00Z");
+        assert_eq!(
+            times(wanted(&windows, &[late.clone(), morning.clone()])),
+            [morning.prediction_time]
+        );
+        // Saturday 8am
+        assert!(wanted(&windows, &[flood("2026-10-17T15:00:00Z")]).is_empty());
+        assert_eq!(wanted(&[], &[late]).len(), 1);
+        // Friday 11:30pm floods into Saturday
+        let weekend = parse_windows("sat 0:00-1:00").unwrap();
+        assert_eq!(wanted(&weekend, &[flood("2026-10-17T06:30:00Z")]).len(), 1);
+    }
+}
@@ -0,0 +1,219 @@
+use askama::Template;
+use axum::Form;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use serde::Deserialize;
+use std::env;
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::AppState;
+use crate::error::AppError;
+use crate::honeypot::{Submission, check_submission, issue_form_token};
+
+const SEND_FAILED: &str = "Your message couldn't be sent. Please try again later.";
+
+/// Where contact form messages are forwarded, from `CONTACT_FORWARD_TO`.
+/// The form is only shown when it's set.
+pub fn forward_address() -> Option<String> {
+    env::var("CONTACT_FORWARD_TO")
+        .ok()
+        .filter(|address| !address.trim().is_empty())
+}
+
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct ContactRequest {
+    #[serde(default)]
+    #[validate(length(max = 100, message = "Please keep your name under 100 characters."))]
+    pub name: String,
+    #[validate(custom(
+        function = "crate::email_address::validate",
+        message = "Please provide a valid email address so we can reply."
+    ))]
+    pub email: String,
+    #[validate(length(
+        min = 10,
+        max = 5000,
+        message = "Please write a message between 10 and 5000 characters."
+    ))]
+    pub message: String,
+    /// Honeypot field hidden from people, only bots fill it in.
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub form_token: Option<String>,
+}
+
+impl ContactRequest {
+    /// Plain text body of the forwarded email.
+    pub fn forwarded_text(&self) -> String {
+        let name = self.name.trim();
+        let from = if name.is_empty() {
+            self.email.clone()
+        } else {
+            format!("{} <{}>", name, self.email)
+        };
+        format!(
+            "From: {}\n\n{}\n\nSent from the contact form. Reply to this email to answer.",
+            from,
+            self.message.trim()
+        )
+    }
+}
+
+#[derive(Template)]
+#[template(path = "contact.html")]
+pub struct ContactTemplate {
+    pub form_token: String,
+    pub name: String,
+    pub email: String,
+    pub message: String,
+    pub sent: bool,
+    pub error: Option<String>,
+}
+
+fn render(status: StatusCode, template: ContactTemplate) -> Result<Response, AppError> {
+    Ok((status, Html(template.render()?)).into_response())
+}
+
+fn form_page(state: &AppState, request: ContactRequest, error: Option<&str>) -> ContactTemplate {
+    ContactTemplate {
+        form_token: issue_form_token(&state.unsubscribe_secret),
+        name: request.name,
+        email: request.email,
+        message: request.message,
+        sent: false,
+        error: error.map(str::to_string),
+    }
+}
+
+fn sent_page(state: &AppState) -> ContactTemplate {
+    ContactTemplate {
+        sent: true,
+        ..form_page(state, ContactRequest::default(), None)
+    }
+}
+
+/// The contact form, so path users can report problems without an address
+/// being published.
+pub async fn contact_handler(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    if forward_address().is_none() {
+        return Err(AppError::NotFound(
+            "There's nothing at this address.".to_string(),
+        ));
+    }
+    render(
+        StatusCode::OK,
+        form_page(&state, ContactRequest::default(), None),
+    )
+}
+
+/// Checks a message like a signup, dropping bots quietly, then forwards it
+/// with the sender as the Reply-To.
+pub async fn send_contact_handler(
+    State(state): State<Arc<AppState>>,
+    Form(request): Form<ContactRequest>,
+) -> Result<Response, AppError> {
+    let Some(to) = forward_address() else {
+        return Err(AppError::NotFound(
+            "There's nothing at this address.".to_string(),
+        ));
+    };
+
+    let submission = check_submission(
+        &request.website,
+        request.form_token.as_deref(),
+        &state.unsubscribe_secret,
+    );
+    if submission.is_bot() {
+        println!(
+            "Dropping bot contact message ({:?}): {}",
+            submission, request.email
+        );
+        return render(StatusCode::OK, sent_page(&state));
+    }
+    if submission == Submission::Expired {
+        return render(
+            StatusCode::BAD_REQUEST,
+            form_page(
+                &state,
+                request,
+                Some("This page was open for too long, please send your message again."),
+            ),
+        );
+    }
+
+    if let Err(errors) = request.validate() {
+        let error = errors
+            .field_errors()
+            .into_values()
+            .flatten()
+            .find_map(|error| error.message.clone())
+            .map(|message| message.to_string());
+        return render(
+            StatusCode::BAD_REQUEST,
+            form_page(&state, request, error.as_deref()),
+        );
+    }
+
+    if let Some(mx_validator) = &state.mx_validator
+        && !mx_validator.accepts_mail(&request.email).await
+    {
+        return render(
+            StatusCode::BAD_REQUEST,
+            form_page(
+                &state,
+                request,
+                Some("That email domain can't receive mail, so we couldn't reply."),
+            ),
+        );
+    }
+
+    match state.mailer.send_contact_message(&to, &request).await {
+        Ok(()) => {
+            println!("Forwarded contact message from {}", request.email);
+            render(StatusCode::OK, sent_page(&state))
+        }
+        Err(e) => {
+            eprintln!("Error forwarding contact message: {:?}", e);
+            render(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                form_page(&state, request, Some(SEND_FAILED)),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contact_request() {
+        let mut request = ContactRequest {
+            name: " Sam ".to_string(),
+            email: "sam@example.com".to_string(),
+            message: "The path is flooded near the bridge.\n".to_string(),
+            ..Default::default()
+        };
+        assert!(request.validate().is_ok());
+        assert_eq!(
+            request.forwarded_text(),
+            "From: Sam <sam@example.com>\n\nThe path is flooded near the bridge.\n\nSent from the contact form. Reply to this email to answer."
+        );
+
+        request.name = String::new();
+        assert!(
+            request
+                .forwarded_text()
+                .starts_with("From: sam@example.com\n")
+        );
+
+        request.message = "Hi".to_string();
+        assert!(request.validate().is_err());
+        request.message = "A longer message".to_string();
+        request.email = "not-an-email".to_string();
+        assert!(request.validate().is_err());
+    }
+}
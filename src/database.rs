@@ -0,0 +1,501 @@
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use clap::ValueEnum;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use sqlx::ConnectOptions;
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous,
+};
+use std::collections::BTreeMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::AppState;
+use crate::error::AppError;
+use crate::site::{self, site};
+
+/// Queries slower than this are logged at warn, with their time.
+const SLOW_QUERY: Duration = Duration::from_millis(250);
+const MAX_CONNECTIONS: u32 = 5;
+/// How long a query waits for a free connection before failing, unless
+/// `SQLITE_ACQUIRE_TIMEOUT_SECS` says otherwise.
+const DEFAULT_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Waits for a connection longer than this are logged at warn.
+const SLOW_ACQUIRE: Duration = Duration::from_millis(500);
+/// How often each site's pool is probed for how long a connection takes.
+const POOL_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+/// Tables keyed by a user id without a foreign key, whose rows should go
+/// with the user. `events` keeps its own copy of the email on purpose.
+const USER_TABLES: [&str; 2] = ["notification_log", "reminder_log"];
+
+/// How the SQLite database is run. Litestream and LiteFS both replicate the
+/// WAL, so it stays in WAL mode either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DbConfig {
+    /// From `SQLITE_REPLICATED`, set when Litestream or LiteFS ships the WAL
+    pub replicated: bool,
+    /// From `SQLITE_WAL_AUTOCHECKPOINT`, pages of WAL before SQLite
+    /// checkpoints on commit. 0 leaves checkpoints to the replicator.
+    pub wal_autocheckpoint: Option<u32>,
+    /// From `SQLITE_ACQUIRE_TIMEOUT_SECS`, how long to wait for a connection
+    pub acquire_timeout: Option<Duration>,
+}
+
+impl DbConfig {
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let var = |key: &str| var(key).filter(|value| !value.trim().is_empty());
+        let replicated = match var("SQLITE_REPLICATED") {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| "SQLITE_REPLICATED must be true or false".to_string())?,
+            None => false,
+        };
+        let wal_autocheckpoint = var("SQLITE_WAL_AUTOCHECKPOINT")
+            .map(|value| {
+                value.trim().parse().map_err(|_| {
+                    format!(
+                        "SQLITE_WAL_AUTOCHECKPOINT must be a number of pages: {}",
+                        value
+                    )
+                })
+            })
+            .transpose()?;
+        let acquire_timeout = var("SQLITE_ACQUIRE_TIMEOUT_SECS")
+            .map(|value| match value.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+                _ => Err(format!(
+                    "SQLITE_ACQUIRE_TIMEOUT_SECS must be a number of seconds above 0: {}",
+                    value
+                )),
+            })
+            .transpose()?;
+        Ok(DbConfig {
+            replicated,
+            wal_autocheckpoint,
+            acquire_timeout,
+        })
+    }
+
+    pub fn acquire_timeout(&self) -> Duration {
+        self.acquire_timeout.unwrap_or(DEFAULT_ACQUIRE_TIMEOUT)
+    }
+
+    pub fn pool_options(&self) -> SqlitePoolOptions {
+        SqlitePoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .acquire_timeout(self.acquire_timeout())
+            .acquire_slow_threshold(SLOW_ACQUIRE)
+            .acquire_slow_level(LevelFilter::Warn)
+    }
+
+    pub fn connect_options(&self, database_url: &str) -> Result<SqliteConnectOptions, sqlx::Error> {
+        let mut options = SqliteConnectOptions::from_str(database_url)?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .log_statements(LevelFilter::Debug)
+            .log_slow_statements(LevelFilter::Warn, SLOW_QUERY);
+        if let Some(pages) = self.wal_autocheckpoint {
+            options = options.pragma("wal_autocheckpoint", pages.to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// The last probe of a site's pool, and the probes since the server started.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PoolProbe {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+    /// How long the latest probe waited for a connection
+    pub last_wait: Option<Duration>,
+    pub total_wait: Duration,
+    pub probes: u64,
+    /// Probes that gave up waiting after the acquire timeout
+    pub timeouts: u64,
+}
+
+/// Pool probes by site id.
+static POOL_PROBES: LazyLock<Mutex<BTreeMap<String, PoolProbe>>> = LazyLock::new(Default::default);
+
+/// Every site's latest pool probe.
+pub fn pool_probes() -> BTreeMap<String, PoolProbe> {
+    POOL_PROBES.lock().unwrap().clone()
+}
+
+/// The current site's pool as it is now, with the waits of its probes.
+pub fn pool_probe(pool: &SqlitePool) -> PoolProbe {
+    let probe = POOL_PROBES
+        .lock()
+        .unwrap()
+        .get(&site().id)
+        .copied()
+        .unwrap_or_default();
+    PoolProbe {
+        size: pool.size(),
+        idle: pool.num_idle(),
+        max_connections: pool.options().get_max_connections(),
+        ..probe
+    }
+}
+
+/// Times taking a connection from `pool`, recording it as the current
+/// site's latest probe.
+async fn probe_pool(pool: &SqlitePool) {
+    let started = Instant::now();
+    // Given straight back, so the idle count below doesn't include it
+    let acquired = pool.acquire().await.map(drop);
+    let waited = started.elapsed();
+    let mut probes = POOL_PROBES.lock().unwrap();
+    let probe = probes.entry(site().id.clone()).or_default();
+    probe.probes += 1;
+    match acquired {
+        Ok(_) => {
+            probe.last_wait = Some(waited);
+            probe.total_wait += waited;
+        }
+        Err(e) => {
+            probe.last_wait = None;
+            if matches!(e, sqlx::Error::PoolTimedOut) {
+                probe.timeouts += 1;
+            }
+            eprintln!(
+                "Waited {:.1}s for a database connection, with all {} in use: {}",
+                waited.as_secs_f64(),
+                pool.size(),
+                e
+            );
+        }
+    }
+    *probe = PoolProbe {
+        size: pool.size(),
+        idle: pool.num_idle(),
+        max_connections: pool.options().get_max_connections(),
+        ..*probe
+    };
+}
+
+/// Probes the current site's pool every few seconds, so its size, idle
+/// connections and how long a connection takes can be reported.
+pub fn spawn_pool_probe(pool: SqlitePool) {
+    site::spawn(async move {
+        let mut interval = tokio::time::interval(POOL_PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            probe_pool(&pool).await;
+        }
+    });
+}
+
+/// The `PRAGMA wal_checkpoint` modes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointMode {
+    /// Copies what it can without waiting on readers or writers
+    #[default]
+    Passive,
+    /// Waits for writers, then copies the whole WAL
+    Full,
+    /// Like full, then waits for readers so the WAL starts over
+    Restart,
+    /// Like restart, then truncates the WAL file to zero bytes
+    Truncate,
+}
+
+impl CheckpointMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Restart => "RESTART",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// The result of a checkpoint, in WAL frames.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Checkpoint {
+    /// A reader or writer stopped it finishing
+    pub busy: bool,
+    pub wal_frames: i64,
+    pub checkpointed_frames: i64,
+}
+
+/// Copies the WAL back into the database file.
+pub async fn checkpoint(
+    pool: &SqlitePool,
+    mode: CheckpointMode,
+) -> Result<Checkpoint, sqlx::Error> {
+    let (busy, wal_frames, checkpointed_frames): (i64, i64, i64) =
+        sqlx::query_as(&format!("PRAGMA wal_checkpoint({})", mode.as_str()))
+            .fetch_one(pool)
+            .await?;
+    Ok(Checkpoint {
+        busy: busy != 0,
+        wal_frames,
+        checkpointed_frames,
+    })
+}
+
+/// Every problem `PRAGMA integrity_check` (or the faster `quick_check`) and
+/// `PRAGMA foreign_key_check` find. Empty when the database is sound.
+pub async fn integrity_problems(
+    pool: &SqlitePool,
+    quick: bool,
+) -> Result<Vec<String>, sqlx::Error> {
+    let pragma = if quick {
+        "quick_check"
+    } else {
+        "integrity_check"
+    };
+    let mut problems: Vec<String> = sqlx::query_scalar(&format!("PRAGMA {}", pragma))
+        .fetch_all(pool)
+        .await?;
+    problems.retain(|problem| problem != "ok");
+
+    let orphans: Vec<(String, Option<i64>, String)> =
+        sqlx::query_as("SELECT \"table\", rowid, parent FROM pragma_foreign_key_check")
+            .fetch_all(pool)
+            .await?;
+    problems.extend(orphans.into_iter().map(|(table, rowid, parent)| {
+        format!(
+            "{} row {} references a missing {} row",
+            table,
+            rowid.map(|rowid| rowid.to_string()).unwrap_or_default(),
+            parent
+        )
+    }));
+    Ok(problems)
+}
+
+/// Prints the integrity check, failing if it found problems.
+pub async fn print_integrity_check(pool: &SqlitePool, quick: bool) -> Result<(), AppError> {
+    let problems = integrity_problems(pool, quick).await?;
+    if problems.is_empty() {
+        println!("ok");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    Err(AppError::Internal(format!(
+        "The integrity check found {} problems",
+        problems.len()
+    )))
+}
+
+/// Rows left behind in the `USER_TABLES` by deleted users.
+pub async fn orphaned_rows(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let mut problems = Vec::new();
+    for table in USER_TABLES {
+        let count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE user_id NOT IN (SELECT id FROM users)",
+            table
+        ))
+        .fetch_one(pool)
+        .await?;
+        if count > 0 {
+            problems.push(format!("{} {} rows are for deleted users", count, table));
+        }
+    }
+    Ok(problems)
+}
+
+/// Prints a report of the integrity, foreign key and orphaned row checks
+/// for a periodic health job, failing if any found problems.
+pub async fn print_health_check(pool: &SqlitePool, quick: bool) -> Result<(), AppError> {
+    let sections = [
+        ("Integrity", integrity_problems(pool, quick).await?),
+        ("Orphaned rows", orphaned_rows(pool).await?),
+    ];
+    let mut found = 0;
+    for (name, problems) in &sections {
+        if problems.is_empty() {
+            println!("{}: ok", name);
+            continue;
+        }
+        println!("{}: {} problems", name, problems.len());
+        for problem in problems {
+            println!("  {}", problem);
+        }
+        found += problems.len();
+    }
+    if found > 0 {
+        return Err(AppError::Internal(format!(
+            "The health check found {} problems",
+            found
+        )));
+    }
+    Ok(())
+}
+
+pub async fn print_checkpoint(pool: &SqlitePool, mode: CheckpointMode) -> Result<(), AppError> {
+    let result = checkpoint(pool, mode).await?;
+    println!(
+        "Checkpointed {} of {} WAL frames{}",
+        result.checkpointed_frames,
+        result.wal_frames,
+        if result.busy { " (busy)" } else { "" }
+    );
+    Ok(())
+}
+
+/// Rebuilds the database file to reclaim free pages. Refused when replicated
+/// unless forced: VACUUM rewrites every page, which Litestream ships as a
+/// new snapshot and LiteFS as one huge transaction to every replica.
+pub async fn vacuum(pool: &SqlitePool, config: &DbConfig, force: bool) -> Result<(), AppError> {
+    if config.replicated && !force {
+        return Err(AppError::Conflict(
+            "Not vacuuming a replicated database, pass --force to anyway".to_string(),
+        ));
+    }
+    sqlx::query("VACUUM").execute(pool).await?;
+    println!("Vacuumed the database");
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct CheckpointQuery {
+    #[serde(default)]
+    mode: CheckpointMode,
+}
+
+/// `POST /admin/api/checkpoint?mode=truncate`, e.g. before a Litestream
+/// snapshot or to shrink the WAL after a large sync.
+pub async fn checkpoint_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CheckpointQuery>,
+) -> Response {
+    match checkpoint(&state.pool, query.mode).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => {
+            eprintln!("Database error checkpointing: {:?}", e);
+            AppError::Internal("Internal server error".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_pool_probe() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(50))
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let held = pool.acquire().await.unwrap();
+        probe_pool(&pool).await;
+        drop(held);
+        probe_pool(&pool).await;
+
+        let probe = pool_probe(&pool);
+        assert_eq!(probe.probes, 2);
+        assert_eq!(probe.timeouts, 1);
+        assert!(probe.last_wait.is_some());
+        assert_eq!((probe.size, probe.max_connections), (1, 1));
+        assert_eq!(pool_probes()[&site().id], probe);
+    }
+
+    #[tokio::test]
+    async fn test_orphaned_rows() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        sqlx::query("INSERT INTO users (id, email, verification_token) VALUES ('kept', 'kept@example.com', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (id, user_id) in [("1", "kept"), ("2", "gone"), ("3", "gone")] {
+            sqlx::query(
+                "INSERT INTO notification_log (id, user_id, email, subject) VALUES (?, ?, '', '')",
+            )
+            .bind(id)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            orphaned_rows(&pool).await.unwrap(),
+            ["2 notification_log rows are for deleted users"]
+        );
+        assert!(print_health_check(&pool, true).await.is_err());
+
+        sqlx::query("DELETE FROM notification_log WHERE user_id = 'gone'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(print_health_check(&pool, true).await.is_ok());
+    }
+
+    #[test]
+    fn test_db_config() {
+        let config = |vars: &[(&str, &str)]| {
+            let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+            DbConfig::from_vars(|key| vars.get(key).map(|value| value.to_string()))
+        };
+
+        assert_eq!(config(&[]), Ok(DbConfig::default()));
+        assert_eq!(
+            config(&[
+                ("SQLITE_REPLICATED", "true"),
+                ("SQLITE_WAL_AUTOCHECKPOINT", "0")
+            ]),
+            Ok(DbConfig {
+                replicated: true,
+                wal_autocheckpoint: Some(0),
+                acquire_timeout: None,
+            })
+        );
+        let timeout = config(&[("SQLITE_ACQUIRE_TIMEOUT_SECS", "5")]).unwrap();
+        assert_eq!(timeout.acquire_timeout(), Duration::from_secs(5));
+        assert_eq!(
+            DbConfig::default().acquire_timeout(),
+            DEFAULT_ACQUIRE_TIMEOUT
+        );
+        assert!(config(&[("SQLITE_ACQUIRE_TIMEOUT_SECS", "0")]).is_err());
+        assert!(config(&[("SQLITE_REPLICATED", "litestream")]).is_err());
+        assert!(config(&[("SQLITE_WAL_AUTOCHECKPOINT", "-1")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_and_integrity_check() {
+        let path = env::temp_dir().join(format!("checkpoint-test-{}.db", uuid::Uuid::new_v4()));
+        let config = DbConfig {
+            replicated: true,
+            wal_autocheckpoint: Some(0),
+            acquire_timeout: None,
+        };
+        let options = config
+            .connect_options(&format!("sqlite:{}", path.display()))
+            .unwrap()
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        let result = checkpoint(&pool, CheckpointMode::Truncate).await.unwrap();
+        assert!(!result.busy);
+        assert_eq!(result.wal_frames, 0);
+        assert!(integrity_problems(&pool, false).await.unwrap().is_empty());
+        assert!(vacuum(&pool, &config, false).await.is_err());
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use chrono::NaiveDateTime;
+use idna::domain_to_ascii;
+use sqlx::sqlite::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use validator::{ValidateEmail, ValidationError};
+
+/// The address as it's stored and sent to, trimmed and lowercased so
+/// `Bob@example.com` and `bob@example.com` are the same subscriber.
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Validates a signup or contact address. `validator` follows the HTML spec,
+/// which only allows ASCII before the @, so letters beyond it (RFC 6531
+/// addresses like `josé@example.com`) are checked as if they were ASCII ones.
+/// Internationalized domains were already accepted.
+pub fn validate(email: &str) -> Result<(), ValidationError> {
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Err(ValidationError::new("email"));
+    };
+    let local: String = local
+        .chars()
+        .map(|c| {
+            if !c.is_ascii() && c.is_alphanumeric() {
+                'a'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if format!("{}@{}", local, domain).validate_email() {
+        Ok(())
+    } else {
+        Err(ValidationError::new("email"))
+    }
+}
+
+/// The address with its domain in punycode, as it's sent to. Mail servers
+/// that don't support SMTPUTF8 can still take it when the local part is
+/// ASCII, and lettre asks for SMTPUTF8 when it isn't.
+pub fn deliverable(email: &str) -> String {
+    match email.rsplit_once('@') {
+        Some((local, domain)) if !domain.is_ascii() => match domain_to_ascii(domain) {
+            Ok(domain) => format!("{}@{}", local, domain),
+            Err(_) => email.to_string(),
+        },
+        _ => email.to_string(),
+    }
+}
+
+/// Domains whose mailboxes ignore dots in the local part.
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// How addresses are canonicalized into the key subscribers are deduplicated
+/// and matched against past unsubscribes by.
+#[derive(Debug, Clone, Copy, Default)]
+struct KeyRules {
+    /// `DEDUP_PLUS_ADDRESSES`: `bob+tides@example.com` is `bob@example.com`
+    strip_plus: bool,
+    /// `DEDUP_GMAIL_DOTS`: `b.o.b@googlemail.com` is `bob@gmail.com`
+    gmail_dots: bool,
+}
+
+impl KeyRules {
+    fn from_env() -> Self {
+        let enabled = |name| env::var(name).is_ok_and(|value| value.trim() == "true");
+        KeyRules {
+            strip_plus: enabled("DEDUP_PLUS_ADDRESSES"),
+            gmail_dots: enabled("DEDUP_GMAIL_DOTS"),
+        }
+    }
+
+    fn key(self, email: &str) -> String {
+        let email = deliverable(&normalize(email));
+        let Some((local, domain)) = email.rsplit_once('@') else {
+            return email;
+        };
+        let mut local = local.to_string();
+        let mut domain = domain;
+        if self.strip_plus
+            && let Some((untagged, _)) = local.split_once('+')
+            && !untagged.is_empty()
+        {
+            local = untagged.to_string();
+        }
+        if self.gmail_dots && GMAIL_DOMAINS.contains(&domain) {
+            local.retain(|c| c != '.');
+            domain = GMAIL_DOMAINS[0];
+        }
+        format!("{}@{}", local, domain)
+    }
+}
+
+/// What subscribers are looked up and deduplicated by, and unsubscribes
+/// matched by. Mail still goes to the address they signed up with.
+pub fn key(email: &str) -> String {
+    KeyRules::from_env().key(email)
+}
+
+/// A user as `rekey` and `merge_duplicates` see them.
+struct KeyedUser {
+    id: String,
+    email: String,
+    email_key: Option<String>,
+    is_verified: bool,
+    is_subscribed: bool,
+    created_at: Option<NaiveDateTime>,
+    ntfy_topic: Option<String>,
+    pushover_user_key: Option<String>,
+    signal_number: Option<String>,
+}
+
+/// Users by the key they should have, the one kept when merging first:
+/// verified subscribers win, then the latest signup, like the normalizing
+/// migration.
+async fn users_by_key(pool: &SqlitePool) -> Result<HashMap<String, Vec<KeyedUser>>, sqlx::Error> {
+    let users = sqlx::query_as!(
+        KeyedUser,
+        r#"
+        SELECT id AS "id!", email, email_key, is_verified AS "is_verified: bool",
+            is_subscribed AS "is_subscribed: bool", created_at AS "created_at: NaiveDateTime",
+            ntfy_topic, pushover_user_key, signal_number
+        FROM users
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut by_key: HashMap<String, Vec<KeyedUser>> = HashMap::new();
+    for user in users {
+        by_key.entry(key(&user.email)).or_default().push(user);
+    }
+    for users in by_key.values_mut() {
+        users.sort_by(|a, b| {
+            (b.is_verified, b.is_subscribed, b.created_at, &b.id).cmp(&(
+                a.is_verified,
+                a.is_subscribed,
+                a.created_at,
+                &a.id,
+            ))
+        });
+    }
+    Ok(by_key)
+}
+
+/// Brings every user's and event's key in line with `DEDUP_PLUS_ADDRESSES`
+/// and `DEDUP_GMAIL_DOTS`, for when they were turned on or off. Users that
+/// would share a key keep their old ones and are logged, for
+/// `users merge-duplicates` to merge.
+pub async fn rekey(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let by_key = users_by_key(pool).await?;
+    // Keys the users waiting to be merged still hold
+    let held: HashSet<&str> = by_key
+        .values()
+        .filter(|users| users.len() > 1)
+        .flatten()
+        .filter_map(|user| user.email_key.as_deref())
+        .collect();
+
+    let mut rekeyed = Vec::new();
+    for (key, users) in &by_key {
+        if let [user] = users.as_slice() {
+            if user.email_key.as_deref() == Some(key.as_str()) {
+                continue;
+            }
+            if held.contains(key.as_str()) {
+                eprintln!(
+                    "{} would take the key of an address waiting to be merged, leaving it as is",
+                    user.email
+                );
+                continue;
+            }
+            rekeyed.push((&user.id, key));
+        } else {
+            let emails: Vec<&str> = users.iter().map(|user| user.email.as_str()).collect();
+            eprintln!(
+                "{} are the same subscriber once normalized, run `users merge-duplicates` to merge them",
+                emails.join(", ")
+            );
+        }
+    }
+    let mut tx = pool.begin().await?;
+    // Cleared first so a key can pass from one user to another
+    for (id, _) in &rekeyed {
+        sqlx::query!(r#"UPDATE users SET email_key = NULL WHERE id = ?"#, id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for (id, key) in &rekeyed {
+        sqlx::query!(r#"UPDATE users SET email_key = ? WHERE id = ?"#, key, id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    if !rekeyed.is_empty() {
+        println!("Updated the lookup key of {} addresses", rekeyed.len());
+    }
+
+    let events = sqlx::query!(r#"SELECT DISTINCT email, email_key FROM events"#)
+        .fetch_all(pool)
+        .await?;
+    for event in events {
+        let key = key(&event.email);
+        if event.email_key.as_deref() != Some(key.as_str()) {
+            sqlx::query!(
+                r#"UPDATE events SET email_key = ? WHERE email = ?"#,
+                key,
+                event.email
+            )
+            .execute(pool)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the users that share a key once normalized, and with `apply`
+/// merges each group into the user `users_by_key` keeps. The others'
+/// notifications, reminders and flood feedback move over to it, it takes
+/// their push channels where it has none, and they're deleted. Rows the
+/// kept user already has for the same tide win.
+pub async fn merge_duplicates(pool: &SqlitePool, apply: bool) -> Result<(), sqlx::Error> {
+    let mut merged = 0;
+    for users in users_by_key(pool).await?.into_values() {
+        let mut users = users.into_iter();
+        let Some(mut kept) = users.next() else {
+            continue;
+        };
+        let duplicates: Vec<KeyedUser> = users.collect();
+        merged += duplicates.len();
+        if !apply {
+            for duplicate in &duplicates {
+                println!("Would merge {} into {}", duplicate.email, kept.email);
+            }
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        for duplicate in &duplicates {
+            kept.ntfy_topic = kept.ntfy_topic.or(duplicate.ntfy_topic.clone());
+            kept.pushover_user_key = kept
+                .pushover_user_key
+                .or(duplicate.pushover_user_key.clone());
+            kept.signal_number = kept.signal_number.or(duplicate.signal_number.clone());
+            sqlx::query!(
+                r#"UPDATE notification_log SET user_id = ? WHERE user_id = ?"#,
+                kept.id,
+                duplicate.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                r#"UPDATE OR IGNORE reminder_log SET user_id = ? WHERE user_id = ?"#,
+                kept.id,
+                duplicate.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                r#"DELETE FROM reminder_log WHERE user_id = ?"#,
+                duplicate.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                r#"UPDATE OR IGNORE flood_feedback SET user_id = ? WHERE user_id = ?"#,
+                kept.id,
+                duplicate.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                r#"DELETE FROM flood_feedback WHERE user_id = ?"#,
+                duplicate.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(r#"DELETE FROM users WHERE id = ?"#, duplicate.id)
+                .execute(&mut *tx)
+                .await?;
+            println!("Merged {} into {}", duplicate.email, kept.email);
+        }
+        sqlx::query!(
+            r#"UPDATE users SET ntfy_topic = ?, pushover_user_key = ?, signal_number = ? WHERE id = ?"#,
+            kept.ntfy_topic,
+            kept.pushover_user_key,
+            kept.signal_number,
+            kept.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+    }
+    if merged == 0 {
+        println!("No addresses share a key");
+    } else if apply {
+        println!("Merged {} addresses into another subscriber", merged);
+        rekey(pool).await?;
+    } else {
+        println!("Run again with --apply to merge them");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(normalize(" Bob@Example.com "), "bob@example.com");
+        let plus = KeyRules {
+            strip_plus: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            KeyRules::default().key("Bob+Tides@x.com"),
+            "bob+tides@x.com"
+        );
+        assert_eq!(plus.key("Bob+Tides@x.com"), "bob@x.com");
+        assert_eq!(plus.key("+tides@x.com"), "+tides@x.com");
+        assert_eq!(plus.key("not an address"), "not an address");
+
+        let gmail = KeyRules {
+            strip_plus: true,
+            gmail_dots: true,
+        };
+        assert_eq!(gmail.key("B.o.b+tides@GoogleMail.com"), "bob@gmail.com");
+        assert_eq!(gmail.key("b.o.b@x.com"), "b.o.b@x.com");
+        assert_eq!(plus.key("b.o.b@gmail.com"), "b.o.b@gmail.com");
+        assert_eq!(
+            KeyRules::default().key("José@Bücher.de"),
+            KeyRules::default().key("josé@xn--bcher-kva.de")
+        );
+    }
+
+    #[test]
+    fn test_international_addresses() {
+        for valid in [
+            "josé@example.com",
+            "用户@例子.广告",
+            "δοκιμή@παράδειγμα.δοκιμή",
+            "bob@bücher.de",
+        ] {
+            assert!(validate(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["josé", "jo sé@example.com", "josé@", "→@example.com"] {
+            assert!(validate(invalid).is_err(), "{}", invalid);
+        }
+
+        assert_eq!(deliverable("bob@bücher.de"), "bob@xn--bcher-kva.de");
+        assert_eq!(deliverable("josé@example.com"), "josé@example.com");
+        let mailbox: lettre::message::Mailbox = deliverable("josé@bücher.de").parse().unwrap();
+        assert_eq!(mailbox.email.domain(), "xn--bcher-kva.de");
+    }
+
+    #[tokio::test]
+    async fn test_migration_deduplicates() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let migrator = sqlx::migrate!();
+        let (before, after): (Vec<_>, Vec<_>) = migrator
+            .iter()
+            .partition(|migration| migration.version < 20261015092600);
+        for migration in before {
+            sqlx::raw_sql(&migration.sql).execute(&pool).await.unwrap();
+        }
+        sqlx::query(
+            "INSERT INTO users (id, email, verification_token, is_verified, is_subscribed)
+             VALUES ('old', 'Bob@X.com', '', 1, 1), ('new', 'bob@x.com', '', 0, 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO reminder_log (user_id, prediction_time, sent_at)
+             VALUES ('new', '2026-11-03 08:00:00', '2026-11-02 08:00:00')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for migration in after {
+            sqlx::raw_sql(&migration.sql).execute(&pool).await.unwrap();
+        }
+
+        let users: Vec<(String, String, String)> =
+            sqlx::query_as("SELECT id, email, email_key FROM users")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            users,
+            [(
+                "old".to_string(),
+                "bob@x.com".to_string(),
+                "bob@x.com".to_string()
+            )]
+        );
+        let reminders: Vec<String> = sqlx::query_scalar("SELECT user_id FROM reminder_log")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(reminders, ["old"]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_duplicates() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO users (id, email, verification_token, is_verified, is_subscribed, created_at, ntfy_topic)
+             VALUES ('old', 'bob@x.com', '', 1, 1, '2026-01-01 00:00:00', NULL),
+                    ('new', 'Bob@X.com', '', 0, 0, '2026-10-01 00:00:00', 'https://ntfy.sh/bob')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO reminder_log (user_id, prediction_time)
+             VALUES ('old', '2026-11-03 08:00:00'), ('new', '2026-11-03 08:00:00'),
+                    ('new', '2026-11-04 09:00:00');
+             INSERT INTO notification_log (id, user_id, email, subject) VALUES ('n', 'new', '', '');
+             INSERT INTO flood_feedback (user_id, prediction_time, flooded)
+             VALUES ('new', '2026-11-03 08:00:00', 1)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let user_ids = |table: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, String>(&format!("SELECT user_id FROM {}", table))
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Starting up only logs them, as does merging without --apply
+        rekey(&pool).await.unwrap();
+        merge_duplicates(&pool, false).await.unwrap();
+        assert_eq!(user_ids("flood_feedback").await, ["new"]);
+
+        merge_duplicates(&pool, true).await.unwrap();
+        let users: Vec<(String, String, Option<String>)> =
+            sqlx::query_as("SELECT id, email_key, ntfy_topic FROM users")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            users,
+            [(
+                "old".to_string(),
+                "bob@x.com".to_string(),
+                Some("https://ntfy.sh/bob".to_string())
+            )]
+        );
+        assert_eq!(user_ids("reminder_log").await, ["old", "old"]);
+        assert_eq!(user_ids("notification_log").await, ["old"]);
+        assert_eq!(user_ids("flood_feedback").await, ["old"]);
+    }
+}
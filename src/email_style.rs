@@ -0,0 +1,119 @@
+use std::env;
+
+/// How much the compiled emails show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailLayout {
+    /// Greeting, floods, tips and sign off with roomy spacing
+    #[default]
+    Detailed,
+    /// Just the floods and links, tighter, for reading on a phone
+    Compact,
+}
+
+impl EmailLayout {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmailLayout::Detailed => "detailed",
+            EmailLayout::Compact => "compact",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "detailed" => Some(EmailLayout::Detailed),
+            "compact" => Some(EmailLayout::Compact),
+            _ => None,
+        }
+    }
+}
+
+/// The variant a compiled email is rendered in. Admin overrides are used as
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EmailStyle {
+    pub layout: EmailLayout,
+    /// Adds a palette for mail apps in dark mode, rather than leaving them
+    /// to invert the light one
+    pub dark_mode: bool,
+}
+
+impl EmailStyle {
+    /// `EMAIL_LAYOUT` and `EMAIL_DARK_MODE`, for emails to addresses
+    /// without a preference.
+    pub fn site() -> Self {
+        EmailStyle {
+            layout: env::var("EMAIL_LAYOUT")
+                .ok()
+                .and_then(|name| EmailLayout::parse(&name))
+                .unwrap_or_default(),
+            dark_mode: env::var("EMAIL_DARK_MODE").is_ok_and(|value| value.trim() == "true"),
+        }
+    }
+
+    /// The site's style with the layout a subscriber chose, stored by name.
+    pub fn for_subscriber(layout: Option<&str>) -> Self {
+        let site = EmailStyle::site();
+        EmailStyle {
+            layout: layout.and_then(EmailLayout::parse).unwrap_or(site.layout),
+            ..site
+        }
+    }
+
+    pub fn detailed(&self) -> bool {
+        self.layout == EmailLayout::Detailed
+    }
+
+    pub fn color_scheme(&self) -> &'static str {
+        if self.dark_mode {
+            "light dark"
+        } else {
+            "light"
+        }
+    }
+
+    /// Around the card.
+    pub fn outer_padding(&self) -> &'static str {
+        match self.layout {
+            EmailLayout::Detailed => "20px",
+            EmailLayout::Compact => "8px",
+        }
+    }
+
+    /// Inside each of the card's sections.
+    pub fn padding(&self) -> &'static str {
+        match self.layout {
+            EmailLayout::Detailed => "30px",
+            EmailLayout::Compact => "16px",
+        }
+    }
+
+    pub fn heading_size(&self) -> &'static str {
+        match self.layout {
+            EmailLayout::Detailed => "24px",
+            EmailLayout::Compact => "20px",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_subscriber() {
+        assert_eq!(EmailLayout::parse("compact"), Some(EmailLayout::Compact));
+        assert_eq!(EmailLayout::parse("tiny"), None);
+        let compact = EmailStyle::for_subscriber(Some("compact"));
+        assert!(!compact.detailed());
+        assert_eq!(compact.padding(), "16px");
+        assert_eq!(
+            EmailStyle::for_subscriber(Some("tiny")).layout,
+            EmailStyle::site().layout
+        );
+        let dark = EmailStyle {
+            dark_mode: true,
+            ..Default::default()
+        };
+        assert_eq!(dark.color_scheme(), "light dark");
+    }
+}
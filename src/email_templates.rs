@@ -0,0 +1,445 @@
+use askama::Template;
+use chrono::NaiveDateTime;
+use sqlx::sqlite::SqlitePool;
+
+use crate::error::template_failed;
+use crate::models::FloodDisplay;
+use crate::site::site;
+
+/// Starting points for overrides: the compiled emails in the default style,
+/// with their values as the variables an override uses. Kept as their own
+/// files to be edited alongside `verification_email.html` and
+/// `notification_email.html`.
+const VERIFICATION_DEFAULT: &str = include_str!("../templates/email_overrides/verification.html");
+const NOTIFICATION_DEFAULT: &str = include_str!("../templates/email_overrides/notification.html");
+/// Variables holding HTML we render ourselves, left unescaped.
+const RAW_VARIABLES: [&str; 2] = ["floods", "logo"];
+
+/// The emails whose copy can be overridden from the admin UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailKind {
+    Verification,
+    Notification,
+}
+
+impl EmailKind {
+    pub const ALL: [EmailKind; 2] = [EmailKind::Verification, EmailKind::Notification];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmailKind::Verification => "verification",
+            EmailKind::Notification => "notification",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        EmailKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == name)
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            EmailKind::Verification => "Verification email",
+            EmailKind::Notification => "Flood notification email",
+        }
+    }
+
+    /// The only variables an override may use. Values are HTML escaped in
+    /// the HTML body, apart from `floods` which we render ourselves.
+    pub fn variables(self) -> &'static [&'static str] {
+        match self {
+            EmailKind::Verification => &[
+                "verification_link",
+                "verification_code",
+                "verify_page_link",
+                "unsubscribe_link",
+                "site_name",
+                "logo",
+                "accent_color",
+                "footer_text",
+            ],
+            EmailKind::Notification => &[
+                "floods",
+                "forecast_days",
+                "homepage_url",
+                "reminders_link",
+                "feedback_link",
+                "unsubscribe_link",
+                "alternate_route",
+                "site_name",
+                "logo",
+                "accent_color",
+                "footer_text",
+            ],
+        }
+    }
+
+    /// The compiled template, as a starting point for an override.
+    pub fn default_template(self) -> EmailTemplate {
+        match self {
+            EmailKind::Verification => EmailTemplate {
+                subject: "Please verify your email".to_string(),
+                html_body: VERIFICATION_DEFAULT.to_string(),
+                text_body: "Welcome! Please verify your email address: {{ verification_link }}\n\nOr enter the code {{ verification_code }} at {{ verify_page_link }}".to_string(),
+            },
+            EmailKind::Notification => EmailTemplate {
+                subject: format!("{} Flooding Forecasted", site().name),
+                html_body: NOTIFICATION_DEFAULT.to_string(),
+                text_body: "Upcoming potential floods for the {{ site_name }}. Please visit {{ homepage_url }} for details. {{ alternate_route }}\n\nGet a reminder the evening before or morning of each flood: {{ reminders_link }}\n\nWas the path flooded at the last ones? Let us know: {{ feedback_link }}\n\nUnsubscribe link: {{ unsubscribe_link }}".to_string(),
+            },
+        }
+    }
+}
+
+/// The theme variables every email has, from the current site.
+pub fn theme_variables() -> [(&'static str, String); 3] {
+    let logo = EmailLogoTemplate
+        .render()
+        .inspect_err(|e| template_failed("email_logo", e))
+        .unwrap_or_default();
+    [
+        ("logo", logo.trim().to_string()),
+        ("accent_color", site().accent_or("#0056b3").to_string()),
+        (
+            "footer_text",
+            site().footer_text.clone().unwrap_or_default(),
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailTemplate {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+impl EmailTemplate {
+    /// Fills in the template. The text body gets its own variables since
+    /// some, like `floods`, are rendered differently as plain text.
+    pub fn render(
+        &self,
+        html_variables: &[(&str, String)],
+        text_variables: &[(&str, String)],
+    ) -> RenderedEmail {
+        RenderedEmail {
+            subject: substitute(&self.subject, text_variables, false),
+            html_body: substitute(&self.html_body, html_variables, true),
+            text_body: substitute(&self.text_body, text_variables, false),
+        }
+    }
+
+    /// Variables used in the template that the email doesn't provide.
+    pub fn unknown_variables(&self, kind: EmailKind) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for name in [&self.subject, &self.html_body, &self.text_body]
+            .into_iter()
+            .flat_map(|part| placeholders(part))
+        {
+            if !kind.variables().contains(&name.as_str()) && !unknown.contains(&name) {
+                unknown.push(name);
+            }
+        }
+        unknown
+    }
+}
+
+#[derive(Template)]
+#[template(path = "fragments/email_logo.html")]
+struct EmailLogoTemplate;
+
+#[derive(Template)]
+#[template(path = "fragments/email_floods.html")]
+pub struct EmailFloodsTemplate<'a> {
+    pub predictions: &'a [FloodDisplay],
+}
+
+/// Plain text version of the `floods` variable.
+pub fn floods_text(predictions: &[FloodDisplay]) -> String {
+    predictions
+        .iter()
+        .map(|p| format!("- {}: {} ft", p.datetime, p.height))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn placeholders(template: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        names.push(rest[start + 2..start + end].trim().to_string());
+        rest = &rest[start + end + 2..];
+    }
+    names
+}
+
+/// Replaces `{{ name }}` placeholders. Unknown placeholders are left as is.
+fn substitute(template: &str, variables: &[(&str, String)], html: bool) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        let name = rest[start + 2..start + end].trim();
+        match variables.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) if html && !RAW_VARIABLES.contains(&name) => {
+                output.push_str(&escape_html(value))
+            }
+            Some((_, value)) => output.push_str(value),
+            None => output.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Returns the override stored for an email, if any.
+pub async fn load_override(
+    pool: &SqlitePool,
+    kind: EmailKind,
+) -> Result<Option<EmailTemplate>, sqlx::Error> {
+    let name = kind.as_str();
+    let row = sqlx::query!(
+        r#"
+        SELECT subject, html_body, text_body FROM email_templates WHERE name = ?
+        "#,
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| EmailTemplate {
+        subject: row.subject,
+        html_body: row.html_body,
+        text_body: row.text_body,
+    }))
+}
+
+/// When each email's override was last saved.
+pub async fn overridden_at(
+    pool: &SqlitePool,
+) -> Result<Vec<(EmailKind, NaiveDateTime)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT name, updated_at AS "updated_at!: NaiveDateTime" FROM email_templates
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| Some((EmailKind::parse(&row.name)?, row.updated_at)))
+        .collect())
+}
+
+pub async fn save_override(
+    pool: &SqlitePool,
+    kind: EmailKind,
+    template: &EmailTemplate,
+) -> Result<(), sqlx::Error> {
+    let name = kind.as_str();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_templates (name, subject, html_body, text_body)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(name) DO UPDATE
+        SET subject = excluded.subject,
+            html_body = excluded.html_body,
+            text_body = excluded.text_body,
+            updated_at = CURRENT_TIMESTAMP;
+        "#,
+        name,
+        template.subject,
+        template.html_body,
+        template.text_body
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_override(pool: &SqlitePool, kind: EmailKind) -> Result<(), sqlx::Error> {
+    let name = kind.as_str();
+    sqlx::query!("DELETE FROM email_templates WHERE name = ?;", name)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let variables = [
+            (
+                "verification_link",
+                "https://x.test/verify?a=1&b=2".to_string(),
+            ),
+            ("floods", "<div>7.0 ft</div>".to_string()),
+        ];
+        assert_eq!(
+            substitute(
+                "<a href=\"{{ verification_link }}\">{{floods}}</a>",
+                &variables,
+                true
+            ),
+            "<a href=\"https://x.test/verify?a=1&amp;b=2\"><div>7.0 ft</div></a>"
+        );
+        assert_eq!(
+            substitute(
+                "Go to {{ verification_link }} {{ other }}",
+                &variables,
+                false
+            ),
+            "Go to https://x.test/verify?a=1&b=2 {{ other }}"
+        );
+    }
+
+    #[test]
+    fn test_unknown_variables() {
+        let template = EmailTemplate {
+            subject: "Verify {{ name }}".to_string(),
+            html_body: "{{ verification_link }} {{ name }}".to_string(),
+            text_body: "{{ user.password }}".to_string(),
+        };
+        assert_eq!(
+            template.unknown_variables(EmailKind::Verification),
+            vec!["name".to_string(), "user.password".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_default_with_layout() {
+        let html = EmailKind::Notification.default_template().html_body;
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("Dear Subscriber"));
+        assert!(html.contains("{{ floods }}"));
+        assert!(html.contains("padding: 30px;"));
+        assert!(html.contains("You can unsubscribe at any time"));
+        assert!(!html.contains("prefers-color-scheme"));
+        let verification = EmailKind::Verification.default_template().html_body;
+        assert!(verification.contains("{{ site_name }} Flooding Alerts"));
+        assert!(verification.contains("{{ verification_code }}"));
+    }
+
+    /// Collapses whitespace, which differs where the compiled templates have
+    /// tags.
+    fn words(html: &str) -> String {
+        html.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn test_defaults_render_like_compiled_emails() {
+        use crate::email_style::EmailStyle;
+        use crate::mail::{NotificationTemplate, VerifyTemplate};
+
+        let mut variables = vec![
+            (
+                "verification_link",
+                "http://x.test/verify?token=1".to_string(),
+            ),
+            ("verification_code", "042317".to_string()),
+            ("verify_page_link", "http://x.test/verify".to_string()),
+            ("homepage_url", "http://x.test/".to_string()),
+            ("reminders_link", "http://x.test/reminders".to_string()),
+            ("feedback_link", "http://x.test/feedback".to_string()),
+            ("unsubscribe_link", "http://x.test/unsubscribe".to_string()),
+            ("forecast_days", "7".to_string()),
+            (
+                "alternate_route",
+                crate::station_settings::alternate_route(),
+            ),
+            ("site_name", site().name.clone()),
+        ];
+        variables.extend(theme_variables());
+        let predictions = vec![FloodDisplay::new(
+            "2026-10-17T16:50:00Z".parse().unwrap(),
+            6.9,
+        )];
+        variables.push((
+            "floods",
+            EmailFloodsTemplate {
+                predictions: &predictions,
+            }
+            .render()
+            .unwrap(),
+        ));
+        let variable = |name: &str| {
+            variables
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.as_str())
+                .unwrap()
+        };
+
+        let verification = VerifyTemplate {
+            verification_link: variable("verification_link"),
+            verification_code: variable("verification_code"),
+            verify_page_link: variable("verify_page_link"),
+            unsubscribe_link: variable("unsubscribe_link"),
+            style: EmailStyle::default(),
+        };
+        assert_eq!(
+            words(
+                &EmailKind::Verification
+                    .default_template()
+                    .render(&variables, &variables)
+                    .html_body
+            ),
+            words(&verification.render().unwrap())
+        );
+        let notification = NotificationTemplate {
+            predictions: &predictions,
+            homepage_url: variable("homepage_url"),
+            reminders_link: variable("reminders_link"),
+            feedback_link: variable("feedback_link"),
+            unsubscribe_link: variable("unsubscribe_link"),
+            forecast_days: 7,
+            chart: false,
+            style: EmailStyle::default(),
+        };
+        assert_eq!(
+            words(
+                &EmailKind::Notification
+                    .default_template()
+                    .render(&variables, &variables)
+                    .html_body
+            ),
+            words(&notification.render().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_defaults_only_use_known_variables() {
+        for kind in EmailKind::ALL {
+            let template = kind.default_template();
+            assert!(template.unknown_variables(kind).is_empty(), "{:?}", kind);
+            assert!(!template.html_body.contains("{%"), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use askama::Template;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use mill_valley_sausalito_bikepath_flood_alert::api_types::ErrorResponse;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use crate::mail::EmailError;
+use crate::metrics;
+use crate::request_id;
+use crate::services::SignupError;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An error from a handler, job or command. Renders as an RFC 7807
+/// problem+json body, which `html_errors` swaps for an error page when a
+/// browser asked for it. Server side failures keep their detail for logs and
+/// job runs but only show a generic message.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    /// A request body that failed validation, with the messages for each field
+    #[error("{message}")]
+    Invalid {
+        message: String,
+        fields: BTreeMap<String, Vec<String>>,
+    },
+    /// An extractor's rejection, keeping its status
+    #[error("{1}")]
+    Rejected(StatusCode, String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("Method not allowed")]
+    MethodNotAllowed,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{message}")]
+    TooManyRequests {
+        message: String,
+        retry_after_secs: Option<u64>,
+    },
+    /// A failure whose message is safe to show, already logged by the caller
+    #[error("{0}")]
+    Internal(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Template error: {0}")]
+    Template(#[from] askama::Error),
+    /// NOAA couldn't be reached or answered with an error
+    #[error("NOAA error: {0}")]
+    Noaa(String),
+    /// NOAA sent predictions that failed their checks, which fetching them
+    /// again won't fix
+    #[error("NOAA data error: {0}")]
+    NoaaData(String),
+    #[error("Mail error: {0}")]
+    Mail(#[from] EmailError),
+    /// Missing or invalid settings
+    #[error("Configuration error: {0}")]
+    Config(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::Invalid { .. } => StatusCode::BAD_REQUEST,
+            AppError::Rejected(status, _) => *status,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Noaa(_) | AppError::NoaaData(_) | AppError::Mail(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            AppError::Database(sqlx::Error::PoolTimedOut) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_)
+            | AppError::Database(_)
+            | AppError::Template(_)
+            | AppError::Config(_)
+            | AppError::Io(_)
+            | AppError::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A short name for metrics and logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) | AppError::Invalid { .. } | AppError::Rejected(..) => {
+                "validation"
+            }
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::NotFound(_) | AppError::MethodNotAllowed => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::TooManyRequests { .. } => "rate_limited",
+            AppError::Internal(_) => "internal",
+            AppError::Database(sqlx::Error::PoolTimedOut) => "db_pool",
+            AppError::Database(_) => "db",
+            AppError::Template(_) => "template",
+            AppError::Noaa(_) | AppError::NoaaData(_) => "noaa",
+            AppError::Mail(_) => "mail",
+            AppError::Config(_) => "config",
+            AppError::Io(_) => "io",
+            AppError::Json(_) => "json",
+        }
+    }
+
+    /// Whether trying again later could succeed: NOAA or the mail server
+    /// being down, or the database being busy. A queued job isn't retried
+    /// otherwise.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AppError::Noaa(_) => true,
+            AppError::Mail(EmailError::SmtpTransportError(e)) => !e.is_permanent(),
+            AppError::Database(sqlx::Error::PoolTimedOut | sqlx::Error::Io(_)) => true,
+            // SQLITE_BUSY and SQLITE_LOCKED, including their extended codes
+            AppError::Database(e) => e
+                .as_database_error()
+                .and_then(|e| e.code())
+                .and_then(|code| code.parse::<i32>().ok())
+                .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+            _ => false,
+        }
+    }
+
+    /// What the response says, hiding the detail of server side failures.
+    fn detail(&self) -> String {
+        match self {
+            AppError::Noaa(_) | AppError::NoaaData(_) => {
+                "Tide predictions couldn't be fetched from NOAA".to_string()
+            }
+            AppError::Mail(_) => "The mail server couldn't be reached".to_string(),
+            AppError::Database(sqlx::Error::PoolTimedOut) => {
+                "The server is too busy to answer right now, try again shortly".to_string()
+            }
+            AppError::Database(_)
+            | AppError::Template(_)
+            | AppError::Config(_)
+            | AppError::Io(_)
+            | AppError::Json(_) => "Internal server error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    fn problem(&self) -> ErrorResponse {
+        let status = self.status();
+        let detail = self.detail();
+        ErrorResponse {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: detail.clone(),
+            error: detail,
+            fields: match self {
+                AppError::Invalid { fields, .. } => fields.clone(),
+                _ => BTreeMap::new(),
+            },
+            request_id: request_id::current(),
+        }
+    }
+}
+
+impl From<SignupError> for AppError {
+    fn from(e: SignupError) -> Self {
+        let message = e.to_string();
+        match e {
+            SignupError::InvalidEmail
+            | SignupError::FormExpired
+            | SignupError::NoMailServers
+            | SignupError::InvalidChannel(_) => AppError::BadRequest(message),
+            SignupError::RecentlySent => AppError::TooManyRequests {
+                message,
+                retry_after_secs: None,
+            },
+            SignupError::AlreadyVerified => AppError::Conflict(message),
+            SignupError::Database(e) => AppError::Database(e),
+            SignupError::Mail(e) => {
+                eprintln!("Mailgun error during verification: {:?}", e);
+                AppError::Internal(message)
+            }
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        metrics::count_error(self.kind());
+        match &self {
+            AppError::Template(e) => {
+                tracing::error!(error = ?e, request_id = ?request_id::current(), "Template failed to render")
+            }
+            AppError::Internal(_) => {}
+            _ if self.status().is_server_error() => eprintln!("{}", self),
+            _ => {}
+        }
+        let problem = self.problem();
+        let mut response = match serde_json::to_vec(&problem) {
+            Ok(body) => (
+                self.status(),
+                [(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+                body,
+            )
+                .into_response(),
+            Err(_) => self.status().into_response(),
+        };
+        let headers = response.headers_mut();
+        match &self {
+            AppError::Unauthorized(_) => {
+                headers.insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            AppError::TooManyRequests {
+                retry_after_secs: Some(secs),
+                ..
+            } => {
+                headers.insert(RETRY_AFTER, HeaderValue::from(*secs));
+            }
+            _ => {}
+        }
+        response.extensions_mut().insert(problem);
+        response
+    }
+}
+
+/// Logs and counts a template that failed to render, for the places that
+/// carry on without it rather than failing.
+pub fn template_failed(name: &str, e: &askama::Error) {
+    tracing::error!(template = name, error = ?e, "Template failed to render");
+    metrics::count_error("template");
+}
+
+/// The error page when even `error.html` fails to render, so a browser still
+/// gets a page rather than problem+json. Only has the status and its reason,
+/// which are safe to include as is.
+fn fallback_page(status: u16, title: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; display: flex; align-items: center; justify-content: center; min-height: 100vh; margin: 0; }}
+main {{ max-width: 500px; padding: 2rem; text-align: center; }}
+h1 {{ color: #c62828; }}
+</style>
+</head>
+<body>
+<main>
+<h1>{title}</h1>
+<p>Something went wrong on our end. Please try again in a moment.</p>
+<p><small>Error {status}</small></p>
+<p><a href="/">Return to Home</a></p>
+</main>
+</body>
+</html>
+"#
+    )
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate<'a> {
+    status: u16,
+    title: &'a str,
+    detail: &'a str,
+    request_id: Option<&'a str>,
+}
+
+/// Whether the request came from a browser page rather than the API.
+fn wants_html(req: &Request) -> bool {
+    let path = req.uri().path();
+    if path.starts_with("/api/") || path.starts_with("/admin/api/") {
+        return false;
+    }
+    req.headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"))
+}
+
+/// Middleware rendering `AppError`s as an HTML error page for browsers,
+/// keeping the status and headers.
+pub async fn html_errors(req: Request, next: Next) -> Response {
+    let wants_html = wants_html(&req);
+    let response = next.run(req).await;
+    if wants_html {
+        error_page(response)
+    } else {
+        response
+    }
+}
+
+fn error_page(response: Response) -> Response {
+    let Some(problem) = response.extensions().get::<ErrorResponse>() else {
+        return response;
+    };
+    let template = ErrorTemplate {
+        status: problem.status,
+        title: &problem.title,
+        detail: &problem.detail,
+        request_id: problem.request_id.as_deref(),
+    };
+    let html = template.render().unwrap_or_else(|e| {
+        template_failed("error.html", &e);
+        fallback_page(problem.status, &problem.title)
+    });
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    Response::from_parts(parts, Body::from(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    fn request(uri: &str, accept: &str) -> Request {
+        Request::builder()
+            .uri(uri)
+            .header(ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_error_responses() {
+        assert!(wants_html(&request("/", "text/html,application/xhtml+xml")));
+        assert!(!wants_html(&request("/signup", "application/json")));
+        assert!(!wants_html(&request("/api/v1/predictions", "text/html")));
+
+        let response = AppError::NotFound("No such page".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.title, "Not Found");
+        assert_eq!(problem.detail, "No such page");
+        assert_eq!(problem.error, "No such page");
+
+        let response = error_page(
+            AppError::TooManyRequests {
+                message: "Slow down".to_string(),
+                retry_after_secs: Some(30),
+            }
+            .into_response(),
+        );
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "30");
+        assert!(
+            response.headers()[CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Slow down"));
+    }
+
+    #[tokio::test]
+    async fn test_error_kinds() {
+        let noaa = AppError::Noaa("Network error".to_string());
+        assert_eq!(noaa.kind(), "noaa");
+        assert!(noaa.is_retryable());
+        let response = noaa.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem.detail,
+            "Tide predictions couldn't be fetched from NOAA"
+        );
+
+        let failed_check = AppError::NoaaData("Predictions for 3 days are missing".to_string());
+        assert_eq!(failed_check.kind(), "noaa");
+        assert!(!failed_check.is_retryable());
+
+        let config = AppError::Config("SQLITE_REPLICATED must be true or false".to_string());
+        assert_eq!(config.kind(), "config");
+        assert!(!config.is_retryable());
+        assert_eq!(
+            config.to_string(),
+            "Configuration error: SQLITE_REPLICATED must be true or false"
+        );
+        let pool_timeout = AppError::Database(sqlx::Error::PoolTimedOut);
+        assert!(pool_timeout.is_retryable());
+        assert_eq!(pool_timeout.kind(), "db_pool");
+        assert_eq!(pool_timeout.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!AppError::Database(sqlx::Error::RowNotFound).is_retryable());
+        assert_eq!(AppError::BadRequest("No".to_string()).kind(), "validation");
+    }
+
+    #[tokio::test]
+    async fn test_template_error_page() {
+        let response = error_page(AppError::Template(askama::Error::Fmt).into_response());
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8_lossy(&body);
+        assert!(html.contains("Internal server error"));
+        assert!(!html.contains("Template"));
+
+        let fallback = fallback_page(500, "Internal Server Error");
+        assert!(fallback.contains("<h1>Internal Server Error</h1>"));
+        assert!(fallback.contains("Error 500"));
+    }
+}
@@ -0,0 +1,235 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::email_address;
+use crate::error::AppError;
+use crate::ops_webhook::{self, OpsEvent};
+use crate::request_id;
+
+/// Something that happened to a subscriber, kept so support questions like
+/// "why did I stop getting emails?" can be answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventType {
+    Signup,
+    /// Signed up again after unsubscribing or being suppressed.
+    Resubscribe,
+    Verification,
+    Unsubscribe,
+    /// The mail server permanently rejected an email to the address.
+    Bounce,
+    /// The address was removed from the list without them asking.
+    Suppression,
+}
+
+impl EventType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::Signup => "signup",
+            EventType::Resubscribe => "resubscribe",
+            EventType::Verification => "verification",
+            EventType::Unsubscribe => "unsubscribe",
+            EventType::Bounce => "bounce",
+            EventType::Suppression => "suppression",
+        }
+    }
+}
+
+/// Where the event came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    Web,
+    Api,
+    Cli,
+}
+
+impl EventSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventSource::Web => "web",
+            EventSource::Api => "api",
+            EventSource::Cli => "cli",
+        }
+    }
+}
+
+/// Records an event, posting signups and unsubscribes to the ops webhook in
+/// the background. Failing to record one is logged rather than failing the
+/// signup or send it's describing.
+pub async fn record(
+    pool: &SqlitePool,
+    user_id: &str,
+    email: &str,
+    event_type: EventType,
+    source: EventSource,
+    detail: Option<&str>,
+) {
+    let ops_event = match event_type {
+        EventType::Signup | EventType::Resubscribe => Some(OpsEvent::Signup {
+            email: email.to_string(),
+            source: source.as_str().to_string(),
+            resubscribe: event_type == EventType::Resubscribe,
+        }),
+        EventType::Unsubscribe => Some(OpsEvent::Unsubscribe {
+            email: email.to_string(),
+            email_only: detail == Some("email"),
+        }),
+        _ => None,
+    };
+    if let Some(ops_event) = ops_event {
+        tokio::spawn(ops_webhook::post(ops_event));
+    }
+    let event_type = event_type.as_str();
+    let source = source.as_str();
+    let request_id = request_id::current();
+    let email_key = email_address::key(email);
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO events (user_id, email, email_key, event_type, source, detail, request_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?);
+        "#,
+        user_id,
+        email,
+        email_key,
+        event_type,
+        source,
+        detail,
+        request_id
+    )
+    .execute(pool)
+    .await
+    {
+        eprintln!("Database error recording {} event: {:?}", event_type, e);
+    }
+}
+
+/// Whether the address, or one with the same key, has left the list
+/// before, so a new signup counts as a resubscribe.
+pub async fn has_left_before(pool: &SqlitePool, email: &str) -> Result<bool, sqlx::Error> {
+    let key = email_address::key(email);
+    let left = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM events
+            WHERE email_key = ? AND event_type IN ('unsubscribe', 'suppression')
+        ) AS "left!: bool"
+        "#,
+        key
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(left)
+}
+
+/// Prints every event for an email address, oldest first.
+pub async fn print_history(pool: &SqlitePool, email: &str) -> Result<(), AppError> {
+    let events = sqlx::query!(
+        r#"
+        SELECT created_at AS "created_at: NaiveDateTime", event_type, source, detail
+        FROM events
+        WHERE email = ? COLLATE NOCASE
+        ORDER BY id ASC
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if events.is_empty() {
+        println!("No events for {}", email);
+    }
+    let last_notified = sqlx::query!(
+        r#"
+        SELECT
+            last_notified_at AS "last_notified_at: NaiveDateTime",
+            last_window_start AS "last_window_start: DateTime<Utc>",
+            last_window_end AS "last_window_end: DateTime<Utc>"
+        FROM users
+        WHERE email = ? COLLATE NOCASE
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(user) = last_notified
+        && let Some(notified_at) = user.last_notified_at
+    {
+        println!(
+            "Last notified {} about floods from {} to {}",
+            notified_at,
+            user.last_window_start
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+            user.last_window_end
+                .map(|t| t.to_string())
+                .unwrap_or_default()
+        );
+    }
+    for event in events {
+        println!(
+            "{}  {:<12}  {:<4}  {}",
+            event.created_at.map(|t| t.to_string()).unwrap_or_default(),
+            event.event_type,
+            event.source,
+            event.detail.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_and_has_left_before() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        record(
+            &pool,
+            "1",
+            "Bob@example.com",
+            EventType::Signup,
+            EventSource::Web,
+            None,
+        )
+        .await;
+        record(
+            &pool,
+            "1",
+            "bob@example.com",
+            EventType::Verification,
+            EventSource::Web,
+            Some("code"),
+        )
+        .await;
+        assert!(!has_left_before(&pool, "bob@example.com").await.unwrap());
+
+        record(
+            &pool,
+            "1",
+            "bob@example.com",
+            EventType::Bounce,
+            EventSource::Cli,
+            Some("550"),
+        )
+        .await;
+        record(
+            &pool,
+            "1",
+            "bob@example.com",
+            EventType::Suppression,
+            EventSource::Cli,
+            None,
+        )
+        .await;
+        assert!(has_left_before(&pool, "BOB@example.com").await.unwrap());
+
+        // Rows that failed a CHECK constraint would be missing here
+        let count = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM events"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 4);
+    }
+}
@@ -0,0 +1,252 @@
+use sqlx::sqlite::SqlitePool;
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::error::AppError;
+
+/// A running subject line experiment for notification emails.
+pub struct Experiment {
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+pub struct Variant {
+    pub id: String,
+    pub label: String,
+    pub subject: String,
+}
+
+impl Experiment {
+    /// Picks a variant uniformly at random for one send.
+    pub fn assign(&self) -> Option<&Variant> {
+        if self.variants.is_empty() {
+            return None;
+        }
+        let index = (Uuid::new_v4().as_u128() % self.variants.len() as u128) as usize;
+        self.variants.get(index)
+    }
+}
+
+/// Returns the running experiment, if there is one.
+pub async fn active(pool: &SqlitePool) -> Result<Option<Experiment>, sqlx::Error> {
+    let Some(experiment) = sqlx::query!(
+        r#"
+        SELECT id, name FROM subject_experiments WHERE ended_at IS NULL
+        "#
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let variants = sqlx::query_as!(
+        Variant,
+        r#"
+        SELECT id, label, subject FROM subject_variants
+        WHERE experiment_id = ?
+        ORDER BY label ASC
+        "#,
+        experiment.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(Experiment {
+        name: experiment.name,
+        variants,
+    }))
+}
+
+/// Labels variants A, B, C... in the order they're given.
+fn label(index: usize) -> String {
+    char::from(b'A' + (index % 26) as u8).to_string()
+}
+
+pub async fn start(pool: &SqlitePool, name: &str, subjects: &[String]) -> Result<(), AppError> {
+    if subjects.len() < 2 {
+        return Err(AppError::BadRequest(
+            "An experiment needs at least two --variant subjects".to_string(),
+        ));
+    }
+    if subjects.len() > 26 {
+        return Err(AppError::BadRequest(
+            "An experiment can have at most 26 variants".to_string(),
+        ));
+    }
+    if let Some(running) = active(pool).await? {
+        return Err(AppError::Conflict(format!(
+            "Stop the running experiment \"{}\" first",
+            running.name
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+    let id = Uuid::new_v7(Timestamp::now(NoContext)).to_string();
+    sqlx::query!(
+        "INSERT INTO subject_experiments (id, name) VALUES (?, ?);",
+        id,
+        name
+    )
+    .execute(&mut *tx)
+    .await?;
+    for (index, subject) in subjects.iter().enumerate() {
+        let variant_id = Uuid::new_v7(Timestamp::now(NoContext)).to_string();
+        let label = label(index);
+        sqlx::query!(
+            r#"
+            INSERT INTO subject_variants (id, experiment_id, label, subject)
+            VALUES (?, ?, ?, ?);
+            "#,
+            variant_id,
+            id,
+            label,
+            subject
+        )
+        .execute(&mut *tx)
+        .await?;
+        println!("Variant {}: {}", label, subject);
+    }
+    tx.commit().await?;
+
+    println!("Started experiment {}", name);
+    Ok(())
+}
+
+pub async fn stop(pool: &SqlitePool) -> Result<(), AppError> {
+    let stopped = sqlx::query!(
+        r#"
+        UPDATE subject_experiments SET ended_at = CURRENT_TIMESTAMP
+        WHERE ended_at IS NULL
+        RETURNING name;
+        "#
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match stopped {
+        Some(experiment) => println!("Stopped experiment {}", experiment.name),
+        None => println!("No experiment is running"),
+    }
+    Ok(())
+}
+
+fn rate(count: i64, sends: i64) -> String {
+    if sends == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", count as f64 * 100.0 / sends as f64)
+}
+
+/// Prints sends, opens and clicks per variant. Opens and clicks are only
+/// counted for messages sent while tracking was enabled.
+pub async fn report(pool: &SqlitePool, name: Option<&str>) -> Result<(), AppError> {
+    let experiment = sqlx::query!(
+        r#"
+        SELECT id, name FROM subject_experiments
+        WHERE name = ? OR ? IS NULL
+        ORDER BY started_at DESC
+        LIMIT 1
+        "#,
+        name,
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(experiment) = experiment else {
+        println!("No experiment found");
+        return Ok(());
+    };
+
+    let variants = sqlx::query!(
+        r#"
+        SELECT
+            v.label,
+            v.subject,
+            COUNT(l.id) AS "sends!: i64",
+            COUNT(l.opened_at) AS "opens!: i64",
+            COUNT(l.clicked_at) AS "clicks!: i64"
+        FROM subject_variants v
+        LEFT JOIN notification_log l ON l.subject_variant_id = v.id
+        WHERE v.experiment_id = ?
+        GROUP BY v.id
+        ORDER BY v.label ASC
+        "#,
+        experiment.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    println!("Experiment {}", experiment.name);
+    for variant in variants {
+        println!(
+            "{}  sends {:>5}  opens {:>5} ({:>6})  clicks {:>5} ({:>6})  {}",
+            variant.label,
+            variant.sends,
+            variant.opens,
+            rate(variant.opens, variant.sends),
+            variant.clicks,
+            rate(variant.clicks, variant.sends),
+            variant.subject
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiment(variant_count: usize) -> Experiment {
+        Experiment {
+            name: "test".to_string(),
+            variants: (0..variant_count)
+                .map(|index| Variant {
+                    id: index.to_string(),
+                    label: label(index),
+                    subject: format!("Subject {}", index),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_assign_uses_every_variant() {
+        let experiment = experiment(2);
+        let mut seen = [false; 2];
+        for _ in 0..200 {
+            let variant = experiment.assign().unwrap();
+            seen[variant.id.parse::<usize>().unwrap()] = true;
+        }
+        assert_eq!(seen, [true, true]);
+
+        assert!(self::experiment(0).assign().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_start_and_stop() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        let subjects = ["Flooding ahead".to_string(), "High tide".to_string()];
+        assert!(start(&pool, "one", &subjects[..1]).await.is_err());
+        start(&pool, "first", &subjects).await.unwrap();
+        assert!(start(&pool, "second", &subjects).await.is_err());
+
+        let running = active(&pool).await.unwrap().unwrap();
+        assert_eq!(running.name, "first");
+        let labels: Vec<&str> = running.variants.iter().map(|v| v.label.as_str()).collect();
+        assert_eq!(labels, ["A", "B"]);
+
+        stop(&pool).await.unwrap();
+        assert!(active(&pool).await.unwrap().is_none());
+        start(&pool, "second", &subjects).await.unwrap();
+    }
+
+    #[test]
+    fn test_label_and_rate() {
+        assert_eq!(label(0), "A");
+        assert_eq!(label(2), "C");
+        assert_eq!(rate(1, 4), "25.0%");
+        assert_eq!(rate(0, 0), "-");
+    }
+}
@@ -0,0 +1,107 @@
+use axum::Json;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Request};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use validator::{Validate, ValidationErrors};
+
+use crate::error::AppError;
+
+/// A JSON body that has been deserialized and passed its `validator` rules.
+/// Either failing is rejected with an `AppError`, listing the message for
+/// each invalid field when validation failed.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(json_rejection)?;
+        value.validate().map_err(validation_rejection)?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+fn json_rejection(rejection: JsonRejection) -> AppError {
+    AppError::Rejected(rejection.status(), rejection.body_text())
+}
+
+/// A 400 whose detail joins every field's messages, so clients that only
+/// read `error` still get something to show.
+fn validation_rejection(errors: ValidationErrors) -> AppError {
+    let fields = field_messages(&errors);
+    let message = fields
+        .values()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+    AppError::Invalid { message, fields }
+}
+
+fn field_messages(errors: &ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|error| match &error.message {
+                    Some(message) => message.to_string(),
+                    None => format!("{} is invalid ({})", field, error.code),
+                })
+                .collect();
+            (field.to_string(), messages)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SignUpRequest;
+    use axum::body::{Body, to_bytes};
+    use axum::http::header::CONTENT_TYPE;
+    use axum::response::IntoResponse;
+    use mill_valley_sausalito_bikepath_flood_alert::api_types::ErrorResponse;
+
+    async fn extract(body: &str) -> Result<SignUpRequest, ErrorResponse> {
+        let req = Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        match ValidatedJson::<SignUpRequest>::from_request(req, &()).await {
+            Ok(ValidatedJson(value)) => Ok(value),
+            Err(error) => {
+                let body = to_bytes(error.into_response().into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                Err(serde_json::from_slice(&body).unwrap())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validated_json() {
+        let request = extract(r#"{"email": "a@example.com"}"#).await.unwrap();
+        assert_eq!(request.email, "a@example.com");
+
+        let error = extract(r#"{"email": "not-an-email"}"#).await.unwrap_err();
+        assert_eq!(error.error, "Please provide a valid email address.");
+        assert_eq!(
+            error.fields["email"],
+            ["Please provide a valid email address."]
+        );
+
+        let error = extract(r#"{"website": ""}"#).await.unwrap_err();
+        assert!(error.error.contains("missing field `email`"));
+        assert!(error.fields.is_empty());
+    }
+}
@@ -0,0 +1,358 @@
+use askama::Template;
+use axum::Form;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::AppState;
+use crate::error::AppError;
+use crate::models::{FloodDisplay, UnsubscribeParams, User};
+use crate::tides::get_flood_tides_between;
+
+/// Days back the feedback page asks about floods for.
+const FEEDBACK_DAYS: i64 = 7;
+/// Days of answers `feedback` reports on by default.
+pub const DEFAULT_FEEDBACK_DAYS: i64 = 90;
+
+/// Link to the page for saying whether recent floods happened, signed like
+/// the unsubscribe link.
+pub fn feedback_link(base_url: &str, user: &User, secret: &str) -> String {
+    format!(
+        "{}/feedback?id={}&token={}",
+        base_url,
+        user.id,
+        user.generate_unsubscribe_token(secret)
+    )
+}
+
+/// A recent predicted flood and the subscriber's answer so far.
+pub struct FeedbackFlood {
+    pub prediction_time: DateTime<Utc>,
+    pub display: FloodDisplay,
+    pub flooded: Option<bool>,
+}
+
+#[derive(Template)]
+#[template(path = "feedback.html")]
+pub struct FeedbackTemplate {
+    pub user_id: String,
+    pub token: String,
+    pub floods: Vec<FeedbackFlood>,
+    pub feedback_days: i64,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Posted by each flood's buttons.
+#[derive(Deserialize)]
+pub struct FeedbackForm {
+    prediction_time: DateTime<Utc>,
+    flooded: bool,
+}
+
+fn render(status: StatusCode, template: FeedbackTemplate) -> Response {
+    match template.render() {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => AppError::from(e).into_response(),
+    }
+}
+
+fn error_page(params: UnsubscribeParams, status: StatusCode, error: &str) -> Response {
+    render(
+        status,
+        FeedbackTemplate {
+            user_id: params.id,
+            token: params.token,
+            floods: Vec::new(),
+            feedback_days: FEEDBACK_DAYS,
+            message: None,
+            error: Some(error.to_string()),
+        },
+    )
+}
+
+/// Lists the last week's predicted floods, asking whether the path flooded.
+pub async fn feedback_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UnsubscribeParams>,
+) -> Response {
+    feedback_page(&state, params, None).await
+}
+
+pub async fn record_feedback_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UnsubscribeParams>,
+    Form(form): Form<FeedbackForm>,
+) -> Response {
+    feedback_page(&state, params, Some(form)).await
+}
+
+async fn feedback_page(
+    state: &AppState,
+    params: UnsubscribeParams,
+    answer: Option<FeedbackForm>,
+) -> Response {
+    let user = User {
+        id: params.id.clone(),
+        ..Default::default()
+    };
+    if !user.verify_unsubscribe_token(&params.token, &state.unsubscribe_secret) {
+        return error_page(
+            params,
+            StatusCode::BAD_REQUEST,
+            "This link is invalid. Please use the link from your most recent email.",
+        );
+    }
+
+    let now = Utc::now();
+    let floods = match recent_floods(&state.pool, &params.id, now).await {
+        Ok(Some(floods)) => floods,
+        Ok(None) => {
+            return error_page(
+                params,
+                StatusCode::NOT_FOUND,
+                "This address isn't subscribed, so there's no feedback to give.",
+            );
+        }
+        Err(e) => {
+            eprintln!("Database error loading floods for feedback: {:?}", e);
+            return error_page(
+                params,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "An internal error occurred. Please try again later.",
+            );
+        }
+    };
+
+    let Some(answer) = answer else {
+        return render(
+            StatusCode::OK,
+            FeedbackTemplate {
+                user_id: params.id,
+                token: params.token,
+                floods,
+                feedback_days: FEEDBACK_DAYS,
+                message: None,
+                error: None,
+            },
+        );
+    };
+    // Only the floods on the page can be answered
+    if !floods
+        .iter()
+        .any(|flood| flood.prediction_time == answer.prediction_time)
+    {
+        return error_page(
+            params,
+            StatusCode::BAD_REQUEST,
+            "That flood can't be answered anymore. Please open the link from your most recent email again.",
+        );
+    }
+    if let Err(e) = record(&state.pool, &params.id, &answer).await {
+        eprintln!("Database error recording feedback: {:?}", e);
+        return error_page(
+            params,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An internal error occurred. Please try again later.",
+        );
+    }
+    let floods = floods
+        .into_iter()
+        .map(|flood| FeedbackFlood {
+            flooded: if flood.prediction_time == answer.prediction_time {
+                Some(answer.flooded)
+            } else {
+                flood.flooded
+            },
+            ..flood
+        })
+        .collect();
+    render(
+        StatusCode::OK,
+        FeedbackTemplate {
+            user_id: params.id,
+            token: params.token,
+            floods,
+            feedback_days: FEEDBACK_DAYS,
+            message: Some("Thanks, that helps keep the forecast accurate.".to_string()),
+            error: None,
+        },
+    )
+}
+
+/// The floods predicted in the last `FEEDBACK_DAYS`, newest first, with the
+/// subscriber's answers. None when they aren't subscribed.
+async fn recent_floods(
+    pool: &SqlitePool,
+    user_id: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<Vec<FeedbackFlood>>, AppError> {
+    let subscribed = sqlx::query_scalar!(
+        r#"SELECT id FROM users WHERE id = ? AND is_verified = 1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if subscribed.is_none() {
+        return Ok(None);
+    }
+    let since = now - Duration::days(FEEDBACK_DAYS);
+    let since_naive = since.naive_utc();
+    let answers: HashMap<DateTime<Utc>, bool> = sqlx::query!(
+        r#"
+        SELECT prediction_time AS "prediction_time: DateTime<Utc>", flooded AS "flooded: bool"
+        FROM flood_feedback
+        WHERE user_id = ? AND prediction_time >= ?
+        "#,
+        user_id,
+        since_naive
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|answer| (answer.prediction_time, answer.flooded))
+    .collect();
+    let floods = get_flood_tides_between(pool, since, now)
+        .await?
+        .into_iter()
+        .rev()
+        .map(|flood| FeedbackFlood {
+            prediction_time: flood.prediction_time,
+            display: FloodDisplay::new(flood.prediction_time, flood.height_ft),
+            flooded: answers.get(&flood.prediction_time).copied(),
+        })
+        .collect();
+    Ok(Some(floods))
+}
+
+async fn record(
+    pool: &SqlitePool,
+    user_id: &str,
+    answer: &FeedbackForm,
+) -> Result<(), sqlx::Error> {
+    // Stored as naive UTC like the tides it's joined against
+    let prediction_time = answer.prediction_time.naive_utc();
+    sqlx::query!(
+        r#"
+        INSERT INTO flood_feedback (user_id, prediction_time, flooded)
+        VALUES (?, ?, ?)
+        ON CONFLICT(user_id, prediction_time) DO UPDATE
+        SET flooded = excluded.flooded, created_at = CURRENT_TIMESTAMP
+        "#,
+        user_id,
+        prediction_time,
+        answer.flooded
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A predicted flood with how many subscribers said the path flooded and
+/// how many said it stayed dry.
+#[derive(Debug, PartialEq)]
+pub struct FloodAccuracy {
+    pub prediction_time: DateTime<Utc>,
+    pub height_ft: f64,
+    pub flooded: i64,
+    pub dry: i64,
+}
+
+/// Answers since `since` joined against the predictions they were about,
+/// newest first.
+pub async fn accuracy(
+    pool: &SqlitePool,
+    since: DateTime<Utc>,
+) -> Result<Vec<FloodAccuracy>, sqlx::Error> {
+    let since = since.naive_utc();
+    sqlx::query_as!(
+        FloodAccuracy,
+        r#"
+        SELECT
+            t.prediction_time AS "prediction_time!: DateTime<Utc>",
+            t.height_ft AS "height_ft!: f64",
+            COALESCE(SUM(f.flooded = 1), 0) AS "flooded!: i64",
+            COALESCE(SUM(f.flooded = 0), 0) AS "dry!: i64"
+        FROM flood_feedback f
+        JOIN tides t ON t.prediction_time = f.prediction_time
+        WHERE f.prediction_time >= ?
+        GROUP BY t.prediction_time, t.height_ft
+        ORDER BY t.prediction_time DESC
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Prints each flood with answers in the last `days` and how many of them
+/// most subscribers said really flooded.
+pub async fn print_report(pool: &SqlitePool, days: i64) -> Result<(), AppError> {
+    let floods = accuracy(pool, Utc::now() - Duration::days(days)).await?;
+    if floods.is_empty() {
+        println!("No feedback on floods in the last {} days", days);
+        return Ok(());
+    }
+    for flood in &floods {
+        let display = FloodDisplay::new(flood.prediction_time, flood.height_ft);
+        println!(
+            "{:<36}  {:>5} ft  flooded {:>4}  dry {:>4}",
+            display.datetime, display.height, flood.flooded, flood.dry
+        );
+    }
+    let confirmed = floods
+        .iter()
+        .filter(|flood| flood.flooded > flood.dry)
+        .count();
+    println!(
+        "\n{} of {} floods were confirmed by most answers",
+        confirmed,
+        floods.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_accuracy() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO tides (prediction_time, height_ft, tide_type)
+             VALUES ('2026-10-10 16:50:00', 6.9, 'High'), ('2026-10-12 18:00:00', 6.5, 'High')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let flood = "2026-10-10T16:50:00Z".parse::<DateTime<Utc>>().unwrap();
+        let answer = |flooded| FeedbackForm {
+            prediction_time: flood,
+            flooded,
+        };
+        record(&pool, "1", &answer(false)).await.unwrap();
+        // Changing an answer replaces it
+        record(&pool, "1", &answer(true)).await.unwrap();
+        record(&pool, "2", &answer(true)).await.unwrap();
+        record(&pool, "3", &answer(false)).await.unwrap();
+
+        let since = "2026-10-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            accuracy(&pool, since).await.unwrap(),
+            [FloodAccuracy {
+                prediction_time: flood,
+                height_ft: 6.9,
+                flooded: 2,
+                dry: 1,
+            }]
+        );
+    }
+}
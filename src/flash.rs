@@ -0,0 +1,105 @@
+use axum_extra::extract::cookie::{Cookie, SameSite, SignedCookieJar};
+
+const FLASH_COOKIE: &str = "flash";
+
+/// One-time message shown on the next page load after a redirect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flash {
+    pub success: bool,
+    pub message: String,
+}
+
+impl Flash {
+    pub fn success(message: impl Into<String>) -> Self {
+        Flash {
+            success: true,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Flash {
+            success: false,
+            message: message.into(),
+        }
+    }
+
+    /// Stores the message in a signed cookie so it can't be forged to show
+    /// arbitrary text on the homepage.
+    pub fn set(self, jar: SignedCookieJar) -> SignedCookieJar {
+        let cookie = Cookie::build((FLASH_COOKIE, self.encode()))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        jar.add(cookie)
+    }
+
+    /// Reads and clears the flash message, if any.
+    pub fn take(jar: SignedCookieJar) -> (SignedCookieJar, Option<Flash>) {
+        match jar.get(FLASH_COOKIE) {
+            Some(cookie) => {
+                let flash = Flash::decode(cookie.value());
+                (jar.remove(Cookie::build(FLASH_COOKIE).path("/")), flash)
+            }
+            None => (jar, None),
+        }
+    }
+
+    // The message is hex encoded since cookie values can't contain spaces
+    fn encode(&self) -> String {
+        format!(
+            "{}.{}",
+            if self.success { "s" } else { "e" },
+            hex::encode(&self.message)
+        )
+    }
+
+    fn decode(value: &str) -> Option<Flash> {
+        let (kind, message) = value.split_once('.')?;
+        let message = String::from_utf8(hex::decode(message).ok()?).ok()?;
+        match kind {
+            "s" => Some(Flash::success(message)),
+            "e" => Some(Flash::error(message)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use axum::http::header::{COOKIE, SET_COOKIE};
+    use axum::response::IntoResponse;
+    use axum_extra::extract::cookie::Key;
+
+    #[test]
+    fn test_flash_encode_decode() {
+        let flash = Flash::success("Check your email; then verify!");
+        assert_eq!(Flash::decode(&flash.encode()), Some(flash));
+
+        let flash = Flash::error("Please provide a valid email address.");
+        assert_eq!(Flash::decode(&flash.encode()), Some(flash));
+
+        assert_eq!(Flash::decode("x.00"), None);
+        assert_eq!(Flash::decode("s.not-hex"), None);
+    }
+
+    #[test]
+    fn test_flash_round_trip_through_signed_cookie() {
+        let key = Key::generate();
+        let jar = Flash::success("Verification email sent!").set(SignedCookieJar::new(key.clone()));
+        let response = jar.into_response();
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, cookie.parse().unwrap());
+        let (_, flash) = Flash::take(SignedCookieJar::from_headers(&headers, key));
+        assert_eq!(flash, Some(Flash::success("Verification email sent!")));
+
+        // A cookie signed with a different key is ignored
+        let (_, flash) = Flash::take(SignedCookieJar::from_headers(&headers, Key::generate()));
+        assert_eq!(flash, None);
+    }
+}
@@ -0,0 +1,245 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use crate::tides::{self, FloodTide, flood_threshold};
+
+/// The path starts flooding before high tide and drains after it, so each
+/// flood is taken to last this many minutes either side of the peak.
+pub const FLOOD_MARGIN_MINUTES: i64 = 60;
+
+/// A flood starting within this many hours is `upcoming`.
+const UPCOMING_HOURS: i64 = 24;
+
+/// Flood tides less than this far apart belong to the same event. High tides
+/// come about every 12.5 hours, so this joins floods on consecutive days.
+const EVENT_GAP_HOURS: i64 = 30;
+
+/// How far above the flood threshold the peak of an event is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Up to 0.3 ft over, puddles across the path
+    Minor,
+    /// Up to 0.6 ft over
+    Moderate,
+    Major,
+}
+
+impl Severity {
+    pub fn from_height(height_ft: f64) -> Self {
+        let over = height_ft - flood_threshold();
+        if over < 0.3 {
+            Severity::Minor
+        } else if over < 0.6 {
+            Severity::Moderate
+        } else {
+            Severity::Major
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Minor => "minor",
+            Severity::Moderate => "moderate",
+            Severity::Major => "major",
+        }
+    }
+}
+
+/// Whether the path is flooded right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodStatus {
+    Flooding,
+    /// A flood starts within the next day
+    Upcoming,
+    Clear,
+}
+
+impl FloodStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FloodStatus::Flooding => "flooding",
+            FloodStatus::Upcoming => "upcoming",
+            FloodStatus::Clear => "clear",
+        }
+    }
+}
+
+/// The flood in progress or next to start at `now`, and the status of the
+/// path. `tides` are sorted by time.
+pub fn next_flood(tides: &[FloodTide], now: DateTime<Utc>) -> (FloodStatus, Option<&FloodTide>) {
+    let margin = Duration::minutes(FLOOD_MARGIN_MINUTES);
+    let Some(next) = tides
+        .iter()
+        .find(|tide| tide.prediction_time + margin >= now)
+    else {
+        return (FloodStatus::Clear, None);
+    };
+    let starts = next.prediction_time - margin;
+    let status = if starts <= now {
+        FloodStatus::Flooding
+    } else if starts - now <= Duration::hours(UPCOMING_HOURS) {
+        FloodStatus::Upcoming
+    } else {
+        FloodStatus::Clear
+    };
+    (status, Some(next))
+}
+
+/// The flood tides on one Pacific day of an event.
+pub struct FloodDay {
+    pub date: NaiveDate,
+    pub peak_height_ft: f64,
+    pub tides: Vec<FloodTide>,
+}
+
+/// A run of flooding high tides on consecutive days, such as a king tide
+/// series.
+pub struct FloodEvent {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub peak_time: DateTime<Utc>,
+    pub peak_height_ft: f64,
+    pub severity: Severity,
+    pub days: Vec<FloodDay>,
+}
+
+/// Groups flood tides, sorted by time, into events.
+pub fn group_flood_events(tides: Vec<FloodTide>) -> Vec<FloodEvent> {
+    let mut groups: Vec<Vec<FloodTide>> = Vec::new();
+    for tide in tides {
+        match groups.last_mut() {
+            Some(group)
+                if tide.prediction_time - group.last().unwrap().prediction_time
+                    <= Duration::hours(EVENT_GAP_HOURS) =>
+            {
+                group.push(tide)
+            }
+            _ => groups.push(vec![tide]),
+        }
+    }
+    groups.into_iter().map(flood_event).collect()
+}
+
+fn flood_event(tides: Vec<FloodTide>) -> FloodEvent {
+    let peak = tides
+        .iter()
+        .max_by(|a, b| a.height_ft.total_cmp(&b.height_ft))
+        .unwrap();
+    let (start, end) = (
+        tides.first().unwrap().prediction_time,
+        tides.last().unwrap().prediction_time,
+    );
+    let (peak_time, peak_height_ft) = (peak.prediction_time, peak.height_ft);
+
+    let mut days: Vec<FloodDay> = Vec::new();
+    for tide in tides {
+        let date = tides::local(tide.prediction_time).date();
+        match days.last_mut() {
+            Some(day) if day.date == date => {
+                day.peak_height_ft = day.peak_height_ft.max(tide.height_ft);
+                day.tides.push(tide);
+            }
+            _ => days.push(FloodDay {
+                date,
+                peak_height_ft: tide.height_ft,
+                tides: vec![tide],
+            }),
+        }
+    }
+
+    FloodEvent {
+        start,
+        end,
+        peak_time,
+        peak_height_ft,
+        severity: Severity::from_height(peak_height_ft),
+        days,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    /// A flood tide at Pacific time `time`
+    fn tide(time: &str, height_ft: f64) -> FloodTide {
+        FloodTide {
+            prediction_time: tides::from_local(
+                NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(),
+            ),
+            height_ft,
+        }
+    }
+
+    #[test]
+    fn test_group_flood_events() {
+        let events = group_flood_events(vec![
+            tide("2026-12-12 08:10", 6.5),
+            tide("2026-12-13 08:50", 7.1),
+            tide("2026-12-13 21:20", 6.6),
+            tide("2026-12-14 09:35", 6.8),
+            // Two weeks later is a separate event
+            tide("2026-12-28 08:00", 6.5),
+        ]);
+
+        assert_eq!(events.len(), 2);
+        let king_tides = &events[0];
+        assert_eq!(king_tides.days.len(), 3);
+        assert_eq!(king_tides.days[1].tides.len(), 2);
+        assert_eq!(king_tides.days[1].peak_height_ft, 7.1);
+        assert_eq!(
+            king_tides.peak_time,
+            tide("2026-12-13 08:50", 0.0).prediction_time
+        );
+        assert_eq!(king_tides.severity, Severity::Major);
+        assert_eq!(
+            king_tides.end,
+            tide("2026-12-14 09:35", 0.0).prediction_time
+        );
+
+        assert_eq!(events[1].severity, Severity::Minor);
+        assert!(group_flood_events(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_next_flood() {
+        let tides = [tide("2026-12-13 08:50", 7.1), tide("2026-12-14 09:35", 6.8)];
+        let at = |time| tide(time, 0.0).prediction_time;
+
+        let (status, flood) = next_flood(&tides, at("2026-12-12 07:00"));
+        assert_eq!(status, FloodStatus::Clear);
+        assert_eq!(flood.unwrap().height_ft, 7.1);
+        assert_eq!(
+            next_flood(&tides, at("2026-12-13 07:00")).0,
+            FloodStatus::Upcoming
+        );
+        // Still flooding just after the peak
+        let (status, flood) = next_flood(&tides, at("2026-12-13 09:30"));
+        assert_eq!(status, FloodStatus::Flooding);
+        assert_eq!(flood.unwrap().height_ft, 7.1);
+        assert_eq!(
+            next_flood(&tides, at("2026-12-13 10:00"))
+                .1
+                .unwrap()
+                .height_ft,
+            6.8
+        );
+        assert!(matches!(
+            next_flood(&tides, at("2026-12-15 10:00")),
+            (FloodStatus::Clear, None)
+        ));
+    }
+
+    #[test]
+    fn test_severity() {
+        assert_eq!(Severity::from_height(flood_threshold()), Severity::Minor);
+        assert_eq!(
+            Severity::from_height(flood_threshold() + 0.4),
+            Severity::Moderate
+        );
+        assert_eq!(
+            Severity::from_height(flood_threshold() + 0.7),
+            Severity::Major
+        );
+    }
+}
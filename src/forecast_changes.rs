@@ -0,0 +1,280 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::sqlite::SqlitePool;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+
+use crate::AppState;
+use crate::error::AppError;
+use crate::feedback::feedback_link;
+use crate::mail::{self, NotificationLinks};
+use crate::models::{FloodDisplay, User};
+use crate::reminders::reminders_link;
+use crate::tides::{self, FORECAST_DAYS, FloodTide, Tide};
+
+/// Predictions of the same high tide can move by this much between syncs.
+const SAME_TIDE_MINUTES: i64 = 90;
+
+/// A flood the sync added to or removed from the forecast.
+pub enum ForecastChange {
+    /// A flood that wasn't forecast before
+    Appeared(FloodTide),
+    /// A flood that's no longer forecast, with the tide's new height when
+    /// NOAA still predicts it
+    Cancelled {
+        flood: FloodTide,
+        height_ft: Option<f64>,
+    },
+}
+
+impl fmt::Display for ForecastChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForecastChange::Appeared(flood) => {
+                let display = FloodDisplay::new(flood.prediction_time, flood.height_ft);
+                write!(f, "New flood {}, {} ft", display.datetime, display.height)
+            }
+            ForecastChange::Cancelled { flood, height_ft } => {
+                let display = FloodDisplay::new(flood.prediction_time, flood.height_ft);
+                write!(
+                    f,
+                    "No longer a flood {}, was {} ft",
+                    display.datetime, display.height
+                )?;
+                match height_ft {
+                    Some(height_ft) => write!(f, " and is now {:.2} ft", height_ft),
+                    None => write!(f, " and is no longer predicted"),
+                }
+            }
+        }
+    }
+}
+
+fn same_tide(a: DateTime<Utc>, b: DateTime<Utc>) -> bool {
+    (a - b).abs() <= Duration::minutes(SAME_TIDE_MINUTES)
+}
+
+/// The height now predicted for `flood`'s high tide, if there still is one.
+fn new_height(flood: &FloodTide, tides: &[Tide]) -> Option<f64> {
+    tides
+        .iter()
+        .filter(|tide| tide.tide_type.as_deref() == Some("High"))
+        .find(|tide| same_tide(flood.prediction_time, tide.prediction_time))
+        .map(|tide| tide.height_ft)
+}
+
+/// How the floods in `after` differ from those in `before`, given every
+/// tide in `after`'s window to find what became of a cancelled flood.
+fn diff(before: &[FloodTide], after: &[FloodTide], tides: &[Tide]) -> Vec<ForecastChange> {
+    let mut changes: Vec<ForecastChange> = before
+        .iter()
+        .filter(|flood| {
+            !after
+                .iter()
+                .any(|other| same_tide(flood.prediction_time, other.prediction_time))
+        })
+        .map(|flood| ForecastChange::Cancelled {
+            flood: flood.clone(),
+            height_ft: new_height(flood, tides),
+        })
+        .collect();
+    changes.extend(
+        after
+            .iter()
+            .filter(|flood| {
+                !before
+                    .iter()
+                    .any(|other| same_tide(flood.prediction_time, other.prediction_time))
+            })
+            .map(|flood| ForecastChange::Appeared(flood.clone())),
+    );
+    changes
+}
+
+/// Whether `NOTIFY_CANCELLED_FLOODS` is on, emailing a correction to
+/// everyone told about a flood that's no longer forecast.
+fn corrections_enabled() -> bool {
+    env::var("NOTIFY_CANCELLED_FLOODS").is_ok_and(|value| value.trim() == "true")
+}
+
+/// Logs how the sync changed the forecast, given the flood tides from
+/// before it, and sends any corrections. Errors are logged rather than
+/// failing the sync.
+pub async fn report(pool: &SqlitePool, before: &[FloodTide]) {
+    let now = Utc::now();
+    let end = now + Duration::days(FORECAST_DAYS);
+    let after = tides::get_flood_tides_between(pool, now, end).await;
+    let tides = tides::get_tides_between(pool, now, end).await;
+    let (after, tides) = match (after, tides) {
+        (Ok(after), Ok(tides)) => (after, tides),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Error comparing the forecast: {}", e);
+            return;
+        }
+    };
+    let changes = diff(before, &after, &tides);
+    for change in &changes {
+        println!("Forecast changed: {}", change);
+    }
+    let cancelled: Vec<&FloodTide> = changes
+        .iter()
+        .filter_map(|change| match change {
+            ForecastChange::Cancelled { flood, .. } if flood.prediction_time > now => Some(flood),
+            _ => None,
+        })
+        .collect();
+    if cancelled.is_empty() || !corrections_enabled() {
+        return;
+    }
+    if let Err(e) = send_corrections(pool, &cancelled, &tides).await {
+        eprintln!("Error sending flood corrections: {}", e);
+    }
+}
+
+/// The subscribers whose last notification covered a cancelled flood, with
+/// the floods each was told about.
+async fn recipients(
+    pool: &SqlitePool,
+    cancelled: &[&FloodTide],
+) -> Result<Vec<(User, Vec<FloodTide>)>, sqlx::Error> {
+    let mut recipients: BTreeMap<String, (User, Vec<FloodTide>)> = BTreeMap::new();
+    for flood in cancelled {
+        // Bound as naive UTC to match the stored format, which sorts as text
+        let time = flood.prediction_time.naive_utc();
+        let users = sqlx::query!(
+            r#"
+            SELECT id, email, email_layout, lang, clock_24h AS "clock_24h: bool" FROM users
+            WHERE is_verified = 1 AND is_subscribed = 1 AND wants_email = 1
+                AND last_window_start <= ? AND last_window_end >= ?
+            "#,
+            time,
+            time
+        )
+        .fetch_all(pool)
+        .await?;
+        for record in users {
+            let user = User {
+                id: record.id.clone(),
+                email: record.email,
+                email_layout: record.email_layout,
+                lang: record.lang,
+                clock_24h: record.clock_24h,
+                ..Default::default()
+            };
+            recipients
+                .entry(record.id)
+                .or_insert_with(|| (user, Vec::new()))
+                .1
+                .push((*flood).clone());
+        }
+    }
+    Ok(recipients.into_values().collect())
+}
+
+async fn send_corrections(
+    pool: &SqlitePool,
+    cancelled: &[&FloodTide],
+    tides: &[Tide],
+) -> Result<(), AppError> {
+    let recipients = recipients(pool, cancelled).await?;
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    let app_state = AppState::from_pool(pool.clone());
+    for (user, floods) in &recipients {
+        // Shown with their new heights, or none when NOAA dropped the tide
+        let predictions: Vec<FloodDisplay> = floods
+            .iter()
+            .map(|flood| {
+                let height = new_height(flood, tides);
+                let mut display = FloodDisplay::localized(
+                    flood.prediction_time,
+                    height.unwrap_or(0.0),
+                    user.date_format(),
+                );
+                if height.is_none() {
+                    display.height.clear();
+                }
+                display
+            })
+            .collect();
+        let links = NotificationLinks {
+            homepage: app_state.base_url.clone(),
+            reminders: reminders_link(&app_state.base_url, user, &app_state.unsubscribe_secret),
+            feedback: feedback_link(&app_state.base_url, user, &app_state.unsubscribe_secret),
+            unsubscribe: format!(
+                "{}/unsubscribe?id={}&token={}",
+                app_state.base_url,
+                user.id,
+                user.generate_unsubscribe_token(&app_state.unsubscribe_secret)
+            ),
+            pixel: None,
+        };
+        match app_state
+            .mailer
+            .send_correction_email(user, &predictions, &links)
+            .await
+        {
+            Ok(()) => println!(
+                "Told {} that {} floods are no longer forecast",
+                user.email,
+                floods.len()
+            ),
+            // The next notify run records the bounce and suppresses the address
+            Err(e) if mail::is_bounce(&e) => {
+                eprintln!("Correction to {} bounced: {}", user.email, e);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_diff() {
+        let time = |day, hour, minute| {
+            Utc.with_ymd_and_hms(2026, 11, day, hour, minute, 0)
+                .unwrap()
+        };
+        let flood = |prediction_time, height_ft| FloodTide {
+            prediction_time,
+            height_ft,
+        };
+        let high = |prediction_time, height_ft| Tide {
+            prediction_time,
+            height_ft,
+            tide_type: Some("High".to_string()),
+        };
+        let before = [flood(time(3, 8, 10), 6.6), flood(time(4, 9, 0), 6.5)];
+        // The first tide moved a few minutes, the second dropped under the
+        // threshold and a third crossed it
+        let after = [flood(time(3, 8, 20), 6.7), flood(time(5, 9, 40), 6.5)];
+        let tides = [
+            high(time(3, 8, 20), 6.7),
+            high(time(4, 9, 5), 6.3),
+            high(time(5, 9, 40), 6.5),
+        ];
+
+        let changes = diff(&before, &after, &tides);
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(
+            &changes[0],
+            ForecastChange::Cancelled { flood, height_ft: Some(6.3) }
+                if flood.prediction_time == time(4, 9, 0)
+        ));
+        assert!(matches!(
+            &changes[1],
+            ForecastChange::Appeared(flood) if flood.prediction_time == time(5, 9, 40)
+        ));
+        assert_eq!(
+            changes[0].to_string(),
+            "No longer a flood Wednesday, November 4 at 1:00AM, was 6.50 ft and is now 6.30 ft"
+        );
+        assert!(diff(&before, &before, &tides).is_empty());
+    }
+}
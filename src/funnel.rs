@@ -0,0 +1,148 @@
+use sqlx::sqlite::SqlitePool;
+
+use crate::error::AppError;
+
+/// Weeks of history shown by default.
+pub const DEFAULT_FUNNEL_WEEKS: i64 = 12;
+
+/// Signups in a week and how many of them verified within a day and within
+/// a week. A low 24 hour rate is a sign verification emails are going to spam.
+#[derive(Debug)]
+pub struct FunnelWeek {
+    /// Monday the week starts on
+    pub week: String,
+    pub signups: i64,
+    pub verified_24h: i64,
+    pub verified_7d: i64,
+}
+
+impl FunnelWeek {
+    pub fn rate_24h(&self) -> Option<f64> {
+        rate(self.verified_24h, self.signups)
+    }
+
+    pub fn rate_7d(&self) -> Option<f64> {
+        rate(self.verified_7d, self.signups)
+    }
+}
+
+fn rate(count: i64, total: i64) -> Option<f64> {
+    (total > 0).then(|| count as f64 / total as f64)
+}
+
+/// Built from the events table rather than users, so addresses that have
+/// since unsubscribed still count. The latest week's 7 day numbers are
+/// incomplete until a week has passed.
+pub async fn verification_funnel(
+    pool: &SqlitePool,
+    weeks: i64,
+) -> Result<Vec<FunnelWeek>, sqlx::Error> {
+    sqlx::query_as!(
+        FunnelWeek,
+        r#"
+        SELECT
+            date(s.created_at, 'weekday 0', '-6 days') AS "week!: String",
+            COUNT(*) AS "signups!: i64",
+            COALESCE(SUM(EXISTS (
+                SELECT 1 FROM events v
+                WHERE v.user_id = s.user_id AND v.event_type = 'verification'
+                    AND v.created_at >= s.created_at
+                    AND v.created_at <= datetime(s.created_at, '+1 day')
+            )), 0) AS "verified_24h!: i64",
+            COALESCE(SUM(EXISTS (
+                SELECT 1 FROM events v
+                WHERE v.user_id = s.user_id AND v.event_type = 'verification'
+                    AND v.created_at >= s.created_at
+                    AND v.created_at <= datetime(s.created_at, '+7 days')
+            )), 0) AS "verified_7d!: i64"
+        FROM events s
+        WHERE s.event_type IN ('signup', 'resubscribe')
+            AND s.created_at >= date('now', 'weekday 0', '-6 days', printf('-%d days', (? - 1) * 7))
+        GROUP BY 1
+        ORDER BY 1 DESC
+        "#,
+        weeks
+    )
+    .fetch_all(pool)
+    .await
+}
+
+fn format_rate(rate: Option<f64>) -> String {
+    rate.map(|r| format!("{:.0}%", r * 100.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub async fn print_report(pool: &SqlitePool, weeks: i64) -> Result<(), AppError> {
+    let funnel = verification_funnel(pool, weeks).await?;
+    if funnel.is_empty() {
+        println!("No signups in the last {} weeks", weeks);
+    }
+    for week in funnel {
+        println!(
+            "Week of {}  signups {:>4}  verified in 24h {:>4} ({:>4})  in 7d {:>4} ({:>4})",
+            week.week,
+            week.signups,
+            week.verified_24h,
+            format_rate(week.rate_24h()),
+            week.verified_7d,
+            format_rate(week.rate_7d())
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn event(pool: &SqlitePool, user_id: &str, event_type: &str, age: &str) {
+        sqlx::query(
+            "INSERT INTO events (user_id, email, event_type, source, created_at)
+             VALUES (?, 'a@example.com', ?, 'web', datetime('now', ?))",
+        )
+        .bind(user_id)
+        .bind(event_type)
+        .bind(age)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verification_funnel() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        // Verified within the hour, after three days, and never
+        event(&pool, "1", "signup", "-10 days").await;
+        event(&pool, "1", "verification", "-10 days").await;
+        event(&pool, "2", "signup", "-10 days").await;
+        event(&pool, "2", "verification", "-7 days").await;
+        event(&pool, "3", "resubscribe", "-10 days").await;
+        // Too old to be included
+        event(&pool, "4", "signup", "-200 days").await;
+
+        let funnel = verification_funnel(&pool, 4).await.unwrap();
+        let (signups, in_24h, in_7d) = funnel.iter().fold((0, 0, 0), |acc, week| {
+            (
+                acc.0 + week.signups,
+                acc.1 + week.verified_24h,
+                acc.2 + week.verified_7d,
+            )
+        });
+        assert_eq!((signups, in_24h, in_7d), (3, 1, 2));
+    }
+
+    #[test]
+    fn test_rates() {
+        let week = FunnelWeek {
+            week: "2026-10-12".to_string(),
+            signups: 4,
+            verified_24h: 1,
+            verified_7d: 3,
+        };
+        assert_eq!(week.rate_24h(), Some(0.25));
+        assert_eq!(format_rate(week.rate_7d()), "75%");
+        assert_eq!(format_rate(rate(0, 0)), "-");
+    }
+}
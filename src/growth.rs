@@ -0,0 +1,210 @@
+use chrono::{Datelike, Days, NaiveDate};
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+
+/// Days of history in the daily series by default.
+pub const DEFAULT_GROWTH_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Day,
+    /// Starting on Monday, like the verification funnel's
+    Week,
+}
+
+impl Interval {
+    fn as_str(self) -> &'static str {
+        match self {
+            Interval::Day => "day",
+            Interval::Week => "week",
+        }
+    }
+
+    /// Start of the period `day` falls in.
+    fn start(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Interval::Day => day,
+            Interval::Week => day - Days::new(day.weekday().num_days_from_monday().into()),
+        }
+    }
+
+    fn length(self) -> Days {
+        match self {
+            Interval::Day => Days::new(1),
+            Interval::Week => Days::new(7),
+        }
+    }
+}
+
+/// Subscribers gained and lost in a day or week.
+#[derive(Debug, PartialEq)]
+pub struct GrowthPeriod {
+    pub start: NaiveDate,
+    /// Addresses that verified, including ones coming back
+    pub gained: i64,
+    /// Unsubscribes from everything and suppressions of verified addresses.
+    /// Stopping just the emails isn't leaving.
+    pub lost: i64,
+}
+
+impl GrowthPeriod {
+    pub fn net(&self) -> i64 {
+        self.gained - self.lost
+    }
+
+    /// Heights of the bars in the dashboard's chart, as a percentage of the
+    /// largest gain or loss in `growth`.
+    pub fn gained_percent(&self, growth: &[GrowthPeriod]) -> i64 {
+        bar_percent(self.gained, growth)
+    }
+
+    pub fn lost_percent(&self, growth: &[GrowthPeriod]) -> i64 {
+        bar_percent(self.lost, growth)
+    }
+}
+
+/// The last `periods` days or weeks up to `today`, oldest first, from the
+/// events table so addresses that have since left still count. Periods
+/// without any changes are included with zeros.
+pub async fn subscriber_growth(
+    pool: &SqlitePool,
+    interval: Interval,
+    periods: i64,
+    today: NaiveDate,
+) -> Result<Vec<GrowthPeriod>, sqlx::Error> {
+    let last = interval.start(today);
+    let first = (1..periods).fold(last, |start, _| start - interval.length());
+    let interval_name = interval.as_str();
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            CASE WHEN ?1 = 'week' THEN date(e.created_at, 'weekday 0', '-6 days')
+                ELSE date(e.created_at) END AS "start!: NaiveDate",
+            COALESCE(SUM(e.event_type = 'verification'), 0) AS "gained!: i64",
+            COALESCE(SUM(
+                (e.event_type = 'unsubscribe' AND e.detail IS NOT 'email')
+                OR (e.event_type = 'suppression' AND EXISTS (
+                    SELECT 1 FROM events v
+                    WHERE v.user_id = e.user_id AND v.event_type = 'verification'
+                        AND v.created_at <= e.created_at
+                ))
+            ), 0) AS "lost!: i64"
+        FROM events e
+        WHERE e.event_type IN ('verification', 'unsubscribe', 'suppression')
+            AND e.created_at >= ?2
+        GROUP BY 1
+        "#,
+        interval_name,
+        first
+    )
+    .fetch_all(pool)
+    .await?;
+    let counts: HashMap<NaiveDate, (i64, i64)> = rows
+        .into_iter()
+        .map(|row| (row.start, (row.gained, row.lost)))
+        .collect();
+
+    let mut growth = Vec::new();
+    let mut start = first;
+    while start <= last {
+        let (gained, lost) = counts.get(&start).copied().unwrap_or_default();
+        growth.push(GrowthPeriod {
+            start,
+            gained,
+            lost,
+        });
+        start = start + interval.length();
+    }
+    Ok(growth)
+}
+
+fn bar_percent(count: i64, growth: &[GrowthPeriod]) -> i64 {
+    let largest = growth
+        .iter()
+        .map(|period| period.gained.max(period.lost))
+        .max()
+        .unwrap_or(0);
+    if largest == 0 {
+        0
+    } else {
+        count * 100 / largest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn event(
+        pool: &SqlitePool,
+        user_id: &str,
+        event_type: &str,
+        detail: Option<&str>,
+        at: &str,
+    ) {
+        sqlx::query(
+            "INSERT INTO events (user_id, email, event_type, source, detail, created_at)
+             VALUES (?, 'a@example.com', ?, 'web', ?, ?)",
+        )
+        .bind(user_id)
+        .bind(event_type)
+        .bind(detail)
+        .bind(at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_growth() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        // Thursday
+        let today = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+
+        event(&pool, "1", "signup", None, "2026-10-05 09:00:00").await;
+        event(&pool, "1", "verification", None, "2026-10-05 09:05:00").await;
+        event(&pool, "2", "verification", None, "2026-10-13 10:00:00").await;
+        event(&pool, "3", "verification", None, "2026-10-13 11:00:00").await;
+        event(
+            &pool,
+            "2",
+            "unsubscribe",
+            Some("email"),
+            "2026-10-14 08:00:00",
+        )
+        .await;
+        event(&pool, "1", "unsubscribe", None, "2026-10-15 08:00:00").await;
+        event(&pool, "3", "suppression", None, "2026-10-15 09:00:00").await;
+        // Never verified, so was never a subscriber
+        event(&pool, "4", "suppression", None, "2026-10-15 10:00:00").await;
+        // Before the series
+        event(&pool, "5", "verification", None, "2026-08-01 10:00:00").await;
+
+        let weekly = subscriber_growth(&pool, Interval::Week, 3, today)
+            .await
+            .unwrap();
+        let weeks: Vec<(String, i64, i64)> = weekly
+            .iter()
+            .map(|week| (week.start.to_string(), week.gained, week.lost))
+            .collect();
+        assert_eq!(
+            weeks,
+            [
+                ("2026-09-28".to_string(), 0, 0),
+                ("2026-10-05".to_string(), 1, 0),
+                ("2026-10-12".to_string(), 2, 2),
+            ]
+        );
+
+        let daily = subscriber_growth(&pool, Interval::Day, 3, today)
+            .await
+            .unwrap();
+        let nets: Vec<i64> = daily.iter().map(GrowthPeriod::net).collect();
+        assert_eq!(nets, [2, 0, -2]);
+        assert_eq!(daily[0].gained_percent(&daily), 100);
+        assert_eq!(daily[2].lost_percent(&daily), 100);
+        assert_eq!(bar_percent(1, &daily), 50);
+        assert_eq!(bar_percent(0, &[]), 0);
+    }
+}
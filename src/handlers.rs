@@ -1,143 +1,292 @@
 use askama::Template;
-use axum::response::{Html, IntoResponse};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::{
-    Json,
-    extract::{Query, State},
-    http::{Method, StatusCode},
+    Form,
+    extract::{FromRequest, Query, Request, State},
+    http::{HeaderMap, Method, StatusCode, header::CONTENT_TYPE},
 };
+use axum_extra::extract::cookie::SignedCookieJar;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use std::path::Path as FsPath;
 use std::sync::Arc;
 use validator::Validate;
 
 use crate::AppState;
-use crate::models::{FloodDisplay, SignUpRequest, UnsubscribeParams, User, VerifyParams};
-use crate::tides::{FLOOD_THRESHOLD_FT, FORECAST_DAYS, get_flood_predictions};
+use crate::api::LEGACY_JSON_SIGNUP;
+use crate::attribution::normalize_source;
+use crate::channels;
+use crate::error::AppError;
+use crate::events::EventSource;
+use crate::extract::ValidatedJson;
+use crate::flash::Flash;
+use crate::honeypot::issue_form_token;
+use crate::i18n::{Locale, Strings};
+use crate::models::{
+    FloodDisplay, HomeParams, SignUpRequest, UnsubscribeParams, UnsubscribeScope,
+    VerifyCodeRequest, VerifyParams, is_verification_code,
+};
+use crate::pages::{self, Page};
+use crate::services::{SignupService, UnsubscribeService, VerificationService};
+use crate::tides::{self, FORECAST_DAYS, FloodTide};
+use crate::{cleanup, contact, render, snapshot};
 
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct IndexTemplate {
+    pub t: &'static Strings,
     pub predictions: Vec<FloodDisplay>,
     pub forecast_days: i64,
-    pub flood_threshold: f64,
+    pub form_token: String,
+    pub flash: Option<Flash>,
+    pub signup_source: Option<String>,
+    /// When the forecast was taken, for the static export made by `render`,
+    /// which has no signup form or live refresh
+    pub static_snapshot: Option<String>,
+    /// When the snapshot shown was taken, if the database couldn't be read
+    pub stale_since: Option<String>,
+    /// When NOAA sent the predictions shown, if that was a while ago
+    pub outdated_since: Option<String>,
+    /// The bias correction in the heights, like `+0.30`, if one is applied
+    pub adjustment: Option<String>,
+    /// Whether `PUSHOVER_APP_TOKEN` is set, so subscribers can add a user key
+    pub pushover_enabled: bool,
+    /// Whether a Signal gateway is set up, so subscribers can add a number
+    pub signal_enabled: bool,
+    /// Whether `CONTACT_FORWARD_TO` is set, so the footer links the contact form
+    pub contact_enabled: bool,
+    /// Content pages linked from the footer
+    pub pages: Vec<Page>,
+}
+
+/// Request body that is either JSON (from the signup script) or a plain
+/// urlencoded form post (browsers without JavaScript). JSON is validated like
+/// `ValidatedJson`, while forms are validated by the handler so errors can be
+/// shown on the page.
+pub enum JsonOrForm<T> {
+    Json(T),
+    Form(T),
 }
 
-pub async fn home_handler(State(state): State<Arc<AppState>>) -> impl axum::response::IntoResponse {
-    let predictions = match get_flood_predictions(&state.pool, FORECAST_DAYS).await {
-        Ok(preds) => preds,
+impl<T, S> FromRequest<S> for JsonOrForm<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+        if is_form {
+            let Form(payload) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(|rejection| {
+                    AppError::Rejected(rejection.status(), rejection.body_text())
+                })?;
+            Ok(JsonOrForm::Form(payload))
+        } else {
+            let ValidatedJson(payload) = ValidatedJson::<T>::from_request(req, state).await?;
+            Ok(JsonOrForm::Json(payload))
+        }
+    }
+}
+
+pub async fn home_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HomeParams>,
+    locale: Locale,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (jar, flash) = Flash::take(SignedCookieJar::from_headers(
+        &headers,
+        state.cookie_key.clone(),
+    ));
+
+    let (predictions, stale) = match snapshot::flood_tides(&state, FORECAST_DAYS).await {
+        Ok((tides, stale)) => (flood_displays(tides), stale),
         Err(e) => {
             eprintln!("Error fetching predictions: {}", e);
-            Vec::new()
+            (Vec::new(), None)
         }
     };
 
     let template = IndexTemplate {
+        t: locale.strings(),
         predictions,
         forecast_days: FORECAST_DAYS,
-        flood_threshold: FLOOD_THRESHOLD_FT,
+        form_token: issue_form_token(&state.unsubscribe_secret),
+        flash,
+        signup_source: params
+            .source
+            .or(params.utm_source)
+            .as_deref()
+            .and_then(normalize_source),
+        static_snapshot: None,
+        stale_since: stale.map(|stale| render::snapshot_time(stale.0)),
+        outdated_since: state
+            .tide_snapshot
+            .outdated_since(Utc::now())
+            .map(render::snapshot_time),
+        adjustment: adjustment(),
+        pushover_enabled: channels::pushover_app_token().is_some(),
+        signal_enabled: channels::SignalGateway::from_env().is_some(),
+        contact_enabled: contact::forward_address().is_some(),
+        pages: pages::list(FsPath::new(pages::CONTENT_DIR)).unwrap_or_else(|e| {
+            eprintln!("Error listing pages: {}", e);
+            Vec::new()
+        }),
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(_) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Template Error",
-        )
-            .into_response(),
-    }
+    Ok((locale, jar, Html(template.render()?)).into_response())
 }
 
 pub async fn sign_up_handler(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<SignUpRequest>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    if payload.validate().is_err() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Please provide a valid email address.".to_string(),
-        ));
-    }
-
-    let user = User::new(payload.email);
-
-    let result = sqlx::query!(
-        r#"
-        INSERT INTO users (id, email, is_verified, verification_token, is_subscribed)
-        VALUES (?, ?, ?, ?, ?)
-        ON CONFLICT(email) DO UPDATE
-        SET verification_token = excluded.verification_token, is_verified = 0, is_subscribed = 0
-        WHERE users.is_verified = 0 OR users.is_subscribed = 0
-        RETURNING id;
-        "#,
-        user.id,
-        user.email,
-        user.is_verified,
-        user.verification_token,
-        user.is_subscribed
-    )
-    .fetch_optional(&state.pool)
-    .await;
-
-    match result {
-        Ok(None) => Err((
-            StatusCode::CONFLICT,
-            "Email already registered and verified".to_string(),
-        )),
-        Ok(Some(res)) => {
-            let user = User { id: res.id, ..user };
-            let validation_link = format!(
-                "{}/verify?token={}",
-                &state.base_url, user.verification_token
-            );
-            let unsubscribe_link = format!(
-                "{}/unsubscribe?id={}&token={}",
-                &state.base_url,
-                user.id,
-                user.generate_unsubscribe_token(&state.unsubscribe_secret)
-            );
-            match state
-                .mailer
-                .send_verification_email(&user, &validation_link, &unsubscribe_link)
+    locale: Locale,
+    headers: HeaderMap,
+    payload: JsonOrForm<SignUpRequest>,
+) -> Response {
+    match payload {
+        JsonOrForm::Json(payload) => {
+            let mut response = sign_up(&state, payload, EventSource::Api)
                 .await
-            {
-                Ok(_) => Ok((StatusCode::OK, "Verification email sent!".to_string())),
-                Err(e) => {
-                    eprintln!("Mailgun error during verification: {:?}", e);
-
-                    Err((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "Failed to add to mailing list.".to_string(),
-                    ))
-                }
-            }
+                .into_response();
+            LEGACY_JSON_SIGNUP.apply(&mut response);
+            response
         }
-        Err(e) => {
-            eprintln!("Database error: {:?}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".to_string(),
-            ))
+        JsonOrForm::Form(mut payload) => {
+            payload
+                .lang
+                .get_or_insert_with(|| locale.lang.code().to_string());
+            // Redirect form posts back to the homepage with the outcome as a flash message
+            let flash = match sign_up(&state, payload, EventSource::Web).await {
+                Ok(_) => Flash::success(locale.strings().signup_sent),
+                Err(e) => Flash::error(e.to_string()),
+            };
+            let jar = flash.set(SignedCookieJar::from_headers(
+                &headers,
+                state.cookie_key.clone(),
+            ));
+            (jar, Redirect::to("/#signup")).into_response()
         }
     }
 }
 
+#[derive(Template)]
+#[template(path = "fragments/predictions.html")]
+pub struct PredictionsFragment {
+    pub t: &'static Strings,
+    pub predictions: Vec<FloodDisplay>,
+    pub forecast_days: i64,
+    /// When the snapshot shown was taken, if the database couldn't be read
+    pub stale_since: Option<String>,
+    /// When NOAA sent the predictions shown, if that was a while ago
+    pub outdated_since: Option<String>,
+    pub adjustment: Option<String>,
+}
+
+/// The applied bias correction, for labelling heights as adjusted.
+pub fn adjustment() -> Option<String> {
+    tides::prediction_offset().map(|offset| format!("{:+.2}", offset))
+}
+
+fn flood_displays(tides: Vec<FloodTide>) -> Vec<FloodDisplay> {
+    tides
+        .into_iter()
+        .map(|tide| FloodDisplay::new(tide.prediction_time, tide.height_ft))
+        .collect()
+}
+
+/// Predictions table swapped into the homepage by htmx to keep the forecast fresh.
+pub async fn predictions_fragment_handler(
+    State(state): State<Arc<AppState>>,
+    locale: Locale,
+) -> Result<(Locale, Html<String>), AppError> {
+    let (tides, stale) = snapshot::flood_tides(&state, FORECAST_DAYS).await?;
+    let predictions = flood_displays(tides);
+
+    let template = PredictionsFragment {
+        t: locale.strings(),
+        predictions,
+        forecast_days: FORECAST_DAYS,
+        stale_since: stale.map(|stale| render::snapshot_time(stale.0)),
+        outdated_since: state
+            .tide_snapshot
+            .outdated_since(Utc::now())
+            .map(render::snapshot_time),
+        adjustment: adjustment(),
+    };
+    Ok((locale, Html(template.render()?)))
+}
+
+#[derive(Template)]
+#[template(path = "fragments/signup_result.html")]
+pub struct SignupResultFragment {
+    pub flash: Option<Flash>,
+}
+
+/// Inline signup for htmx. Always responds with 200 so htmx swaps in the
+/// message, with an `HX-Trigger` header telling the form to reset on success.
+pub async fn signup_result_fragment_handler(
+    State(state): State<Arc<AppState>>,
+    locale: Locale,
+    Form(payload): Form<SignUpRequest>,
+) -> Result<Response, AppError> {
+    let (success, flash) = match sign_up(&state, payload, EventSource::Web).await {
+        Ok(_) => (true, Flash::success(locale.strings().signup_sent)),
+        Err(e) => (false, Flash::error(e.to_string())),
+    };
+
+    let html = Html(SignupResultFragment { flash: Some(flash) }.render()?);
+    if success {
+        Ok(([("HX-Trigger", "signup-success")], html).into_response())
+    } else {
+        Ok(html.into_response())
+    }
+}
+
+pub async fn sign_up(
+    state: &AppState,
+    payload: SignUpRequest,
+    source: EventSource,
+) -> Result<String, AppError> {
+    SignupService::from_state(state)
+        .sign_up(payload, source)
+        .await?;
+    Ok("Verification email sent!".to_string())
+}
+
 #[derive(Template)]
 #[template(path = "unsubscribe.html")]
 pub struct UnsubscribeTemplate {
+    pub t: &'static Strings,
     pub user_id: String,
     pub token: String,
+    /// Offers stopping just the emails
+    pub other_channels: bool,
 }
 
 pub async fn unsubscribe_handler(
     method: Method,
     State(state): State<Arc<AppState>>,
     Query(params): Query<UnsubscribeParams>,
-) -> impl IntoResponse {
-    let user = User {
-        id: params.id.clone(),
-        ..Default::default()
-    };
-
-    if !user.verify_unsubscribe_token(&params.token, &state.unsubscribe_secret) {
-        return (StatusCode::BAD_REQUEST, "Invalid unsubscribe token").into_response();
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let t = locale.strings();
+    let service = UnsubscribeService::from_state(&state);
+    if !service.token_is_valid(&params.id, &params.token) {
+        let template = UnsubscribeResultTemplate {
+            t,
+            success: false,
+            email_only: false,
+            message: t.invalid_unsubscribe_link.to_string(),
+        };
+        return Ok((StatusCode::BAD_REQUEST, locale, Html(template.render()?)).into_response());
     }
     println!(
         "Unsubscribe request for user_id: {}, {}",
@@ -145,127 +294,169 @@ pub async fn unsubscribe_handler(
     );
     match method {
         Method::GET => {
+            let other_channels = service
+                .can_stop_email_only(&params.id)
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Database error: {:?}", e);
+                    false
+                });
             let template = UnsubscribeTemplate {
+                t,
                 user_id: params.id,
                 token: params.token,
+                other_channels,
             };
-            Html(
-                template
-                    .render()
-                    .unwrap_or_else(|_| "Template Error".into()),
-            )
-            .into_response()
+            Ok((locale, Html(template.render()?)).into_response())
         }
         Method::POST => {
-            let result = sqlx::query!(
-                r#"
-            DELETE FROM users
-            WHERE id = ?;
-            "#,
-                params.id
-            )
-            .execute(&state.pool)
-            .await;
-
-            let (success, message) = match result {
-                Ok(res) if res.rows_affected() > 0 => {
-                    (true, "You have been successfully unsubscribed.".to_string())
-                }
-                Ok(_) => (false, "You are already unsubscribed.".to_string()),
+            let stopped = service.unsubscribe(&params.id, params.scope).await;
+            let email_only = matches!(stopped, Ok(Some(UnsubscribeScope::Email)));
+            let (success, message) = match stopped {
+                Ok(Some(UnsubscribeScope::Email)) => (true, t.emails_stopped),
+                Ok(Some(UnsubscribeScope::All)) => (true, t.unsubscribed),
+                Ok(None) => (false, t.already_unsubscribed),
                 Err(e) => {
                     eprintln!("Database error: {:?}", e);
-                    (
-                        false,
-                        "An internal error occurred. Please try again later.".to_string(),
-                    )
+                    (false, t.try_again_later)
                 }
             };
-            let verify_template = VerifyResultTemplate { success, message };
-            match verify_template.render() {
-                Ok(html) => Html(html).into_response(),
-                Err(_) => (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    "Template Error",
-                )
-                    .into_response(),
-            }
+            let result_template = UnsubscribeResultTemplate {
+                t,
+                success,
+                email_only,
+                message: message.to_string(),
+            };
+            Ok((locale, Html(result_template.render()?)).into_response())
         }
-        _ => (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed").into_response(),
+        _ => Err(AppError::MethodNotAllowed),
     }
 }
 
+#[derive(Template)]
+#[template(path = "unsubscribe_result.html")]
+pub struct UnsubscribeResultTemplate {
+    pub t: &'static Strings,
+    pub success: bool,
+    /// Only the emails stopped, other channels carry on
+    pub email_only: bool,
+    pub message: String,
+}
+
 #[derive(Template)]
 #[template(path = "verify_result.html")]
 pub struct VerifyResultTemplate {
+    pub t: &'static Strings,
     pub success: bool,
     pub message: String,
 }
 
 #[derive(Template)]
 #[template(path = "privacy_policy.html")]
-pub struct PrivacyPolicyTemplate;
+pub struct PrivacyPolicyTemplate {
+    pub open_tracking: bool,
+    pub pushover_enabled: bool,
+    pub signal_enabled: bool,
+    /// Days before an unverified signup is deleted, None when it isn't
+    pub unverified_retention_days: Option<i64>,
+}
 
-pub async fn privacy_policy_handler() -> impl IntoResponse {
-    let template = PrivacyPolicyTemplate;
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Template Error").into_response(),
-    }
+pub async fn privacy_policy_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Html<String>, AppError> {
+    let template = PrivacyPolicyTemplate {
+        open_tracking: state.open_tracking,
+        pushover_enabled: channels::pushover_app_token().is_some(),
+        signal_enabled: channels::SignalGateway::from_env().is_some(),
+        unverified_retention_days: cleanup::unverified_retention_days(),
+    };
+    Ok(Html(template.render()?))
+}
+
+#[derive(Template)]
+#[template(path = "verify_code.html")]
+pub struct VerifyCodeTemplate {
+    pub t: &'static Strings,
 }
 
 pub async fn verify_handler(
     State(state): State<Arc<AppState>>,
     Query(params): Query<VerifyParams>,
-) -> impl axum::response::IntoResponse {
-    let result = sqlx::query!(
-        r#"
-        UPDATE users
-        SET is_verified = 1, is_subscribed = 1
-        WHERE verification_token = ? AND is_verified = 0
-        RETURNING email;
-        "#,
-        params.token
-    )
-    .fetch_optional(&state.pool)
-    .await;
+    locale: Locale,
+) -> Result<(Locale, Html<String>), AppError> {
+    let t = locale.strings();
+    // Without a token show the form for entering the emailed code instead
+    let Some(token) = params.token else {
+        return Ok((locale, Html(VerifyCodeTemplate { t }.render()?)));
+    };
 
+    let result = VerificationService::from_state(&state)
+        .verify_token(&token)
+        .await;
     let (success, message) = match result {
-        Ok(None) => (
-            false,
-            "Invalid or already used verification token".to_string(),
-        ),
-        Ok(res) => (
-            true,
-            format!("Email: {} verified successfully", res.unwrap().email),
-        ),
+        Ok(None) => (false, t.invalid_token.to_string()),
+        Ok(Some(email)) => (true, verified_message(t, &email)),
         Err(e) => {
             eprintln!("Database error: {:?}", e);
-            (false, "Internal server error".to_string())
+            (false, t.internal_error.to_string())
         }
     };
 
-    let template = VerifyResultTemplate { success, message };
+    render_verify_result(locale, success, message)
+}
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(_) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Template Error",
-        )
-            .into_response(),
+pub async fn verify_code_handler(
+    State(state): State<Arc<AppState>>,
+    locale: Locale,
+    Form(payload): Form<VerifyCodeRequest>,
+) -> Result<(Locale, Html<String>), AppError> {
+    let t = locale.strings();
+    let email = payload.email.trim();
+    let code = payload.code.trim();
+    if !is_verification_code(code) {
+        return render_verify_result(locale, false, t.invalid_code.to_string());
     }
+
+    let result = VerificationService::from_state(&state)
+        .verify_code(email, code)
+        .await;
+    let (success, message) = match result {
+        Ok(Some(email)) => (true, verified_message(t, &email)),
+        Ok(None) => (false, t.invalid_code.to_string()),
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            (false, t.internal_error.to_string())
+        }
+    };
+
+    render_verify_result(locale, success, message)
+}
+
+fn verified_message(t: &Strings, email: &str) -> String {
+    format!("{} {} {}", t.verified_before, email, t.verified_after)
 }
 
-pub async fn fallback_handler(
-    State(_): State<Arc<AppState>>,
-    Json(_): Json<SignUpRequest>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    Err((StatusCode::NOT_FOUND, "Not Found".to_string()))
+fn render_verify_result(
+    locale: Locale,
+    success: bool,
+    message: String,
+) -> Result<(Locale, Html<String>), AppError> {
+    let template = VerifyResultTemplate {
+        t: locale.strings(),
+        success,
+        message,
+    };
+    Ok((locale, Html(template.render()?)))
+}
+
+pub async fn fallback_handler() -> AppError {
+    AppError::NotFound("There's nothing at this address.".to_string())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::i18n::Lang;
     use validator::Validate;
 
     #[test]
@@ -273,12 +464,30 @@ mod tests {
         // Valid email
         let req = SignUpRequest {
             email: "valid@example.com".to_string(),
+            website: String::new(),
+            form_token: None,
+            source: None,
+            heard_from: None,
+            reminders: false,
+            ntfy_topic: None,
+            pushover_user_key: None,
+            signal_number: None,
+            lang: None,
         };
         assert!(req.validate().is_ok());
 
         // Invalid email
         let req = SignUpRequest {
             email: "invalid-email".to_string(),
+            website: String::new(),
+            form_token: None,
+            source: None,
+            heard_from: None,
+            reminders: false,
+            ntfy_topic: None,
+            pushover_user_key: None,
+            signal_number: None,
+            lang: None,
         };
         assert!(req.validate().is_err());
     }
@@ -286,12 +495,27 @@ mod tests {
     #[test]
     fn test_index_template_render() {
         let template = IndexTemplate {
+            t: Lang::En.strings(),
             predictions: vec![FloodDisplay {
                 datetime: "Monday, January 1 at 5:00PM".to_string(),
                 height: "7.0".to_string(),
             }],
             forecast_days: 30,
-            flood_threshold: 6.5,
+            form_token: "1700000000.abc123".to_string(),
+            flash: Some(Flash::success("Verification email sent!")),
+            signup_source: Some("qr-gate".to_string()),
+            static_snapshot: None,
+            stale_since: None,
+            outdated_since: None,
+            adjustment: Some("+0.30".to_string()),
+            pushover_enabled: true,
+            signal_enabled: false,
+            contact_enabled: true,
+            pages: vec![Page {
+                slug: "faq".to_string(),
+                title: "Frequently Asked Questions".to_string(),
+                html: String::new(),
+            }],
         };
 
         let rendered = template.render();
@@ -303,5 +527,155 @@ mod tests {
         assert!(html.contains("Monday, January 1 at 5:00PM"));
         assert!(html.contains("7.0"));
         assert!(html.contains("Forecasted Floods"));
+        assert!(html.contains("Mill Valley-Sausalito Bike Path Flood Forecast"));
+        assert!(html.contains("9414819"));
+        assert!(html.contains("1700000000.abc123"));
+        assert!(html.contains("Verification email sent!"));
+        assert!(html.contains("Heights are adjusted by +0.30 ft"));
+        assert!(html.contains(r#"name="source" value="qr-gate""#));
+        assert!(html.contains(r#"name="pushover_user_key""#));
+        assert!(!html.contains(r#"name="signal_number""#));
+        assert!(html.contains(r#"href="/contact""#));
+        assert!(html.contains("Frequently Asked Questions"));
+        assert!(html.contains(r#"href="/pages/faq""#));
+    }
+
+    #[test]
+    fn test_privacy_policy_render() {
+        let html = PrivacyPolicyTemplate {
+            open_tracking: false,
+            pushover_enabled: true,
+            signal_enabled: false,
+            unverified_retention_days: Some(30),
+        }
+        .render()
+        .unwrap();
+        assert!(html.contains("Pushover User Key"));
+        assert!(!html.contains("Signal Phone Number"));
+        assert!(!html.contains("was opened"));
+        assert!(html.contains("within 30 days"));
+
+        let html = PrivacyPolicyTemplate {
+            open_tracking: true,
+            pushover_enabled: false,
+            signal_enabled: true,
+            unverified_retention_days: None,
+        }
+        .render()
+        .unwrap();
+        assert!(!html.contains("Pushover User Key"));
+        assert!(html.contains("Signal Phone Number"));
+        assert!(html.contains("was opened"));
+        assert!(!html.contains("days of signing up"));
+    }
+
+    #[test]
+    fn test_fragment_templates_render() {
+        let predictions = PredictionsFragment {
+            t: Lang::En.strings(),
+            predictions: vec![],
+            forecast_days: 30,
+            stale_since: None,
+            outdated_since: None,
+            adjustment: None,
+        }
+        .render()
+        .unwrap();
+        assert!(predictions.contains("No upcoming floods predicted in the next 30 days."));
+        assert!(!predictions.contains("<html"));
+        assert!(!predictions.contains("may be stale"));
+        assert!(!predictions.contains("last fetched"));
+
+        let stale = PredictionsFragment {
+            t: Lang::En.strings(),
+            predictions: vec![],
+            forecast_days: 30,
+            stale_since: Some("Monday, January 1 at 5:00PM".to_string()),
+            outdated_since: Some("Sunday, December 31 at 9:00AM".to_string()),
+            adjustment: None,
+        }
+        .render()
+        .unwrap();
+        assert!(stale.contains("may be stale"));
+        assert!(stale.contains("Monday, January 1 at 5:00PM"));
+        assert!(stale.contains("were last fetched Sunday, December 31 at 9:00AM"));
+
+        let result = SignupResultFragment {
+            flash: Some(Flash::error("Please provide a valid email address.")),
+        }
+        .render()
+        .unwrap();
+        assert!(result.contains("Please provide a valid email address."));
+        assert!(result.contains("--pico-del-color"));
+    }
+
+    #[test]
+    fn test_verify_result_template_render() {
+        let success = VerifyResultTemplate {
+            t: Lang::En.strings(),
+            success: true,
+            message: "Email: test@example.com verified successfully".to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(success.contains("test@example.com verified successfully"));
+        assert!(success.contains("What happens next"));
+        assert!(success.contains("href=\"/\""));
+
+        let failure = VerifyResultTemplate {
+            t: Lang::En.strings(),
+            success: false,
+            message: "Invalid or already used verification token".to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(failure.contains("Verification Failed"));
+        assert!(failure.contains("href=\"/verify\""));
+
+        let spanish = VerifyResultTemplate {
+            t: Lang::Es.strings(),
+            success: true,
+            message: Lang::Es.strings().invalid_token.to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(spanish.contains(r#"<html lang="es">"#));
+        assert!(spanish.contains("¡Ya estás suscrito!"));
+        assert!(!spanish.contains("What happens next"));
+    }
+
+    #[test]
+    fn test_unsubscribe_result_template_render() {
+        let success = UnsubscribeResultTemplate {
+            t: Lang::En.strings(),
+            success: true,
+            email_only: false,
+            message: "You have been successfully unsubscribed.".to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(success.contains("successfully unsubscribed"));
+        assert!(success.contains("sign up again"));
+
+        let email_only = UnsubscribeResultTemplate {
+            t: Lang::En.strings(),
+            success: true,
+            email_only: true,
+            message: "You won't receive any more flood emails.".to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(email_only.contains("other channels carry on"));
+        assert!(!email_only.contains("has been removed"));
+
+        let failure = UnsubscribeResultTemplate {
+            t: Lang::En.strings(),
+            success: false,
+            email_only: false,
+            message: "This unsubscribe link is invalid.".to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(failure.contains("Something Went Wrong"));
     }
 }
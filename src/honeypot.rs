@@ -0,0 +1,181 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Default number of seconds a person needs at minimum to fill in a form.
+const DEFAULT_MIN_SUBMIT_SECONDS: i64 = 3;
+/// Seconds a form token stays valid, so one scraped token can't be replayed forever.
+const MAX_FORM_AGE_SECONDS: i64 = 24 * 60 * 60;
+
+/// Outcome of checking a submitted form for bot behaviour.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Submission {
+    Human,
+    /// The honeypot field was filled in.
+    Honeypot,
+    /// The form was submitted faster than a person could fill it in.
+    TooFast,
+    /// The form was rendered more than a day ago, likely in a tab left open.
+    Expired,
+    /// The form token is missing or was not issued by us.
+    InvalidToken,
+}
+
+impl Submission {
+    /// Whether to drop the submission quietly. Expired forms are from people
+    /// too, who should be told to reload the page.
+    pub fn is_bot(&self) -> bool {
+        !matches!(self, Submission::Human | Submission::Expired)
+    }
+}
+
+/// Issues a signed token embedding the time the form was rendered.
+pub fn issue_form_token(secret: &str) -> String {
+    sign(Utc::now().timestamp(), secret)
+}
+
+fn form_mac(rendered_at: i64, secret: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(format!("form:{}", secret).as_bytes()).unwrap();
+    mac.update(rendered_at.to_string().as_bytes());
+    mac
+}
+
+/// The form token for a form rendered at `rendered_at`.
+pub fn sign(rendered_at: i64, secret: &str) -> String {
+    format!(
+        "{}.{}",
+        rendered_at,
+        hex::encode(form_mac(rendered_at, secret).finalize().into_bytes())
+    )
+}
+
+fn min_submit_seconds() -> i64 {
+    env::var("MIN_SUBMIT_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MIN_SUBMIT_SECONDS)
+}
+
+/// Checks the honeypot field and the form token of a submission.
+pub fn check_submission(honeypot: &str, form_token: Option<&str>, secret: &str) -> Submission {
+    check_submission_at(
+        honeypot,
+        form_token,
+        secret,
+        Utc::now().timestamp(),
+        min_submit_seconds(),
+    )
+}
+
+fn check_submission_at(
+    honeypot: &str,
+    form_token: Option<&str>,
+    secret: &str,
+    now: i64,
+    min_seconds: i64,
+) -> Submission {
+    if !honeypot.trim().is_empty() {
+        return Submission::Honeypot;
+    }
+
+    let Some((rendered_at, signature)) = form_token
+        .and_then(|token| token.split_once('.'))
+        .and_then(|(timestamp, signature)| {
+            Some((timestamp.parse::<i64>().ok()?, hex::decode(signature).ok()?))
+        })
+    else {
+        return Submission::InvalidToken;
+    };
+
+    // Compared in constant time
+    if form_mac(rendered_at, secret)
+        .verify_slice(&signature)
+        .is_err()
+    {
+        return Submission::InvalidToken;
+    }
+
+    if now - rendered_at < min_seconds {
+        return Submission::TooFast;
+    }
+    if now - rendered_at > MAX_FORM_AGE_SECONDS {
+        return Submission::Expired;
+    }
+
+    Submission::Human
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "super-secret-key";
+
+    #[test]
+    fn test_human_submission() {
+        let token = sign(1_000, SECRET);
+        assert_eq!(
+            check_submission_at("", Some(&token), SECRET, 1_010, 3),
+            Submission::Human
+        );
+    }
+
+    #[test]
+    fn test_honeypot_filled() {
+        let token = sign(1_000, SECRET);
+        let result = check_submission_at("http://spam.example", Some(&token), SECRET, 1_010, 3);
+        assert_eq!(result, Submission::Honeypot);
+        assert!(result.is_bot());
+    }
+
+    #[test]
+    fn test_submitted_too_fast() {
+        let token = sign(1_000, SECRET);
+        assert_eq!(
+            check_submission_at("", Some(&token), SECRET, 1_001, 3),
+            Submission::TooFast
+        );
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let token = sign(1_000, SECRET);
+        let day_later = 1_000 + MAX_FORM_AGE_SECONDS;
+        assert_eq!(
+            check_submission_at("", Some(&token), SECRET, day_later, 3),
+            Submission::Human
+        );
+        let expired = check_submission_at("", Some(&token), SECRET, day_later + 1, 3);
+        assert_eq!(expired, Submission::Expired);
+        assert!(!expired.is_bot());
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        assert_eq!(
+            check_submission_at("", None, SECRET, 1_010, 3),
+            Submission::InvalidToken
+        );
+        assert_eq!(
+            check_submission_at("", Some("garbage"), SECRET, 1_010, 3),
+            Submission::InvalidToken
+        );
+        // Forged timestamp with someone else's signature
+        let token = sign(1_000, "wrong-secret");
+        assert_eq!(
+            check_submission_at("", Some(&token), SECRET, 1_010, 3),
+            Submission::InvalidToken
+        );
+        // Signed with the secret itself rather than the form key
+        let mut mac = HmacSha256::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(b"1000");
+        let token = format!("1000.{}", hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(
+            check_submission_at("", Some(&token), SECRET, 1_010, 3),
+            Submission::InvalidToken
+        );
+    }
+}
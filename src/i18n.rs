@@ -0,0 +1,644 @@
+use axum::extract::{FromRequestParts, Query};
+use axum::http::HeaderValue;
+use axum::http::header::{ACCEPT_LANGUAGE, SET_COOKIE, VARY};
+use axum::http::request::Parts;
+use axum::response::{IntoResponseParts, ResponseParts};
+use axum_extra::extract::cookie::CookieJar;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Deserialize;
+use std::convert::Infallible;
+
+use crate::attribution::HEARD_FROM;
+use crate::tides;
+
+const LANG_COOKIE: &str = "lang";
+/// How long a language picked with `?lang=` is remembered, a year.
+const LANG_COOKIE_MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Languages the homepage and confirmation pages are translated into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Es,
+}
+
+impl Lang {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Es => "es",
+        }
+    }
+
+    /// Matches a language tag such as `es` or `es-MX` by its primary subtag.
+    pub fn parse(tag: &str) -> Option<Lang> {
+        let primary = tag.trim().split(['-', '_']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Lang::En),
+            "es" => Some(Lang::Es),
+            _ => None,
+        }
+    }
+
+    pub fn strings(&self) -> &'static Strings {
+        match self {
+            Lang::En => &EN,
+            Lang::Es => &ES,
+        }
+    }
+}
+
+/// The supported language the visitor prefers most from an
+/// `Accept-Language` header, skipping ones refused with `q=0`.
+fn accept_language(header: &str) -> Option<Lang> {
+    let mut ranges: Vec<(Lang, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let lang = Lang::parse(parts.next()?)?;
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            Some((lang, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    // Stable, so equal weights keep the header's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.first().map(|(lang, _)| *lang)
+}
+
+/// The visitor's language, from a `?lang=` param, then the `lang` cookie it
+/// sets, then `Accept-Language`, defaulting to English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub lang: Lang,
+    /// Picked with `?lang=`, so it's remembered in the cookie
+    chosen: bool,
+}
+
+impl Locale {
+    fn negotiate(param: Option<&str>, cookie: Option<&str>, header: Option<&str>) -> Locale {
+        if let Some(lang) = param.and_then(Lang::parse) {
+            return Locale { lang, chosen: true };
+        }
+        let lang = cookie
+            .and_then(Lang::parse)
+            .or_else(|| header.and_then(accept_language))
+            .unwrap_or(Lang::En);
+        Locale {
+            lang,
+            chosen: false,
+        }
+    }
+
+    pub fn strings(&self) -> &'static Strings {
+        self.lang.strings()
+    }
+}
+
+#[derive(Deserialize)]
+struct LangParams {
+    lang: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let param = Query::<LangParams>::try_from_uri(&parts.uri)
+            .ok()
+            .and_then(|Query(params)| params.lang);
+        let jar = CookieJar::from_headers(&parts.headers);
+        let header = parts
+            .headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok());
+        Ok(Locale::negotiate(
+            param.as_deref(),
+            jar.get(LANG_COOKIE).map(|cookie| cookie.value()),
+            header,
+        ))
+    }
+}
+
+/// How flood times are written for a recipient, in their language and with
+/// a 12 or 24 hour clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateFormat {
+    pub lang: Lang,
+    pub clock_24h: bool,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat::new(Lang::En, None)
+    }
+}
+
+impl DateFormat {
+    /// The clock usual for `lang` unless they chose one.
+    pub fn new(lang: Lang, clock_24h: Option<bool>) -> Self {
+        DateFormat {
+            lang,
+            clock_24h: clock_24h.unwrap_or(lang != Lang::En),
+        }
+    }
+
+    /// From a subscriber's stored language code and clock, English when
+    /// they have none.
+    pub fn for_subscriber(lang: Option<&str>, clock_24h: Option<bool>) -> Self {
+        DateFormat::new(lang.and_then(Lang::parse).unwrap_or(Lang::En), clock_24h)
+    }
+
+    /// `time` in Pacific time, like `Thursday, October 5 at 2:30PM` or
+    /// `jueves, 5 de octubre a las 14:30`.
+    pub fn format(&self, time: DateTime<Utc>) -> String {
+        let local = tides::local(time);
+        let t = self.lang.strings();
+        let weekday = t.weekdays[local.weekday().num_days_from_monday() as usize];
+        let month = t.months[local.month0() as usize];
+        let (hour, clock) = if self.clock_24h {
+            let hour = local.hour();
+            (hour, format!("{:02}:{:02}", hour, local.minute()))
+        } else {
+            let (pm, hour) = local.hour12();
+            let suffix = match (self.lang, pm) {
+                (Lang::En, false) => "AM",
+                (Lang::En, true) => "PM",
+                (Lang::Es, false) => " a. m.",
+                (Lang::Es, true) => " p. m.",
+            };
+            (hour, format!("{}:{:02}{}", hour, local.minute(), suffix))
+        };
+        match self.lang {
+            Lang::En => format!("{}, {} {} at {}", weekday, month, local.day(), clock),
+            // "a la 1:30" but "a las 2:30"
+            Lang::Es => format!(
+                "{}, {} de {} {} {}",
+                weekday,
+                local.day(),
+                month,
+                if hour == 1 { "a la" } else { "a las" },
+                clock
+            ),
+        }
+    }
+}
+
+/// Marks the response as varying by language and remembers a language
+/// picked with `?lang=`.
+impl IntoResponseParts for Locale {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.headers_mut()
+            .append(VARY, HeaderValue::from_static("Accept-Language, Cookie"));
+        if self.chosen {
+            let cookie = format!(
+                "{}={}; Path=/; Max-Age={}; SameSite=Lax",
+                LANG_COOKIE,
+                self.lang.code(),
+                LANG_COOKIE_MAX_AGE_SECS
+            );
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                res.headers_mut().append(SET_COOKIE, value);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Text of the homepage and confirmation pages in one language. Sentences
+/// around a link or value are split into `_before` and `_after` parts.
+pub struct Strings {
+    pub lang: &'static str,
+    pub nav_predictions: &'static str,
+    pub nav_signup: &'static str,
+    pub nav_about: &'static str,
+    pub heading_before: &'static str,
+    pub heading_after: &'static str,
+    pub tagline: &'static str,
+    pub photo_alt: &'static str,
+    pub predictions_heading: &'static str,
+    pub predictions_intro: &'static str,
+    pub forecast_updated: &'static str,
+    pub calendar_link: &'static str,
+    pub calendar_after: &'static str,
+    pub stale_banner: &'static str,
+    pub outdated_banner: &'static str,
+    pub adjusted_before: &'static str,
+    pub adjusted_after: &'static str,
+    pub high_tide_time: &'static str,
+    pub height_feet: &'static str,
+    pub no_floods_before: &'static str,
+    pub no_floods_after: &'static str,
+    pub signup_heading: &'static str,
+    pub signup_intro: &'static str,
+    pub email_placeholder: &'static str,
+    pub subscribe: &'static str,
+    pub reminders_label: &'static str,
+    pub push_with: &'static str,
+    pub message_on: &'static str,
+    pub optional: &'static str,
+    pub heard_from: &'static str,
+    /// Labels of `attribution::HEARD_FROM`, in its order
+    pub heard_from_answers: [&'static str; 6],
+    pub pushover_placeholder: &'static str,
+    pub agree_to: &'static str,
+    pub privacy_policy: &'static str,
+    pub unsubscribe_note: &'static str,
+    pub about_heading: &'static str,
+    pub about_before: &'static str,
+    pub about_station: &'static str,
+    pub station: &'static str,
+    pub about_over: &'static str,
+    pub feet: &'static str,
+    pub map_alt: &'static str,
+    pub alternate_route: &'static str,
+    pub data_source_heading: &'static str,
+    pub data_source_before: &'static str,
+    pub data_source_after: &'static str,
+    pub station_id_label: &'static str,
+    pub threshold_before: &'static str,
+    pub threshold_after: &'static str,
+    pub open_source: &'static str,
+    pub contact: &'static str,
+    pub contact_form: &'static str,
+    pub signup_sent: &'static str,
+    pub verify_title: &'static str,
+    pub verify_heading: &'static str,
+    pub verify_intro: &'static str,
+    pub code_placeholder: &'static str,
+    pub code_label: &'static str,
+    pub verify_button: &'static str,
+    pub verification_title: &'static str,
+    pub subscribed_heading: &'static str,
+    pub verify_failed_heading: &'static str,
+    pub verified_before: &'static str,
+    pub verified_after: &'static str,
+    pub invalid_token: &'static str,
+    pub invalid_code: &'static str,
+    pub internal_error: &'static str,
+    pub what_next: &'static str,
+    pub what_next_body: &'static str,
+    pub every_email_unsubscribe: &'static str,
+    pub verify_failed_help: &'static str,
+    pub use_recent_email: &'static str,
+    pub enter_code: &'static str,
+    pub from_that_email: &'static str,
+    pub sign_up_again: &'static str,
+    pub new_email_after: &'static str,
+    pub return_home: &'static str,
+    pub unsubscribe_title: &'static str,
+    pub unsubscribe_confirm: &'static str,
+    pub confirm_unsubscribe: &'static str,
+    pub nevermind: &'static str,
+    pub unsubscribed_heading: &'static str,
+    pub something_wrong: &'static str,
+    pub unsubscribed_body: &'static str,
+    pub unsubscribed_body_after: &'static str,
+    pub unsubscribe_failed_body: &'static str,
+    pub invalid_unsubscribe_link: &'static str,
+    pub unsubscribed: &'static str,
+    pub already_unsubscribed: &'static str,
+    pub unsubscribe_channels_confirm: &'static str,
+    pub stop_emails_only: &'static str,
+    pub stop_everything: &'static str,
+    pub emails_stopped: &'static str,
+    pub emails_stopped_body: &'static str,
+    pub try_again_later: &'static str,
+    /// Monday first, as in flood times in emails
+    pub weekdays: [&'static str; 7],
+    pub months: [&'static str; 12],
+}
+
+impl Strings {
+    /// The homepage heading around the site name.
+    pub fn heading(&self, site_name: &str) -> String {
+        [self.heading_before, site_name, self.heading_after]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The signup form's answers to how they heard about the site, with
+    /// their labels.
+    pub fn heard_from_choices(&self) -> Vec<(&'static str, &'static str)> {
+        HEARD_FROM
+            .into_iter()
+            .zip(self.heard_from_answers)
+            .collect()
+    }
+}
+
+static EN: Strings = Strings {
+    lang: "en",
+    nav_predictions: "Forecasted Floods",
+    nav_signup: "Sign Up",
+    nav_about: "About",
+    heading_before: "",
+    heading_after: "Flood Forecast",
+    tagline: "To know when you should avoid and plan a different route.",
+    photo_alt: "Photo of the bike path.",
+    predictions_heading: "Upcoming Predicted Floods",
+    predictions_intro: "Below are the predicted times of high tides that have a high likelihood to flood the bike path. These are only tidal predictions and do not account for weather conditions such as wind, rain or storm surges that may also result in flooding even if the predicted tide level is below the height of the bike path.",
+    forecast_updated: "Forecast updated",
+    calendar_link: "Add the predicted floods to your calendar",
+    calendar_after: "by subscribing to the link in your calendar app.",
+    stale_banner: "The forecast can't be refreshed right now, so this data may be stale. It was last loaded",
+    outdated_banner: "The forecast may be missing NOAA's latest predictions. NOAA's predictions were last fetched",
+    adjusted_before: "Heights are adjusted by",
+    adjusted_after: "ft from NOAA's predictions, for how far the water levels measured at the station have been running from them lately.",
+    high_tide_time: "Date and time of high tide",
+    height_feet: "Height (feet)",
+    no_floods_before: "No upcoming floods predicted in the next",
+    no_floods_after: "days.",
+    signup_heading: "Sign Up for Flood Notifications",
+    signup_intro: "If you want to receive email notifications and stay informed about potential flooding events on the bike path, you can sign up below. Emails will be a weekly reminder if there are upcoming floods predicted for the week. No emails will be sent if there are no floods predicted.",
+    email_placeholder: "Email address",
+    subscribe: "Subscribe",
+    reminders_label: "Also remind me the evening before or morning of each flood",
+    push_with: "Also push to my phone with",
+    message_on: "Also message me on",
+    optional: "(optional)",
+    heard_from: "How did you hear about us?",
+    heard_from_answers: [
+        "Signs on the path",
+        "Nextdoor",
+        "The bike coalition newsletter",
+        "A friend or neighbor",
+        "A web search",
+        "Somewhere else",
+    ],
+    pushover_placeholder: "Your Pushover user key",
+    agree_to: "I agree to the",
+    privacy_policy: "Privacy Policy",
+    unsubscribe_note: "You can unsubscribe at any time with the unsubscribe link that is always included in the emails.",
+    about_heading: "About",
+    about_before: "Sections of the",
+    about_station: "are prone to tidal flooding when the predicted tide level at the NOAA",
+    station: "station",
+    about_over: "is over",
+    feet: "feet",
+    map_alt: "Map of the bike path section prone to flooding",
+    alternate_route: "When it floods:",
+    data_source_heading: "Data Source",
+    data_source_before: "Tidal predictions are sourced from the",
+    data_source_after: "service using their public API for the closest station to the path,",
+    station_id_label: "Station ID:",
+    threshold_before: "The flood threshold of",
+    threshold_after: "feet is based on observations and reports that I found from the",
+    open_source: "This project is open source, available on:",
+    contact: "Questions or problems? Email",
+    contact_form: "Report a problem or send a message",
+    signup_sent: "Verification email sent! Check your inbox (and spam folder) for the verification link.",
+    verify_title: "Verify Email",
+    verify_heading: "Verify Your Email",
+    verify_intro: "Enter your email address and the 6-digit code from the verification email we sent you.",
+    code_placeholder: "6-digit code",
+    code_label: "Verification code",
+    verify_button: "Verify",
+    verification_title: "Verification",
+    subscribed_heading: "You're Subscribed!",
+    verify_failed_heading: "Verification Failed",
+    verified_before: "Email:",
+    verified_after: "verified successfully",
+    invalid_token: "Invalid or already used verification token",
+    invalid_code: "Invalid or expired verification code. Please check the code or sign up again to get a new one.",
+    internal_error: "Internal server error",
+    what_next: "What happens next:",
+    what_next_body: "whenever tides high enough to flood the bike path are predicted in the coming week, we'll send you an email with the expected times. No floods predicted means no email.",
+    every_email_unsubscribe: "Every email includes an unsubscribe link if you change your mind.",
+    verify_failed_help: "Verification links and codes can only be used once and are replaced whenever you sign up again. If your address is already verified there's nothing else to do. Otherwise you can:",
+    use_recent_email: "Use the most recent email we sent you, or",
+    enter_code: "enter the 6-digit code",
+    from_that_email: "from that email, or",
+    sign_up_again: "sign up again",
+    new_email_after: "to get a new email.",
+    return_home: "Return to Home",
+    unsubscribe_title: "Unsubscribe",
+    unsubscribe_confirm: "Confirm below to stop receiving flood notifications . You are always welcome to sign up again later.",
+    confirm_unsubscribe: "Confirm Unsubscribe",
+    nevermind: "Nevermind, take me back",
+    unsubscribed_heading: "Unsubscribed",
+    something_wrong: "Something Went Wrong",
+    unsubscribed_body: "Your email address has been removed and you won't receive any more flood notifications. The forecast is always available on the website, and you're welcome to",
+    unsubscribed_body_after: "at any time.",
+    unsubscribe_failed_body: "If you keep receiving emails you didn't ask for, use the unsubscribe link from the most recent email, as older links may no longer be valid.",
+    invalid_unsubscribe_link: "This unsubscribe link is invalid.",
+    unsubscribed: "You have been successfully unsubscribed.",
+    already_unsubscribed: "You are already unsubscribed.",
+    unsubscribe_channels_confirm: "You also get flood alerts on another channel. Stop just the emails, or every alert?",
+    stop_emails_only: "Stop emails only",
+    stop_everything: "Stop everything",
+    emails_stopped: "You won't receive any more flood emails.",
+    emails_stopped_body: "Alerts on your other channels carry on. To get emails again, you're welcome to",
+    try_again_later: "An internal error occurred. Please try again later.",
+    weekdays: [
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ],
+    months: [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ],
+};
+
+static ES: Strings = Strings {
+    lang: "es",
+    nav_predictions: "Inundaciones previstas",
+    nav_signup: "Suscribirse",
+    nav_about: "Acerca de",
+    heading_before: "Pronóstico de inundaciones:",
+    heading_after: "",
+    tagline: "Para saber cuándo evitarlo y planear otra ruta.",
+    photo_alt: "Foto del sendero para bicicletas.",
+    predictions_heading: "Próximas inundaciones previstas",
+    predictions_intro: "A continuación se muestran las horas previstas de las mareas altas con alta probabilidad de inundar el sendero. Son solo predicciones de marea y no tienen en cuenta condiciones meteorológicas como el viento, la lluvia o las marejadas, que también pueden causar inundaciones aunque el nivel de marea previsto esté por debajo de la altura del sendero.",
+    forecast_updated: "Pronóstico actualizado",
+    calendar_link: "Añade las inundaciones previstas a tu calendario",
+    calendar_after: "suscribiéndote al enlace desde tu aplicación de calendario.",
+    stale_banner: "No se puede actualizar el pronóstico en este momento, así que estos datos pueden estar desactualizados. Se cargaron por última vez",
+    outdated_banner: "Puede que al pronóstico le falten las últimas predicciones de NOAA. Se obtuvieron por última vez",
+    adjusted_before: "Las alturas están ajustadas en",
+    adjusted_after: "pies respecto a las predicciones de NOAA, por lo que los niveles de agua medidos en la estación se han desviado de ellas últimamente.",
+    high_tide_time: "Fecha y hora de la marea alta",
+    height_feet: "Altura (pies)",
+    no_floods_before: "No hay inundaciones previstas en los próximos",
+    no_floods_after: "días.",
+    signup_heading: "Suscríbete a los avisos de inundación",
+    signup_intro: "Si quieres recibir avisos por correo electrónico y estar al tanto de posibles inundaciones en el sendero, puedes suscribirte abajo. Recibirás un recordatorio semanal si hay inundaciones previstas para la semana. No se enviarán correos si no hay inundaciones previstas.",
+    email_placeholder: "Correo electrónico",
+    subscribe: "Suscribirme",
+    reminders_label: "Recordarme también la tarde anterior o la mañana de cada inundación",
+    push_with: "Enviar también a mi teléfono con",
+    message_on: "Enviarme también un mensaje por",
+    optional: "(opcional)",
+    heard_from: "¿Cómo nos conociste?",
+    heard_from_answers: [
+        "Carteles en el sendero",
+        "Nextdoor",
+        "El boletín de la coalición de ciclistas",
+        "Un amigo o vecino",
+        "Una búsqueda en internet",
+        "Otro lugar",
+    ],
+    pushover_placeholder: "Tu clave de usuario de Pushover",
+    agree_to: "Acepto la",
+    privacy_policy: "Política de privacidad",
+    unsubscribe_note: "Puedes darte de baja en cualquier momento con el enlace que se incluye en todos los correos.",
+    about_heading: "Acerca de",
+    about_before: "Algunos tramos de",
+    about_station: "suelen inundarse con la marea cuando el nivel de marea previsto en la estación de NOAA",
+    station: "",
+    about_over: "supera los",
+    feet: "pies",
+    map_alt: "Mapa del tramo del sendero propenso a inundaciones",
+    alternate_route: "Cuando se inunda:",
+    data_source_heading: "Fuente de datos",
+    data_source_before: "Las predicciones de marea provienen del servicio",
+    data_source_after: "a través de su API pública, para la estación más cercana al sendero,",
+    station_id_label: "ID de estación:",
+    threshold_before: "El umbral de inundación de",
+    threshold_after: "pies se basa en observaciones y reportes que encontré de la",
+    open_source: "Este proyecto es de código abierto y está disponible en:",
+    contact: "¿Preguntas o problemas? Escribe a",
+    contact_form: "Informar de un problema o enviar un mensaje",
+    signup_sent: "¡Correo de verificación enviado! Revisa tu bandeja de entrada (y la carpeta de spam) para encontrar el enlace de verificación.",
+    verify_title: "Verificar correo",
+    verify_heading: "Verifica tu correo",
+    verify_intro: "Introduce tu correo electrónico y el código de 6 dígitos del correo de verificación que te enviamos.",
+    code_placeholder: "Código de 6 dígitos",
+    code_label: "Código de verificación",
+    verify_button: "Verificar",
+    verification_title: "Verificación",
+    subscribed_heading: "¡Ya estás suscrito!",
+    verify_failed_heading: "La verificación falló",
+    verified_before: "Correo:",
+    verified_after: "verificado correctamente",
+    invalid_token: "El enlace de verificación no es válido o ya se usó",
+    invalid_code: "El código de verificación no es válido o caducó. Revisa el código o suscríbete de nuevo para recibir uno nuevo.",
+    internal_error: "Error interno del servidor",
+    what_next: "Qué pasa ahora:",
+    what_next_body: "cada vez que se prevean mareas lo bastante altas para inundar el sendero en la próxima semana, te enviaremos un correo con las horas previstas. Si no hay inundaciones previstas, no recibirás correos.",
+    every_email_unsubscribe: "Todos los correos incluyen un enlace para darte de baja si cambias de opinión.",
+    verify_failed_help: "Los enlaces y códigos de verificación solo se pueden usar una vez y se reemplazan cada vez que te suscribes de nuevo. Si tu correo ya está verificado no tienes que hacer nada más. Si no, puedes:",
+    use_recent_email: "Usar el correo más reciente que te enviamos, o",
+    enter_code: "introducir el código de 6 dígitos",
+    from_that_email: "de ese correo, o",
+    sign_up_again: "suscribirte de nuevo",
+    new_email_after: "para recibir un correo nuevo.",
+    return_home: "Volver al inicio",
+    unsubscribe_title: "Darse de baja",
+    unsubscribe_confirm: "Confirma abajo para dejar de recibir avisos de inundación. Siempre puedes volver a suscribirte más adelante.",
+    confirm_unsubscribe: "Confirmar la baja",
+    nevermind: "Mejor no, volver",
+    unsubscribed_heading: "Baja confirmada",
+    something_wrong: "Algo salió mal",
+    unsubscribed_body: "Hemos eliminado tu correo y no recibirás más avisos de inundación. El pronóstico siempre está disponible en el sitio web, y puedes",
+    unsubscribed_body_after: "cuando quieras.",
+    unsubscribe_failed_body: "Si sigues recibiendo correos que no pediste, usa el enlace para darte de baja del correo más reciente, ya que los enlaces anteriores pueden haber dejado de funcionar.",
+    invalid_unsubscribe_link: "Este enlace para darse de baja no es válido.",
+    unsubscribed: "Te has dado de baja correctamente.",
+    already_unsubscribed: "Ya te habías dado de baja.",
+    unsubscribe_channels_confirm: "También recibes avisos de inundación por otro canal. ¿Quieres dejar solo los correos, o todos los avisos?",
+    stop_emails_only: "Dejar solo los correos",
+    stop_everything: "Dejar todo",
+    emails_stopped: "No recibirás más correos de inundación.",
+    emails_stopped_body: "Los avisos por tus otros canales continúan. Para volver a recibir correos, puedes",
+    try_again_later: "Ocurrió un error interno. Inténtalo de nuevo más tarde.",
+    weekdays: [
+        "lunes",
+        "martes",
+        "miércoles",
+        "jueves",
+        "viernes",
+        "sábado",
+        "domingo",
+    ],
+    months: [
+        "enero",
+        "febrero",
+        "marzo",
+        "abril",
+        "mayo",
+        "junio",
+        "julio",
+        "agosto",
+        "septiembre",
+        "octubre",
+        "noviembre",
+        "diciembre",
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(accept_language("es-MX,es;q=0.9,en;q=0.8"), Some(Lang::Es));
+        assert_eq!(accept_language("fr-FR, en;q=0.5, es;q=0.7"), Some(Lang::Es));
+        assert_eq!(accept_language("es;q=0, en-GB"), Some(Lang::En));
+        assert_eq!(accept_language("fr, de;q=0.9"), None);
+        assert_eq!(accept_language("*"), None);
+
+        let locale = Locale::negotiate(None, None, Some("es-419,es;q=0.9"));
+        assert_eq!((locale.lang, locale.chosen), (Lang::Es, false));
+        let locale = Locale::negotiate(None, Some("en"), Some("es"));
+        assert_eq!((locale.lang, locale.chosen), (Lang::En, false));
+        let locale = Locale::negotiate(Some("es"), Some("en"), Some("en"));
+        assert_eq!((locale.lang, locale.chosen), (Lang::Es, true));
+        let locale = Locale::negotiate(Some("klingon"), None, None);
+        assert_eq!((locale.lang, locale.chosen), (Lang::En, false));
+    }
+
+    #[test]
+    fn test_date_format() {
+        // 2:30PM on a Thursday in Pacific daylight time
+        let time = "2023-10-05T21:30:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            DateFormat::default().format(time),
+            "Thursday, October 5 at 2:30PM"
+        );
+        assert_eq!(
+            DateFormat::new(Lang::En, Some(true)).format(time),
+            "Thursday, October 5 at 14:30"
+        );
+        assert_eq!(
+            DateFormat::new(Lang::Es, None).format(time),
+            "jueves, 5 de octubre a las 14:30"
+        );
+        assert_eq!(
+            DateFormat::new(Lang::Es, Some(false)).format(time),
+            "jueves, 5 de octubre a las 2:30 p. m."
+        );
+        let one = "2023-10-05T08:05:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            DateFormat::new(Lang::Es, Some(false)).format(one),
+            "jueves, 5 de octubre a la 1:05 a. m."
+        );
+        assert_eq!(
+            DateFormat::new(Lang::En, Some(false)).format(one),
+            "Thursday, October 5 at 1:05AM"
+        );
+    }
+}
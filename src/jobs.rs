@@ -0,0 +1,622 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::instrument;
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::AppState;
+use crate::alerts;
+use crate::error::AppError;
+use crate::events::EventSource;
+use crate::leader::Lease;
+use crate::metrics;
+use crate::notify;
+use crate::request_id;
+use crate::site;
+use crate::tides::update_tide_predictions;
+
+/// Runs shown by `runs list` and `/admin/api/runs` by default.
+pub const DEFAULT_RUNS_LIMIT: i64 = 20;
+/// A run still marked running after this long was cut short, e.g. by the
+/// process being killed, and doesn't block new ones.
+const STALE_RUN_MINUTES: i64 = 60;
+/// Attempts a queued run gets before it's marked failed.
+const QUEUE_MAX_ATTEMPTS: i64 = 3;
+/// Wait before the first retry, doubling after each failed attempt.
+const RETRY_BACKOFF_MINUTES: i64 = 5;
+/// How often the worker checks for due runs.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A job whose runs are recorded in `job_runs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Job {
+    Sync,
+    Notify,
+}
+
+impl Job {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Job::Sync => "sync",
+            Job::Notify => "notify",
+        }
+    }
+
+    fn parse(job: &str) -> Option<Self> {
+        match job {
+            "sync" => Some(Job::Sync),
+            "notify" => Some(Job::Notify),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobRun {
+    pub id: String,
+    pub job: String,
+    /// queued, running, succeeded or failed
+    pub status: String,
+    /// cli, web or api
+    pub source: String,
+    /// Arguments as JSON, none for sync and notify
+    pub payload: String,
+    /// When a queued run is due, None for runs started straight away
+    pub run_at: Option<NaiveDateTime>,
+    pub attempts: i64,
+    pub max_attempts: i64,
+    /// Tide predictions stored by a sync, or notifications sent by a notify
+    pub count: Option<i64>,
+    /// The last attempt's error
+    pub error: Option<String>,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[instrument(level = "debug", skip(pool))]
+async fn get_run(pool: &SqlitePool, id: &str) -> Result<Option<JobRun>, sqlx::Error> {
+    sqlx::query_as!(
+        JobRun,
+        r#"
+        SELECT id, job, status, source, payload, run_at AS "run_at: NaiveDateTime",
+            attempts, max_attempts, count, error,
+            started_at AS "started_at: NaiveDateTime",
+            finished_at AS "finished_at: NaiveDateTime"
+        FROM job_runs WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The latest runs, newest first.
+#[instrument(level = "debug", skip(pool))]
+pub async fn recent_runs(pool: &SqlitePool, limit: i64) -> Result<Vec<JobRun>, sqlx::Error> {
+    sqlx::query_as!(
+        JobRun,
+        r#"
+        SELECT id, job, status, source, payload, run_at AS "run_at: NaiveDateTime",
+            attempts, max_attempts, count, error,
+            started_at AS "started_at: NaiveDateTime",
+            finished_at AS "finished_at: NaiveDateTime"
+        FROM job_runs
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// When a run of `job` last finished successfully, in UTC.
+#[instrument(level = "debug", skip(pool))]
+pub async fn last_succeeded(
+    pool: &SqlitePool,
+    job: Job,
+) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    let job_name = job.as_str();
+    sqlx::query_scalar!(
+        r#"
+        SELECT MAX(finished_at) AS "finished_at: NaiveDateTime" FROM job_runs
+        WHERE job = ? AND status = 'succeeded'
+        "#,
+        job_name
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// The id of a run of `job` in progress or due to start, if there is one.
+#[instrument(level = "debug", skip(pool))]
+async fn running(pool: &SqlitePool, job: Job) -> Result<Option<String>, sqlx::Error> {
+    let job_name = job.as_str();
+    let stale = format!("-{} minutes", STALE_RUN_MINUTES);
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM job_runs
+        WHERE job = ? AND (
+            (status = 'running' AND started_at > datetime('now', ?))
+            OR (status = 'queued' AND run_at <= datetime('now'))
+        )
+        "#,
+        job_name,
+        stale
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[instrument(level = "debug", skip(pool, source))]
+async fn start_run(
+    pool: &SqlitePool,
+    job: Job,
+    source: EventSource,
+) -> Result<String, sqlx::Error> {
+    let id = Uuid::new_v7(Timestamp::now(NoContext)).to_string();
+    let job_name = job.as_str();
+    let source = source.as_str();
+    let request_id = request_id::current();
+    sqlx::query!(
+        r#"
+        INSERT INTO job_runs (id, job, status, source, attempts, started_at, request_id)
+        VALUES (?, ?, 'running', ?, 1, CURRENT_TIMESTAMP, ?)
+        "#,
+        id,
+        job_name,
+        source,
+        request_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Queues a run of `job` for the worker, due in `delay_minutes`, with
+/// retries if it fails.
+#[instrument(level = "debug", skip(pool, source))]
+pub async fn enqueue(
+    pool: &SqlitePool,
+    job: Job,
+    source: EventSource,
+    delay_minutes: i64,
+) -> Result<String, sqlx::Error> {
+    let id = Uuid::new_v7(Timestamp::now(NoContext)).to_string();
+    let job_name = job.as_str();
+    let source = source.as_str();
+    let delay = format!("+{} minutes", delay_minutes);
+    let request_id = request_id::current();
+    sqlx::query!(
+        r#"
+        INSERT INTO job_runs (id, job, status, source, run_at, max_attempts, request_id)
+        VALUES (?, ?, 'queued', ?, datetime('now', ?), ?, ?)
+        "#,
+        id,
+        job_name,
+        source,
+        delay,
+        QUEUE_MAX_ATTEMPTS,
+        request_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(id)
+}
+
+/// A queued run the worker has claimed.
+struct Claimed {
+    id: String,
+    job: String,
+    source: String,
+    attempts: i64,
+    max_attempts: i64,
+    /// The request that queued the run, carried into its logs
+    request_id: Option<String>,
+}
+
+/// Marks the next due run as running and returns it. Claiming in a single
+/// update means a run is only ever picked up once.
+#[instrument(level = "debug", skip_all)]
+async fn claim_due(pool: &SqlitePool) -> Result<Option<Claimed>, sqlx::Error> {
+    sqlx::query_as!(
+        Claimed,
+        r#"
+        UPDATE job_runs
+        SET status = 'running', attempts = attempts + 1, started_at = CURRENT_TIMESTAMP,
+            finished_at = NULL
+        WHERE id = (
+            SELECT id FROM job_runs
+            WHERE status = 'queued' AND run_at <= datetime('now')
+            ORDER BY run_at, id
+            LIMIT 1
+        )
+        RETURNING id AS "id!", job AS "job!", source AS "source!", attempts AS "attempts!",
+            max_attempts AS "max_attempts!", request_id
+        "#
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Puts a failed queued run back in the queue after a backoff, or marks it
+/// failed once it's out of attempts.
+async fn retry_or_fail(pool: &SqlitePool, run: &Claimed) {
+    if run.attempts >= run.max_attempts {
+        return;
+    }
+    let backoff = RETRY_BACKOFF_MINUTES * 2_i64.pow((run.attempts - 1).clamp(0, 10) as u32);
+    let delay = format!("+{} minutes", backoff);
+    match sqlx::query!(
+        r#"
+        UPDATE job_runs SET status = 'queued', run_at = datetime('now', ?), finished_at = NULL
+        WHERE id = ?
+        "#,
+        delay,
+        run.id
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(_) => println!(
+            "Retrying {} job {} in {} minutes (attempt {} of {})",
+            run.job,
+            run.id,
+            backoff,
+            run.attempts + 1,
+            run.max_attempts
+        ),
+        Err(e) => eprintln!("Database error requeueing job run {}: {:?}", run.id, e),
+    }
+}
+
+fn parse_source(source: &str) -> EventSource {
+    match source {
+        "cli" => EventSource::Cli,
+        "web" => EventSource::Web,
+        _ => EventSource::Api,
+    }
+}
+
+/// Runs every due queued run, one at a time.
+async fn work_queue(pool: &SqlitePool) {
+    loop {
+        let run = match claim_due(pool).await {
+            Ok(Some(run)) => run,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Database error claiming a queued job: {:?}", e);
+                return;
+            }
+        };
+        let Some(job) = Job::parse(&run.job) else {
+            finish_run(
+                pool,
+                &run.id,
+                None,
+                Some(format!("Unknown job {}", run.job)),
+            )
+            .await;
+            continue;
+        };
+        println!("Running queued {} job {}", run.job, run.id);
+        let execution = execute(pool, job, parse_source(&run.source), &run.id);
+        let result = request_id::scope(run.request_id.clone(), execution).await;
+        if let Err(e) = result {
+            eprintln!("Queued {} job {} failed: {}", run.job, run.id, e);
+            if e.is_retryable() {
+                retry_or_fail(pool, &run).await;
+            } else {
+                println!(
+                    "Not retrying {} job {}, the {} error won't clear up",
+                    run.job,
+                    run.id,
+                    e.kind()
+                );
+            }
+        }
+    }
+}
+
+/// Starts the worker that runs queued jobs, checking every few seconds.
+/// With several replicas only the one holding the worker lease runs them,
+/// recovering the runs the last leader left behind when it takes over.
+pub fn spawn_worker(pool: SqlitePool) {
+    let leader = Lease::from_env("worker").spawn_heartbeat(pool.clone(), |pool| async move {
+        if let Err(e) = recover_interrupted(&pool).await {
+            eprintln!("Database error recovering interrupted job runs: {:?}", e);
+        }
+    });
+    site::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if leader.load(Ordering::SeqCst) {
+                work_queue(&pool).await;
+            }
+        }
+    });
+}
+
+#[instrument(level = "debug", skip(pool, count, error))]
+async fn finish_run(pool: &SqlitePool, id: &str, count: Option<i64>, error: Option<String>) {
+    let status = if error.is_some() {
+        "failed"
+    } else {
+        "succeeded"
+    };
+    if let Err(e) = sqlx::query!(
+        r#"
+        UPDATE job_runs SET status = ?, count = ?, error = ?, finished_at = CURRENT_TIMESTAMP
+        WHERE id = ?
+        "#,
+        status,
+        count,
+        error,
+        id
+    )
+    .execute(pool)
+    .await
+    {
+        eprintln!("Database error finishing job run {}: {:?}", id, e);
+    }
+}
+
+/// Recovers runs the last leader left running when it stopped, run by a
+/// replica when it becomes the leader. Queued runs go
+/// back in the queue if they have attempts left, and dashboard runs are
+/// marked failed. A `sync` or `notify` from the CLI may be running alongside
+/// it, so those are left alone.
+#[instrument(level = "debug", skip_all)]
+pub async fn recover_interrupted(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE job_runs
+        SET status = CASE
+                WHEN run_at IS NOT NULL AND attempts < max_attempts THEN 'queued'
+                ELSE 'failed'
+            END,
+            error = 'Interrupted by a restart',
+            finished_at = CASE
+                WHEN run_at IS NOT NULL AND attempts < max_attempts THEN NULL
+                ELSE CURRENT_TIMESTAMP
+            END
+        WHERE status = 'running' AND (run_at IS NOT NULL OR source = 'web')
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Runs `job` now, recording it in `job_runs`. Returns how much it did.
+pub async fn run(pool: &SqlitePool, job: Job, source: EventSource) -> Result<usize, AppError> {
+    let id = start_run(pool, job, source).await?;
+    execute(pool, job, source, &id).await
+}
+
+async fn execute(
+    pool: &SqlitePool,
+    job: Job,
+    source: EventSource,
+    id: &str,
+) -> Result<usize, AppError> {
+    let result = match job {
+        Job::Sync => update_tide_predictions(pool.clone()).await,
+        Job::Notify => notify::check_and_send_notifications(pool.clone(), source).await,
+    };
+    finish_run(
+        pool,
+        id,
+        result.as_ref().ok().map(|count| *count as i64),
+        result.as_ref().err().map(|e| e.to_string()),
+    )
+    .await;
+    if let Err(e) = &result {
+        metrics::count_error(e.kind());
+        alert_failure(pool, id, e.is_retryable()).await;
+    }
+    result
+}
+
+/// Alerts the operator to a failed run, once it won't be retried so a
+/// queued run only alerts if its last attempt fails too.
+async fn alert_failure(pool: &SqlitePool, id: &str, retryable: bool) {
+    match get_run(pool, id).await {
+        Ok(Some(run)) if !retryable || run.attempts >= run.max_attempts => {
+            alerts::job_failed(pool, &run).await
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Database error loading failed job run {}: {:?}", id, e),
+    }
+}
+
+/// Prints the latest runs, for `runs list`.
+pub async fn print_runs(pool: &SqlitePool, limit: i64) -> Result<(), AppError> {
+    let runs = recent_runs(pool, limit).await?;
+    if runs.is_empty() {
+        println!("No job runs yet");
+    }
+    for run in runs {
+        println!(
+            "{}  {}  {:<6}  {:<9}  {:<3}  {:>5}  {}",
+            run.id,
+            run.started_at
+                .or(run.run_at)
+                .map(|time| time.to_string())
+                .unwrap_or_default(),
+            run.job,
+            run.status,
+            run.source,
+            run.count.map(|count| count.to_string()).unwrap_or_default(),
+            run.error.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Queues `job` for the worker and returns its run, or the run already in
+/// progress or due with a 409.
+async fn enqueue_response(state: Arc<AppState>, job: Job) -> Response {
+    let queued = match running(&state.pool, job).await {
+        Ok(Some(running)) => {
+            return run_response(&state.pool, &running, StatusCode::CONFLICT).await;
+        }
+        Ok(None) => enqueue(&state.pool, job, EventSource::Api, 0).await,
+        Err(e) => Err(e),
+    };
+    match queued {
+        Ok(id) => run_response(&state.pool, &id, StatusCode::ACCEPTED).await,
+        Err(e) => {
+            eprintln!("Database error queueing {} job: {:?}", job.as_str(), e);
+            AppError::Internal("Internal server error".to_string()).into_response()
+        }
+    }
+}
+
+async fn run_response(pool: &SqlitePool, id: &str, status: StatusCode) -> Response {
+    match get_run(pool, id).await {
+        Ok(Some(run)) => (status, Json(run)).into_response(),
+        Ok(None) => AppError::NotFound("No such job run".to_string()).into_response(),
+        Err(e) => {
+            eprintln!("Database error fetching job run {}: {:?}", id, e);
+            AppError::Internal("Internal server error".to_string()).into_response()
+        }
+    }
+}
+
+/// `POST /admin/api/sync`
+pub async fn sync_job_handler(State(state): State<Arc<AppState>>) -> Response {
+    enqueue_response(state, Job::Sync).await
+}
+
+/// `POST /admin/api/notify`
+pub async fn notify_job_handler(State(state): State<Arc<AppState>>) -> Response {
+    enqueue_response(state, Job::Notify).await
+}
+
+#[derive(Deserialize)]
+pub struct RunsQuery {
+    limit: Option<i64>,
+}
+
+/// `GET /admin/api/runs`, the latest runs from any source.
+pub async fn job_runs_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<RunsQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_RUNS_LIMIT).clamp(1, 100);
+    match recent_runs(&state.pool, limit).await {
+        Ok(runs) => Json(runs).into_response(),
+        Err(e) => {
+            eprintln!("Database error listing job runs: {:?}", e);
+            AppError::Internal("Internal server error".to_string()).into_response()
+        }
+    }
+}
+
+/// `GET /admin/api/runs/{id}`, to check on a triggered job.
+pub async fn job_run_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    run_response(&state.pool, &id, StatusCode::OK).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_runs() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        let id = start_run(&pool, Job::Sync, EventSource::Api).await.unwrap();
+        assert_eq!(running(&pool, Job::Sync).await.unwrap(), Some(id.clone()));
+        assert_eq!(running(&pool, Job::Notify).await.unwrap(), None);
+        let notify_id = start_run(&pool, Job::Notify, EventSource::Web)
+            .await
+            .unwrap();
+        // Ids only sort by time across milliseconds
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let cli_id = start_run(&pool, Job::Notify, EventSource::Cli)
+            .await
+            .unwrap();
+
+        finish_run(&pool, &id, Some(120), None).await;
+        let run = get_run(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(run.status, "succeeded");
+        assert_eq!(run.count, Some(120));
+        assert!(run.finished_at.is_some());
+        assert_eq!(running(&pool, Job::Sync).await.unwrap(), None);
+
+        recover_interrupted(&pool).await.unwrap();
+        let run = get_run(&pool, &notify_id).await.unwrap().unwrap();
+        assert_eq!(run.status, "failed");
+        assert_eq!(run.error.as_deref(), Some("Interrupted by a restart"));
+        let run = get_run(&pool, &cli_id).await.unwrap().unwrap();
+        assert_eq!(run.status, "running");
+
+        let runs = recent_runs(&pool, 2).await.unwrap();
+        let ids: Vec<&str> = runs.iter().map(|run| run.id.as_str()).collect();
+        assert_eq!(ids, [cli_id.as_str(), notify_id.as_str()]);
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        let later = enqueue(&pool, Job::Sync, EventSource::Cli, 30)
+            .await
+            .unwrap();
+        assert_eq!(running(&pool, Job::Sync).await.unwrap(), None);
+        assert!(claim_due(&pool).await.unwrap().is_none());
+
+        let id = enqueue(&pool, Job::Notify, EventSource::Api, 0)
+            .await
+            .unwrap();
+        assert_eq!(running(&pool, Job::Notify).await.unwrap(), Some(id.clone()));
+        let claimed = claim_due(&pool).await.unwrap().unwrap();
+        assert_eq!(claimed.id, id);
+        assert_eq!(claimed.attempts, 1);
+        assert!(claim_due(&pool).await.unwrap().is_none());
+
+        finish_run(&pool, &id, None, Some("SMTP down".to_string())).await;
+        retry_or_fail(&pool, &claimed).await;
+        let run = get_run(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(run.status, "queued");
+        assert_eq!(run.error.as_deref(), Some("SMTP down"));
+        assert!(run.finished_at.is_none());
+
+        // Out of attempts, the failure sticks
+        let last = Claimed {
+            attempts: QUEUE_MAX_ATTEMPTS,
+            ..claimed
+        };
+        finish_run(&pool, &id, None, Some("SMTP down".to_string())).await;
+        retry_or_fail(&pool, &last).await;
+        assert_eq!(get_run(&pool, &id).await.unwrap().unwrap().status, "failed");
+
+        // A claimed run interrupted by a restart goes back in the queue
+        sqlx::query!(
+            "UPDATE job_runs SET status = 'running', attempts = 1 WHERE id = ?",
+            later
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        recover_interrupted(&pool).await.unwrap();
+        let run = get_run(&pool, &later).await.unwrap().unwrap();
+        assert_eq!(run.status, "queued");
+        assert_eq!(run.error.as_deref(), Some("Interrupted by a restart"));
+    }
+}
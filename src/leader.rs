@@ -0,0 +1,119 @@
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use uuid::{NoContext, Timestamp, Uuid};
+
+/// Seconds a leader's lease lasts without a heartbeat.
+const DEFAULT_LEASE_SECONDS: i64 = 30;
+
+/// A named lease in the `leases` table that at most one replica holds at a
+/// time. The holder renews it with a heartbeat, and any replica can take it
+/// once it expires.
+pub struct Lease {
+    name: &'static str,
+    /// Identifies this replica, fresh each start
+    holder: String,
+    seconds: i64,
+}
+
+impl Lease {
+    /// The lease for `name`, lasting `LEADER_LEASE_SECONDS`.
+    pub fn from_env(name: &'static str) -> Self {
+        let seconds = env::var("LEADER_LEASE_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|seconds| *seconds > 0)
+            .unwrap_or(DEFAULT_LEASE_SECONDS);
+        Lease {
+            name,
+            holder: Uuid::new_v7(Timestamp::now(NoContext)).to_string(),
+            seconds,
+        }
+    }
+
+    /// Takes the lease if it's free or expired, or renews it if this replica
+    /// already holds it. Returns whether this replica holds it now.
+    pub async fn try_acquire(&self, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+        let expires = format!("+{} seconds", self.seconds);
+        let held = sqlx::query_scalar!(
+            r#"
+            INSERT INTO leases (name, holder, expires_at)
+            VALUES (?, ?, datetime('now', ?))
+            ON CONFLICT (name) DO UPDATE
+            SET holder = excluded.holder, expires_at = excluded.expires_at
+            WHERE leases.holder = excluded.holder OR leases.expires_at <= datetime('now')
+            RETURNING holder
+            "#,
+            self.name,
+            self.holder,
+            expires
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(held.is_some())
+    }
+
+    /// Renews the lease in the background, a few times per lease. The
+    /// returned flag says whether this replica is the leader, and
+    /// `on_elected` runs each time it becomes one.
+    pub fn spawn_heartbeat<F, Fut>(self, pool: SqlitePool, on_elected: F) -> Arc<AtomicBool>
+    where
+        F: Fn(SqlitePool) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let leader = Arc::new(AtomicBool::new(false));
+        let flag = leader.clone();
+        crate::site::spawn(async move {
+            let period = Duration::from_secs((self.seconds as u64 / 3).max(1));
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let held = match self.try_acquire(&pool).await {
+                    Ok(held) => held,
+                    Err(e) => {
+                        eprintln!("Database error renewing the {} lease: {:?}", self.name, e);
+                        false
+                    }
+                };
+                let was_leader = flag.swap(held, Ordering::SeqCst);
+                if held && !was_leader {
+                    println!("Replica {} is now the {} leader", self.holder, self.name);
+                    on_elected(pool.clone()).await;
+                } else if !held && was_leader {
+                    println!(
+                        "Replica {} is no longer the {} leader",
+                        self.holder, self.name
+                    );
+                }
+            }
+        });
+        leader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lease() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let first = Lease::from_env("worker");
+        let second = Lease::from_env("worker");
+
+        assert!(first.try_acquire(&pool).await.unwrap());
+        assert!(!second.try_acquire(&pool).await.unwrap());
+        assert!(first.try_acquire(&pool).await.unwrap());
+
+        // An expired lease is up for grabs
+        sqlx::query!("UPDATE leases SET expires_at = datetime('now', '-1 seconds')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(second.try_acquire(&pool).await.unwrap());
+        assert!(!first.try_acquire(&pool).await.unwrap());
+    }
+}
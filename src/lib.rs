@@ -0,0 +1,10 @@
+//! Types for consuming the flood alert API from other Rust services. With
+//! the `client` feature this also has a small client for the API:
+//!
+//! ```toml
+//! mill-valley-sausalito-bikepath-flood-alert = { git = "https://github.com/jbandoro/mill-valley-sausalito-bikepath-flood-alert", default-features = false, features = ["client"] }
+//! ```
+
+pub mod api_types;
+#[cfg(feature = "client")]
+pub mod client;
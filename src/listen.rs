@@ -0,0 +1,165 @@
+use axum::Router;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::env;
+use std::io;
+use std::net::{AddrParseError, SocketAddr, ToSocketAddrs};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+
+use crate::error::AppError;
+
+/// Port listened on when `LISTEN_ADDRS` isn't set.
+const DEFAULT_PORT: u16 = 3000;
+const BACKLOG: i32 = 1024;
+
+/// Where `serve` listens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenConfig {
+    /// From `LISTEN_ADDRS`, or `HOST` on port 3000
+    pub public: Vec<SocketAddr>,
+    /// From `INTERNAL_LISTEN_ADDRS`, e.g. a localhost only port for operators
+    pub internal: Vec<SocketAddr>,
+    /// Serve `/admin` and `/metrics` on the internal addresses only
+    pub admin_internal_only: bool,
+}
+
+/// Comma separated socket addresses, e.g. `[::]:3000,0.0.0.0:3000`.
+pub fn parse_addrs(value: &str) -> Result<Vec<SocketAddr>, AddrParseError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+impl ListenConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |key: &str| var(key).filter(|value| !value.trim().is_empty());
+        let addrs = |key: &str| {
+            var(key).map(|value| {
+                parse_addrs(&value)
+                    .unwrap_or_else(|e| panic!("{} has an invalid address: {}", key, e))
+            })
+        };
+        let public = addrs("LISTEN_ADDRS").unwrap_or_else(|| {
+            let host = var("HOST").unwrap_or_else(|| "127.0.0.1".to_string());
+            (host.as_str(), DEFAULT_PORT)
+                .to_socket_addrs()
+                .unwrap_or_else(|e| panic!("HOST can't be listened on: {}", e))
+                .take(1)
+                .collect()
+        });
+        ListenConfig {
+            public,
+            internal: addrs("INTERNAL_LISTEN_ADDRS").unwrap_or_default(),
+            admin_internal_only: var("ADMIN_INTERNAL_ONLY").is_some_and(|value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("ADMIN_INTERNAL_ONLY must be true or false"))
+            }),
+        }
+    }
+}
+
+/// Binds like `TcpListener::bind`. With `only_v6` an IPv6 address doesn't
+/// also take IPv4, so `[::]:3000` and `0.0.0.0:3000` can be listened on
+/// together.
+fn bind(addr: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves `public` and `internal` on their addresses until one of the
+/// listeners fails, telling systemd it's ready once they're all bound.
+pub async fn serve(
+    config: &ListenConfig,
+    public: Router,
+    internal: Router,
+) -> Result<(), AppError> {
+    let all: Vec<SocketAddr> = config
+        .public
+        .iter()
+        .chain(&config.internal)
+        .copied()
+        .collect();
+    let mut servers = JoinSet::new();
+    for (addrs, app, kind) in [
+        (&config.public, public, "public"),
+        (&config.internal, internal, "internal"),
+    ] {
+        for &addr in addrs {
+            let only_v6 = all
+                .iter()
+                .any(|other| other.is_ipv4() && other.port() == addr.port());
+            let listener = bind(addr, only_v6).map_err(|e| {
+                io::Error::new(e.kind(), format!("Can't listen on {}: {}", addr, e))
+            })?;
+            println!("Server running on http://{} ({})", addr, kind);
+            let service = app
+                .clone()
+                .into_make_service_with_connect_info::<SocketAddr>();
+            servers.spawn(async move { axum::serve(listener, service).await });
+        }
+    }
+    crate::systemd::ready();
+    while let Some(result) = servers.join_next().await {
+        result.map_err(|e| AppError::Internal(format!("A listener failed: {}", e)))??;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_listen_config() {
+        let config = |vars: &[(&str, &str)]| {
+            let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+            ListenConfig::from_vars(|key| vars.get(key).map(|value| value.to_string()))
+        };
+
+        assert_eq!(
+            config(&[]),
+            ListenConfig {
+                public: vec!["127.0.0.1:3000".parse().unwrap()],
+                internal: Vec::new(),
+                admin_internal_only: false,
+            }
+        );
+        assert_eq!(
+            config(&[("HOST", "::")]).public,
+            vec!["[::]:3000".parse().unwrap()]
+        );
+        assert_eq!(
+            config(&[
+                ("HOST", "0.0.0.0"),
+                ("LISTEN_ADDRS", "[::]:3000, 0.0.0.0:3000"),
+                ("INTERNAL_LISTEN_ADDRS", "127.0.0.1:3001"),
+                ("ADMIN_INTERNAL_ONLY", "true"),
+            ]),
+            ListenConfig {
+                public: vec![
+                    "[::]:3000".parse().unwrap(),
+                    "0.0.0.0:3000".parse().unwrap()
+                ],
+                internal: vec!["127.0.0.1:3001".parse().unwrap()],
+                admin_internal_only: true,
+            }
+        );
+        assert!(parse_addrs("localhost:3000").is_err());
+    }
+}
@@ -1,21 +1,50 @@
+use crate::calendar;
+use crate::contact::ContactRequest;
+use crate::email_address;
+use crate::email_style::EmailStyle;
+use crate::email_templates::{
+    EmailFloodsTemplate, EmailKind, EmailTemplate, floods_text, theme_variables,
+};
+use crate::error::template_failed;
+use crate::i18n::DateFormat;
+use crate::metrics;
 use crate::models::{FloodDisplay, User};
+use crate::site::site;
+use crate::tide_chart;
+use crate::tides::{self, FloodTide};
+use crate::tracking;
 use askama::Template;
-use lettre::message::MultiPart;
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart};
+use std::env;
+use std::path::PathBuf;
 use thiserror::Error;
+use tracing::instrument;
 
 use lettre::message::header::{HeaderName, HeaderValue};
 use lettre::transport::smtp::authentication::Credentials;
-use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::{
+    AsyncFileTransport, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio1Executor,
+};
 
 use lettre::transport::smtp::client::{Tls, TlsParameters};
 
 pub const NOTIFY_EMAIL_FORECAST_DAYS: i64 = 7;
+/// Where the file transport writes messages without `MAIL_DIR`.
+pub const DEFAULT_MAIL_DIR: &str = "data/mail";
+/// Label of email sends in the delivery metrics.
+const EMAIL_CHANNEL: &str = "email";
 
 #[derive(Template)]
 #[template(path = "verification_email.html")]
 pub struct VerifyTemplate<'a> {
     pub verification_link: &'a str,
+    pub verification_code: &'a str,
+    pub verify_page_link: &'a str,
     pub unsubscribe_link: &'a str,
+    pub style: EmailStyle,
 }
 
 #[derive(Template)]
@@ -23,8 +52,33 @@ pub struct VerifyTemplate<'a> {
 pub struct NotificationTemplate<'a> {
     pub predictions: &'a Vec<FloodDisplay>,
     pub homepage_url: &'a str,
+    pub reminders_link: &'a str,
+    pub feedback_link: &'a str,
     pub unsubscribe_link: &'a str,
     pub forecast_days: i64,
+    /// Shows the inline tide chart
+    pub chart: bool,
+    pub style: EmailStyle,
+}
+
+#[derive(Template)]
+#[template(path = "reminder_email.html")]
+pub struct ReminderTemplate<'a> {
+    pub predictions: &'a [FloodDisplay],
+    pub window_hours: i64,
+    pub homepage_url: &'a str,
+    pub reminders_link: &'a str,
+    pub unsubscribe_link: &'a str,
+    pub style: EmailStyle,
+}
+
+#[derive(Template)]
+#[template(path = "correction_email.html")]
+pub struct CorrectionTemplate<'a> {
+    pub predictions: &'a [FloodDisplay],
+    pub homepage_url: &'a str,
+    pub unsubscribe_link: &'a str,
+    pub style: EmailStyle,
 }
 
 #[derive(Error, Debug)]
@@ -35,23 +89,228 @@ pub enum EmailError {
     MessageBuildError(#[from] lettre::error::Error),
     #[error("SMTP transport error: {0}")]
     SmtpTransportError(#[from] lettre::transport::smtp::Error),
+    #[error("Sendmail error: {0}")]
+    SendmailError(#[from] lettre::transport::sendmail::Error),
+    #[error("Mail file error: {0}")]
+    FileError(#[from] lettre::transport::file::Error),
 }
 
-pub struct SmtpClient {
-    pub transport: AsyncSmtpTransport<Tokio1Executor>,
-    pub from_email: String,
-    pub base_url: String,
+/// Replies meaning the mailbox doesn't exist or can't receive mail, rather
+/// than a problem with our own server or credentials.
+fn is_bounce_code(code: &str) -> bool {
+    matches!(code, "550" | "551" | "553")
 }
 
-impl SmtpClient {
-    pub fn new(
-        host: String,
-        port: u16,
-        user: String,
-        pass: String,
-        from_email: String,
-        base_url: String,
-    ) -> Self {
+/// Whether the recipient's mail server permanently rejected the message.
+pub fn is_bounce(error: &EmailError) -> bool {
+    match error {
+        EmailError::SmtpTransportError(e) => e
+            .status()
+            .is_some_and(|code| is_bounce_code(&code.to_string())),
+        _ => false,
+    }
+}
+
+/// A file attached to an email.
+pub struct EmailAttachment {
+    pub filename: String,
+    pub content_type: ContentType,
+    pub body: Vec<u8>,
+    /// Shown in the HTML body by `cid:` rather than attached
+    pub content_id: Option<String>,
+}
+
+impl EmailAttachment {
+    /// The floods as calendar events, for adding with one tap.
+    pub fn calendar(calendar: String) -> Self {
+        EmailAttachment {
+            filename: "floods.ics".to_string(),
+            content_type: ContentType::parse("text/calendar; charset=utf-8; method=PUBLISH")
+                .expect("the calendar content type is valid"),
+            body: calendar.into_bytes(),
+            content_id: None,
+        }
+    }
+
+    /// An invite for the flood at `time`.
+    pub fn invite(time: DateTime<Utc>, invite: String) -> Self {
+        EmailAttachment {
+            filename: format!("flood-{}.ics", tides::local(time).format("%Y-%m-%d-%H%M")),
+            content_type: ContentType::parse("text/calendar; charset=utf-8; method=REQUEST")
+                .expect("the invite content type is valid"),
+            body: invite.into_bytes(),
+            content_id: None,
+        }
+    }
+
+    /// The tide chart, shown inline.
+    pub fn tide_chart(png: Vec<u8>) -> Self {
+        EmailAttachment {
+            filename: "tide-chart.png".to_string(),
+            content_type: ContentType::parse("image/png").expect("the PNG content type is valid"),
+            body: png,
+            content_id: Some(tide_chart::CONTENT_ID.to_string()),
+        }
+    }
+
+    fn part(&self) -> lettre::message::SinglePart {
+        let attachment = match &self.content_id {
+            Some(content_id) => Attachment::new_inline(content_id.clone()),
+            None => Attachment::new(self.filename.clone()),
+        };
+        attachment.body(self.body.clone(), self.content_type.clone())
+    }
+}
+
+/// The feed attached to a notification, and when its events were generated.
+struct NotificationCalendar {
+    generated_at: DateTime<Utc>,
+    feed: String,
+}
+
+/// The floods of a notification as written in one date format.
+struct NotificationFloods {
+    format: DateFormat,
+    predictions: Vec<FloodDisplay>,
+    html: String,
+    text: String,
+}
+
+impl NotificationFloods {
+    fn new(floods: &[FloodTide], format: DateFormat) -> Self {
+        let predictions: Vec<FloodDisplay> = floods
+            .iter()
+            .map(|tide| FloodDisplay::localized(tide.prediction_time, tide.height_ft, format))
+            .collect();
+        let html = EmailFloodsTemplate {
+            predictions: &predictions,
+        }
+        .render()
+        .inspect_err(|e| template_failed("email_floods", e))
+        .unwrap_or_default();
+        let text = floods_text(&predictions);
+        NotificationFloods {
+            format,
+            predictions,
+            html,
+            text,
+        }
+    }
+}
+
+/// A flood notification, rendered once and then personalised per recipient.
+pub struct Notification {
+    floods: Vec<FloodTide>,
+    /// In the default date format, re-rendered for recipients who read
+    /// dates differently
+    shown: NotificationFloods,
+    template: Option<EmailTemplate>,
+    /// Attached to every email, as invites for those who asked for them
+    calendar: Option<NotificationCalendar>,
+    /// PNG of the water level over the period, shown by the default template
+    chart: Option<Vec<u8>>,
+}
+
+impl Notification {
+    pub fn new(floods: &[FloodTide], template: Option<EmailTemplate>) -> Self {
+        Notification {
+            floods: floods.to_vec(),
+            shown: NotificationFloods::new(floods, DateFormat::default()),
+            template,
+            calendar: None,
+            chart: None,
+        }
+    }
+
+    pub fn with_chart(self, png: Vec<u8>) -> Self {
+        Notification {
+            chart: Some(png),
+            ..self
+        }
+    }
+
+    pub fn with_calendar(self, generated_at: DateTime<Utc>) -> Self {
+        Notification {
+            calendar: Some(NotificationCalendar {
+                generated_at,
+                feed: calendar::flood_calendar(&self.floods, generated_at),
+            }),
+            ..self
+        }
+    }
+
+    /// The same notification about just `floods`, for a subscriber who only
+    /// wants some of them.
+    pub fn only(&self, floods: &[FloodTide]) -> Self {
+        let notification = Notification {
+            chart: self.chart.clone(),
+            ..Notification::new(floods, self.template.clone())
+        };
+        match &self.calendar {
+            Some(calendar) => notification.with_calendar(calendar.generated_at),
+            None => notification,
+        }
+    }
+}
+
+/// The links in one recipient's notification.
+pub struct NotificationLinks {
+    /// Goes through the click tracking redirect.
+    pub homepage: String,
+    /// Page for turning flood reminders on or off.
+    pub reminders: String,
+    /// Page for saying whether recent floods really flooded the path.
+    pub feedback: String,
+    pub unsubscribe: String,
+    /// Open tracking pixel, when enabled.
+    pub pixel: Option<String>,
+}
+
+/// The `MAIL_TRANSPORT` values.
+pub const MAIL_TRANSPORTS: [&str; 3] = ["smtp", "sendmail", "file"];
+
+/// How mail leaves the server, picked with `MAIL_TRANSPORT`.
+pub enum MailTransport {
+    /// `smtp`, the default: the relay at `SMTP_SERVER`, over TLS
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    /// `sendmail`: the system's sendmail, or the command in `SENDMAIL_COMMAND`
+    Sendmail(AsyncSendmailTransport<Tokio1Executor>),
+    /// `file`: each message written to `MAIL_DIR` as an .eml file, for
+    /// testing and CI without a relay
+    File(AsyncFileTransport<Tokio1Executor>),
+}
+
+impl MailTransport {
+    pub fn from_env() -> Self {
+        let var = |key| env::var(key).ok().filter(|value| !value.trim().is_empty());
+        match var("MAIL_TRANSPORT").as_deref().map(str::trim) {
+            None | Some("smtp") => MailTransport::smtp(
+                env::var("SMTP_SERVER").expect("SMTP_SERVER must be set"),
+                env::var("SMTP_PORT")
+                    .expect("SMTP_PORT must be set")
+                    .parse()
+                    .expect("SMTP_PORT must be a valid u16"),
+                env::var("SMTP_USER").expect("SMTP_USER must be set"),
+                env::var("SMTP_PASSWORD").expect("SMTP_PASSWORD must be set"),
+            ),
+            Some("sendmail") => MailTransport::Sendmail(match var("SENDMAIL_COMMAND") {
+                Some(command) => AsyncSendmailTransport::new_with_command(command),
+                None => AsyncSendmailTransport::new(),
+            }),
+            Some("file") => {
+                let dir = mail_dir();
+                std::fs::create_dir_all(&dir).expect("MAIL_DIR must be a writable directory");
+                MailTransport::File(AsyncFileTransport::new(dir))
+            }
+            Some(other) => panic!(
+                "MAIL_TRANSPORT must be one of {}: {}",
+                MAIL_TRANSPORTS.join(", "),
+                other
+            ),
+        }
+    }
+
+    pub fn smtp(host: String, port: u16, user: String, pass: String) -> Self {
         let creds = Credentials::new(user, pass);
 
         let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&host[..])
@@ -62,7 +321,42 @@ impl SmtpClient {
                 TlsParameters::new(host.clone()).expect("Failed to create TLS parameters"),
             ))
             .build();
+        MailTransport::Smtp(transport)
+    }
+
+    pub async fn send(&self, email: Message) -> Result<(), EmailError> {
+        match self {
+            MailTransport::Smtp(transport) => {
+                transport.send(email).await?;
+            }
+            MailTransport::Sendmail(transport) => {
+                transport.send(email).await?;
+            }
+            MailTransport::File(transport) => {
+                transport.send(email).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The file transport's directory, from `MAIL_DIR`.
+pub fn mail_dir() -> PathBuf {
+    env::var("MAIL_DIR")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_MAIL_DIR.to_string())
+        .into()
+}
+
+pub struct SmtpClient {
+    pub transport: MailTransport,
+    pub from_email: String,
+    pub base_url: String,
+}
 
+impl SmtpClient {
+    pub fn new(transport: MailTransport, from_email: String, base_url: String) -> Self {
         Self {
             transport,
             from_email,
@@ -70,58 +364,312 @@ impl SmtpClient {
         }
     }
 
+    /// Sends the verification email, using the admin edited `template` in
+    /// place of the compiled one when there is one.
+    #[instrument(level = "debug", skip_all, fields(template = "verification"))]
     pub async fn send_verification_email(
         &self,
         user: &User,
         verification_link: &str,
         unsubscribe_link: &str,
+        template: Option<&EmailTemplate>,
     ) -> Result<(), EmailError> {
-        let subject = "Please verify your email";
+        metrics::track(EMAIL_CHANNEL, "verification", async {
+            let verify_page_link = format!("{}/verify", self.base_url);
+            let mut variables = vec![
+                ("verification_link", verification_link.to_string()),
+                ("verification_code", user.verification_code.clone()),
+                ("verify_page_link", verify_page_link.clone()),
+                ("unsubscribe_link", unsubscribe_link.to_string()),
+                ("site_name", site().name.clone()),
+            ];
+            variables.extend(theme_variables());
 
-        let template = VerifyTemplate {
-            verification_link,
-            unsubscribe_link,
-        };
-        let html_body = template.render().unwrap_or_default();
-        let text_body = format!(
-            "Welcome! Please verify your email address: {}",
-            verification_link
-        );
-        let email = self.build_email(subject, &text_body, &html_body, user, unsubscribe_link)?;
-        self.transport.send(email).await?;
-        Ok(())
+            let rendered = match template {
+                Some(template) => template.render(&variables, &variables),
+                None => {
+                    let mut rendered = EmailKind::Verification
+                        .default_template()
+                        .render(&variables, &variables);
+                    // Keeps the plainer default template's HTML if it fails
+                    match (VerifyTemplate {
+                        verification_link,
+                        verification_code: &user.verification_code,
+                        verify_page_link: &verify_page_link,
+                        unsubscribe_link,
+                        style: user.email_style(),
+                    })
+                    .render()
+                    {
+                        Ok(html) => rendered.html_body = html,
+                        Err(e) => template_failed("verify", &e),
+                    }
+                    rendered
+                }
+            };
+            let email = self.build_email(
+                &rendered.subject,
+                &rendered.text_body,
+                &rendered.html_body,
+                user,
+                unsubscribe_link,
+                &[],
+            )?;
+            self.transport.send(email).await?;
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn send_list_notification_email(
+    /// Sends a notification to one recipient, with `subject` in place of the
+    /// template's when given, and an invite for each flood when they want
+    /// `invites`. Returns the subject that was sent.
+    #[instrument(level = "debug", skip_all, fields(template = "notification"))]
+    pub async fn send_notification_email(
         &self,
-        predictions: Vec<FloodDisplay>,
-        recipients: Vec<User>,
-        unsubscribe_links: Vec<String>,
-    ) -> Result<(), EmailError> {
-        let subject = "MV-Sausalito Bike Path Flooding Forecasted";
+        notification: &Notification,
+        user: &User,
+        links: &NotificationLinks,
+        subject: Option<&str>,
+        invites: bool,
+    ) -> Result<String, EmailError> {
+        metrics::track(EMAIL_CHANNEL, "notification", async {
+            let variables = |floods: &str| {
+                let mut variables = vec![
+                    ("floods", floods.to_string()),
+                    ("forecast_days", NOTIFY_EMAIL_FORECAST_DAYS.to_string()),
+                    ("homepage_url", links.homepage.clone()),
+                    ("reminders_link", links.reminders.clone()),
+                    ("feedback_link", links.feedback.clone()),
+                    ("unsubscribe_link", links.unsubscribe.clone()),
+                    (
+                        "alternate_route",
+                        crate::station_settings::alternate_route(),
+                    ),
+                    ("site_name", site().name.clone()),
+                ];
+                variables.extend(theme_variables());
+                variables
+            };
+            let localized;
+            let shown = match user.date_format() {
+                format if format == notification.shown.format => &notification.shown,
+                format => {
+                    localized = NotificationFloods::new(&notification.floods, format);
+                    &localized
+                }
+            };
+            let (html_variables, text_variables) = (variables(&shown.html), variables(&shown.text));
 
-        for (user, unsubscribe_link) in recipients.iter().zip(unsubscribe_links.iter()) {
-            let template = NotificationTemplate {
-                predictions: &predictions,
-                homepage_url: &self.base_url,
-                unsubscribe_link,
-                forecast_days: NOTIFY_EMAIL_FORECAST_DAYS,
+            let mut rendered = match &notification.template {
+                Some(template) => template.render(&html_variables, &text_variables),
+                None => {
+                    let mut rendered = EmailKind::Notification
+                        .default_template()
+                        .render(&html_variables, &text_variables);
+                    match (NotificationTemplate {
+                        predictions: &shown.predictions,
+                        homepage_url: &links.homepage,
+                        reminders_link: &links.reminders,
+                        feedback_link: &links.feedback,
+                        unsubscribe_link: &links.unsubscribe,
+                        forecast_days: NOTIFY_EMAIL_FORECAST_DAYS,
+                        chart: notification.chart.is_some(),
+                        style: user.email_style(),
+                    })
+                    .render()
+                    {
+                        Ok(html) => rendered.html_body = html,
+                        Err(e) => template_failed("notification", &e),
+                    }
+                    rendered
+                }
+            };
+            if let Some(subject) = subject {
+                rendered.subject = subject.to_string();
+            }
+            if let Some(pixel) = &links.pixel {
+                rendered.html_body = tracking::with_pixel(&rendered.html_body, pixel);
+            }
+
+            let mut attachments = match &notification.calendar {
+                Some(calendar) if invites => {
+                    let organizer = self.from_email.parse::<Mailbox>()?.email.to_string();
+                    let attendee = email_address::deliverable(&user.email);
+                    notification
+                        .floods
+                        .iter()
+                        .map(|flood| {
+                            EmailAttachment::invite(
+                                flood.prediction_time,
+                                calendar::flood_invite(
+                                    flood,
+                                    &organizer,
+                                    &attendee,
+                                    calendar.generated_at,
+                                ),
+                            )
+                        })
+                        .collect()
+                }
+                Some(calendar) => vec![EmailAttachment::calendar(calendar.feed.clone())],
+                None => Vec::new(),
             };
-            let html_body = template.render().unwrap_or_default();
+            // Admin templates have no place for it
+            if notification.template.is_none()
+                && let Some(chart) = &notification.chart
+            {
+                attachments.push(EmailAttachment::tide_chart(chart.clone()));
+            }
+            let email = self.build_email(
+                &rendered.subject,
+                &rendered.text_body,
+                &rendered.html_body,
+                user,
+                &links.unsubscribe,
+                &attachments,
+            )?;
+            self.transport.send(email).await?;
+            Ok(rendered.subject)
+        })
+        .await
+    }
+
+    /// Sends a reminder about floods coming up in the next `window_hours`.
+    #[instrument(level = "debug", skip_all, fields(template = "reminder"))]
+    pub async fn send_reminder_email(
+        &self,
+        user: &User,
+        predictions: &[FloodDisplay],
+        window_hours: i64,
+        links: &NotificationLinks,
+    ) -> Result<(), EmailError> {
+        metrics::track(EMAIL_CHANNEL, "reminder", async {
+            let html_body = ReminderTemplate {
+                predictions,
+                window_hours,
+                homepage_url: &links.homepage,
+                reminders_link: &links.reminders,
+                unsubscribe_link: &links.unsubscribe,
+                style: user.email_style(),
+            }
+            .render()
+            .inspect_err(|e| template_failed("reminder", e))
+            .unwrap_or_default();
+            let alternate_route = crate::station_settings::alternate_route();
             let text_body = format!(
-                "Upcoming potential floods for the MV-Sausalito bike path. Please visit {} for details.\n\nUnsubscribe link: {}",
-                &self.base_url, &unsubscribe_link
+                "The {} is likely to flood in the next {} hours, around these predicted high tides:\n\n{}\n\n{}Latest forecast: {}\nTurn reminders off: {}",
+                site().name,
+                window_hours,
+                floods_text(predictions),
+                if alternate_route.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n\n", alternate_route)
+                },
+                links.homepage,
+                links.reminders
             );
 
-            let email_msg =
-                self.build_email(subject, &text_body, &html_body, user, unsubscribe_link)?;
+            let email = self.build_email(
+                &format!("Reminder: {} Flooding Soon", site().name),
+                &text_body,
+                &html_body,
+                user,
+                &links.unsubscribe,
+                &[],
+            )?;
+            self.transport.send(email).await?;
+            Ok(())
+        })
+        .await
+    }
 
-            self.transport.send(email_msg).await?;
-        }
+    /// Tells a subscriber that floods they were notified about are no longer
+    /// forecast, each shown with its new height, empty when NOAA dropped it.
+    #[instrument(level = "debug", skip_all, fields(template = "correction"))]
+    pub async fn send_correction_email(
+        &self,
+        user: &User,
+        predictions: &[FloodDisplay],
+        links: &NotificationLinks,
+    ) -> Result<(), EmailError> {
+        metrics::track(EMAIL_CHANNEL, "correction", async {
+            let html_body = CorrectionTemplate {
+                predictions,
+                homepage_url: &links.homepage,
+                unsubscribe_link: &links.unsubscribe,
+                style: user.email_style(),
+            }
+            .render()
+            .inspect_err(|e| template_failed("correction", e))
+            .unwrap_or_default();
+            let floods = predictions
+                .iter()
+                .map(|p| match p.height.as_str() {
+                    "" => format!("- {}: no longer predicted", p.datetime),
+                    height => format!("- {}: now {} ft", p.datetime, height),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let text_body = format!(
+                "NOAA has revised its predictions, and these high tides we told you about are no longer expected to flood the {}:\n\n{}\n\nAny other floods in our last email are still forecast. Latest forecast: {}",
+                site().name,
+                floods,
+                links.homepage
+            );
 
-        Ok(())
+            let email = self.build_email(
+                &format!("Update: Fewer {} Floods Forecasted", site().name),
+                &text_body,
+                &html_body,
+                user,
+                &links.unsubscribe,
+                &[],
+            )?;
+            self.transport.send(email).await?;
+            Ok(())
+        })
+        .await
     }
 
+    /// Forwards a contact form message to `to`, replying to the sender.
+    #[instrument(level = "debug", skip_all, fields(template = "contact"))]
+    pub async fn send_contact_message(
+        &self,
+        to: &str,
+        request: &ContactRequest,
+    ) -> Result<(), EmailError> {
+        metrics::track(EMAIL_CHANNEL, "contact", async {
+            let email = Message::builder()
+                .from(self.from_email.parse()?)
+                .to(to.parse()?)
+                .reply_to(email_address::deliverable(&request.email).parse()?)
+                .subject(format!("{} contact form: {}", site().name, request.email))
+                .singlepart(lettre::message::SinglePart::plain(request.forwarded_text()))?;
+            self.transport.send(email).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Emails the operator at `to` about something that needs attention.
+    #[instrument(level = "debug", skip_all, fields(template = "alert"))]
+    pub async fn send_alert(&self, to: &str, subject: &str, text: &str) -> Result<(), EmailError> {
+        metrics::track(EMAIL_CHANNEL, "alert", async {
+            let email = Message::builder()
+                .from(self.from_email.parse()?)
+                .to(to.parse()?)
+                .subject(format!("{}: {}", site().name, subject))
+                .singlepart(lettre::message::SinglePart::plain(text.to_string()))?;
+            self.transport.send(email).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The email to `user`, with the text and HTML bodies as alternatives and
+    /// any `attachments` after them, or beside the HTML when shown inline.
     pub fn build_email(
         &self,
         subject: &str,
@@ -129,10 +677,33 @@ impl SmtpClient {
         html_body: &str,
         user: &User,
         unsubscribe_link: &str,
+        attachments: &[EmailAttachment],
     ) -> Result<Message, EmailError> {
+        let body = MultiPart::alternative().singlepart(lettre::message::SinglePart::plain(
+            format!("{}\n\nUnsubscribe link:{}", text_body, unsubscribe_link),
+        ));
+        let html = lettre::message::SinglePart::html(html_body.to_string());
+        let (inline, attached): (Vec<_>, Vec<_>) = attachments
+            .iter()
+            .partition(|attachment| attachment.content_id.is_some());
+        let body = match inline[..] {
+            [] => body.singlepart(html),
+            _ => body.multipart(inline.iter().fold(
+                MultiPart::related().singlepart(html),
+                |related, attachment| related.singlepart(attachment.part()),
+            )),
+        };
+        let body = match attached[..] {
+            [] => body,
+            _ => attached
+                .iter()
+                .fold(MultiPart::mixed().multipart(body), |mixed, attachment| {
+                    mixed.singlepart(attachment.part())
+                }),
+        };
         Ok(Message::builder()
             .from(self.from_email.parse()?)
-            .to(user.email.parse()?)
+            .to(email_address::deliverable(&user.email).parse()?)
             .subject(subject)
             .raw_header(HeaderValue::new(
                 HeaderName::new_from_ascii_str("List-Unsubscribe"),
@@ -142,33 +713,81 @@ impl SmtpClient {
                 HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
                 "List-Unsubscribe=One-Click".to_string(),
             ))
-            .multipart(
-                MultiPart::alternative()
-                    .singlepart(lettre::message::SinglePart::plain(format!(
-                        "{}\n\nUnsubscribe link:{}",
-                        text_body, unsubscribe_link
-                    )))
-                    .singlepart(lettre::message::SinglePart::html(html_body.to_string())),
-            )?)
+            .multipart(body)?)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::email_style::EmailLayout;
     use crate::models::FloodDisplay;
 
     #[test]
     fn test_verify_template_render() {
         let template = VerifyTemplate {
             verification_link: "http://example.com/verify?token=123",
+            verification_code: "042317",
+            verify_page_link: "http://example.com/verify",
             unsubscribe_link: "http://example.com/unsubscribe?token=123",
+            style: EmailStyle::default(),
         };
         let rendered = template.render().unwrap();
         assert!(rendered.contains("http://example.com/verify?token=123"));
+        assert!(rendered.contains("042317"));
         assert!(rendered.contains("http://example.com/unsubscribe?token=123"));
     }
 
+    #[test]
+    fn test_build_email_attachments() {
+        let client = SmtpClient::new(
+            MailTransport::File(AsyncFileTransport::new(mail_dir())),
+            "alerts@example.com".to_string(),
+            "http://example.com".to_string(),
+        );
+        let user = User::new("a@example.com".to_string());
+        let build = |attachments: &[EmailAttachment]| {
+            let email = client
+                .build_email(
+                    "Floods",
+                    "text",
+                    "<p>html</p>",
+                    &user,
+                    "http://u",
+                    attachments,
+                )
+                .unwrap();
+            String::from_utf8(email.formatted()).unwrap()
+        };
+
+        let plain = build(&[]);
+        assert!(plain.contains("multipart/alternative"));
+        assert!(!plain.contains("multipart/mixed"));
+
+        let calendar = "BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n".to_string();
+        let attached = build(&[EmailAttachment::calendar(calendar)]);
+        assert!(attached.contains("multipart/mixed"));
+        assert!(attached.contains("multipart/alternative"));
+        assert!(attached.contains("Content-Disposition: attachment; filename=\"floods.ics\""));
+        assert!(attached.contains("text/calendar; charset=utf-8; method=PUBLISH"));
+
+        let chart = EmailAttachment::tide_chart(b"\x89PNG".to_vec());
+        let charted = build(&[chart]);
+        assert!(charted.contains("multipart/related"));
+        assert!(!charted.contains("multipart/mixed"));
+        assert!(charted.contains("Content-ID: <tide-chart>"));
+        assert!(charted.contains("Content-Disposition: inline"));
+    }
+
+    #[test]
+    fn test_is_bounce_code() {
+        assert!(is_bounce_code("550"));
+        assert!(is_bounce_code("553"));
+        // Auth failures and full mailboxes aren't the address's fault
+        assert!(!is_bounce_code("535"));
+        assert!(!is_bounce_code("452"));
+    }
+
     #[test]
     fn test_notification_template_render() {
         let predictions = vec![
@@ -185,8 +804,12 @@ mod tests {
         let template = NotificationTemplate {
             predictions: &predictions,
             homepage_url: "http://example.com",
+            reminders_link: "http://example.com/reminders",
+            feedback_link: "http://example.com/feedback?id=1&token=abc",
             unsubscribe_link: "http://example.com/unsub",
             forecast_days: NOTIFY_EMAIL_FORECAST_DAYS,
+            chart: false,
+            style: EmailStyle::default(),
         };
 
         let rendered = template.render().unwrap();
@@ -195,6 +818,117 @@ mod tests {
         assert!(rendered.contains("Tuesday, January 2 at 11:00AM"));
         assert!(rendered.contains("7.0"));
         assert!(rendered.contains("http://example.com/unsub"));
+        assert!(rendered.contains("http://example.com/feedback?id=1"));
         assert!(rendered.contains("next 7 days"));
+        assert!(rendered.contains("Dear Subscriber"));
+        assert!(!rendered.contains("prefers-color-scheme"));
+
+        let compact = NotificationTemplate {
+            style: EmailStyle {
+                layout: EmailLayout::Compact,
+                dark_mode: true,
+            },
+            ..template
+        }
+        .render()
+        .unwrap();
+        assert!(compact.contains("Monday, January 1 at 10:00AM"));
+        assert!(!compact.contains("Dear Subscriber"));
+        assert!(compact.contains("padding: 16px;"));
+        assert!(compact.contains("@media (prefers-color-scheme: dark)"));
+        assert!(compact.contains(r#"<meta name="color-scheme" content="light dark">"#));
+    }
+
+    #[tokio::test]
+    async fn test_notification_only_some_floods() {
+        let dir = env::temp_dir().join(format!("notification-{}", User::new(String::new()).id));
+        std::fs::create_dir_all(&dir).unwrap();
+        let client = SmtpClient::new(
+            MailTransport::File(AsyncFileTransport::new(&dir)),
+            "alerts@example.com".to_string(),
+            "http://example.com".to_string(),
+        );
+        // Thursday 9:45am and 11pm Pacific
+        let floods = [
+            ("2026-10-15T16:45:00Z", 6.61),
+            ("2026-10-16T06:00:00Z", 7.23),
+        ]
+        .map(|(time, height_ft)| FloodTide {
+            prediction_time: time.parse().unwrap(),
+            height_ft,
+        });
+        let notification = Notification::new(&floods, None).with_calendar(Utc::now());
+        let links = NotificationLinks {
+            homepage: "http://example.com".to_string(),
+            reminders: "http://example.com/reminders".to_string(),
+            feedback: "http://example.com/feedback".to_string(),
+            unsubscribe: "http://example.com/unsub".to_string(),
+            pixel: None,
+        };
+        client
+            .send_notification_email(
+                &notification.only(&floods[..1]),
+                &User::new("a@example.com".to_string()),
+                &links,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+
+        let sent = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect::<String>();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(sent.contains("6.61 ft"));
+        assert!(!sent.contains("7.23"));
+        assert_eq!(sent.matches("BEGIN:VEVENT").count(), 1);
+    }
+
+    #[test]
+    fn test_correction_template_render() {
+        let predictions = vec![
+            FloodDisplay {
+                datetime: "Friday, October 16 at 7:30AM".to_string(),
+                height: "6.20".to_string(),
+            },
+            FloodDisplay {
+                datetime: "Saturday, October 17 at 8:10AM".to_string(),
+                height: String::new(),
+            },
+        ];
+        let rendered = CorrectionTemplate {
+            predictions: &predictions,
+            homepage_url: "http://example.com",
+            unsubscribe_link: "http://example.com/unsub",
+            style: EmailStyle::default(),
+        }
+        .render()
+        .unwrap();
+        assert!(rendered.contains("now 6.20 ft"));
+        assert!(rendered.contains("no longer predicted"));
+        assert!(rendered.contains("http://example.com/unsub"));
+    }
+
+    #[test]
+    fn test_reminder_template_render() {
+        let predictions = vec![FloodDisplay {
+            datetime: "Friday, October 16 at 7:30AM".to_string(),
+            height: "6.80".to_string(),
+        }];
+        let rendered = ReminderTemplate {
+            predictions: &predictions,
+            window_hours: 18,
+            homepage_url: "http://example.com",
+            reminders_link: "http://example.com/reminders?id=1&token=abc",
+            unsubscribe_link: "http://example.com/unsub",
+            style: EmailStyle::default(),
+        }
+        .render()
+        .unwrap();
+        assert!(rendered.contains("Friday, October 16 at 7:30AM"));
+        assert!(rendered.contains("next 18 hours"));
+        assert!(rendered.contains("http://example.com/reminders?id=1"));
     }
 }
@@ -0,0 +1,210 @@
+use reqwest::Url;
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashSet;
+use std::env;
+use uuid::Uuid;
+
+use crate::floods::{FloodEvent, group_flood_events};
+use crate::site;
+use crate::tides::{self, FORECAST_DAYS, FloodTide, get_flood_tides};
+
+/// Bot account posting to Matrix rooms, enabled by setting
+/// `MATRIX_HOMESERVER`, `MATRIX_ACCESS_TOKEN` and `MATRIX_ROOM_IDS`.
+pub struct MatrixConfig {
+    homeserver: Url,
+    access_token: String,
+    room_ids: Vec<String>,
+}
+
+impl MatrixConfig {
+    pub fn from_env() -> Option<Self> {
+        let homeserver = Url::parse(&env::var("MATRIX_HOMESERVER").ok()?).ok()?;
+        let access_token = env::var("MATRIX_ACCESS_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())?;
+        let room_ids: Vec<String> = site::var("MATRIX_ROOM_IDS")?
+            .split(',')
+            .map(|room| room.trim().to_string())
+            .filter(|room| !room.is_empty())
+            .collect();
+        (!room_ids.is_empty()).then_some(MatrixConfig {
+            homeserver,
+            access_token,
+            room_ids,
+        })
+    }
+
+    fn send_url(&self, room_id: &str) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("homeserver URL can't be a base")
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room_id,
+                "send",
+                "m.room.message",
+                &Uuid::new_v4().to_string(),
+            ]);
+        url
+    }
+}
+
+/// Events in `after` that start on a day no event in `before` started on.
+/// Compared by day since an updated prediction can shift by a few minutes.
+fn new_events(before: Vec<FloodTide>, after: Vec<FloodTide>) -> Vec<FloodEvent> {
+    let known: HashSet<_> = group_flood_events(before)
+        .into_iter()
+        .map(|event| tides::local(event.start).date())
+        .collect();
+    group_flood_events(after)
+        .into_iter()
+        .filter(|event| !known.contains(&tides::local(event.start).date()))
+        .collect()
+}
+
+fn describe(event: &FloodEvent) -> String {
+    let (start, end) = (tides::local(event.start), tides::local(event.end));
+    let days = if start.date() == end.date() {
+        format!("on {}", start.format("%a, %b %-d"))
+    } else {
+        format!(
+            "from {} to {}",
+            start.format("%a, %b %-d"),
+            end.format("%a, %b %-d")
+        )
+    };
+    format!(
+        "{}, peaking at {:.2} ft at {} ({})",
+        days,
+        event.peak_height_ft,
+        tides::local(event.peak_time).format("%-I:%M%p on %a, %b %-d"),
+        event.severity.as_str()
+    )
+}
+
+/// Plain and HTML bodies of the room message.
+fn message(events: &[FloodEvent], homepage: &str) -> (String, String) {
+    let lines: Vec<String> = events.iter().map(describe).collect();
+    let plain = format!(
+        "New bike path flooding predicted:\n{}\n{}",
+        lines
+            .iter()
+            .map(|line| format!("- {}", line))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        homepage
+    );
+    let html = format!(
+        "<p>New bike path flooding predicted:</p><ul>{}</ul><p><a href=\"{}\">Full forecast</a></p>",
+        lines
+            .iter()
+            .map(|line| format!("<li>{}</li>", line))
+            .collect::<String>(),
+        homepage
+    );
+    (plain, html)
+}
+
+/// Posts flood events the sync added to the rooms in `MATRIX_ROOM_IDS`,
+/// given the flood tides from before it. Errors are logged rather than
+/// failing the sync.
+pub async fn post_new_events(pool: &SqlitePool, before: Vec<FloodTide>) {
+    let Some(config) = MatrixConfig::from_env() else {
+        return;
+    };
+    let after = match get_flood_tides(pool, FORECAST_DAYS).await {
+        Ok(after) => after,
+        Err(e) => {
+            eprintln!("Error fetching floods for Matrix: {}", e);
+            return;
+        }
+    };
+    let events = new_events(before, after);
+    if events.is_empty() {
+        return;
+    }
+
+    let homepage = site::var("BASE_URL").unwrap_or_default();
+    let (plain, html) = message(&events, &homepage);
+    let body = json!({
+        "msgtype": "m.notice",
+        "body": plain,
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    });
+    let http = reqwest::Client::new();
+    for room_id in &config.room_ids {
+        let result = http
+            .put(config.send_url(room_id))
+            .bearer_auth(&config.access_token)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => println!("Posted {} new flood events to {}", events.len(), room_id),
+            Err(e) => eprintln!("Error posting to Matrix room {}: {}", room_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn tide(time: &str, height_ft: f64) -> FloodTide {
+        FloodTide {
+            prediction_time: tides::from_local(
+                NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(),
+            ),
+            height_ft,
+        }
+    }
+
+    #[test]
+    fn test_new_events() {
+        let before = vec![tide("2026-12-12 08:10", 6.5), tide("2026-12-13 08:50", 7.1)];
+        let after = vec![
+            // Shifted by a couple of minutes, still the same event
+            tide("2026-12-12 08:12", 6.5),
+            tide("2026-12-13 08:50", 7.1),
+            tide("2026-12-28 08:00", 6.5),
+        ];
+        let events = new_events(before, after);
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            describe(&events[0]),
+            "on Mon, Dec 28, peaking at 6.50 ft at 8:00AM on Mon, Dec 28 (minor)"
+        );
+
+        let (plain, html) = message(&events, "https://example.com");
+        assert!(plain.starts_with("New bike path flooding predicted:\n- on Mon, Dec 28"));
+        assert!(plain.ends_with("\nhttps://example.com"));
+        assert!(html.contains("<li>on Mon, Dec 28"));
+
+        let all = new_events(
+            Vec::new(),
+            vec![tide("2026-12-12 08:10", 6.5), tide("2026-12-13 08:50", 7.1)],
+        );
+        assert!(describe(&all[0]).starts_with("from Sat, Dec 12 to Sun, Dec 13"));
+    }
+
+    #[test]
+    fn test_send_url() {
+        let config = MatrixConfig {
+            homeserver: Url::parse("https://matrix.example.org/").unwrap(),
+            access_token: "token".to_string(),
+            room_ids: vec!["!abc:example.org".to_string()],
+        };
+        let url = config.send_url("!abc:example.org").to_string();
+        assert!(url.starts_with(
+            "https://matrix.example.org/_matrix/client/v3/rooms/!abc:example.org/send/m.room.message/"
+        ));
+    }
+}
@@ -0,0 +1,288 @@
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+
+use crate::api_keys::{bearer_token, hash_key};
+use crate::database::{self, PoolProbe};
+use crate::error::AppError;
+
+/// The Prometheus text exposition format.
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Each counter's outcome and help text.
+const COUNTERS: [(&str, &str); 3] = [
+    ("attempted", "Messages the server started sending"),
+    (
+        "succeeded",
+        "Messages accepted by the mail server or push service",
+    ),
+    ("failed", "Messages that couldn't be built or were rejected"),
+];
+
+/// A channel and template, e.g. `("email", "verification")`.
+type SendKey = (&'static str, &'static str);
+
+static SENDS: LazyLock<Mutex<BTreeMap<SendKey, SendCounts>>> = LazyLock::new(Default::default);
+
+/// Errors by `AppError::kind` since the process started.
+static ERRORS: LazyLock<Mutex<BTreeMap<&'static str, u64>>> = LazyLock::new(Default::default);
+
+/// Sends of one template over one channel since the process started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct SendCounts {
+    attempted: u64,
+    succeeded: u64,
+    failed: u64,
+}
+
+impl SendCounts {
+    fn get(&self, outcome: &str) -> u64 {
+        match outcome {
+            "attempted" => self.attempted,
+            "succeeded" => self.succeeded,
+            _ => self.failed,
+        }
+    }
+}
+
+/// Counts `send` as attempted, then as succeeded or failed once it finishes,
+/// e.g. `track("email", "verification", async { ... })`.
+pub async fn track<T, E>(
+    channel: &'static str,
+    template: &'static str,
+    send: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    update(channel, template, |counts| counts.attempted += 1);
+    let result = send.await;
+    update(channel, template, |counts| match result {
+        Ok(_) => counts.succeeded += 1,
+        Err(_) => counts.failed += 1,
+    });
+    result
+}
+
+fn update(channel: &'static str, template: &'static str, change: impl FnOnce(&mut SendCounts)) {
+    let mut sends = SENDS.lock().unwrap();
+    change(sends.entry((channel, template)).or_default());
+}
+
+/// Reports the counters as OTLP observable counters, read at each export.
+#[cfg(feature = "otel")]
+pub fn register(meter: &opentelemetry::metrics::Meter) {
+    use opentelemetry::KeyValue;
+
+    for (outcome, help) in COUNTERS {
+        meter
+            .u64_observable_counter(format!("flood_alert_sends_{}", outcome))
+            .with_description(help)
+            .with_callback(move |observer| {
+                for ((channel, template), counts) in SENDS.lock().unwrap().iter() {
+                    observer.observe(
+                        counts.get(outcome),
+                        &[
+                            KeyValue::new("channel", *channel),
+                            KeyValue::new("template", *template),
+                        ],
+                    );
+                }
+            })
+            .build();
+    }
+    meter
+        .u64_observable_counter("flood_alert_errors")
+        .with_description("Errors from requests and job runs")
+        .with_callback(|observer| {
+            for (kind, count) in ERRORS.lock().unwrap().iter() {
+                observer.observe(*count, &[KeyValue::new("kind", *kind)]);
+            }
+        })
+        .build();
+    for (name, _, help, value) in POOL_METRICS {
+        meter
+            .f64_observable_gauge(name)
+            .with_description(help)
+            .with_callback(move |observer| {
+                for (site, probe) in database::pool_probes() {
+                    observer.observe(value(&probe), &[KeyValue::new("site", site)]);
+                }
+            })
+            .build();
+    }
+}
+
+/// Counts an error of `kind`, from a response or a failed job run.
+pub fn count_error(kind: &'static str) {
+    *ERRORS.lock().unwrap().entry(kind).or_default() += 1;
+}
+
+fn render_errors(errors: &BTreeMap<&'static str, u64>) -> String {
+    let mut text = String::new();
+    let name = "flood_alert_errors_total";
+    let _ = writeln!(text, "# HELP {} Errors from requests and job runs.", name);
+    let _ = writeln!(text, "# TYPE {} counter", name);
+    for (kind, count) in errors {
+        let _ = writeln!(text, "{}{{kind=\"{}\"}} {}", name, kind, count);
+    }
+    text
+}
+
+/// Each pool gauge and counter, its type, help text and value.
+type PoolMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&PoolProbe) -> f64,
+);
+
+const POOL_METRICS: [PoolMetric; 6] = [
+    (
+        "flood_alert_db_pool_connections",
+        "gauge",
+        "Open database connections",
+        |probe| probe.size as f64,
+    ),
+    (
+        "flood_alert_db_pool_idle_connections",
+        "gauge",
+        "Open database connections not in use",
+        |probe| probe.idle as f64,
+    ),
+    (
+        "flood_alert_db_pool_max_connections",
+        "gauge",
+        "Database connections the pool can open",
+        |probe| probe.max_connections as f64,
+    ),
+    (
+        "flood_alert_db_pool_acquire_wait_seconds",
+        "gauge",
+        "How long the latest probe waited for a database connection",
+        |probe| probe.last_wait.map_or(0.0, |wait| wait.as_secs_f64()),
+    ),
+    (
+        "flood_alert_db_pool_acquire_wait_seconds_total",
+        "counter",
+        "Time every probe waited for a database connection",
+        |probe| probe.total_wait.as_secs_f64(),
+    ),
+    (
+        "flood_alert_db_pool_acquire_timeouts_total",
+        "counter",
+        "Probes that timed out waiting for a database connection",
+        |probe| probe.timeouts as f64,
+    ),
+];
+
+fn render_pools(probes: &BTreeMap<String, PoolProbe>) -> String {
+    let mut text = String::new();
+    for (name, kind, help, value) in POOL_METRICS {
+        let _ = writeln!(text, "# HELP {} {}.", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for (site, probe) in probes {
+            let _ = writeln!(text, "{}{{site=\"{}\"}} {}", name, site, value(probe));
+        }
+    }
+    text
+}
+
+fn render(sends: &BTreeMap<SendKey, SendCounts>) -> String {
+    let mut text = String::new();
+    for (outcome, help) in COUNTERS {
+        let name = format!("flood_alert_sends_{}_total", outcome);
+        let _ = writeln!(text, "# HELP {} {}.", name, help);
+        let _ = writeln!(text, "# TYPE {} counter", name);
+        for ((channel, template), counts) in sends {
+            let _ = writeln!(
+                text,
+                "{}{{channel=\"{}\",template=\"{}\"}} {}",
+                name,
+                channel,
+                template,
+                counts.get(outcome)
+            );
+        }
+    }
+    text
+}
+
+/// The scrape token from `METRICS_TOKEN`. `/metrics` is off without one.
+fn metrics_token() -> Option<String> {
+    env::var("METRICS_TOKEN")
+        .ok()
+        .filter(|token| !token.trim().is_empty())
+}
+
+/// Delivery counters in the Prometheus text format, for a scraper sending
+/// `Authorization: Bearer <METRICS_TOKEN>`.
+pub async fn metrics_handler(headers: HeaderMap) -> Result<Response, AppError> {
+    let Some(expected) = metrics_token() else {
+        return Err(AppError::NotFound(
+            "There's nothing at this address.".to_string(),
+        ));
+    };
+    // Compared as hashes so the time taken doesn't leak the token
+    let authorized =
+        bearer_token(&headers).is_some_and(|token| hash_key(token) == hash_key(&expected));
+    if !authorized {
+        return Err(AppError::Unauthorized(
+            "A valid metrics token is required".to_string(),
+        ));
+    }
+    let text = render(&SENDS.lock().unwrap())
+        + &render_errors(&ERRORS.lock().unwrap())
+        + &render_pools(&database::pool_probes());
+    Ok((
+        [(CONTENT_TYPE, HeaderValue::from_static(PROMETHEUS_TEXT))],
+        text,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_track() {
+        let ok: Result<(), &str> = track("test", "verification", async { Ok(()) }).await;
+        assert!(ok.is_ok());
+        let failed: Result<(), &str> = track("test", "verification", async { Err("535") }).await;
+        assert!(failed.is_err());
+
+        let counts = SENDS.lock().unwrap()[&("test", "verification")];
+        assert_eq!(
+            counts,
+            SendCounts {
+                attempted: 2,
+                succeeded: 1,
+                failed: 1
+            }
+        );
+
+        let sends = BTreeMap::from([(("email", "reminder"), counts)]);
+        let text = render(&sends);
+        assert!(text.contains("# TYPE flood_alert_sends_attempted_total counter\n"));
+        assert!(text.contains(
+            "flood_alert_sends_failed_total{channel=\"email\",template=\"reminder\"} 1\n"
+        ));
+
+        let errors = BTreeMap::from([("noaa", 2)]);
+        assert!(render_errors(&errors).ends_with("flood_alert_errors_total{kind=\"noaa\"} 2\n"));
+
+        let probe = PoolProbe {
+            size: 3,
+            idle: 1,
+            max_connections: 5,
+            last_wait: Some(std::time::Duration::from_millis(250)),
+            ..PoolProbe::default()
+        };
+        let text = render_pools(&BTreeMap::from([("default".to_string(), probe)]));
+        assert!(text.contains("flood_alert_db_pool_idle_connections{site=\"default\"} 1\n"));
+        assert!(text.contains("flood_alert_db_pool_acquire_wait_seconds{site=\"default\"} 0.25\n"));
+        assert!(text.contains("# TYPE flood_alert_db_pool_acquire_timeouts_total counter\n"));
+    }
+}
@@ -0,0 +1,305 @@
+use chrono::NaiveDateTime;
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use sqlx::{QueryBuilder, Row};
+
+use crate::error::AppError;
+
+/// Tables copied, users first.
+const TABLES: [&str; 7] = [
+    "users",
+    "tides",
+    "events",
+    "notification_log",
+    "reminder_log",
+    "job_runs",
+    "sync_runs",
+];
+/// Rows per INSERT, well under Postgres' 65535 bind parameters.
+const BATCH_ROWS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Text,
+    Integer,
+    Real,
+    Boolean,
+    Timestamp,
+}
+
+impl ColumnType {
+    /// From the type a SQLite column was declared with, following SQLite's
+    /// affinity rules for the ones without a Postgres equivalent.
+    fn from_declared(declared: &str) -> Self {
+        let declared = declared.to_uppercase();
+        if declared == "BOOLEAN" {
+            ColumnType::Boolean
+        } else if declared == "DATETIME" {
+            ColumnType::Timestamp
+        } else if declared.contains("INT") {
+            ColumnType::Integer
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|real| declared.contains(real))
+        {
+            ColumnType::Real
+        } else {
+            ColumnType::Text
+        }
+    }
+
+    fn postgres(self) -> &'static str {
+        match self {
+            ColumnType::Text => "TEXT",
+            ColumnType::Integer => "BIGINT",
+            ColumnType::Real => "DOUBLE PRECISION",
+            ColumnType::Boolean => "BOOLEAN",
+            ColumnType::Timestamp => "TIMESTAMP",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Column {
+    name: String,
+    column_type: ColumnType,
+    not_null: bool,
+    /// Position in the primary key from 1, or 0 when it's not part of it
+    pk: i64,
+}
+
+/// A value read from SQLite, as the Rust type Postgres takes for its column.
+enum Value {
+    Text(Option<String>),
+    Integer(Option<i64>),
+    Real(Option<f64>),
+    Boolean(Option<bool>),
+    Timestamp(Option<NaiveDateTime>),
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+async fn columns(sqlite: &SqlitePool, table: &str) -> Result<Vec<Column>, sqlx::Error> {
+    let rows: Vec<(String, String, bool, i64)> =
+        sqlx::query_as("SELECT name, type, \"notnull\", pk FROM pragma_table_info(?) ORDER BY cid")
+            .bind(table)
+            .fetch_all(sqlite)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(name, declared, not_null, pk)| Column {
+            name,
+            column_type: ColumnType::from_declared(&declared),
+            not_null,
+            pk,
+        })
+        .collect())
+}
+
+/// The integer primary key SQLite assigns, which becomes an identity column
+/// so Postgres carries on numbering after the copied rows.
+fn identity(columns: &[Column]) -> Option<&Column> {
+    match columns.iter().filter(|c| c.pk > 0).collect::<Vec<_>>()[..] {
+        [column] if column.column_type == ColumnType::Integer => Some(column),
+        _ => None,
+    }
+}
+
+fn create_table(table: &str, columns: &[Column]) -> String {
+    let identity = identity(columns).map(|column| column.name.as_str());
+    let mut definitions: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            let mut definition =
+                format!("{} {}", quote(&column.name), column.column_type.postgres());
+            if identity == Some(column.name.as_str()) {
+                definition.push_str(" GENERATED BY DEFAULT AS IDENTITY");
+            }
+            if column.not_null || column.pk > 0 {
+                definition.push_str(" NOT NULL");
+            }
+            definition
+        })
+        .collect();
+    let mut pk: Vec<&Column> = columns.iter().filter(|column| column.pk > 0).collect();
+    pk.sort_by_key(|column| column.pk);
+    if !pk.is_empty() {
+        let names: Vec<String> = pk.iter().map(|column| quote(&column.name)).collect();
+        definitions.push(format!("PRIMARY KEY ({})", names.join(", ")));
+    }
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        table,
+        definitions.join(", ")
+    )
+}
+
+fn read_row(row: &SqliteRow, columns: &[Column]) -> Result<Vec<Value>, sqlx::Error> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            Ok(match column.column_type {
+                ColumnType::Text => Value::Text(row.try_get(i)?),
+                ColumnType::Integer => Value::Integer(row.try_get(i)?),
+                ColumnType::Real => Value::Real(row.try_get(i)?),
+                ColumnType::Boolean => Value::Boolean(row.try_get(i)?),
+                ColumnType::Timestamp => Value::Timestamp(row.try_get(i)?),
+            })
+        })
+        .collect()
+}
+
+/// Copies `table` into `schema`, creating it there. Returns the rows in
+/// SQLite and then in Postgres, to verify they match.
+async fn copy_table(
+    sqlite: &SqlitePool,
+    postgres: &PgPool,
+    schema: &str,
+    table: &str,
+) -> Result<(i64, i64), AppError> {
+    let columns = columns(sqlite, table).await?;
+    if columns.is_empty() {
+        return Err(AppError::NotFound(format!(
+            "The SQLite database has no {} table",
+            table
+        )));
+    }
+    let target = format!("{}.{}", quote(schema), quote(table));
+    let names: Vec<String> = columns.iter().map(|column| quote(&column.name)).collect();
+
+    let mut tx = postgres.begin().await?;
+    sqlx::query(&create_table(&target, &columns))
+        .execute(&mut *tx)
+        .await?;
+    let existing: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", target))
+        .fetch_one(&mut *tx)
+        .await?;
+    if existing > 0 {
+        return Err(AppError::Conflict(format!(
+            "{}.{} already has {} rows",
+            schema, table, existing
+        )));
+    }
+
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM {}",
+        names.join(", "),
+        quote(table)
+    ))
+    .fetch_all(sqlite)
+    .await?;
+    let values = rows
+        .iter()
+        .map(|row| read_row(row, &columns))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::Internal(format!("Error reading {}: {}", table, e)))?;
+    for batch in values.chunks(BATCH_ROWS) {
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} ({}) ",
+            target,
+            names.join(", ")
+        ));
+        query.push_values(batch, |mut b, row| {
+            for value in row {
+                match value {
+                    Value::Text(value) => b.push_bind(value.clone()),
+                    Value::Integer(value) => b.push_bind(*value),
+                    Value::Real(value) => b.push_bind(*value),
+                    Value::Boolean(value) => b.push_bind(*value),
+                    Value::Timestamp(value) => b.push_bind(*value),
+                };
+            }
+        });
+        query.build().execute(&mut *tx).await?;
+    }
+    if let Some(column) = identity(&columns)
+        && !values.is_empty()
+    {
+        sqlx::query(&format!(
+            "SELECT setval(pg_get_serial_sequence($1, $2), MAX({})) FROM {}",
+            quote(&column.name),
+            target
+        ))
+        .bind(&target)
+        .bind(&column.name)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    let in_sqlite: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", quote(table)))
+        .fetch_one(sqlite)
+        .await?;
+    let in_postgres: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", target))
+        .fetch_one(postgres)
+        .await?;
+    Ok((in_sqlite, in_postgres))
+}
+
+/// Copies the users, tides and logs into `schema` of the Postgres database
+/// at `url`, each table in a transaction, then checks every table has as
+/// many rows there. Tables that already have rows there are refused.
+pub async fn run(sqlite: &SqlitePool, url: &str, schema: &str) -> Result<(), AppError> {
+    let postgres = PgPool::connect(url).await?;
+    sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", quote(schema)))
+        .execute(&postgres)
+        .await?;
+
+    let mut mismatched = Vec::new();
+    let mut total = 0;
+    for table in TABLES {
+        let (in_sqlite, in_postgres) = copy_table(sqlite, &postgres, schema, table).await?;
+        println!(
+            "{}: {} rows in SQLite, {} in Postgres",
+            table, in_sqlite, in_postgres
+        );
+        if in_sqlite != in_postgres {
+            mismatched.push(table);
+        }
+        total += in_postgres;
+    }
+    postgres.close().await;
+    if !mismatched.is_empty() {
+        return Err(AppError::Internal(format!(
+            "Row counts differ for {}",
+            mismatched.join(", ")
+        )));
+    }
+    println!(
+        "Copied {} rows in {} tables into the {} schema",
+        total,
+        TABLES.len(),
+        schema
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_create_table() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        assert_eq!(ColumnType::from_declared("datetime"), ColumnType::Timestamp);
+
+        let reminders = columns(&pool, "reminder_log").await.unwrap();
+        assert!(identity(&reminders).is_none());
+        assert_eq!(
+            create_table("\"public\".\"reminder_log\"", &reminders),
+            "CREATE TABLE IF NOT EXISTS \"public\".\"reminder_log\" (\"user_id\" TEXT NOT NULL, \
+             \"prediction_time\" TIMESTAMP NOT NULL, \"sent_at\" TIMESTAMP NOT NULL, \
+             PRIMARY KEY (\"user_id\", \"prediction_time\"))"
+        );
+
+        let runs = create_table("sync_runs", &columns(&pool, "sync_runs").await.unwrap());
+        assert!(runs.contains("\"id\" BIGINT GENERATED BY DEFAULT AS IDENTITY NOT NULL"));
+        assert!(runs.contains("\"duration_ms\" BIGINT NOT NULL"));
+        let users = create_table("users", &columns(&pool, "users").await.unwrap());
+        assert!(users.contains("\"is_verified\" BOOLEAN NOT NULL"));
+    }
+}
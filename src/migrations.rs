@@ -0,0 +1,192 @@
+use chrono::NaiveDateTime;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqlitePool;
+use std::collections::BTreeMap;
+
+use crate::error::AppError;
+
+/// The migrations compiled into this build.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Where a migration stands in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationState {
+    Applied(NaiveDateTime),
+    Pending,
+    /// Started but didn't finish, `migrate run` refuses until it's fixed by hand
+    Failed,
+    /// Applied, but its file has been edited since
+    Changed,
+    /// Applied by a newer version of the app
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// A row of `_sqlx_migrations`: version, description, installed_on, success
+/// and checksum.
+type AppliedRow = (i64, String, NaiveDateTime, bool, Vec<u8>);
+
+async fn applied(pool: &SqlitePool) -> Result<BTreeMap<i64, AppliedRow>, sqlx::Error> {
+    let exists: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if exists == 0 {
+        return Ok(BTreeMap::new());
+    }
+    let rows: Vec<AppliedRow> = sqlx::query_as(
+        "SELECT version, description, installed_on, success, checksum FROM _sqlx_migrations",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|row| (row.0, row)).collect())
+}
+
+/// Every migration this build knows about or the database has applied, by
+/// version.
+pub async fn status(pool: &SqlitePool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let mut applied = applied(pool).await?;
+    let mut statuses: Vec<MigrationStatus> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let state = match applied.remove(&migration.version) {
+                None => MigrationState::Pending,
+                Some((_, _, _, false, _)) => MigrationState::Failed,
+                Some((_, _, _, _, checksum)) if *checksum != *migration.checksum => {
+                    MigrationState::Changed
+                }
+                Some((_, _, installed_on, _, _)) => MigrationState::Applied(installed_on),
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    statuses.extend(
+        applied
+            .into_values()
+            .map(|(version, description, ..)| MigrationStatus {
+                version,
+                description,
+                state: MigrationState::Unknown,
+            }),
+    );
+    statuses.sort_by_key(|status| status.version);
+    Ok(statuses)
+}
+
+/// How many migrations are waiting to be applied.
+pub async fn pending(pool: &SqlitePool) -> Result<usize, sqlx::Error> {
+    Ok(status(pool)
+        .await?
+        .iter()
+        .filter(|status| status.state == MigrationState::Pending)
+        .count())
+}
+
+pub async fn print_status(pool: &SqlitePool) -> Result<(), AppError> {
+    for status in status(pool).await? {
+        let state = match &status.state {
+            MigrationState::Applied(installed_on) => format!("applied {}", installed_on),
+            MigrationState::Pending => "pending".to_string(),
+            MigrationState::Failed => "failed".to_string(),
+            MigrationState::Changed => "changed since applied".to_string(),
+            MigrationState::Unknown => "unknown to this version".to_string(),
+        };
+        println!("{}  {:<29}  {}", status.version, state, status.description);
+    }
+    Ok(())
+}
+
+/// Applies the pending migrations.
+pub async fn run(pool: &SqlitePool) -> Result<(), AppError> {
+    let pending = pending(pool).await?;
+    MIGRATOR.run(pool).await.map_err(sqlx::Error::from)?;
+    println!("Applied {} migrations", pending);
+    Ok(())
+}
+
+/// Reverts the migrations applied after `to`, by default just the latest.
+/// Only migrations added with a `.down.sql` can be reverted; the rest need
+/// the database restored from a backup.
+pub async fn revert(pool: &SqlitePool, to: Option<i64>) -> Result<(), AppError> {
+    let applied: Vec<i64> = applied(pool)
+        .await?
+        .into_values()
+        .filter(|row| row.3)
+        .map(|row| row.0)
+        .collect();
+    let Some(latest) = applied.last() else {
+        println!("No migrations have been applied");
+        return Ok(());
+    };
+    let target = to.unwrap_or_else(|| applied.iter().rev().nth(1).copied().unwrap_or(0));
+    // sqlx skips migrations without a down script, which would look like success
+    let reversible = |version: i64| {
+        MIGRATOR.iter().any(|migration| {
+            migration.version == version && migration.migration_type.is_down_migration()
+        })
+    };
+    if let Some(version) = applied
+        .iter()
+        .rev()
+        .find(|version| **version > target && !reversible(**version))
+    {
+        return Err(AppError::Conflict(format!(
+            "Migration {} has no .down.sql, restore a backup from before it instead",
+            version
+        )));
+    }
+    if *latest <= target {
+        println!("Nothing was applied after {}", target);
+        return Ok(());
+    }
+    MIGRATOR
+        .undo(pool, target)
+        .await
+        .map_err(sqlx::Error::from)?;
+    println!("Reverted the migrations after {}", target);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_status_run_and_revert() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let before = status(&pool).await.unwrap();
+        assert!(!before.is_empty());
+        assert!(
+            before
+                .iter()
+                .all(|status| status.state == MigrationState::Pending)
+        );
+        assert_eq!(pending(&pool).await.unwrap(), before.len());
+
+        run(&pool).await.unwrap();
+        assert_eq!(pending(&pool).await.unwrap(), 0);
+        sqlx::query("INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (99990101000000, 'from the future', 1, x'00', 0)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let after = status(&pool).await.unwrap();
+        assert!(matches!(after[0].state, MigrationState::Applied(_)));
+        assert_eq!(after.last().unwrap().state, MigrationState::Unknown);
+
+        // None of the migrations have a down script yet
+        let error = revert(&pool, None).await.unwrap_err();
+        assert!(error.to_string().contains("has no .down.sql"));
+    }
+}
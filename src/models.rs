@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::{NoContext, Timestamp, Uuid};
@@ -7,23 +7,83 @@ use validator::Validate;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 
+use crate::email_style::EmailStyle;
+use crate::i18n::DateFormat;
+
 type HmacSha256 = Hmac<Sha256>;
 
 #[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct SignUpRequest {
-    #[validate(email(message = "Invalid email format"))]
+    #[validate(custom(
+        function = "crate::email_address::validate",
+        message = "Please provide a valid email address."
+    ))]
     pub email: String,
+    /// Honeypot field hidden from people, only bots fill it in.
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub form_token: Option<String>,
+    /// Where the signup came from, e.g. `qr-gate`.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// How they heard about the site, one of `attribution::HEARD_FROM`.
+    #[serde(default)]
+    pub heard_from: Option<String>,
+    /// Also send a reminder shortly before each flood.
+    #[serde(default)]
+    pub reminders: bool,
+    /// ntfy topic URL to also get push notifications on.
+    #[serde(default)]
+    pub ntfy_topic: Option<String>,
+    /// Pushover user key to also get push notifications on.
+    #[serde(default)]
+    pub pushover_user_key: Option<String>,
+    /// Phone number to also get Signal messages on.
+    #[serde(default)]
+    pub signal_number: Option<String>,
+    /// Language flood times in emails are written in, e.g. `es`. Form
+    /// signups get the page's.
+    #[serde(default)]
+    pub lang: Option<String>,
+}
+
+/// Query parameters of the homepage, used to attribute signups.
+#[derive(Debug, Deserialize)]
+pub struct HomeParams {
+    pub source: Option<String>,
+    pub utm_source: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct VerifyParams {
-    pub token: String,
+    pub token: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyCodeRequest {
+    pub email: String,
+    pub code: String,
+}
+
+/// What an unsubscribe stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnsubscribeScope {
+    /// Just the emails, keeping any other channels. The one-click
+    /// `List-Unsubscribe-Post` from mail clients does this.
+    #[default]
+    Email,
+    /// Every channel, deleting the subscriber
+    All,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct UnsubscribeParams {
     pub id: String,
     pub token: String,
+    #[serde(default)]
+    pub scope: UnsubscribeScope,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Default)]
@@ -32,7 +92,25 @@ pub struct User {
     pub email: String,
     pub is_verified: bool,
     pub verification_token: String,
+    pub verification_code: String,
     pub is_subscribed: bool,
+    /// When the last notification was sent, by any channel
+    pub last_notified_at: Option<NaiveDateTime>,
+    /// First and last flood the last notification was about
+    pub last_window_start: Option<NaiveDateTime>,
+    pub last_window_end: Option<NaiveDateTime>,
+    /// `detailed` or `compact` when they chose one over the site's
+    pub email_layout: Option<String>,
+    /// Language they signed up in, for dates in emails
+    pub lang: Option<String>,
+    /// 24 hour times, or 12 hour, when they chose over their language's usual
+    pub clock_24h: Option<bool>,
+}
+
+/// Whether `code` looks like one `User::new` makes, six digits, so empty
+/// and short codes never reach the database.
+pub fn is_verification_code(code: &str) -> bool {
+    code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit())
 }
 
 impl User {
@@ -40,16 +118,34 @@ impl User {
         let timestamp: Timestamp = Timestamp::now(NoContext);
         let id = Uuid::new_v7(timestamp).to_string();
         let verification_token = Uuid::new_v4().to_string();
+        let verification_code = format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000);
 
         User {
             id,
             email,
             is_verified: false,
             verification_token,
+            verification_code,
             is_subscribed: false,
+            last_notified_at: None,
+            last_window_start: None,
+            last_window_end: None,
+            email_layout: None,
+            lang: None,
+            clock_24h: None,
         }
     }
 
+    /// How compiled emails to them are laid out.
+    pub fn email_style(&self) -> EmailStyle {
+        EmailStyle::for_subscriber(self.email_layout.as_deref())
+    }
+
+    /// How flood times in emails to them are written.
+    pub fn date_format(&self) -> DateFormat {
+        DateFormat::for_subscriber(self.lang.as_deref(), self.clock_24h)
+    }
+
     pub fn generate_unsubscribe_token(&self, secret: &str) -> String {
         let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
         mac.update(self.id.as_bytes());
@@ -70,9 +166,15 @@ pub struct FloodDisplay {
 }
 
 impl FloodDisplay {
-    pub fn new(prediction_time: NaiveDateTime, height_ft: f64) -> Self {
+    /// Shows `prediction_time` in Pacific time, in English.
+    pub fn new(prediction_time: DateTime<Utc>, height_ft: f64) -> Self {
+        FloodDisplay::localized(prediction_time, height_ft, DateFormat::default())
+    }
+
+    /// Shows `prediction_time` in Pacific time as a recipient reads it.
+    pub fn localized(prediction_time: DateTime<Utc>, height_ft: f64, format: DateFormat) -> Self {
         FloodDisplay {
-            datetime: prediction_time.format("%A, %B %-d at %-I:%M%p").to_string(),
+            datetime: format.format(prediction_time),
             height: format!("{:.2}", height_ft),
         }
     }
@@ -87,8 +189,9 @@ mod tests {
     fn test_flood_display_formatting() {
         let dt = NaiveDate::from_ymd_opt(2023, 10, 5)
             .unwrap()
-            .and_hms_opt(14, 30, 0)
-            .unwrap();
+            .and_hms_opt(21, 30, 0)
+            .unwrap()
+            .and_utc();
         let display = FloodDisplay::new(dt, 6.789);
 
         assert_eq!(display.datetime, "Thursday, October 5 at 2:30PM");
@@ -106,6 +209,8 @@ mod tests {
             !user.verification_token.is_empty(),
             "Token should be generated"
         );
+        assert_eq!(user.verification_code.len(), 6);
+        assert!(user.verification_code.chars().all(|c| c.is_ascii_digit()));
         assert!(!user.is_verified);
         assert!(!user.is_subscribed);
     }
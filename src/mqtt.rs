@@ -0,0 +1,215 @@
+use async_trait::async_trait;
+use mill_valley_sausalito_bikepath_flood_alert::api_types::{PredictionsResponse, SensorResponse};
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, Outgoing, QoS};
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::api::{current_sensor, predictions_response};
+use crate::error::AppError;
+use crate::floods::FloodStatus;
+use crate::notify::{Notifier, NotifyRun, Subscriber};
+use crate::site;
+use crate::tides::{FORECAST_DAYS, get_flood_tides};
+
+const DEFAULT_TOPIC_PREFIX: &str = "mv-bikepath-flood";
+/// Gives up on a broker that doesn't respond, so it can't hold up a job.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Broker to publish the forecast to, enabled by setting `MQTT_HOST`.
+pub struct MqttConfig {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    client_id: String,
+    topic_prefix: String,
+}
+
+impl MqttConfig {
+    pub fn from_env() -> Option<Self> {
+        let host = env::var("MQTT_HOST").ok().filter(|host| !host.is_empty())?;
+        let credentials = match (env::var("MQTT_USERNAME"), env::var("MQTT_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        Some(MqttConfig {
+            host,
+            port: env::var("MQTT_PORT")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1883),
+            credentials,
+            client_id: env::var("MQTT_CLIENT_ID")
+                .unwrap_or_else(|_| "mv-bikepath-flood-alert".to_string()),
+            topic_prefix: site::var("MQTT_TOPIC_PREFIX")
+                .map(|prefix| prefix.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| DEFAULT_TOPIC_PREFIX.to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MqttError {
+    #[error("Client error: {0}")]
+    Client(#[from] rumqttc::ClientError),
+    #[error("Connection error: {0}")]
+    Connection(#[from] rumqttc::ConnectionError),
+}
+
+/// A message to publish: topic, JSON payload and whether the broker keeps it
+/// for subscribers that connect later.
+type Message = (String, String, bool);
+
+/// The predictions and status are retained so displays that connect later
+/// get the latest. A `flooding` message is only sent while the path is
+/// flooded.
+fn messages(
+    prefix: &str,
+    predictions: &PredictionsResponse,
+    sensor: &SensorResponse,
+) -> Result<Vec<Message>, serde_json::Error> {
+    let status = serde_json::to_string(sensor)?;
+    let mut messages = vec![
+        (
+            format!("{}/predictions", prefix),
+            serde_json::to_string(predictions)?,
+            true,
+        ),
+        (format!("{}/status", prefix), status.clone(), true),
+    ];
+    if sensor.status == FloodStatus::Flooding.as_str() {
+        messages.push((format!("{}/flooding", prefix), status, false));
+    }
+    Ok(messages)
+}
+
+async fn publish(config: &MqttConfig, messages: Vec<Message>) -> Result<(), MqttError> {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some((username, password)) = &config.credentials {
+        options.set_credentials(username, password);
+    }
+
+    let mut unacked = messages.len();
+    let (client, mut eventloop) = AsyncClient::new(options, unacked + 1);
+    for (topic, payload, retain) in messages {
+        client
+            .publish(topic, QoS::AtLeastOnce, retain, payload)
+            .await?;
+    }
+
+    // The event loop does the sending. Disconnect only once the broker has
+    // acknowledged every message, or closing the connection can lose them.
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Incoming::PubAck(_)) => {
+                unacked -= 1;
+                if unacked == 0 {
+                    client.disconnect().await?;
+                }
+            }
+            Event::Outgoing(Outgoing::Disconnect) => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// Publishes the forecast and the path's status to the broker in
+/// `MQTT_HOST`, if there is one. Errors are logged rather than failing the
+/// job that called it.
+pub async fn publish_forecast(pool: &SqlitePool) {
+    let Some(config) = MqttConfig::from_env() else {
+        return;
+    };
+
+    let messages = async {
+        let tides = get_flood_tides(pool, FORECAST_DAYS).await?;
+        let sensor = current_sensor(pool).await?;
+        Ok::<_, AppError>(messages(
+            &config.topic_prefix,
+            &predictions_response(tides),
+            &sensor,
+        )?)
+    };
+    let messages = match messages.await {
+        Ok(messages) => messages,
+        Err(e) => {
+            eprintln!("Error building MQTT messages: {}", e);
+            return;
+        }
+    };
+
+    let count = messages.len();
+    match tokio::time::timeout(PUBLISH_TIMEOUT, publish(&config, messages)).await {
+        Ok(Ok(())) => println!(
+            "Published {} messages to MQTT topics under {}/",
+            count, config.topic_prefix
+        ),
+        Ok(Err(e)) => eprintln!("Error publishing to MQTT: {}", e),
+        Err(_) => eprintln!("Timed out publishing to MQTT at {}", config.host),
+    }
+}
+
+/// Publishes the forecast on every `notify`, flooding or not. Nothing is
+/// sent per subscriber.
+pub struct MqttNotifier;
+
+#[async_trait]
+impl Notifier for MqttNotifier {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    async fn prepare(&mut self, run: &NotifyRun<'_>) -> Result<bool, AppError> {
+        publish_forecast(run.pool).await;
+        Ok(false)
+    }
+
+    async fn deliver(
+        &mut self,
+        _run: &NotifyRun<'_>,
+        _subscriber: &Subscriber,
+    ) -> Result<bool, AppError> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sensor(status: FloodStatus) -> SensorResponse {
+        SensorResponse {
+            status: status.as_str().to_string(),
+            next_flood_time: None,
+            next_flood_height: Some(7.1),
+            seconds_until_flood: Some(0),
+            adjustment_ft: None,
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let predictions = predictions_response(Vec::new());
+
+        let clear = messages("home/bikepath", &predictions, &sensor(FloodStatus::Clear)).unwrap();
+        let topics: Vec<&str> = clear.iter().map(|(topic, _, _)| topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            ["home/bikepath/predictions", "home/bikepath/status"]
+        );
+        assert!(clear.iter().all(|(_, _, retain)| *retain));
+        assert!(clear[1].1.contains(r#""status":"clear""#));
+
+        let flooding = messages(
+            "home/bikepath",
+            &predictions,
+            &sensor(FloodStatus::Flooding),
+        )
+        .unwrap();
+        assert_eq!(flooding.len(), 3);
+        assert_eq!(flooding[2].0, "home/bikepath/flooding");
+        assert!(!flooding[2].2);
+    }
+}
@@ -0,0 +1,105 @@
+use hickory_resolver::TokioResolver;
+use hickory_resolver::proto::rr::RData;
+use std::env;
+
+/// Checks that the domain of an email address has mail servers before we
+/// try to send to it, catching typos like "gmial.com".
+pub struct MxValidator {
+    resolver: TokioResolver,
+}
+
+impl MxValidator {
+    /// Returns a validator when `VALIDATE_EMAIL_MX` is enabled.
+    pub fn from_env() -> Option<Self> {
+        let enabled = env::var("VALIDATE_EMAIL_MX")
+            .map(|value| value == "true" || value == "1")
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let resolver = TokioResolver::builder_tokio()
+            .expect("Failed to read system DNS configuration")
+            .build()
+            .expect("Failed to create DNS resolver");
+        Some(MxValidator { resolver })
+    }
+
+    /// Returns false only when DNS positively says the domain has no mail servers:
+    /// it doesn't exist, has a null MX, or has neither MX nor address records.
+    /// Lookup failures such as timeouts let the address through.
+    pub async fn accepts_mail(&self, email: &str) -> bool {
+        let Some(domain) = email_domain(email) else {
+            return false;
+        };
+        let name = format!("{}.", domain);
+
+        let exchanges = match self.resolver.mx_lookup(name.as_str()).await {
+            Ok(lookup) => lookup
+                .answers()
+                .iter()
+                .filter_map(|record| match &record.data {
+                    RData::MX(mx) => Some(mx.exchange.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            Err(e) if e.is_nx_domain() => return false,
+            Err(e) if e.is_no_records_found() => Vec::new(),
+            Err(e) => {
+                eprintln!("MX lookup failed for {}: {}", domain, e);
+                return true;
+            }
+        };
+        if let Some(accepts) = mx_verdict(&exchanges) {
+            return accepts;
+        }
+
+        // Without MX records mail goes to the domain's own address (RFC 5321 5.1)
+        match self.resolver.lookup_ip(name.as_str()).await {
+            Ok(lookup) => lookup.iter().next().is_some(),
+            Err(e) if e.is_nx_domain() || e.is_no_records_found() => false,
+            Err(e) => {
+                eprintln!("Address lookup failed for {}: {}", domain, e);
+                true
+            }
+        }
+    }
+}
+
+fn email_domain(email: &str) -> Option<&str> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim())
+        .filter(|domain| !domain.is_empty())
+}
+
+/// Whether the MX records say the domain takes mail, or None without any, when
+/// its A or AAAA record decides. A single MX record pointing at the root
+/// (RFC 7505 "null MX") means the domain explicitly does not accept mail.
+fn mx_verdict(exchanges: &[String]) -> Option<bool> {
+    if exchanges.is_empty() {
+        return None;
+    }
+    Some(exchanges.iter().any(|exchange| exchange != "."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_domain() {
+        assert_eq!(email_domain("bob@example.com"), Some("example.com"));
+        assert_eq!(email_domain("\"a@b\"@example.com"), Some("example.com"));
+        assert_eq!(email_domain("bob@"), None);
+        assert_eq!(email_domain("bob"), None);
+    }
+
+    #[test]
+    fn test_mx_verdict() {
+        assert_eq!(mx_verdict(&["mx1.example.com.".to_string()]), Some(true));
+        assert_eq!(mx_verdict(&[".".to_string()]), Some(false));
+        // Falls back to the address records
+        assert_eq!(mx_verdict(&[]), None);
+    }
+}
@@ -0,0 +1,202 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use noaa_tides::products::predictions::Prediction;
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::sqlite::SqlitePool;
+use tracing::{Instrument, info_span};
+
+use crate::calibration::NOAA_DATA_URL;
+use crate::error::AppError;
+use crate::sync_runs::Source;
+use crate::tides::{FORECAST_DAYS, station_id};
+
+const PREDICTIONS: &str = "predictions";
+/// Days of predictions fetched, well past the forecast, so a cached response
+/// still covers it after NOAA has been down for weeks.
+const CACHED_DAYS: i64 = FORECAST_DAYS * 3;
+
+#[derive(Debug, Deserialize)]
+struct PredictionsResponse {
+    predictions: Option<Vec<Prediction>>,
+    error: Option<NoaaError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NoaaError {
+    message: String,
+}
+
+fn parse_predictions(body: &str) -> Result<Vec<Prediction>, String> {
+    let response: PredictionsResponse = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    match (response.predictions, response.error) {
+        (_, Some(error)) => Err(error.message),
+        (Some(predictions), None) => Ok(predictions),
+        (None, None) => Err("The response has no predictions".to_string()),
+    }
+}
+
+async fn fetch(begin_date: NaiveDate, end_date: NaiveDate) -> Result<String, reqwest::Error> {
+    let url = Url::parse_with_params(
+        NOAA_DATA_URL,
+        [
+            ("product", PREDICTIONS),
+            ("application", "mv-sausalito-bikepath-flood-alert"),
+            ("station", station_id()),
+            ("begin_date", &begin_date.format("%Y%m%d").to_string()),
+            ("end_date", &end_date.format("%Y%m%d").to_string()),
+            ("datum", "MLLW"),
+            ("time_zone", "gmt"),
+            ("interval", "hilo"),
+            ("units", "english"),
+            ("format", "json"),
+        ],
+    )
+    .expect("the NOAA URL is valid");
+    reqwest::get(url).await?.error_for_status()?.text().await
+}
+
+async fn save(
+    pool: &SqlitePool,
+    begin_date: NaiveDate,
+    end_date: NaiveDate,
+    body: &str,
+    fetched_at: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    let station_id = station_id();
+    sqlx::query!(
+        r#"
+        INSERT INTO noaa_responses (station_id, product, begin_date, end_date, body, fetched_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT (station_id, product) DO UPDATE SET
+            begin_date = excluded.begin_date,
+            end_date = excluded.end_date,
+            body = excluded.body,
+            fetched_at = excluded.fetched_at
+        "#,
+        station_id,
+        PREDICTIONS,
+        begin_date,
+        end_date,
+        body,
+        fetched_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The latest response cached for the station, and when it was fetched.
+async fn latest(pool: &SqlitePool) -> Result<Option<(String, NaiveDateTime)>, sqlx::Error> {
+    let station_id = station_id();
+    let row = sqlx::query!(
+        r#"
+        SELECT body, fetched_at AS "fetched_at: NaiveDateTime"
+        FROM noaa_responses WHERE station_id = ? AND product = ?
+        "#,
+        station_id,
+        PREDICTIONS
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| (row.body, row.fetched_at)))
+}
+
+/// NOAA's highs and lows from `begin_date` to `end_date`, without caching
+/// them, for fetching ahead of the forecast.
+pub async fn fetch_range(
+    begin_date: NaiveDate,
+    end_date: NaiveDate,
+) -> Result<Vec<Prediction>, AppError> {
+    let body = fetch(begin_date, end_date)
+        .instrument(info_span!("noaa_fetch", product = PREDICTIONS, %begin_date, %end_date))
+        .await
+        .map_err(|e| AppError::Noaa(e.to_string()))?;
+    parse_predictions(&body).map_err(AppError::Noaa)
+}
+
+/// Predictions for a sync, and where and when NOAA sent them.
+pub struct Fetched {
+    pub predictions: Vec<Prediction>,
+    pub source: Source,
+    pub fetched_at: NaiveDateTime,
+}
+
+/// NOAA's highs and lows from `begin_date` on, caching the raw response.
+/// When NOAA can't be reached or sends an error, the latest cached response
+/// is used instead, so an outage doesn't shrink the forecast.
+pub async fn fetch_predictions(
+    pool: &SqlitePool,
+    begin_date: NaiveDate,
+) -> Result<Fetched, AppError> {
+    let end_date = begin_date + Duration::days(CACHED_DAYS);
+    let fetched = fetch(begin_date, end_date)
+        .instrument(info_span!("noaa_fetch", product = PREDICTIONS, %begin_date, %end_date))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|body| parse_predictions(&body).map(|predictions| (body, predictions)));
+    let error = match fetched {
+        Ok((body, predictions)) => {
+            let fetched_at = Utc::now().naive_utc();
+            save(pool, begin_date, end_date, &body, fetched_at).await?;
+            return Ok(Fetched {
+                predictions,
+                source: Source::Api,
+                fetched_at,
+            });
+        }
+        Err(error) => error,
+    };
+
+    let Some((body, fetched_at)) = latest(pool).await? else {
+        return Err(AppError::Noaa(error));
+    };
+    eprintln!(
+        "Using NOAA's response from {} UTC, fetching failed: {}",
+        fetched_at, error
+    );
+    Ok(Fetched {
+        predictions: parse_predictions(&body).map_err(AppError::Noaa)?,
+        source: Source::Cache,
+        fetched_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cached_predictions() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        assert_eq!(latest(&pool).await.unwrap(), None);
+
+        let body = r#"{"predictions": [
+            {"t": "2026-10-15 08:12", "v": "6.912", "type": "H"},
+            {"t": "2026-10-15 14:40", "v": "0.521", "type": "L"}
+        ]}"#;
+        let predictions = parse_predictions(body).unwrap();
+        assert_eq!(predictions.len(), 2);
+        assert_eq!(predictions[0].height, 6.912);
+
+        let date = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let fetched_at = Utc::now().naive_utc();
+        save(
+            &pool,
+            date,
+            date + Duration::days(CACHED_DAYS),
+            body,
+            fetched_at,
+        )
+        .await
+        .unwrap();
+        let (cached, _) = latest(&pool).await.unwrap().unwrap();
+        assert_eq!(cached, body);
+
+        let error = r#"{"error": {"message": "No Predictions data was found."}}"#;
+        assert_eq!(
+            parse_predictions(error).unwrap_err(),
+            "No Predictions data was found."
+        );
+    }
+}
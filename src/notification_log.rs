@@ -0,0 +1,170 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+use tracing::instrument;
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::models::User;
+use crate::request_id;
+use crate::tides::{self, FloodTide};
+
+/// A new message id, generated before sending so it can be used as the
+/// message's tracking token.
+pub fn new_message_id() -> String {
+    Uuid::new_v7(Timestamp::now(NoContext)).to_string()
+}
+
+/// Records a sent notification, along with the subject line variant it was
+/// assigned when an experiment is running. Failing to record one is logged
+/// rather than stopping the rest of the send.
+#[instrument(level = "debug", skip_all)]
+pub async fn record(
+    pool: &SqlitePool,
+    id: &str,
+    user: &User,
+    subject: &str,
+    variant_id: Option<&str>,
+) {
+    let request_id = request_id::current();
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO notification_log (id, user_id, email, subject, subject_variant_id, request_id)
+        VALUES (?, ?, ?, ?, ?, ?);
+        "#,
+        id,
+        user.id,
+        user.email,
+        subject,
+        variant_id,
+        request_id
+    )
+    .execute(pool)
+    .await
+    {
+        eprintln!(
+            "Database error logging notification to {}: {:?}",
+            user.email, e
+        );
+    }
+}
+
+/// Marks a subscriber as notified now about `floods`, after a successful
+/// send on any channel. Logged rather than failing the send.
+#[instrument(level = "debug", skip_all)]
+pub async fn mark_notified(pool: &SqlitePool, user: &User, floods: &[FloodTide]) {
+    let window_start = floods
+        .first()
+        .map(|flood| flood.prediction_time.naive_utc());
+    let window_end = floods.last().map(|flood| flood.prediction_time.naive_utc());
+    if let Err(e) = sqlx::query!(
+        r#"
+        UPDATE users
+        SET last_notified_at = CURRENT_TIMESTAMP, last_window_start = ?, last_window_end = ?
+        WHERE id = ?;
+        "#,
+        window_start,
+        window_end,
+        user.id
+    )
+    .execute(pool)
+    .await
+    {
+        eprintln!("Database error marking {} as notified: {:?}", user.email, e);
+    }
+}
+
+/// A subscriber's last notification, for the admin dashboard.
+pub struct LastNotified {
+    pub email: String,
+    pub notified_at: NaiveDateTime,
+    pub window_start: Option<DateTime<Utc>>,
+    pub window_end: Option<DateTime<Utc>>,
+}
+
+impl LastNotified {
+    /// The floods the notification was about in Pacific time, e.g.
+    /// "Dec 12 8:10AM to Dec 14 9:30AM".
+    pub fn window(&self) -> String {
+        match (self.window_start, self.window_end) {
+            (Some(start), Some(end)) => format!(
+                "{} to {}",
+                tides::local(start).format("%b %-d %-I:%M%p"),
+                tides::local(end).format("%b %-d %-I:%M%p")
+            ),
+            _ => String::new(),
+        }
+    }
+}
+
+/// The most recently notified subscribers, latest first.
+#[instrument(level = "debug", skip_all)]
+pub async fn recently_notified(
+    pool: &SqlitePool,
+    limit: i64,
+) -> Result<Vec<LastNotified>, sqlx::Error> {
+    sqlx::query_as!(
+        LastNotified,
+        r#"
+        SELECT
+            email,
+            last_notified_at AS "notified_at!: NaiveDateTime",
+            last_window_start AS "window_start: DateTime<Utc>",
+            last_window_end AS "window_end: DateTime<Utc>"
+        FROM users
+        WHERE last_notified_at IS NOT NULL
+        ORDER BY last_notified_at DESC
+        LIMIT ?
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mark_notified() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let user = User::new("bob@example.com".to_string());
+        sqlx::query("INSERT INTO users (id, email, verification_token) VALUES (?, ?, ?)")
+            .bind(&user.id)
+            .bind(&user.email)
+            .bind(&user.verification_token)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let local = |s| tides::from_local(time(s));
+        let floods = [
+            FloodTide {
+                prediction_time: local("2026-12-12 08:10"),
+                height_ft: 6.5,
+            },
+            FloodTide {
+                prediction_time: local("2026-12-14 09:30"),
+                height_ft: 6.8,
+            },
+        ];
+        mark_notified(&pool, &user, &floods).await;
+
+        let row: (Option<NaiveDateTime>, NaiveDateTime, NaiveDateTime) = sqlx::query_as(
+            "SELECT last_notified_at, last_window_start, last_window_end FROM users WHERE id = ?",
+        )
+        .bind(&user.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(row.0.is_some());
+        // Stored in UTC
+        assert_eq!(row.1, time("2026-12-12 16:10"));
+        assert_eq!(row.2, time("2026-12-14 17:30"));
+
+        let notified = recently_notified(&pool, 10).await.unwrap();
+        assert_eq!(notified.len(), 1);
+        assert_eq!(notified[0].window(), "Dec 12 8:10AM to Dec 14 9:30AM");
+    }
+}
@@ -0,0 +1,581 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+use std::sync::Arc;
+use tracing::{Span, instrument};
+
+use crate::AppState;
+use crate::channels::PushNotifier;
+use crate::commute::{self, CommuteWindow};
+use crate::email_templates::{self, EmailKind};
+use crate::error::AppError;
+use crate::events::{self, EventSource, EventType};
+use crate::experiments::{self, Experiment};
+use crate::feedback;
+use crate::jobs::{self, Job};
+use crate::mail::{self, NOTIFY_EMAIL_FORECAST_DAYS, Notification, NotificationLinks};
+use crate::models::User;
+use crate::mqtt::MqttNotifier;
+use crate::ops_webhook::{self, OpsEvent};
+use crate::site;
+use crate::tides::{self, FloodTide, get_flood_tides};
+use crate::{cleanup, notification_log, reminders, tide_chart, tracking};
+
+/// What every notifier gets for one run of `notify`.
+pub struct NotifyRun<'a> {
+    pub pool: &'a SqlitePool,
+    pub base_url: &'a str,
+    pub source: EventSource,
+    /// Floods of the next `NOTIFY_EMAIL_FORECAST_DAYS`
+    pub floods: Vec<FloodTide>,
+    /// When a notify run last succeeded. Subscribers emailed about the same
+    /// floods since then were reached by an attempt that failed partway, and
+    /// aren't emailed again when it's retried.
+    pub last_succeeded: Option<NaiveDateTime>,
+}
+
+/// A verified, subscribed user and the channels they've set up besides email.
+pub struct Subscriber {
+    pub user: User,
+    /// False once they've unsubscribed from just the emails
+    pub wants_email: bool,
+    /// Floods come as calendar invites rather than a calendar to import
+    pub wants_invites: bool,
+    pub ntfy_topic: Option<String>,
+    pub pushover_user_key: Option<String>,
+    pub signal_number: Option<String>,
+    /// Only floods overlapping one of these are worth telling them about
+    pub commute_windows: Vec<CommuteWindow>,
+    /// The run's floods overlapping their commute windows, which are all
+    /// they're told about
+    pub floods: Vec<FloodTide>,
+    /// Emailed since the last successful run, about the floods in `last_window`
+    emailed_since_last_run: bool,
+    last_window: (Option<NaiveDateTime>, Option<NaiveDateTime>),
+}
+
+impl Subscriber {
+    /// Whether an earlier attempt at this run already emailed them about
+    /// these floods.
+    fn already_emailed(&self) -> bool {
+        let window = (
+            self.floods
+                .first()
+                .map(|flood| flood.prediction_time.naive_utc()),
+            self.floods
+                .last()
+                .map(|flood| flood.prediction_time.naive_utc()),
+        );
+        self.emailed_since_last_run && self.last_window == window
+    }
+}
+
+/// One way of telling people about floods. Each run, `prepare` is called
+/// once, then `deliver` for every subscriber and `finish` after the last.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Builds what's sent this run, and sends anything that isn't per
+    /// subscriber. Returns false to skip delivering.
+    async fn prepare(&mut self, run: &NotifyRun<'_>) -> Result<bool, AppError>;
+
+    /// Sends to one subscriber, if they've set this channel up, returning
+    /// whether anything was sent. An error stops the run, so failures that
+    /// shouldn't are logged instead.
+    async fn deliver(
+        &mut self,
+        run: &NotifyRun<'_>,
+        subscriber: &Subscriber,
+    ) -> Result<bool, AppError>;
+
+    async fn finish(&mut self, _run: &NotifyRun<'_>) -> Result<(), AppError> {
+        Ok(())
+    }
+}
+
+/// Every notifier `notify` runs, in order.
+fn registry() -> Vec<Box<dyn Notifier>> {
+    vec![
+        Box::new(MqttNotifier),
+        Box::new(EmailNotifier::default()),
+        Box::new(PushNotifier::default()),
+    ]
+}
+
+/// Checks for floods and runs every notifier in the registry, posting a
+/// summary to the ops webhook when there were any. Returns how many
+/// notifications were sent.
+pub async fn check_and_send_notifications(
+    pool: SqlitePool,
+    source: EventSource,
+) -> Result<usize, AppError> {
+    println!("Checking for flood predictions and sending notifications...");
+    cleanup::purge_unverified(&pool, source).await;
+    let base_url = site::var("BASE_URL").expect("BASE_URL must be set");
+    let run = NotifyRun {
+        pool: &pool,
+        base_url: &base_url,
+        source,
+        floods: get_flood_tides(&pool, NOTIFY_EMAIL_FORECAST_DAYS).await?,
+        last_succeeded: jobs::last_succeeded(&pool, Job::Notify).await?,
+    };
+    let sent = run_notifiers(&run, registry()).await?;
+    if !run.floods.is_empty() {
+        ops_webhook::post(OpsEvent::NotifyRun {
+            floods: run.floods.len(),
+            sent,
+        })
+        .await;
+    }
+    Ok(sent)
+}
+
+async fn run_notifiers(
+    run: &NotifyRun<'_>,
+    notifiers: Vec<Box<dyn Notifier>>,
+) -> Result<usize, AppError> {
+    let mut sent = 0;
+    let mut subscribers = None;
+    for mut notifier in notifiers {
+        if !notifier.prepare(run).await? {
+            continue;
+        }
+        if subscribers.is_none() {
+            let mut fetched = fetch_subscribers(run.pool, run.last_succeeded).await?;
+            for subscriber in &mut fetched {
+                subscriber.floods = commute::wanted(&subscriber.commute_windows, &run.floods);
+            }
+            fetched.retain(|subscriber| !subscriber.floods.is_empty());
+            subscribers = Some(fetched);
+        }
+        let mut delivered = 0;
+        for subscriber in subscribers.iter().flatten() {
+            if notifier.deliver(run, subscriber).await? {
+                delivered += 1;
+            }
+        }
+        notifier.finish(run).await?;
+        println!("Sent {} {} notifications", delivered, notifier.name());
+        sent += delivered;
+    }
+    Ok(sent)
+}
+
+#[instrument(level = "debug", skip_all, fields(rows))]
+async fn fetch_subscribers(
+    pool: &SqlitePool,
+    last_succeeded: Option<NaiveDateTime>,
+) -> Result<Vec<Subscriber>, sqlx::Error> {
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT id, email, wants_email AS "wants_email: bool",
+            wants_invites AS "wants_invites: bool", ntfy_topic, pushover_user_key, signal_number,
+            email_layout, lang, clock_24h AS "clock_24h: bool", commute_windows,
+            last_window_start AS "last_window_start: NaiveDateTime",
+            last_window_end AS "last_window_end: NaiveDateTime",
+            EXISTS (
+                SELECT 1 FROM notification_log
+                WHERE user_id = users.id AND sent_at > COALESCE(?, '')
+            ) AS "emailed_since_last_run!: bool"
+        FROM users
+        WHERE is_verified = 1 AND is_subscribed = 1
+        "#,
+        last_succeeded
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|record| Subscriber {
+        user: User {
+            id: record.id,
+            email: record.email,
+            email_layout: record.email_layout,
+            lang: record.lang,
+            clock_24h: record.clock_24h,
+            ..Default::default()
+        },
+        wants_email: record.wants_email,
+        wants_invites: record.wants_invites,
+        ntfy_topic: record.ntfy_topic,
+        pushover_user_key: record.pushover_user_key,
+        signal_number: record.signal_number,
+        commute_windows: record
+            .commute_windows
+            .as_deref()
+            .and_then(commute::parse_windows)
+            .unwrap_or_default(),
+        floods: Vec::new(),
+        emailed_since_last_run: record.emailed_since_last_run,
+        last_window: (record.last_window_start, record.last_window_end),
+    })
+    .collect::<Vec<_>>();
+    Span::current().record("rows", subscribers.len());
+    Ok(subscribers)
+}
+
+/// The water level over the notified period, from the highs and lows around
+/// it so the curve reaches both edges.
+async fn chart(pool: &SqlitePool, now: DateTime<Utc>) -> Result<Vec<u8>, AppError> {
+    let end = now + Duration::days(NOTIFY_EMAIL_FORECAST_DAYS);
+    let margin = Duration::hours(12);
+    let tides = tides::get_tides_between(pool, now - margin, end + margin).await?;
+    Ok(tide_chart::render(
+        &tides,
+        now,
+        end,
+        tides::flood_threshold(),
+    ))
+}
+
+/// The notification email, with its subject line experiment and bounce
+/// handling.
+#[derive(Default)]
+struct EmailNotifier {
+    prepared: Option<PreparedEmail>,
+    sent: usize,
+    /// User ID, email and reason of each permanent bounce
+    bounces: Vec<(String, String, String)>,
+}
+
+struct PreparedEmail {
+    app_state: Arc<AppState>,
+    notification: Notification,
+    experiment: Option<Experiment>,
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn prepare(&mut self, run: &NotifyRun<'_>) -> Result<bool, AppError> {
+        if run.floods.is_empty() {
+            println!("No flood predictions found. No email notifications to send.");
+            return Ok(false);
+        }
+        println!(
+            "Found {} flood predictions. Sending email notifications...",
+            run.floods.len()
+        );
+
+        let app_state = Arc::new(AppState::from_pool(run.pool.clone()));
+        let template = email_templates::load_override(run.pool, EmailKind::Notification).await?;
+        let experiment = experiments::active(run.pool).await?;
+        if let Some(experiment) = &experiment {
+            let labels: Vec<&str> = experiment
+                .variants
+                .iter()
+                .map(|v| v.label.as_str())
+                .collect();
+            println!(
+                "Subject line experiment {} is running with variants {}",
+                experiment.name,
+                labels.join(", ")
+            );
+        }
+        let now = Utc::now();
+        let mut notification = Notification::new(&run.floods, template).with_calendar(now);
+        match chart(run.pool, now).await {
+            Ok(png) => notification = notification.with_chart(png),
+            Err(e) => eprintln!("Error drawing the tide chart, sending without it: {}", e),
+        }
+        self.prepared = Some(PreparedEmail {
+            app_state,
+            notification,
+            experiment,
+        });
+        Ok(true)
+    }
+
+    async fn deliver(
+        &mut self,
+        run: &NotifyRun<'_>,
+        subscriber: &Subscriber,
+    ) -> Result<bool, AppError> {
+        let Some(prepared) = &self.prepared else {
+            return Ok(false);
+        };
+        if !subscriber.wants_email {
+            return Ok(false);
+        }
+        if subscriber.already_emailed() {
+            println!(
+                "Already emailed {} about these floods",
+                subscriber.user.email
+            );
+            return Ok(false);
+        }
+        let app_state = &prepared.app_state;
+        let user = &subscriber.user;
+        let narrowed;
+        let notification = if subscriber.floods.len() == run.floods.len() {
+            &prepared.notification
+        } else {
+            narrowed = prepared.notification.only(&subscriber.floods);
+            &narrowed
+        };
+        let variant = prepared.experiment.as_ref().and_then(|e| e.assign());
+        let message_id = notification_log::new_message_id();
+        let links = NotificationLinks {
+            homepage: tracking::click_url(
+                &app_state.base_url,
+                &app_state.unsubscribe_secret,
+                &message_id,
+                "/",
+            ),
+            reminders: reminders::reminders_link(
+                &app_state.base_url,
+                user,
+                &app_state.unsubscribe_secret,
+            ),
+            feedback: feedback::feedback_link(
+                &app_state.base_url,
+                user,
+                &app_state.unsubscribe_secret,
+            ),
+            unsubscribe: format!(
+                "{}/unsubscribe?id={}&token={}",
+                app_state.base_url,
+                user.id,
+                user.generate_unsubscribe_token(&app_state.unsubscribe_secret)
+            ),
+            pixel: app_state
+                .open_tracking
+                .then(|| tracking::pixel_url(&app_state.base_url, &message_id)),
+        };
+        self.sent += 1;
+        // One dead mailbox shouldn't stop everyone else's alert
+        match app_state
+            .mailer
+            .send_notification_email(
+                notification,
+                user,
+                &links,
+                variant.map(|v| v.subject.as_str()),
+                subscriber.wants_invites,
+            )
+            .await
+        {
+            Ok(subject) => {
+                notification_log::record(
+                    run.pool,
+                    &message_id,
+                    user,
+                    &subject,
+                    variant.map(|v| v.id.as_str()),
+                )
+                .await;
+                notification_log::mark_notified(run.pool, user, &subscriber.floods).await;
+                Ok(true)
+            }
+            Err(e) if mail::is_bounce(&e) => {
+                eprintln!("Notification to {} bounced: {}", user.email, e);
+                self.bounces
+                    .push((user.id.clone(), user.email.clone(), e.to_string()));
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn finish(&mut self, run: &NotifyRun<'_>) -> Result<(), AppError> {
+        // Every address bouncing points at our own sending setup, not the addresses
+        let suppress = self.bounces.len() < self.sent || self.sent == 1;
+        if !self.bounces.is_empty() && !suppress {
+            eprintln!(
+                "Every notification bounced, check the SMTP settings. Not suppressing anyone."
+            );
+        }
+        for (id, email, reason) in self.bounces.drain(..) {
+            events::record(
+                run.pool,
+                &id,
+                &email,
+                EventType::Bounce,
+                run.source,
+                Some(&reason),
+            )
+            .await;
+            if !suppress {
+                continue;
+            }
+            sqlx::query!("DELETE FROM users WHERE id = ?;", id)
+                .execute(run.pool)
+                .await?;
+            events::record(
+                run.pool,
+                &id,
+                &email,
+                EventType::Suppression,
+                run.source,
+                Some("Removed after a permanent bounce"),
+            )
+            .await;
+            println!("Suppressed {} after a permanent bounce", email);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Each delivery's email and the flood times in it.
+    type Deliveries = Arc<Mutex<Vec<(String, Vec<DateTime<Utc>>)>>>;
+
+    /// Records the emails it delivers to and the floods in each, and only
+    /// delivers when there are floods, like the real notifiers.
+    struct RecordingNotifier(Deliveries);
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn prepare(&mut self, run: &NotifyRun<'_>) -> Result<bool, AppError> {
+            Ok(!run.floods.is_empty())
+        }
+
+        async fn deliver(
+            &mut self,
+            _run: &NotifyRun<'_>,
+            subscriber: &Subscriber,
+        ) -> Result<bool, AppError> {
+            let floods = subscriber
+                .floods
+                .iter()
+                .map(|flood| flood.prediction_time)
+                .collect();
+            self.0
+                .lock()
+                .unwrap()
+                .push((subscriber.user.email.clone(), floods));
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_notifiers() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        for (id, email, verified, windows) in [
+            ("a", "a@example.com", 1, None),
+            ("b", "b@example.com", 0, None),
+            // Only around 3am, when no flood in the test is
+            ("c", "c@example.com", 1, Some("daily 3:00-3:30")),
+            ("d", "d@example.com", 1, Some("weekdays 7:00-9:00")),
+        ] {
+            sqlx::query(
+                "INSERT INTO users (id, email, is_verified, is_subscribed, verification_token, commute_windows)
+                VALUES (?, ?, ?, 1, ?, ?)",
+            )
+            .bind(id)
+            .bind(email)
+            .bind(verified)
+            .bind(id)
+            .bind(windows)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mut run = NotifyRun {
+            pool: &pool,
+            base_url: "http://localhost",
+            source: EventSource::Cli,
+            floods: Vec::new(),
+            last_succeeded: None,
+        };
+        run_notifiers(&run, vec![Box::new(RecordingNotifier(delivered.clone()))])
+            .await
+            .unwrap();
+        assert!(delivered.lock().unwrap().is_empty());
+
+        // Thursday 9:45am Pacific, flooding from 8:45, and 11pm
+        let (morning, late) = (
+            "2026-10-15T16:45:00Z".parse().unwrap(),
+            "2026-10-16T06:00:00Z".parse().unwrap(),
+        );
+        for prediction_time in [morning, late] {
+            run.floods.push(FloodTide {
+                prediction_time,
+                height_ft: 6.5,
+            });
+        }
+        let sent = run_notifiers(&run, vec![Box::new(RecordingNotifier(delivered.clone()))])
+            .await
+            .unwrap();
+        assert_eq!(sent, 2);
+        // The morning commuter doesn't hear about the 11pm flood
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            [
+                ("a@example.com".to_string(), vec![morning, late]),
+                ("d@example.com".to_string(), vec![morning]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_skips_emailed_subscribers() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        for id in ["a", "b"] {
+            sqlx::query(
+                "INSERT INTO users (id, email, is_verified, is_subscribed, verification_token)
+                VALUES (?, ?, 1, 1, ?)",
+            )
+            .bind(id)
+            .bind(format!("{}@example.com", id))
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let floods = vec![FloodTide {
+            prediction_time: "2026-10-15T16:45:00Z".parse().unwrap(),
+            height_ft: 6.5,
+        }];
+        // The failed attempt got as far as emailing a
+        let user = User {
+            id: "a".to_string(),
+            email: "a@example.com".to_string(),
+            ..Default::default()
+        };
+        notification_log::record(&pool, "1", &user, "Floods", None).await;
+        notification_log::mark_notified(&pool, &user, &floods).await;
+
+        let emailed = |last_succeeded, floods: Vec<FloodTide>| {
+            let pool = pool.clone();
+            async move {
+                fetch_subscribers(&pool, last_succeeded)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|subscriber| Subscriber {
+                        floods: floods.clone(),
+                        ..subscriber
+                    })
+                    .filter(Subscriber::already_emailed)
+                    .map(|subscriber| subscriber.user.id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(emailed(None, floods.clone()).await, ["a"]);
+        // A later run, or one about other floods, emails them again
+        let later = Utc::now().naive_utc() + Duration::hours(1);
+        assert!(emailed(Some(later), floods.clone()).await.is_empty());
+        let mut more = floods.clone();
+        more.push(FloodTide {
+            prediction_time: "2026-10-16T06:00:00Z".parse().unwrap(),
+            height_ft: 6.5,
+        });
+        assert!(emailed(None, more).await.is_empty());
+    }
+}
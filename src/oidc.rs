@@ -0,0 +1,378 @@
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use reqwest::Url;
+use serde::Deserialize;
+use std::env;
+use std::sync::Arc;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::admin::start_session;
+
+const LOGIN_KEY: &str = "oidc_login";
+pub const CALLBACK_PATH: &str = "/admin/oidc/callback";
+
+/// Login to the admin area through an OpenID Connect provider such as
+/// Google, limited to the addresses in `ADMIN_EMAILS`.
+pub struct OidcConfig {
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    admin_emails: Vec<String>,
+}
+
+impl OidcConfig {
+    /// Returns the config when `OIDC_ISSUER`, `OIDC_CLIENT_ID`,
+    /// `OIDC_CLIENT_SECRET` and `ADMIN_EMAILS` are all set.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        Some(OidcConfig {
+            issuer: var("OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: var("OIDC_CLIENT_ID")?,
+            client_secret: var("OIDC_CLIENT_SECRET")?,
+            admin_emails: parse_admin_emails(&var("ADMIN_EMAILS")?),
+        })
+    }
+
+    pub fn is_admin(&self, email: &str) -> bool {
+        self.admin_emails
+            .iter()
+            .any(|admin| admin.eq_ignore_ascii_case(email.trim()))
+    }
+}
+
+fn parse_admin_emails(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|email| !email.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+impl ProviderMetadata {
+    /// The document must name the issuer it was fetched for (OpenID Connect
+    /// Discovery 4.3), or its endpoints can't be trusted.
+    fn check_issuer(self, issuer: &str) -> Result<Self, String> {
+        if self.issuer.trim_end_matches('/') != issuer {
+            return Err(format!(
+                "discovery document names issuer {} rather than {}",
+                self.issuer, issuer
+            ));
+        }
+        Ok(self)
+    }
+}
+
+async fn discover(client: &reqwest::Client, issuer: &str) -> Result<ProviderMetadata, String> {
+    let metadata: ProviderMetadata = async {
+        client
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+    .await
+    .map_err(|e: reqwest::Error| e.to_string())?;
+    metadata.check_issuer(issuer)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+/// Reads the claims of an ID token. The signature isn't checked since the
+/// token comes straight from the provider's token endpoint over TLS
+/// (OpenID Connect Core 3.1.3.7).
+fn decode_claims(id_token: &str) -> Option<IdTokenClaims> {
+    let payload = id_token.split('.').nth(1)?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+/// Returns the verified email address the ID token was issued for.
+fn validate_claims(
+    claims: IdTokenClaims,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> Result<String, &'static str> {
+    if claims.iss.trim_end_matches('/') != issuer {
+        return Err("ID token was issued by a different provider");
+    }
+    if !claims.aud.contains(client_id) {
+        return Err("ID token was issued for a different client");
+    }
+    if claims.exp <= now {
+        return Err("ID token has expired");
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err("ID token nonce doesn't match this login");
+    }
+    match claims.email {
+        Some(email) if claims.email_verified => Ok(email),
+        _ => Err("Provider didn't return a verified email address"),
+    }
+}
+
+fn redirect_uri(state: &AppState) -> String {
+    format!("{}{}", state.base_url.trim_end_matches('/'), CALLBACK_PATH)
+}
+
+fn bad_gateway(context: &str, error: impl std::fmt::Display) -> Response {
+    eprintln!("{}: {}", context, error);
+    (
+        StatusCode::BAD_GATEWAY,
+        "Login provider error, please try again.",
+    )
+        .into_response()
+}
+
+/// Sends the browser to the provider, remembering the state and nonce
+/// of this login attempt in the session.
+pub async fn login_handler(State(state): State<Arc<AppState>>, session: Session) -> Response {
+    let Some(oidc) = &state.oidc else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    let metadata = match discover(&reqwest::Client::new(), &oidc.issuer).await {
+        Ok(metadata) => metadata,
+        Err(e) => return bad_gateway("OIDC discovery failed", e),
+    };
+
+    let csrf_state = Uuid::new_v4().simple().to_string();
+    let nonce = Uuid::new_v4().simple().to_string();
+    let url = match Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", oidc.client_id.as_str()),
+            ("redirect_uri", redirect_uri(&state).as_str()),
+            ("scope", "openid email"),
+            ("state", csrf_state.as_str()),
+            ("nonce", nonce.as_str()),
+        ],
+    ) {
+        Ok(url) => url,
+        Err(e) => return bad_gateway("Invalid authorization endpoint", e),
+    };
+
+    if let Err(e) = session.insert(LOGIN_KEY, (csrf_state, nonce)).await {
+        eprintln!("Error saving OIDC login to session: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response();
+    }
+    Redirect::to(url.as_str()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Exchanges the authorization code for an ID token and starts an admin
+/// session if its email address is on the allowlist.
+pub async fn callback_handler(
+    State(state): State<Arc<AppState>>,
+    session: Session,
+    Query(params): Query<CallbackParams>,
+) -> Response {
+    let Some(oidc) = &state.oidc else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    let login: Option<(String, String)> = session.remove(LOGIN_KEY).await.ok().flatten();
+
+    if let Some(error) = params.error {
+        eprintln!("OIDC login was refused: {}", error);
+        return (StatusCode::FORBIDDEN, "Login was cancelled.").into_response();
+    }
+    let (Some((csrf_state, nonce)), Some(code)) = (login, params.code) else {
+        return (StatusCode::BAD_REQUEST, "Login expired, please try again.").into_response();
+    };
+    if params.state.as_deref() != Some(csrf_state.as_str()) {
+        return (StatusCode::BAD_REQUEST, "Login expired, please try again.").into_response();
+    }
+
+    let client = reqwest::Client::new();
+    let metadata = match discover(&client, &oidc.issuer).await {
+        Ok(metadata) => metadata,
+        Err(e) => return bad_gateway("OIDC discovery failed", e),
+    };
+    let redirect_uri = redirect_uri(&state);
+    let token = client
+        .post(&metadata.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("client_id", oidc.client_id.as_str()),
+            ("client_secret", oidc.client_secret.as_str()),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    let token: TokenResponse = match token {
+        Ok(response) => match response.json().await {
+            Ok(token) => token,
+            Err(e) => return bad_gateway("Invalid OIDC token response", e),
+        },
+        Err(e) => return bad_gateway("OIDC token exchange failed", e),
+    };
+
+    let Some(claims) = decode_claims(&token.id_token) else {
+        return bad_gateway(
+            "Invalid OIDC token response",
+            "ID token couldn't be decoded",
+        );
+    };
+    let email = match validate_claims(
+        claims,
+        &oidc.issuer,
+        &oidc.client_id,
+        &nonce,
+        Utc::now().timestamp(),
+    ) {
+        Ok(email) => email,
+        Err(reason) => {
+            eprintln!("Rejected OIDC login: {}", reason);
+            return (StatusCode::FORBIDDEN, "Login failed.").into_response();
+        }
+    };
+
+    if !oidc.is_admin(&email) {
+        eprintln!("Rejected OIDC login for non-admin {}", email);
+        return (StatusCode::FORBIDDEN, "This account isn't an admin.").into_response();
+    }
+
+    if let Err(e) = start_session(&session, &email).await {
+        eprintln!("Error starting admin session: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response();
+    }
+    println!("Admin {} logged in", email);
+    Redirect::to("/admin").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(nonce: &str, exp: i64) -> IdTokenClaims {
+        IdTokenClaims {
+            iss: "https://accounts.google.com".to_string(),
+            aud: Audience::One("client".to_string()),
+            exp,
+            nonce: Some(nonce.to_string()),
+            email: Some("Admin@Example.com".to_string()),
+            email_verified: true,
+        }
+    }
+
+    #[test]
+    fn test_is_admin() {
+        let config = OidcConfig {
+            issuer: "https://accounts.google.com".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            admin_emails: parse_admin_emails(" admin@example.com, Other@Example.com ,"),
+        };
+        assert_eq!(config.admin_emails.len(), 2);
+        assert!(config.is_admin("Admin@Example.com"));
+        assert!(config.is_admin("other@example.com"));
+        assert!(!config.is_admin("someone@example.com"));
+    }
+
+    #[test]
+    fn test_decode_claims() {
+        let payload = URL_SAFE_NO_PAD.encode(
+            r#"{"iss":"https://accounts.google.com","aud":["client","other"],"exp":10,"nonce":"n","email":"a@b.c","email_verified":true}"#,
+        );
+        let claims = decode_claims(&format!("header.{}.signature", payload)).unwrap();
+        assert!(claims.aud.contains("client"));
+        assert_eq!(claims.email.as_deref(), Some("a@b.c"));
+
+        assert!(decode_claims("not-a-jwt").is_none());
+    }
+
+    #[test]
+    fn test_validate_claims() {
+        let issuer = "https://accounts.google.com";
+        assert_eq!(
+            validate_claims(claims("n", 100), issuer, "client", "n", 50),
+            Ok("Admin@Example.com".to_string())
+        );
+        assert!(validate_claims(claims("n", 100), issuer, "client", "other", 50).is_err());
+        assert!(validate_claims(claims("n", 100), issuer, "other-client", "n", 50).is_err());
+        assert!(
+            validate_claims(claims("n", 100), "https://evil.example", "client", "n", 50).is_err()
+        );
+        assert!(validate_claims(claims("n", 40), issuer, "client", "n", 50).is_err());
+
+        let mut unverified = claims("n", 100);
+        unverified.email_verified = false;
+        assert!(validate_claims(unverified, issuer, "client", "n", 50).is_err());
+    }
+
+    #[test]
+    fn test_check_issuer() {
+        let metadata = |issuer: &str| ProviderMetadata {
+            issuer: issuer.to_string(),
+            authorization_endpoint: "https://evil.example/auth".to_string(),
+            token_endpoint: "https://evil.example/token".to_string(),
+        };
+        let issuer = "https://accounts.google.com";
+        assert!(
+            metadata("https://accounts.google.com/")
+                .check_issuer(issuer)
+                .is_ok()
+        );
+        assert!(
+            metadata("https://evil.example")
+                .check_issuer(issuer)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use reqwest::Url;
+use serde_json::{Value, json};
+use std::env;
+use std::time::Duration;
+
+/// Every event name, the default for `OPS_WEBHOOK_EVENTS`.
+pub const EVENT_NAMES: [&str; 4] = ["signup", "unsubscribe", "sync_failed", "notify"];
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Notable things happening on the site, posted to `OPS_WEBHOOK_URL` so the
+/// operator can follow along, e.g. in a private Slack channel. Separate from
+/// `ALERT_WEBHOOK_URL`, which is only for failed jobs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpsEvent {
+    Signup {
+        email: String,
+        source: String,
+        /// Signed up again after leaving
+        resubscribe: bool,
+    },
+    Unsubscribe {
+        email: String,
+        /// Stopped the emails but kept other channels
+        email_only: bool,
+    },
+    SyncFailed {
+        error: String,
+    },
+    /// A notify run that had floods to send
+    NotifyRun {
+        floods: usize,
+        sent: usize,
+    },
+}
+
+impl OpsEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpsEvent::Signup { .. } => "signup",
+            OpsEvent::Unsubscribe { .. } => "unsubscribe",
+            OpsEvent::SyncFailed { .. } => "sync_failed",
+            OpsEvent::NotifyRun { .. } => "notify",
+        }
+    }
+
+    /// One line summary, what Slack and Mattermost incoming webhooks post.
+    fn text(&self) -> String {
+        match self {
+            OpsEvent::Signup {
+                email,
+                source,
+                resubscribe: false,
+            } => format!("New signup: {} (from {})", email, source),
+            OpsEvent::Signup {
+                email,
+                source,
+                resubscribe: true,
+            } => format!("Signed up again: {} (from {})", email, source),
+            OpsEvent::Unsubscribe {
+                email,
+                email_only: false,
+            } => format!("Unsubscribed: {}", email),
+            OpsEvent::Unsubscribe {
+                email,
+                email_only: true,
+            } => format!("Stopped the emails, still on other channels: {}", email),
+            OpsEvent::SyncFailed { error } => format!("Tide prediction sync failed: {}", error),
+            OpsEvent::NotifyRun { floods, sent } => format!(
+                "Notify run: {} floods predicted, {} notifications sent",
+                floods, sent
+            ),
+        }
+    }
+
+    fn body(&self) -> Value {
+        let mut body = match self {
+            OpsEvent::Signup {
+                email,
+                source,
+                resubscribe,
+            } => json!({ "email": email, "source": source, "resubscribe": resubscribe }),
+            OpsEvent::Unsubscribe { email, email_only } => {
+                json!({ "email": email, "email_only": email_only })
+            }
+            OpsEvent::SyncFailed { error } => json!({ "error": error }),
+            OpsEvent::NotifyRun { floods, sent } => json!({ "floods": floods, "sent": sent }),
+        };
+        body["event"] = json!(self.name());
+        body["text"] = json!(self.text());
+        body
+    }
+}
+
+/// `OPS_WEBHOOK_URL`, and `OPS_WEBHOOK_EVENTS` to only post some events.
+struct OpsWebhookConfig {
+    url: Url,
+    events: Vec<String>,
+}
+
+impl OpsWebhookConfig {
+    fn from_env() -> Option<Self> {
+        let url = env::var("OPS_WEBHOOK_URL")
+            .ok()
+            .and_then(|url| Url::parse(url.trim()).ok())?;
+        let events = env::var("OPS_WEBHOOK_EVENTS")
+            .ok()
+            .filter(|events| !events.trim().is_empty())
+            .map(|events| parse_events(&events))
+            .unwrap_or_else(|| EVENT_NAMES.map(String::from).to_vec());
+        Some(OpsWebhookConfig { url, events })
+    }
+}
+
+/// The comma separated event names, lowercased.
+pub fn parse_events(events: &str) -> Vec<String> {
+    events
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Posts `event` to the webhook when it's set and the event is enabled.
+/// Errors are logged, the operator's feed is never worth failing over.
+pub async fn post(event: OpsEvent) {
+    let Some(config) = OpsWebhookConfig::from_env() else {
+        return;
+    };
+    if !config.events.iter().any(|name| name == event.name()) {
+        return;
+    }
+    let result = reqwest::Client::new()
+        .post(config.url)
+        .timeout(TIMEOUT)
+        .json(&event.body())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        eprintln!(
+            "Error posting the {} event to the ops webhook: {}",
+            event.name(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let signup = OpsEvent::Signup {
+            email: "bob@example.com".to_string(),
+            source: "web".to_string(),
+            resubscribe: false,
+        };
+        let body = signup.body();
+        assert_eq!(body["event"], "signup");
+        assert_eq!(body["email"], "bob@example.com");
+        assert_eq!(body["text"], "New signup: bob@example.com (from web)");
+
+        let run = OpsEvent::NotifyRun {
+            floods: 2,
+            sent: 40,
+        };
+        assert_eq!(
+            run.body()["text"],
+            "Notify run: 2 floods predicted, 40 notifications sent"
+        );
+        assert_eq!(parse_events(" Signup, notify,"), ["signup", "notify"]);
+    }
+}
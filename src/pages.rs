@@ -0,0 +1,129 @@
+use askama::Template;
+use axum::extract::Path;
+use axum::response::Html;
+use pulldown_cmark::{Options, Parser, html};
+use std::fs;
+use std::io;
+use std::path::Path as FsPath;
+
+use crate::error::AppError;
+
+/// Markdown files served at `/pages/<slug>`, named `<slug>.md`.
+pub const CONTENT_DIR: &str = "content";
+
+/// A Markdown page from `content/`, rendered to HTML.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub slug: String,
+    /// The first `# ` heading, or the slug without one
+    pub title: String,
+    pub html: String,
+}
+
+/// Slugs are lowercase letters, digits and dashes, so they can't leave
+/// `content/`.
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn title(slug: &str, markdown: &str) -> String {
+    markdown
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|heading| heading.trim().to_string())
+        .unwrap_or_else(|| slug.to_string())
+}
+
+fn to_html(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, options));
+    body
+}
+
+/// Reads the page fresh each time, so edits show up without a restart.
+pub fn load(dir: &FsPath, slug: &str) -> io::Result<Option<Page>> {
+    if !is_valid_slug(slug) {
+        return Ok(None);
+    }
+    let markdown = match fs::read_to_string(dir.join(format!("{}.md", slug))) {
+        Ok(markdown) => markdown,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(Page {
+        slug: slug.to_string(),
+        title: title(slug, &markdown),
+        html: to_html(&markdown),
+    }))
+}
+
+/// Every page in `dir`, sorted by slug.
+pub fn list(dir: &FsPath) -> io::Result<Vec<Page>> {
+    let mut pages = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "md")
+            && let Some(slug) = path.file_stem().and_then(|stem| stem.to_str())
+            && let Some(page) = load(dir, slug)?
+        {
+            pages.push(page);
+        }
+    }
+    pages.sort_by(|a, b| a.slug.cmp(&b.slug));
+    Ok(pages)
+}
+
+#[derive(Template)]
+#[template(path = "page.html")]
+pub struct PageTemplate<'a> {
+    pub page: &'a Page,
+}
+
+pub async fn page_handler(Path(slug): Path<String>) -> Result<Html<String>, AppError> {
+    let page = load(FsPath::new(CONTENT_DIR), &slug).map_err(|e| {
+        eprintln!("Error reading page {}: {}", slug, e);
+        AppError::Internal("Error reading page".to_string())
+    })?;
+    let Some(page) = page else {
+        return Err(AppError::NotFound(
+            "There's nothing at this address.".to_string(),
+        ));
+    };
+    Ok(Html(PageTemplate { page: &page }.render()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages() {
+        assert!(is_valid_slug("why-does-the-path-flood"));
+        assert!(!is_valid_slug("../Cargo"));
+        assert!(!is_valid_slug("FAQ"));
+        assert!(!is_valid_slug(""));
+
+        assert_eq!(
+            title("faq", "Intro\n\n# Questions \n\n# Other"),
+            "Questions"
+        );
+        assert_eq!(title("faq", "No heading"), "faq");
+        assert_eq!(
+            to_html("# Hi\n\n| a |\n|---|\n| 1 |\n"),
+            "<h1>Hi</h1>\n<table><thead><tr><th>a</th></tr></thead><tbody>\n<tr><td>1</td></tr>\n</tbody></table>\n"
+        );
+
+        let dir = FsPath::new(CONTENT_DIR);
+        assert_eq!(load(dir, "../Cargo").unwrap(), None);
+        assert_eq!(load(dir, "missing").unwrap(), None);
+        let pages = list(dir).unwrap();
+        assert!(pages.iter().any(|page| page.slug == "faq"));
+        for page in pages {
+            assert!(page.html.contains("<h1>"), "{}", page.slug);
+        }
+    }
+}
@@ -0,0 +1,596 @@
+use argon2::password_hash::phc::PasswordHash;
+use lettre::Address;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::client::TlsParameters;
+use reqwest::Url;
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashSet;
+use std::env;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::email_templates::{self, EmailKind};
+use crate::listen;
+use crate::mail::{DEFAULT_MAIL_DIR, MAIL_TRANSPORTS};
+use crate::migrations;
+use crate::ops_webhook::{self, EVENT_NAMES};
+use crate::site::{self, OWN_DATABASE_VARS, SITE_VARS, site};
+use crate::tides::{self, DEFAULT_STATION_ID};
+
+/// Settings `serve` can't start without.
+const REQUIRED_VARS: [&str; 3] = ["BASE_URL", "UNSUBSCRIBE_SECRET", "SMTP_FROM"];
+/// Also required when mail goes out over SMTP, the default transport.
+const SMTP_VARS: [&str; 4] = ["SMTP_SERVER", "SMTP_PORT", "SMTP_USER", "SMTP_PASSWORD"];
+
+/// Every problem the startup checks found, so they can all be fixed in one go.
+#[derive(Debug, Error)]
+#[error("Startup checks failed:\n{}", .0.iter().map(|problem| format!("  - {}", problem)).collect::<Vec<_>>().join("\n"))]
+pub struct PreflightError(pub Vec<String>);
+
+/// Checks the configuration, database and templates before `serve` migrates
+/// the database and binds its listener, instead of panicking on the first
+/// request that needs them.
+pub async fn check(pool: &SqlitePool, auto_migrate: bool) -> Result<(), PreflightError> {
+    let mut problems = config_problems(|key| env::var(key).ok());
+    problems.extend(database_problems(pool, auto_migrate).await);
+    #[cfg(feature = "redis")]
+    problems.extend(crate::shared::check().await);
+    if !Path::new("assets").is_dir() {
+        problems.push("The assets directory is missing from the working directory".to_string());
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(PreflightError(problems))
+    }
+}
+
+/// Checks the database of an extra site from `SITES`, whose settings `check`
+/// has already been through.
+pub async fn check_site(pool: &SqlitePool, auto_migrate: bool) -> Result<(), PreflightError> {
+    let problems: Vec<String> = database_problems(pool, auto_migrate)
+        .await
+        .into_iter()
+        .map(|problem| format!("{}: {}", site().id, problem))
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(PreflightError(problems))
+    }
+}
+
+async fn database_problems(pool: &SqlitePool, auto_migrate: bool) -> Vec<String> {
+    let mut problems = migration_problems(pool).await;
+    if !auto_migrate {
+        match migrations::pending(pool).await {
+            Ok(0) => {}
+            Ok(pending) => problems.push(format!(
+                "{} migrations are pending, apply them with `migrate run` first",
+                pending
+            )),
+            Err(e) => problems.push(format!("Couldn't read the migration status: {}", e)),
+        }
+    }
+    if let Err(e) = check_writable(pool).await {
+        problems.push(format!("The database isn't writable: {}", e));
+    }
+    problems.extend(template_problems(pool).await);
+    problems
+}
+
+/// Problems with the extra sites in `SITES`: each needs its own hosts,
+/// database and location, and the settings it overrides are checked like
+/// the default site's.
+fn site_problems(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let var = |key: &str| var(key).filter(|value| !value.trim().is_empty());
+    let mut problems = Vec::new();
+    let mut hosts = HashSet::new();
+    let mut database_urls: HashSet<String> = var("DATABASE_URL").into_iter().collect();
+    for id in site::site_ids(var("SITES").as_deref()) {
+        if id == "default" || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            problems.push(format!(
+                "SITES ids must be letters, digits and dashes other than default: {}",
+                id
+            ));
+            continue;
+        }
+        let key = |key: &str| site::prefixed(&id, key);
+        let own_database = var(&key("DATABASE_URL")).is_some();
+        let required = if own_database {
+            &OWN_DATABASE_VARS[..]
+        } else {
+            &[]
+        };
+        problems.extend(
+            SITE_VARS
+                .iter()
+                .chain(required)
+                .filter(|name| var(&key(name)).is_none())
+                .map(|name| format!("{} must be set for the {} site", key(name), id)),
+        );
+        if !own_database
+            && let Some(station) = var(&key("STATION_ID"))
+            && station.trim() != var("STATION_ID").as_deref().unwrap_or(DEFAULT_STATION_ID)
+        {
+            problems.push(format!(
+                "{} can't differ from STATION_ID while the {} site shares its database, set {} too",
+                key("STATION_ID"),
+                id,
+                key("DATABASE_URL")
+            ));
+        }
+        for host in var(&key("HOSTS")).unwrap_or_default().split(',') {
+            let host = host.trim().to_lowercase();
+            if !host.is_empty() && !hosts.insert(host.clone()) {
+                problems.push(format!("{} is in more than one site's HOSTS", host));
+            }
+        }
+        if let Some(url) = var(&key("DATABASE_URL"))
+            && !database_urls.insert(url.clone())
+        {
+            problems.push(format!(
+                "{} is another site's database, leave it unset to share the default site's: {}",
+                key("DATABASE_URL"),
+                url
+            ));
+        }
+        if let Some(base_url) = var(&key("BASE_URL"))
+            && !Url::parse(&base_url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        {
+            problems.push(format!(
+                "{} must be an http(s) URL: {}",
+                key("BASE_URL"),
+                base_url
+            ));
+        }
+        if let Some(from) = var(&key("SMTP_FROM"))
+            && let Err(e) = from.parse::<Mailbox>()
+        {
+            problems.push(format!("{} isn't a valid address: {}", key("SMTP_FROM"), e));
+        }
+        if let Some(value) = var(&key("FLOOD_THRESHOLD_FT"))
+            && !value.trim().parse::<f64>().is_ok_and(f64::is_finite)
+        {
+            problems.push(format!(
+                "{} must be a number: {}",
+                key("FLOOD_THRESHOLD_FT"),
+                value
+            ));
+        }
+        if let Some(value) = var(&key("FLOOD_THRESHOLDS"))
+            && let Err(e) = tides::parse_thresholds(&value)
+        {
+            problems.push(format!("{} is invalid: {}", key("FLOOD_THRESHOLDS"), e));
+        }
+        problems.extend(theme_problems(&key(""), var));
+    }
+    problems
+}
+
+/// Problems with the theme settings, each named with `prefix`.
+fn theme_problems(prefix: &str, var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let key = |key: &str| format!("{}{}", prefix, key);
+    if let Some(color) = var(&key("ACCENT_COLOR"))
+        && !site::is_hex_color(color.trim())
+    {
+        problems.push(format!(
+            "{} must be a #rgb or #rrggbb color: {}",
+            key("ACCENT_COLOR"),
+            color
+        ));
+    }
+    if let Some(logo) = var(&key("LOGO_PATH")) {
+        let logo = logo.trim();
+        let path = logo.trim_start_matches('/');
+        let path = path.strip_prefix("assets/").unwrap_or(path);
+        if !logo.starts_with("http://")
+            && !logo.starts_with("https://")
+            && !Path::new("assets").join(path).is_file()
+        {
+            problems.push(format!(
+                "{} must be a file in assets/ or a URL: {}",
+                key("LOGO_PATH"),
+                logo
+            ));
+        }
+    }
+    problems
+}
+
+/// Problems with the environment variables, read through `var`.
+fn config_problems(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let var = |key: &str| var(key).filter(|value| !value.is_empty());
+    let transport = var("MAIL_TRANSPORT").unwrap_or_else(|| "smtp".to_string());
+    let smtp_vars = if transport.trim() == "smtp" {
+        &SMTP_VARS[..]
+    } else {
+        &[]
+    };
+    let mut problems: Vec<String> = REQUIRED_VARS
+        .iter()
+        .chain(smtp_vars)
+        .filter(|key| var(key).is_none())
+        .map(|key| format!("{} must be set", key))
+        .collect();
+    if !MAIL_TRANSPORTS.contains(&transport.trim()) {
+        problems.push(format!(
+            "MAIL_TRANSPORT must be one of {}: {}",
+            MAIL_TRANSPORTS.join(", "),
+            transport
+        ));
+    }
+    if transport.trim() == "file" {
+        let dir = var("MAIL_DIR").unwrap_or_else(|| DEFAULT_MAIL_DIR.to_string());
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            problems.push(format!("MAIL_DIR {} can't be created: {}", dir, e));
+        }
+    }
+
+    if let Some(base_url) = var("BASE_URL") {
+        match Url::parse(&base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => problems.push(format!("BASE_URL must be an http(s) URL: {}", base_url)),
+        }
+    }
+    if let Some(port) = var("SMTP_PORT")
+        && port.parse::<u16>().is_err()
+    {
+        problems.push(format!("SMTP_PORT must be a valid port: {}", port));
+    }
+    if let Some(server) = var("SMTP_SERVER")
+        && let Err(e) = TlsParameters::new(server.clone())
+    {
+        problems.push(format!(
+            "SMTP_SERVER {} can't be used for TLS: {}",
+            server, e
+        ));
+    }
+    if let Some(from) = var("SMTP_FROM")
+        && let Err(e) = from.parse::<Mailbox>()
+    {
+        problems.push(format!("SMTP_FROM isn't a valid address: {}", e));
+    }
+    for key in ["CONTACT_EMAIL", "ALERT_EMAIL"] {
+        if let Some(address) = var(key)
+            && address.parse::<Address>().is_err()
+        {
+            problems.push(format!("{} isn't a valid address: {}", key, address));
+        }
+    }
+    if let Some(hash) = var("ADMIN_PASSWORD_HASH")
+        && PasswordHash::new(&hash).is_err()
+    {
+        problems.push("ADMIN_PASSWORD_HASH must be an argon2 hash, see hash-password".to_string());
+    }
+    if var("REDIS_URL").is_some() && !cfg!(feature = "redis") {
+        problems.push("REDIS_URL is set but this build doesn't have the redis feature".to_string());
+    }
+    if let Some(endpoint) = var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        if !cfg!(feature = "otel") {
+            problems.push(
+                "OTEL_EXPORTER_OTLP_ENDPOINT is set but this build doesn't have the otel feature"
+                    .to_string(),
+            );
+        }
+        if !Url::parse(&endpoint).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+            problems.push(format!(
+                "OTEL_EXPORTER_OTLP_ENDPOINT must be an http(s) URL: {}",
+                endpoint
+            ));
+        }
+    }
+    if let Some(path) = var("ACCESS_LOG")
+        && path.trim() != "stdout"
+        && let Err(e) = crate::access_log::open(path.trim())
+    {
+        problems.push(format!(
+            "ACCESS_LOG must be stdout or a writable file: {}",
+            e
+        ));
+    }
+    if let Some(filter) = var("RUST_LOG")
+        && let Err(e) = tracing_subscriber::EnvFilter::try_new(&filter)
+    {
+        problems.push(format!("RUST_LOG isn't a valid filter ({}): {}", e, filter));
+    }
+    if let Some(url) = var("ALERT_WEBHOOK_URL")
+        && !Url::parse(&url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+    {
+        problems.push(format!("ALERT_WEBHOOK_URL must be an http(s) URL: {}", url));
+    }
+    if let Some(url) = var("OPS_WEBHOOK_URL")
+        && !Url::parse(&url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+    {
+        problems.push(format!("OPS_WEBHOOK_URL must be an http(s) URL: {}", url));
+    }
+    if let Some(events) = var("OPS_WEBHOOK_EVENTS") {
+        for name in ops_webhook::parse_events(&events) {
+            if !EVENT_NAMES.contains(&name.as_str()) {
+                problems.push(format!(
+                    "OPS_WEBHOOK_EVENTS has an unknown event {}, expected one of {}",
+                    name,
+                    EVENT_NAMES.join(", ")
+                ));
+            }
+        }
+    }
+    if let Some(homeserver) = var("MATRIX_HOMESERVER")
+        && Url::parse(&homeserver).map_or(true, |url| url.cannot_be_a_base())
+    {
+        problems.push(format!("MATRIX_HOMESERVER must be a URL: {}", homeserver));
+    }
+
+    for key in [
+        "RATE_LIMIT_PERIOD_MS",
+        "RATE_LIMIT_BURST",
+        "SIGNUP_RATE_LIMIT_PERIOD_SECS",
+        "SIGNUP_RATE_LIMIT_BURST",
+    ] {
+        if let Some(value) = var(key)
+            && !value.parse::<u64>().is_ok_and(|value| value > 0)
+        {
+            problems.push(format!("{} must be a positive number: {}", key, value));
+        }
+    }
+    if let Some(value) = var("FLOOD_THRESHOLD_FT")
+        && !value.trim().parse::<f64>().is_ok_and(f64::is_finite)
+    {
+        problems.push(format!("FLOOD_THRESHOLD_FT must be a number: {}", value));
+    }
+    if let Some(value) = var("FLOOD_THRESHOLDS")
+        && let Err(e) = tides::parse_thresholds(&value)
+    {
+        problems.push(format!("FLOOD_THRESHOLDS is invalid: {}", e));
+    }
+    for key in ["LISTEN_ADDRS", "INTERNAL_LISTEN_ADDRS"] {
+        if let Some(value) = var(key)
+            && let Err(e) = listen::parse_addrs(&value)
+        {
+            problems.push(format!("{} has an invalid address ({}): {}", key, e, value));
+        }
+    }
+    for key in ["APPLY_CALIBRATED_THRESHOLD", "PREDICTION_BIAS_CORRECTION"] {
+        if let Some(value) = var(key)
+            && value.trim().parse::<bool>().is_err()
+        {
+            problems.push(format!("{} must be true or false", key));
+        }
+    }
+    match var("ADMIN_INTERNAL_ONLY").map(|value| value.parse::<bool>()) {
+        Some(Err(_)) => problems.push("ADMIN_INTERNAL_ONLY must be true or false".to_string()),
+        Some(Ok(true)) if var("INTERNAL_LISTEN_ADDRS").is_none() => problems.push(
+            "ADMIN_INTERNAL_ONLY needs INTERNAL_LISTEN_ADDRS, or the admin area can't be reached"
+                .to_string(),
+        ),
+        _ => {}
+    }
+    if let Some(value) = var("TRUST_PROXY_HEADERS")
+        && value.parse::<bool>().is_err()
+    {
+        problems.push(format!(
+            "TRUST_PROXY_HEADERS must be true or false: {}",
+            value
+        ));
+    }
+    problems.extend(theme_problems("", var));
+    problems.extend(site_problems(var));
+    problems
+}
+
+async fn table_exists(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+    )
+    .bind(name)
+    .fetch_one(pool)
+    .await
+    .map(|count| count > 0)
+}
+
+/// Migrations that failed part way, or that this build doesn't know about
+/// because the database was migrated by a newer version. Pending ones are
+/// fine unless `--no-auto-migrate` is set, they're applied next.
+async fn migration_problems(pool: &SqlitePool) -> Vec<String> {
+    let applied = match table_exists(pool, "_sqlx_migrations").await {
+        Ok(false) => return Vec::new(),
+        Ok(true) => {
+            sqlx::query_as::<_, (i64, bool)>(
+                "SELECT version, success FROM _sqlx_migrations ORDER BY version",
+            )
+            .fetch_all(pool)
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let applied = match applied {
+        Ok(applied) => applied,
+        Err(e) => return vec![format!("Couldn't read the migration status: {}", e)],
+    };
+    let known: HashSet<i64> = sqlx::migrate!()
+        .iter()
+        .map(|migration| migration.version)
+        .collect();
+    let mut problems = Vec::new();
+    for (version, success) in applied {
+        if !success {
+            problems.push(format!("Migration {} didn't finish", version));
+        } else if !known.contains(&version) {
+            problems.push(format!(
+                "Migration {} was applied by a newer version of the app",
+                version
+            ));
+        }
+    }
+    problems
+}
+
+/// Takes the write lock in a transaction that's rolled back, which fails on
+/// a read-only file or directory.
+async fn check_writable(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("CREATE TABLE preflight_write_check (id INTEGER)")
+        .execute(&mut *tx)
+        .await?;
+    tx.rollback().await
+}
+
+/// Email overrides saved from the admin UI that can't be loaded, or that use
+/// variables the email doesn't provide and would be sent with them as is.
+async fn template_problems(pool: &SqlitePool) -> Vec<String> {
+    let mut problems = Vec::new();
+    // A database that hasn't been migrated yet has no overrides
+    if !table_exists(pool, "email_templates").await.unwrap_or(false) {
+        return problems;
+    }
+    for kind in EmailKind::ALL {
+        match email_templates::load_override(pool, kind).await {
+            Ok(Some(template)) => {
+                let unknown = template.unknown_variables(kind);
+                if !unknown.is_empty() {
+                    problems.push(format!(
+                        "The {} override uses unknown variables: {}",
+                        kind.as_str(),
+                        unknown.join(", ")
+                    ));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => problems.push(format!(
+                "Couldn't load the {} override: {}",
+                kind.as_str(),
+                e
+            )),
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_config_problems() {
+        let mut vars = HashMap::from([
+            ("BASE_URL", "https://example.com"),
+            ("UNSUBSCRIBE_SECRET", "secret"),
+            ("SMTP_SERVER", "smtp.example.com"),
+            ("SMTP_PORT", "587"),
+            ("SMTP_USER", "user"),
+            ("SMTP_PASSWORD", "password"),
+            ("SMTP_FROM", "Flood Alert <info@example.com>"),
+        ]);
+        let problems = |vars: &HashMap<&str, &str>| {
+            config_problems(|key| vars.get(key).map(|value| value.to_string()))
+        };
+        assert!(problems(&vars).is_empty());
+
+        // Only SMTP needs the SMTP settings
+        let mut sendmail = HashMap::from([
+            ("BASE_URL", "https://example.com"),
+            ("UNSUBSCRIBE_SECRET", "secret"),
+            ("SMTP_FROM", "Flood Alert <info@example.com>"),
+            ("MAIL_TRANSPORT", "sendmail"),
+        ]);
+        assert!(problems(&sendmail).is_empty());
+        sendmail.insert("MAIL_TRANSPORT", "pigeon");
+        assert_eq!(
+            problems(&sendmail),
+            ["MAIL_TRANSPORT must be one of smtp, sendmail, file: pigeon"]
+        );
+
+        vars.remove("SMTP_PASSWORD");
+        vars.insert("SMTP_PORT", "smtp");
+        vars.insert("RATE_LIMIT_BURST", "0");
+        vars.insert("CONTACT_EMAIL", "help at example.com");
+        vars.insert("ADMIN_INTERNAL_ONLY", "true");
+        assert_eq!(
+            problems(&vars),
+            [
+                "SMTP_PASSWORD must be set",
+                "SMTP_PORT must be a valid port: smtp",
+                "CONTACT_EMAIL isn't a valid address: help at example.com",
+                "RATE_LIMIT_BURST must be a positive number: 0",
+                "ADMIN_INTERNAL_ONLY needs INTERNAL_LISTEN_ADDRS, or the admin area can't be reached",
+            ]
+        );
+
+        let theme = HashMap::from([("ACCENT_COLOR", "teal"), ("LOGO_PATH", "img/favicon.png")]);
+        assert_eq!(
+            theme_problems("", |key| theme.get(key).map(|value| value.to_string())),
+            ["ACCENT_COLOR must be a #rgb or #rrggbb color: teal"]
+        );
+    }
+
+    #[test]
+    fn test_site_problems() {
+        let mut vars = HashMap::from([
+            ("SITES", "manzanita"),
+            ("DATABASE_URL", "sqlite:data/alerts.db"),
+            ("MANZANITA_HOSTS", "manzanita.example.com"),
+            ("MANZANITA_DATABASE_URL", "sqlite:data/manzanita.db"),
+            ("MANZANITA_SITE_NAME", "Manzanita Highway 1"),
+            ("MANZANITA_STATION_ID", "9437540"),
+            ("MANZANITA_STATION_NAME", "Garibaldi, Tillamook Bay"),
+            ("MANZANITA_BASE_URL", "https://manzanita.example.com"),
+        ]);
+        let problems = |vars: &HashMap<&str, &str>| {
+            site_problems(|key| vars.get(key).map(|value| value.to_string()))
+        };
+        assert!(problems(&vars).is_empty());
+
+        // Another hostname and brand sharing the default site's database
+        vars.insert("SITES", "manzanita,sausalito");
+        vars.insert("SAUSALITO_HOSTS", "sausalitoflood.example");
+        vars.insert("SAUSALITO_SITE_NAME", "Sausalito Bike Path");
+        vars.insert("SAUSALITO_BASE_URL", "https://sausalitoflood.example");
+        assert!(problems(&vars).is_empty());
+
+        vars.insert("SITES", "manzanita, nehalem, sausalito, default");
+        vars.insert("MANZANITA_DATABASE_URL", "sqlite:data/alerts.db");
+        vars.insert("MANZANITA_FLOOD_THRESHOLD_FT", "high");
+        vars.insert("NEHALEM_HOSTS", "Manzanita.example.com");
+        vars.insert("NEHALEM_DATABASE_URL", "sqlite:data/nehalem.db");
+        vars.insert("SAUSALITO_STATION_ID", "9414290");
+        assert_eq!(
+            problems(&vars),
+            [
+                "MANZANITA_DATABASE_URL is another site's database, leave it unset to share the default site's: sqlite:data/alerts.db",
+                "MANZANITA_FLOOD_THRESHOLD_FT must be a number: high",
+                "NEHALEM_SITE_NAME must be set for the nehalem site",
+                "NEHALEM_BASE_URL must be set for the nehalem site",
+                "NEHALEM_STATION_ID must be set for the nehalem site",
+                "NEHALEM_STATION_NAME must be set for the nehalem site",
+                "manzanita.example.com is in more than one site's HOSTS",
+                "SAUSALITO_STATION_ID can't differ from STATION_ID while the sausalito site shares its database, set SAUSALITO_DATABASE_URL too",
+                "SITES ids must be letters, digits and dashes other than default: default",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_database_checks() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        assert!(migration_problems(&pool).await.is_empty());
+        assert!(template_problems(&pool).await.is_empty());
+        sqlx::migrate!().run(&pool).await.unwrap();
+        assert!(migration_problems(&pool).await.is_empty());
+        check_writable(&pool).await.unwrap();
+        assert!(template_problems(&pool).await.is_empty());
+
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (99990101000000, 'from the future', 1, x'00', 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            migration_problems(&pool).await,
+            ["Migration 99990101000000 was applied by a newer version of the app"]
+        );
+    }
+}
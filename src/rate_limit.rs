@@ -0,0 +1,290 @@
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+#[cfg(feature = "redis")]
+use axum::response::IntoResponse;
+use axum::response::Response;
+use governor::middleware::NoOpMiddleware;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tower_governor::GovernorError;
+use tower_governor::governor::{GovernorConfig, GovernorConfigBuilder};
+use tower_governor::key_extractor::KeyExtractor;
+
+use crate::AppState;
+#[cfg(feature = "redis")]
+use crate::error::AppError;
+#[cfg(feature = "redis")]
+use crate::shared;
+
+const CF_CONNECTING_IP: &str = "cf-connecting-ip";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+pub type IpRateLimitConfig = GovernorConfig<TrustedIpKeyExtractor, NoOpMiddleware>;
+
+/// Rate limits read from the environment, with defaults that are generous enough
+/// for a person browsing the site but stop scrapers hammering the server.
+pub struct RateLimitConfig {
+    pub period: Duration,
+    pub burst: u32,
+    pub signup_period: Duration,
+    pub signup_burst: u32,
+    pub trust_proxy_headers: bool,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        RateLimitConfig {
+            period: Duration::from_millis(env_or("RATE_LIMIT_PERIOD_MS", 500)),
+            burst: env_or("RATE_LIMIT_BURST", 20),
+            signup_period: Duration::from_secs(env_or("SIGNUP_RATE_LIMIT_PERIOD_SECS", 60)),
+            signup_burst: env_or("SIGNUP_RATE_LIMIT_BURST", 3),
+            trust_proxy_headers: env_or("TRUST_PROXY_HEADERS", false),
+        }
+    }
+
+    /// Limiter applied to every route on the router.
+    pub fn global(&self) -> Arc<IpRateLimitConfig> {
+        self.build(self.period, self.burst)
+    }
+
+    /// Stricter limiter layered on top of the global one for the signup route,
+    /// since every accepted request there sends an email.
+    pub fn signup(&self) -> Arc<IpRateLimitConfig> {
+        self.build(self.signup_period, self.signup_burst)
+    }
+
+    /// The global limit counted across instances, see `shared_limit`.
+    pub fn shared_global(&self, state: &AppState) -> SharedLimit {
+        self.shared(state, "global", self.period, self.burst)
+    }
+
+    /// A signup limit counted across instances. Each `name` is counted
+    /// separately, like each `signup()` limiter.
+    pub fn shared_signup(&self, state: &AppState, name: &'static str) -> SharedLimit {
+        self.shared(state, name, self.signup_period, self.signup_burst)
+    }
+
+    #[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+    fn shared(
+        &self,
+        state: &AppState,
+        name: &'static str,
+        period: Duration,
+        burst: u32,
+    ) -> SharedLimit {
+        SharedLimit {
+            trust_proxy_headers: self.trust_proxy_headers,
+            #[cfg(feature = "redis")]
+            limit: state.redis.clone().map(|redis| shared::Limit {
+                redis,
+                name,
+                period,
+                burst,
+            }),
+        }
+    }
+
+    fn build(&self, period: Duration, burst: u32) -> Arc<IpRateLimitConfig> {
+        let config = GovernorConfigBuilder::default()
+            .period(period)
+            .burst_size(burst)
+            .key_extractor(TrustedIpKeyExtractor {
+                trust_proxy_headers: self.trust_proxy_headers,
+            })
+            .finish()
+            .expect("Rate limit period and burst size must be non-zero");
+        Arc::new(config)
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value: {}", key, value)),
+        Err(_) => default,
+    }
+}
+
+/// A limit counted in Redis across every instance, checked after the
+/// instance's own `GovernorLayer` so a client can't get more by being spread
+/// over several. Lets everything through without Redis.
+#[derive(Clone)]
+pub struct SharedLimit {
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    trust_proxy_headers: bool,
+    #[cfg(feature = "redis")]
+    limit: Option<shared::Limit>,
+}
+
+/// Middleware applying a `SharedLimit`. If Redis can't be reached the
+/// request is let through, leaving the instance's own limit.
+#[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+pub async fn shared_limit(
+    State(limit): State<SharedLimit>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    #[cfg(feature = "redis")]
+    if let Some(shared) = &limit.limit {
+        let peer_ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if let Some(ip) = client_ip(req.headers(), peer_ip, limit.trust_proxy_headers) {
+            match shared.check(ip).await {
+                Ok(None) => {}
+                Ok(Some(wait)) => {
+                    return AppError::TooManyRequests {
+                        message: "Too many requests, please try again shortly.".to_string(),
+                        retry_after_secs: Some(wait.as_secs().max(1)),
+                    }
+                    .into_response();
+                }
+                Err(e) => eprintln!("Error checking the {} rate limit: {}", shared.name, e),
+            }
+        }
+    }
+    next.run(req).await
+}
+
+/// Keys rate limits on the client IP. Proxy headers are only consulted when
+/// `TRUST_PROXY_HEADERS` is set, i.e. when running behind the Cloudflare tunnel,
+/// otherwise any client could spoof them to dodge the limiter.
+#[derive(Debug, Clone, Copy)]
+pub struct TrustedIpKeyExtractor {
+    trust_proxy_headers: bool,
+}
+
+impl KeyExtractor for TrustedIpKeyExtractor {
+    type Key = IpAddr;
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let peer_ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        client_ip(req.headers(), peer_ip, self.trust_proxy_headers)
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+}
+
+/// Resolves the IP of the client that made the request.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer_ip: Option<IpAddr>,
+    trust_proxy_headers: bool,
+) -> Option<IpAddr> {
+    if !trust_proxy_headers {
+        return peer_ip;
+    }
+
+    header_ip(headers, CF_CONNECTING_IP)
+        .or_else(|| {
+            headers
+                .get(X_FORWARDED_FOR)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| {
+                    value
+                        .split(',')
+                        .find_map(|ip| ip.trim().parse::<IpAddr>().ok())
+                })
+        })
+        .or_else(|| header_ip(headers, X_REAL_IP))
+        .or(peer_ip)
+}
+
+fn header_ip(headers: &HeaderMap, name: &str) -> Option<IpAddr> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Periodically drops limiter state for clients that have not been seen recently
+/// so the keyed store doesn't grow without bound.
+pub fn spawn_cleanup(configs: Vec<Arc<IpRateLimitConfig>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            for config in &configs {
+                config.limiter().retain_recent();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn peer() -> Option<IpAddr> {
+        Some("10.0.0.1".parse().unwrap())
+    }
+
+    #[test]
+    fn test_client_ip_ignores_headers_when_untrusted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CF_CONNECTING_IP, HeaderValue::from_static("203.0.113.7"));
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("203.0.113.8"));
+
+        assert_eq!(client_ip(&headers, peer(), false), peer());
+    }
+
+    #[test]
+    fn test_client_ip_prefers_cloudflare_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CF_CONNECTING_IP, HeaderValue::from_static("203.0.113.7"));
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("203.0.113.8"));
+
+        assert_eq!(
+            client_ip(&headers, peer(), true),
+            Some("203.0.113.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_client_ip_uses_first_valid_forwarded_for() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("unknown, 2001:db8::1, 203.0.113.8"),
+        );
+
+        assert_eq!(
+            client_ip(&headers, peer(), true),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_client_ip_falls_back_to_peer() {
+        let headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, peer(), true), peer());
+        assert_eq!(client_ip(&headers, None, true), None);
+    }
+
+    #[test]
+    fn test_rate_limit_config_builds_limiters() {
+        let config = RateLimitConfig {
+            period: Duration::from_millis(500),
+            burst: 20,
+            signup_period: Duration::from_secs(60),
+            signup_burst: 3,
+            trust_proxy_headers: true,
+        };
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let signup = config.signup();
+        for _ in 0..3 {
+            assert!(signup.limiter().check_key(&ip).is_ok());
+        }
+        assert!(signup.limiter().check_key(&ip).is_err());
+    }
+}
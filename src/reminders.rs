@@ -0,0 +1,541 @@
+use askama::Template;
+use axum::Form;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use std::sync::Arc;
+use tracing::instrument;
+
+use crate::AppState;
+use crate::channels::{
+    INVALID_NTFY_TOPIC, INVALID_PUSHOVER_KEY, INVALID_SIGNAL_NUMBER, SignalGateway, ntfy_servers,
+    parse_ntfy_topic, parse_pushover_key, parse_signal_number, pushover_app_token,
+};
+use crate::commute::{INVALID_COMMUTE_WINDOWS, normalize_windows};
+use crate::email_style::{EmailLayout, EmailStyle};
+use crate::error::AppError;
+use crate::feedback::feedback_link;
+use crate::i18n::DateFormat;
+use crate::mail::{self, NotificationLinks};
+use crate::models::{FloodDisplay, UnsubscribeParams, User};
+use crate::station_settings;
+use crate::tides::{FloodTide, get_flood_tides_within};
+
+/// Default hours ahead of a flood a reminder can be sent, so a run in the
+/// evening covers the next morning's tides.
+const DEFAULT_REMINDER_WINDOW_HOURS: i64 = 18;
+/// Shown when the page is opened without the link from an email.
+const NO_LINK: &str = "There's no password to change here. Your notification preferences open from the link at \
+                       the bottom of every email, please use the one in your most recent email.";
+
+/// The station settings' reminder window, else `REMINDER_WINDOW_HOURS`.
+pub fn window_hours() -> i64 {
+    if let Some(hours) = station_settings::current().reminder_window_hours {
+        return hours;
+    }
+    env::var("REMINDER_WINDOW_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_REMINDER_WINDOW_HOURS)
+}
+
+/// Link to the page for turning reminders on or off, signed like the
+/// unsubscribe link.
+pub fn reminders_link(base_url: &str, user: &User, secret: &str) -> String {
+    format!(
+        "{}/reminders?id={}&token={}",
+        base_url,
+        user.id,
+        user.generate_unsubscribe_token(secret)
+    )
+}
+
+/// Marks a flood as reminded for a subscriber. Returns false when they've
+/// already had a reminder for it.
+#[instrument(level = "debug", skip_all)]
+async fn claim(
+    pool: &SqlitePool,
+    user_id: &str,
+    prediction_time: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let prediction_time = prediction_time.naive_utc();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO reminder_log (user_id, prediction_time) VALUES (?, ?)
+        ON CONFLICT DO NOTHING;
+        "#,
+        user_id,
+        prediction_time
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Undoes claims for a reminder that couldn't be sent, so the next run
+/// tries again.
+#[instrument(level = "debug", skip_all)]
+async fn release(pool: &SqlitePool, user_id: &str, floods: &[&FloodTide]) {
+    for flood in floods {
+        let prediction_time = flood.prediction_time.naive_utc();
+        if let Err(e) = sqlx::query!(
+            "DELETE FROM reminder_log WHERE user_id = ? AND prediction_time = ?;",
+            user_id,
+            prediction_time
+        )
+        .execute(pool)
+        .await
+        {
+            eprintln!("Database error releasing reminder: {:?}", e);
+        }
+    }
+}
+
+/// Emails subscribers who opted in about floods in the next
+/// `REMINDER_WINDOW_HOURS`. Meant to run in the evening and again in the
+/// morning; the reminder log keeps it to one reminder per flood.
+pub async fn send_reminders(pool: SqlitePool) -> Result<(), AppError> {
+    let window_hours = window_hours();
+    let floods = get_flood_tides_within(&pool, Duration::hours(window_hours)).await?;
+    if floods.is_empty() {
+        println!(
+            "No floods in the next {} hours. No reminders to send.",
+            window_hours
+        );
+        return Ok(());
+    }
+
+    let recipients: Vec<User> = sqlx::query!(
+        r#"
+        SELECT id, email, email_layout, lang, clock_24h AS "clock_24h: bool" FROM users
+        WHERE is_verified = 1 AND is_subscribed = 1 AND wants_reminders = 1 AND wants_email = 1
+        "#
+    )
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .map(|record| User {
+        id: record.id,
+        email: record.email,
+        email_layout: record.email_layout,
+        lang: record.lang,
+        clock_24h: record.clock_24h,
+        ..Default::default()
+    })
+    .collect();
+    println!(
+        "Found {} floods in the next {} hours, checking reminders for {} subscribers",
+        floods.len(),
+        window_hours,
+        recipients.len()
+    );
+
+    let app_state = AppState::from_pool(pool);
+    let pool = &app_state.pool;
+    for user in &recipients {
+        let mut claimed = Vec::new();
+        for flood in &floods {
+            if claim(pool, &user.id, flood.prediction_time).await? {
+                claimed.push(flood);
+            }
+        }
+        if claimed.is_empty() {
+            continue;
+        }
+
+        let predictions: Vec<FloodDisplay> = claimed
+            .iter()
+            .map(|flood| {
+                FloodDisplay::localized(flood.prediction_time, flood.height_ft, user.date_format())
+            })
+            .collect();
+        let links = NotificationLinks {
+            homepage: app_state.base_url.clone(),
+            reminders: reminders_link(&app_state.base_url, user, &app_state.unsubscribe_secret),
+            feedback: feedback_link(&app_state.base_url, user, &app_state.unsubscribe_secret),
+            unsubscribe: format!(
+                "{}/unsubscribe?id={}&token={}",
+                app_state.base_url,
+                user.id,
+                user.generate_unsubscribe_token(&app_state.unsubscribe_secret)
+            ),
+            pixel: None,
+        };
+        match app_state
+            .mailer
+            .send_reminder_email(user, &predictions, window_hours, &links)
+            .await
+        {
+            Ok(()) => println!(
+                "Sent a reminder of {} floods to {}",
+                claimed.len(),
+                user.email
+            ),
+            // The next notify run records the bounce and suppresses the address
+            Err(e) if mail::is_bounce(&e) => {
+                eprintln!("Reminder to {} bounced: {}", user.email, e);
+            }
+            Err(e) => {
+                release(pool, &user.id, &claimed).await;
+                return Err(e.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Template)]
+#[template(path = "reminders.html")]
+pub struct RemindersTemplate {
+    pub user_id: String,
+    pub token: String,
+    pub enabled: bool,
+    pub invites: bool,
+    /// Emails come in the compact layout
+    pub compact: bool,
+    /// Flood times in emails use a 24 hour clock
+    pub clock_24h: bool,
+    pub ntfy_topic: Option<String>,
+    pub pushover_enabled: bool,
+    pub pushover_user_key: Option<String>,
+    pub signal_enabled: bool,
+    pub signal_number: Option<String>,
+    /// Times they're on the path, the only floods they're notified about
+    pub commute_windows: Option<String>,
+    pub window_hours: i64,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Posted by any of the page's forms, each setting one field. The push
+/// channel fields are empty to turn the channel off.
+#[derive(Deserialize)]
+pub struct RemindersForm {
+    enabled: Option<bool>,
+    invites: Option<bool>,
+    compact: Option<bool>,
+    clock_24h: Option<bool>,
+    ntfy_topic: Option<String>,
+    pushover_user_key: Option<String>,
+    signal_number: Option<String>,
+    commute_windows: Option<String>,
+}
+
+enum Update {
+    Reminders(bool),
+    Invites(bool),
+    Layout(EmailLayout),
+    Clock(bool),
+    Ntfy(Option<String>),
+    Pushover(Option<String>),
+    Signal(Option<String>),
+    Commute(Option<String>),
+}
+
+/// Parses an optional channel field, where empty turns the channel off.
+fn channel_value(
+    value: &str,
+    parse: impl Fn(&str) -> Option<String>,
+    invalid: &'static str,
+) -> Result<Option<String>, &'static str> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    parse(value).map(Some).ok_or(invalid)
+}
+
+fn form_update(form: RemindersForm) -> Result<Update, &'static str> {
+    if let Some(topic) = form.ntfy_topic {
+        return channel_value(
+            &topic,
+            |topic| parse_ntfy_topic(topic, &ntfy_servers()),
+            INVALID_NTFY_TOPIC,
+        )
+        .map(Update::Ntfy);
+    }
+    if let Some(key) = form.pushover_user_key {
+        return channel_value(&key, parse_pushover_key, INVALID_PUSHOVER_KEY).map(Update::Pushover);
+    }
+    if let Some(number) = form.signal_number {
+        return channel_value(&number, parse_signal_number, INVALID_SIGNAL_NUMBER)
+            .map(Update::Signal);
+    }
+    if let Some(windows) = form.commute_windows {
+        return normalize_windows(&windows)
+            .map(Update::Commute)
+            .ok_or(INVALID_COMMUTE_WINDOWS);
+    }
+    if let Some(invites) = form.invites {
+        return Ok(Update::Invites(invites));
+    }
+    if let Some(clock_24h) = form.clock_24h {
+        return Ok(Update::Clock(clock_24h));
+    }
+    if let Some(compact) = form.compact {
+        return Ok(Update::Layout(if compact {
+            EmailLayout::Compact
+        } else {
+            EmailLayout::Detailed
+        }));
+    }
+    form.enabled
+        .map(Update::Reminders)
+        .ok_or("There was nothing to change.")
+}
+
+fn render(status: StatusCode, template: RemindersTemplate) -> Response {
+    match template.render() {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => AppError::from(e).into_response(),
+    }
+}
+
+fn error_page(params: UnsubscribeParams, status: StatusCode, error: &str) -> Response {
+    render(
+        status,
+        RemindersTemplate {
+            user_id: params.id,
+            token: params.token,
+            enabled: false,
+            invites: false,
+            compact: false,
+            clock_24h: false,
+            ntfy_topic: None,
+            pushover_enabled: false,
+            pushover_user_key: None,
+            signal_enabled: false,
+            signal_number: None,
+            commute_windows: None,
+            window_hours: window_hours(),
+            message: None,
+            error: Some(error.to_string()),
+        },
+    )
+}
+
+/// Shows whether reminders are on, with a button to switch them, and where
+/// push notifications go.
+pub async fn reminders_handler(
+    State(state): State<Arc<AppState>>,
+    params: Result<Query<UnsubscribeParams>, QueryRejection>,
+) -> Response {
+    // Reached without the link, e.g. from /.well-known/change-password
+    let Ok(Query(params)) = params else {
+        return error_page(UnsubscribeParams::default(), StatusCode::OK, NO_LINK);
+    };
+    reminders_page(&state, params, None).await
+}
+
+pub async fn update_reminders_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UnsubscribeParams>,
+    Form(form): Form<RemindersForm>,
+) -> Response {
+    let update = match form_update(form) {
+        Ok(update) => update,
+        Err(error) => return error_page(params, StatusCode::BAD_REQUEST, error),
+    };
+    reminders_page(&state, params, Some(update)).await
+}
+
+async fn reminders_page(
+    state: &AppState,
+    params: UnsubscribeParams,
+    update: Option<Update>,
+) -> Response {
+    let user = User {
+        id: params.id.clone(),
+        ..Default::default()
+    };
+    if !user.verify_unsubscribe_token(&params.token, &state.unsubscribe_secret) {
+        return error_page(
+            params,
+            StatusCode::BAD_REQUEST,
+            "This link is invalid. Please use the link from your most recent email.",
+        );
+    }
+
+    let updated = match &update {
+        Some(Update::Reminders(enabled)) => sqlx::query!(
+            "UPDATE users SET wants_reminders = ? WHERE id = ? AND is_verified = 1;",
+            enabled,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        Some(Update::Invites(invites)) => sqlx::query!(
+            "UPDATE users SET wants_invites = ? WHERE id = ? AND is_verified = 1;",
+            invites,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        Some(Update::Layout(layout)) => {
+            let layout = layout.as_str();
+            sqlx::query!(
+                "UPDATE users SET email_layout = ? WHERE id = ? AND is_verified = 1;",
+                layout,
+                params.id
+            )
+            .execute(&state.pool)
+            .await
+            .map(|_| ())
+        }
+        Some(Update::Clock(clock_24h)) => sqlx::query!(
+            "UPDATE users SET clock_24h = ? WHERE id = ? AND is_verified = 1;",
+            clock_24h,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        Some(Update::Pushover(key)) => sqlx::query!(
+            "UPDATE users SET pushover_user_key = ? WHERE id = ? AND is_verified = 1;",
+            key,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        Some(Update::Signal(number)) => sqlx::query!(
+            "UPDATE users SET signal_number = ? WHERE id = ? AND is_verified = 1;",
+            number,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        Some(Update::Commute(windows)) => sqlx::query!(
+            "UPDATE users SET commute_windows = ? WHERE id = ? AND is_verified = 1;",
+            windows,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        Some(Update::Ntfy(topic)) => sqlx::query!(
+            "UPDATE users SET ntfy_topic = ? WHERE id = ? AND is_verified = 1;",
+            topic,
+            params.id
+        )
+        .execute(&state.pool)
+        .await
+        .map(|_| ()),
+        None => Ok(()),
+    };
+    let result = match updated {
+        Ok(()) => {
+            sqlx::query!(
+                r#"
+                SELECT wants_reminders AS "wants_reminders: bool",
+                    wants_invites AS "wants_invites: bool", ntfy_topic, pushover_user_key,
+                    signal_number, email_layout, lang, clock_24h AS "clock_24h: bool",
+                    commute_windows
+                FROM users
+                WHERE id = ? AND is_verified = 1
+                "#,
+                params.id
+            )
+            .fetch_optional(&state.pool)
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    let preferences = match result {
+        Ok(Some(preferences)) => preferences,
+        Ok(None) => {
+            return error_page(
+                params,
+                StatusCode::NOT_FOUND,
+                "This address isn't subscribed, so there are no reminders to change.",
+            );
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return error_page(
+                params,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "An internal error occurred. Please try again later.",
+            );
+        }
+    };
+
+    let message = update.map(|update| match update {
+        Update::Reminders(true) => "Reminders are turned on.".to_string(),
+        Update::Reminders(false) => "Reminders are turned off.".to_string(),
+        Update::Invites(true) => "Floods will come as calendar invites.".to_string(),
+        Update::Invites(false) => "Floods will come as a calendar to add.".to_string(),
+        Update::Layout(EmailLayout::Compact) => "Emails will be compact.".to_string(),
+        Update::Layout(EmailLayout::Detailed) => "Emails will be detailed.".to_string(),
+        Update::Clock(true) => "Times will use a 24 hour clock.".to_string(),
+        Update::Clock(false) => "Times will use a 12 hour clock.".to_string(),
+        Update::Ntfy(Some(topic)) => format!("Push notifications will go to {}.", topic),
+        Update::Ntfy(None) => "Push notifications are turned off.".to_string(),
+        Update::Pushover(Some(_)) => "Pushover notifications are turned on.".to_string(),
+        Update::Pushover(None) => "Pushover notifications are turned off.".to_string(),
+        Update::Signal(Some(number)) => format!("Signal messages will go to {}.", number),
+        Update::Signal(None) => "Signal messages are turned off.".to_string(),
+        Update::Commute(Some(windows)) => {
+            format!("You'll only be notified about floods during {}.", windows)
+        }
+        Update::Commute(None) => "You'll be notified about every flood.".to_string(),
+    });
+    render(
+        StatusCode::OK,
+        RemindersTemplate {
+            user_id: params.id,
+            token: params.token,
+            enabled: preferences.wants_reminders,
+            invites: preferences.wants_invites,
+            compact: !EmailStyle::for_subscriber(preferences.email_layout.as_deref()).detailed(),
+            clock_24h: DateFormat::for_subscriber(
+                preferences.lang.as_deref(),
+                preferences.clock_24h,
+            )
+            .clock_24h,
+            ntfy_topic: preferences.ntfy_topic,
+            pushover_enabled: pushover_app_token().is_some(),
+            pushover_user_key: preferences.pushover_user_key,
+            signal_enabled: SignalGateway::from_env().is_some(),
+            signal_number: preferences.signal_number,
+            commute_windows: preferences.commute_windows,
+            window_hours: window_hours(),
+            message,
+            error: None,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_claim_once_per_flood() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+
+        let morning = "2026-10-16T14:30:00Z".parse::<DateTime<Utc>>().unwrap();
+        let evening = morning + Duration::hours(12);
+        assert!(claim(&pool, "1", morning).await.unwrap());
+        assert!(!claim(&pool, "1", morning).await.unwrap());
+        assert!(claim(&pool, "2", morning).await.unwrap());
+
+        let flood = FloodTide {
+            prediction_time: evening,
+            height_ft: 6.8,
+        };
+        assert!(claim(&pool, "1", evening).await.unwrap());
+        release(&pool, "1", &[&flood]).await;
+        assert!(claim(&pool, "1", evening).await.unwrap());
+    }
+}
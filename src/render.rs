@@ -0,0 +1,234 @@
+use askama::Template;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use sqlx::sqlite::SqlitePool;
+use std::fs;
+use std::path::Path;
+
+use crate::api::{events_response, predictions_response};
+use crate::assets;
+use crate::calendar::flood_calendar;
+use crate::error::AppError;
+use crate::handlers::{self, IndexTemplate};
+use crate::i18n::Lang;
+use crate::models::FloodDisplay;
+use crate::pages::{self, PageTemplate};
+use crate::site::site;
+use crate::sync_runs;
+use crate::tides::{self, FORECAST_DAYS, FloodTide, get_flood_tides};
+
+/// A single forecast file for other automations, made by `render --format`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ArtifactFormat {
+    /// The `/api/v1/predictions` body
+    Json,
+    /// The flood calendar
+    Ics,
+    /// One line per flood, for pasting into posts
+    Txt,
+}
+
+pub fn snapshot_time(now: DateTime<Utc>) -> String {
+    tides::local(now)
+        .format("%A, %B %-d at %-I:%M%p")
+        .to_string()
+}
+
+pub fn forecast_artifact(
+    format: ArtifactFormat,
+    tides: Vec<FloodTide>,
+    now: DateTime<Utc>,
+) -> Result<String, serde_json::Error> {
+    match format {
+        ArtifactFormat::Json => {
+            serde_json::to_string_pretty(&predictions_response(tides)).map(|json| json + "\n")
+        }
+        ArtifactFormat::Ics => Ok(flood_calendar(&tides, now)),
+        ArtifactFormat::Txt => Ok(forecast_text(&tides, now)),
+    }
+}
+
+fn forecast_text(tides: &[FloodTide], now: DateTime<Utc>) -> String {
+    let mut text = format!(
+        "{} flood forecast, updated {}\n",
+        site().name,
+        snapshot_time(now)
+    );
+    if tides.is_empty() {
+        text.push_str(&format!(
+            "No floods predicted in the next {} days.\n",
+            FORECAST_DAYS
+        ));
+    }
+    for tide in tides {
+        let display = FloodDisplay::new(tide.prediction_time, tide.height_ft);
+        text.push_str(&format!("{}  {} ft\n", display.datetime, display.height));
+    }
+    text
+}
+
+/// Writes a forecast artifact to `output`, or to stdout without one.
+pub async fn render_artifact(
+    pool: &SqlitePool,
+    format: ArtifactFormat,
+    output: Option<&Path>,
+) -> Result<(), AppError> {
+    let tides = get_flood_tides(pool, FORECAST_DAYS).await?;
+    let artifact = forecast_artifact(format, tides, Utc::now())?;
+    match output {
+        Some(path) => {
+            fs::write(path, artifact)?;
+            eprintln!("Wrote the forecast to {}", path.display());
+        }
+        None => print!("{}", artifact),
+    }
+    Ok(())
+}
+
+/// Writes the homepage, calendar and API snapshots as static files, so the
+/// forecast can be hosted without a server on GitHub Pages or Netlify and
+/// refreshed by a scheduled `sync` and `render`.
+pub async fn render_site(pool: &SqlitePool, out_dir: &Path) -> Result<(), AppError> {
+    let tides = get_flood_tides(pool, FORECAST_DAYS).await?;
+    let now = Utc::now();
+    let fetched_at = sync_runs::last_fetched(pool)
+        .await?
+        .map(|fetched_at| fetched_at.and_utc());
+
+    let index = IndexTemplate {
+        t: Lang::En.strings(),
+        predictions: tides
+            .iter()
+            .map(|tide| FloodDisplay::new(tide.prediction_time, tide.height_ft))
+            .collect(),
+        forecast_days: FORECAST_DAYS,
+        form_token: String::new(),
+        flash: None,
+        signup_source: None,
+        static_snapshot: Some(snapshot_time(now)),
+        stale_since: None,
+        outdated_since: fetched_at
+            .filter(|fetched_at| sync_runs::is_outdated(*fetched_at, now))
+            .map(snapshot_time),
+        adjustment: handlers::adjustment(),
+        pushover_enabled: false,
+        signal_enabled: false,
+        contact_enabled: false,
+        pages: pages::list(Path::new(pages::CONTENT_DIR))?,
+    };
+
+    let api_dir = out_dir.join("api").join("v1");
+    fs::create_dir_all(&api_dir)?;
+    fs::write(out_dir.join("index.html"), index.render()?)?;
+    fs::write(
+        out_dir.join("calendar.ics"),
+        forecast_artifact(ArtifactFormat::Ics, tides.clone(), now)?,
+    )?;
+    fs::write(
+        api_dir.join("predictions.json"),
+        forecast_artifact(ArtifactFormat::Json, tides.clone(), now)?,
+    )?;
+    fs::write(
+        api_dir.join("events.json"),
+        serde_json::to_string_pretty(&events_response(tides))?,
+    )?;
+    // In a directory each, so `/pages/<slug>` works on static hosts too
+    for page in &index.pages {
+        let page_dir = out_dir.join("pages").join(&page.slug);
+        fs::create_dir_all(&page_dir)?;
+        fs::write(page_dir.join("index.html"), PageTemplate { page }.render()?)?;
+    }
+    copy_dir(Path::new("assets"), &out_dir.join("assets"))?;
+    // The homepage links to the fingerprinted names, which the server maps
+    // back to the originals but a static host can't
+    for (name, fingerprinted) in assets::manifest().iter() {
+        fs::copy(
+            out_dir.join("assets").join(name),
+            out_dir.join("assets").join(fingerprinted),
+        )?;
+    }
+
+    println!("Rendered the site to {}", out_dir.display());
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render_site() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let tomorrow = (Utc::now() + chrono::Duration::days(1)).naive_utc();
+        sqlx::query(
+            "INSERT INTO tides (prediction_time, height_ft, tide_type) VALUES (?, 6.9, 'High')",
+        )
+        .bind(tomorrow)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let out_dir = std::env::temp_dir().join(format!("render-test-{}", uuid::Uuid::new_v4()));
+        render_site(&pool, &out_dir).await.unwrap();
+
+        let index = fs::read_to_string(out_dir.join("index.html")).unwrap();
+        assert!(index.contains("6.90"));
+        assert!(index.contains("Forecast updated"));
+        assert!(!index.contains("hx-get"));
+        assert!(!index.contains(r#"id="signup""#));
+
+        let calendar = fs::read_to_string(out_dir.join("calendar.ics")).unwrap();
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 1);
+
+        let predictions: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(out_dir.join("api/v1/predictions.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(predictions["predictions"].as_array().unwrap().len(), 1);
+        assert!(out_dir.join("api/v1/events.json").exists());
+        assert!(index.contains(r#"href="/pages/faq""#));
+        assert!(out_dir.join("pages/faq/index.html").exists());
+        assert!(out_dir.join("assets/img/favicon.png").exists());
+        let favicon = assets::asset("img/favicon.png");
+        assert!(index.contains(&favicon));
+        assert!(out_dir.join(favicon.trim_start_matches('/')).exists());
+
+        fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_forecast_artifacts() {
+        let tides = vec![FloodTide {
+            prediction_time: "2026-12-13T16:50:00Z".parse().unwrap(),
+            height_ft: 7.1,
+        }];
+        let now = Utc::now();
+
+        let text = forecast_artifact(ArtifactFormat::Txt, tides.clone(), now).unwrap();
+        assert!(text.ends_with("Sunday, December 13 at 8:50AM  7.10 ft\n"));
+        let none = forecast_artifact(ArtifactFormat::Txt, Vec::new(), now).unwrap();
+        assert!(none.contains("No floods predicted in the next 30 days."));
+
+        let json = forecast_artifact(ArtifactFormat::Json, tides.clone(), now).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["predictions"][0]["height_ft"], 7.1);
+
+        let ics = forecast_artifact(ArtifactFormat::Ics, tides, now).unwrap();
+        assert!(ics.starts_with("BEGIN:VCALENDAR"));
+    }
+}
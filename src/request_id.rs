@@ -0,0 +1,97 @@
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Span;
+use uuid::{NoContext, Timestamp, Uuid};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// Longest incoming ID that's kept rather than replaced.
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request being handled, or of the request that queued the
+/// job being run. None in the CLI.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Runs `future` with `id` as the current request ID, e.g. a queued job
+/// with the ID of the request that queued it.
+pub async fn scope<F: Future>(id: Option<String>, future: F) -> F::Output {
+    match id {
+        Some(id) => REQUEST_ID.scope(id, future).await,
+        None => future.await,
+    }
+}
+
+/// Keeps an ID from a proxy or client when it's a sensible token, so it
+/// can't be used to inject text into logs.
+fn accept(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    valid.then(|| id.to_string())
+}
+
+/// Middleware giving each request an `X-Request-Id`, accepting the caller's
+/// or generating one, and echoing it on the response. Handlers can read it
+/// with `current()`.
+pub async fn middleware(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(accept)
+        .unwrap_or_else(|| Uuid::new_v7(Timestamp::now(NoContext)).simple().to_string());
+    let value = HeaderValue::from_str(&id).expect("request IDs are valid header values");
+    req.headers_mut().insert(X_REQUEST_ID, value.clone());
+
+    let mut response = REQUEST_ID.scope(id, next.run(req)).await;
+    response.headers_mut().insert(X_REQUEST_ID, value);
+    response
+}
+
+/// The `TraceLayer` span for a request, tagged with its ID.
+pub fn make_span(req: &Request) -> Span {
+    let id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        request_id = %id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_id() {
+        assert_eq!(
+            accept(&HeaderValue::from_static("abc-123_x.y")).as_deref(),
+            Some("abc-123_x.y")
+        );
+        assert_eq!(accept(&HeaderValue::from_static("")), None);
+        assert_eq!(accept(&HeaderValue::from_static("a b")), None);
+        assert_eq!(
+            accept(&HeaderValue::from_str(&"a".repeat(129)).unwrap()),
+            None
+        );
+
+        assert_eq!(current(), None);
+        let inside = scope(Some("abc".to_string()), async { current() }).await;
+        assert_eq!(inside.as_deref(), Some("abc"));
+        assert_eq!(scope(None, async { current() }).await, None);
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use noaa_tides::products::predictions::{Prediction, TideType};
+use sqlx::sqlite::SqlitePool;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::tides::{self, FORECAST_DAYS};
+
+/// Marks the subscribers `seed` adds, in `signup_source`.
+const SEED_SOURCE: &str = "seed";
+/// Subscribers added, at `seed-N@example.com` so nothing is ever delivered.
+const SEED_SUBSCRIBERS: usize = 5;
+/// Mean time between a high and the next low, half a lunar day.
+const HALF_TIDE_MINUTES: i64 = 372;
+/// Days from one spring tide to the next.
+const SPRING_NEAP_DAYS: f64 = 14.77;
+
+/// Made up highs and lows from `begin` for `days`, shaped like the bay's
+/// mixed semidiurnal tides: a higher and a lower high each day, swelling
+/// towards spring tides, when the higher high tops `threshold`.
+fn fake_predictions(begin: NaiveDateTime, days: i64, threshold: f64) -> Vec<Prediction> {
+    let end = begin + Duration::days(days);
+    (0..)
+        .map(|i: i64| (i, begin + Duration::minutes(47 + HALF_TIDE_MINUTES * i)))
+        .take_while(|(_, time)| *time < end)
+        .map(|(i, datetime)| {
+            let day = (datetime - begin).num_minutes() as f64 / (24.0 * 60.0);
+            let spring = (2.0 * std::f64::consts::PI * day / SPRING_NEAP_DAYS).cos();
+            let (height, tide_type) = match i % 4 {
+                0 => (threshold - 0.25 + 0.6 * spring, TideType::High),
+                1 => (-0.3 - 0.6 * spring, TideType::Low),
+                2 => (threshold - 1.6 + 0.3 * spring, TideType::High),
+                _ => (2.4 - 0.2 * spring, TideType::Low),
+            };
+            Prediction {
+                datetime,
+                height: ((height * 100.0).round() / 100.0) as f32,
+                tide_type: Some(tide_type),
+            }
+        })
+        .collect()
+}
+
+/// Fills a development database with made up predictions for the forecast
+/// range, including several floods, and a few verified subscribers. Refuses
+/// when the database has real subscribers unless forced.
+pub async fn run(pool: &SqlitePool, force: bool) -> Result<(), AppError> {
+    let real: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE signup_source IS NULL OR signup_source != ?",
+    )
+    .bind(SEED_SOURCE)
+    .fetch_one(pool)
+    .await?;
+    if real > 0 && !force {
+        return Err(AppError::Conflict(format!(
+            "The database has {} real subscribers, pass --force to seed it anyway",
+            real
+        )));
+    }
+
+    let begin = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+    let end = begin + Duration::days(FORECAST_DAYS + 2) - Duration::seconds(1);
+    let predictions = fake_predictions(begin, FORECAST_DAYS + 2, tides::flood_threshold());
+    let stored: Vec<_> = predictions.iter().collect();
+    tides::replace_predictions(pool, &stored, begin, end).await?;
+    let floods = tides::get_flood_tides(pool, FORECAST_DAYS).await?;
+    println!(
+        "Stored {} made up highs and lows, with {} floods",
+        stored.len(),
+        floods.len()
+    );
+
+    let mut added = 0;
+    for n in 1..=SEED_SUBSCRIBERS {
+        let result = sqlx::query(
+            "INSERT OR IGNORE INTO users (id, email, verification_token, is_verified, is_subscribed, signup_source, wants_reminders)
+            VALUES (?, ?, ?, 1, 1, ?, ?)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(format!("seed-{}@example.com", n))
+        .bind(Uuid::new_v4().to_string())
+        .bind(SEED_SOURCE)
+        .bind(n % 2 == 0)
+        .execute(pool)
+        .await?;
+        added += result.rows_affected();
+    }
+    println!("Added {} subscribers at seed-N@example.com", added);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_checks;
+
+    #[tokio::test]
+    async fn test_seed() {
+        let begin = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        let end = begin + Duration::days(FORECAST_DAYS + 2) - Duration::seconds(1);
+        let predictions = fake_predictions(begin, FORECAST_DAYS + 2, 6.0);
+        let stored: Vec<_> = predictions.iter().collect();
+        // Would pass for NOAA's
+        assert!(sync_checks::problems(&stored, begin, end).is_empty());
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        run(&pool, false).await.unwrap();
+        assert!(
+            tides::get_flood_tides(&pool, FORECAST_DAYS)
+                .await
+                .unwrap()
+                .len()
+                >= 3
+        );
+        // Seeding again adds no one
+        run(&pool, false).await.unwrap();
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(users, SEED_SUBSCRIBERS as i64);
+
+        sqlx::query("INSERT INTO users (id, email, verification_token) VALUES ('1', 'a@b.c', 't')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(run(&pool, false).await.is_err());
+    }
+}
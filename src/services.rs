@@ -0,0 +1,1103 @@
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use thiserror::Error;
+use validator::Validate;
+
+use crate::AppState;
+use crate::attribution::{normalize_heard_from, normalize_source};
+use crate::channels;
+use crate::email_address;
+use crate::email_templates::{self, EmailKind, EmailTemplate};
+use crate::events::{self, EventSource, EventType};
+use crate::honeypot::{Submission, check_submission};
+use crate::i18n::Lang;
+use crate::mail::{EmailError, SmtpClient};
+use crate::models::{SignUpRequest, UnsubscribeScope, User};
+use crate::mx::MxValidator;
+
+/// Wrong code entries allowed before code verification is locked for an address.
+const MAX_VERIFICATION_CODE_ATTEMPTS: i64 = 5;
+
+/// Default minutes before another verification email can be sent to the same address.
+const DEFAULT_VERIFICATION_COOLDOWN_MINUTES: i64 = 15;
+
+fn verification_cooldown() -> Duration {
+    let minutes = env::var("VERIFICATION_COOLDOWN_MINUTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_VERIFICATION_COOLDOWN_MINUTES);
+    Duration::minutes(minutes)
+}
+
+/// A user's id and address, as returned when they verify or leave.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserRef {
+    pub id: String,
+    pub email: String,
+}
+
+/// What's stored for a signup.
+pub struct NewSignup {
+    pub user: User,
+    pub signup_source: Option<String>,
+    pub heard_from: Option<String>,
+    pub reminders: bool,
+    pub ntfy_topic: Option<String>,
+    pub pushover_user_key: Option<String>,
+    pub signal_number: Option<String>,
+}
+
+/// Storage for the signup, verification and unsubscribe flows.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Inserts the signup, or resets an existing unverified or unsubscribed
+    /// one, recording its verification email as sent at `sent_at` in the
+    /// same statement so two signups at once can't both send. Returns the
+    /// user's id, or None if they're already verified and subscribed or a
+    /// verification email went to them after `cooldown_start`.
+    async fn upsert_signup(
+        &self,
+        signup: &NewSignup,
+        cooldown_start: NaiveDateTime,
+        sent_at: NaiveDateTime,
+    ) -> Result<Option<String>, sqlx::Error>;
+
+    /// Whether the address is verified and subscribed to emails.
+    async fn is_subscribed(&self, email: &str) -> Result<bool, sqlx::Error>;
+
+    /// Whether the address unsubscribed or was suppressed before.
+    async fn has_left_before(&self, email: &str) -> Result<bool, sqlx::Error>;
+
+    /// Forgets a verification email that failed to send, so they can sign up
+    /// again straight away.
+    async fn release_verification_sent(&self, user_id: &str) -> Result<(), sqlx::Error>;
+
+    /// The admin override of the verification email, if there is one.
+    async fn verification_template(&self) -> Result<Option<EmailTemplate>, sqlx::Error>;
+
+    /// Verifies and subscribes the unverified user with `token`.
+    async fn verify_token(&self, token: &str) -> Result<Option<UserRef>, sqlx::Error>;
+
+    /// Verifies and subscribes the unverified user with `email` and `code`,
+    /// unless they're out of attempts.
+    async fn verify_code(
+        &self,
+        email: &str,
+        code: &str,
+        max_attempts: i64,
+    ) -> Result<Option<UserRef>, sqlx::Error>;
+
+    async fn count_failed_code(&self, email: &str) -> Result<(), sqlx::Error>;
+
+    /// Deletes the user, returning their address if they existed.
+    async fn delete(&self, user_id: &str) -> Result<Option<String>, sqlx::Error>;
+
+    /// Whether the user gets alerts on a channel besides email.
+    async fn has_other_channels(&self, user_id: &str) -> Result<bool, sqlx::Error>;
+
+    /// Stops emails to the user, returning their address, or None if they
+    /// don't exist or already stopped them.
+    async fn stop_email(&self, user_id: &str) -> Result<Option<String>, sqlx::Error>;
+
+    /// Records a subscriber event, logging rather than returning failures.
+    async fn record_event(
+        &self,
+        user: &UserRef,
+        event_type: EventType,
+        source: EventSource,
+        detail: Option<&str>,
+    );
+}
+
+/// Sends the verification email.
+#[async_trait]
+pub trait VerificationMailer: Send + Sync {
+    async fn send_verification(
+        &self,
+        user: &User,
+        verification_link: &str,
+        unsubscribe_link: &str,
+        template: Option<&EmailTemplate>,
+    ) -> Result<(), EmailError>;
+}
+
+#[async_trait]
+impl VerificationMailer for SmtpClient {
+    async fn send_verification(
+        &self,
+        user: &User,
+        verification_link: &str,
+        unsubscribe_link: &str,
+        template: Option<&EmailTemplate>,
+    ) -> Result<(), EmailError> {
+        self.send_verification_email(user, verification_link, unsubscribe_link, template)
+            .await
+    }
+}
+
+/// The `users` table.
+pub struct SqliteUsers {
+    pool: SqlitePool,
+}
+
+impl SqliteUsers {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteUsers { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqliteUsers {
+    async fn upsert_signup(
+        &self,
+        signup: &NewSignup,
+        cooldown_start: NaiveDateTime,
+        sent_at: NaiveDateTime,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let user = &signup.user;
+        let key = email_address::key(&user.email);
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO users (id, email, email_key, is_verified, verification_token, verification_code, is_subscribed, signup_source, heard_from, wants_reminders, ntfy_topic, pushover_user_key, signal_number, lang, verification_sent_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(email_key) DO UPDATE
+            SET email = excluded.email,
+                verification_token = excluded.verification_token,
+                verification_code = excluded.verification_code,
+                verification_code_attempts = 0,
+                verification_nudged_at = NULL,
+                is_verified = 0, is_subscribed = 0, wants_email = 1,
+                signup_source = excluded.signup_source,
+                heard_from = excluded.heard_from,
+                wants_reminders = excluded.wants_reminders,
+                ntfy_topic = excluded.ntfy_topic,
+                pushover_user_key = excluded.pushover_user_key,
+                signal_number = excluded.signal_number,
+                lang = excluded.lang,
+                verification_sent_at = excluded.verification_sent_at
+            WHERE (users.is_verified = 0 OR users.is_subscribed = 0 OR users.wants_email = 0)
+                AND (users.verification_sent_at IS NULL OR users.verification_sent_at < ?)
+            RETURNING id;
+            "#,
+            user.id,
+            user.email,
+            key,
+            user.is_verified,
+            user.verification_token,
+            user.verification_code,
+            user.is_subscribed,
+            signup.signup_source,
+            signup.heard_from,
+            signup.reminders,
+            signup.ntfy_topic,
+            signup.pushover_user_key,
+            signup.signal_number,
+            user.lang,
+            sent_at,
+            cooldown_start
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn is_subscribed(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let key = email_address::key(email);
+        let subscribed = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM users
+            WHERE email_key = ? AND is_verified = 1 AND is_subscribed = 1 AND wants_email = 1
+            "#,
+            key
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(subscribed.is_some())
+    }
+
+    async fn has_left_before(&self, email: &str) -> Result<bool, sqlx::Error> {
+        events::has_left_before(&self.pool, email).await
+    }
+
+    async fn release_verification_sent(&self, user_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE users SET verification_sent_at = NULL WHERE id = ?;
+            "#,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn verification_template(&self) -> Result<Option<EmailTemplate>, sqlx::Error> {
+        email_templates::load_override(&self.pool, EmailKind::Verification).await
+    }
+
+    async fn verify_token(&self, token: &str) -> Result<Option<UserRef>, sqlx::Error> {
+        sqlx::query_as!(
+            UserRef,
+            r#"
+            UPDATE users
+            SET is_verified = 1, is_subscribed = 1
+            WHERE verification_token = ? AND is_verified = 0
+            RETURNING id, email;
+            "#,
+            token
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn verify_code(
+        &self,
+        email: &str,
+        code: &str,
+        max_attempts: i64,
+    ) -> Result<Option<UserRef>, sqlx::Error> {
+        let key = email_address::key(email);
+        sqlx::query_as!(
+            UserRef,
+            r#"
+            UPDATE users
+            SET is_verified = 1, is_subscribed = 1
+            WHERE email_key = ? AND verification_code = ? AND verification_code != ''
+                AND is_verified = 0 AND verification_code_attempts < ?
+            RETURNING id, email;
+            "#,
+            key,
+            code,
+            max_attempts
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn count_failed_code(&self, email: &str) -> Result<(), sqlx::Error> {
+        let key = email_address::key(email);
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET verification_code_attempts = verification_code_attempts + 1
+            WHERE email_key = ? AND is_verified = 0;
+            "#,
+            key
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            DELETE FROM users
+            WHERE id = ?
+            RETURNING email;
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn has_other_channels(&self, user_id: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users
+                WHERE id = ? AND (ntfy_topic IS NOT NULL OR pushover_user_key IS NOT NULL
+                    OR signal_number IS NOT NULL)
+            ) AS "other!: bool"
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn stop_email(&self, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE users SET wants_email = 0
+            WHERE id = ? AND wants_email = 1
+            RETURNING email;
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn record_event(
+        &self,
+        user: &UserRef,
+        event_type: EventType,
+        source: EventSource,
+        detail: Option<&str>,
+    ) {
+        events::record(
+            &self.pool,
+            &user.id,
+            &user.email,
+            event_type,
+            source,
+            detail,
+        )
+        .await;
+    }
+}
+
+/// Why a signup was turned down. The messages are shown to the person
+/// signing up.
+#[derive(Debug, Error)]
+pub enum SignupError {
+    #[error("Please provide a valid email address.")]
+    InvalidEmail,
+    #[error("This page was open for too long, please reload it and sign up again.")]
+    FormExpired,
+    #[error("That email domain doesn't accept mail, please check it for typos.")]
+    NoMailServers,
+    #[error("{0}")]
+    InvalidChannel(&'static str),
+    #[error(
+        "A verification email was sent recently. Please check your inbox (and spam folder) for it."
+    )]
+    RecentlySent,
+    #[error("Email already registered and verified")]
+    AlreadyVerified,
+    #[error("Internal server error")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to add to mailing list.")]
+    Mail(#[from] EmailError),
+}
+
+/// Validates a signup, stores it and sends the verification email.
+pub struct SignupService<'a, R, M> {
+    pub users: R,
+    pub mailer: &'a M,
+    pub base_url: &'a str,
+    pub unsubscribe_secret: &'a str,
+    pub mx_validator: Option<&'a MxValidator>,
+}
+
+impl<'a> SignupService<'a, SqliteUsers, SmtpClient> {
+    pub fn from_state(state: &'a AppState) -> Self {
+        SignupService {
+            users: SqliteUsers::new(state.pool.clone()),
+            mailer: &state.mailer,
+            base_url: &state.base_url,
+            unsubscribe_secret: &state.unsubscribe_secret,
+            mx_validator: state.mx_validator.as_ref(),
+        }
+    }
+}
+
+impl<R: UserRepository, M: VerificationMailer> SignupService<'_, R, M> {
+    /// Signs `payload` up. Bots filling in the web form get the same success
+    /// as people so they don't learn to adapt. API callers have no form to
+    /// fill in, so aren't checked for them.
+    pub async fn sign_up(
+        &self,
+        payload: SignUpRequest,
+        source: EventSource,
+    ) -> Result<(), SignupError> {
+        // JSON bodies were already validated by their extractor, forms weren't
+        if payload.validate().is_err() {
+            return Err(SignupError::InvalidEmail);
+        }
+
+        if source == EventSource::Web {
+            let submission = check_submission(
+                &payload.website,
+                payload.form_token.as_deref(),
+                self.unsubscribe_secret,
+            );
+            if submission == Submission::Expired {
+                return Err(SignupError::FormExpired);
+            }
+            if submission.is_bot() {
+                println!("Dropping bot signup ({:?}): {}", submission, payload.email);
+                return Ok(());
+            }
+        }
+
+        if let Some(mx_validator) = self.mx_validator
+            && !mx_validator.accepts_mail(&payload.email).await
+        {
+            return Err(SignupError::NoMailServers);
+        }
+
+        let signup = NewSignup {
+            signup_source: payload.source.as_deref().and_then(normalize_source),
+            heard_from: payload.heard_from.as_deref().and_then(normalize_heard_from),
+            reminders: payload.reminders,
+            ntfy_topic: optional_channel(
+                payload.ntfy_topic.as_deref(),
+                true,
+                |topic| channels::parse_ntfy_topic(topic, &channels::ntfy_servers()),
+                channels::INVALID_NTFY_TOPIC,
+            )?,
+            pushover_user_key: optional_channel(
+                payload.pushover_user_key.as_deref(),
+                channels::pushover_app_token().is_some(),
+                channels::parse_pushover_key,
+                channels::INVALID_PUSHOVER_KEY,
+            )?,
+            signal_number: optional_channel(
+                payload.signal_number.as_deref(),
+                channels::SignalGateway::from_env().is_some(),
+                channels::parse_signal_number,
+                channels::INVALID_SIGNAL_NUMBER,
+            )?,
+            user: User {
+                lang: payload
+                    .lang
+                    .as_deref()
+                    .and_then(Lang::parse)
+                    .map(|lang| lang.code().to_string()),
+                ..User::new(email_address::normalize(&payload.email))
+            },
+        };
+
+        // Re-signing up rotates the token and sends a new email, so don't let that
+        // be used to flood someone's inbox.
+        let now = Utc::now().naive_utc();
+        let cooldown_start = now - verification_cooldown();
+        let Some(id) = self
+            .users
+            .upsert_signup(&signup, cooldown_start, now)
+            .await?
+        else {
+            if self.users.is_subscribed(&signup.user.email).await? {
+                return Err(SignupError::AlreadyVerified);
+            }
+            return Err(SignupError::RecentlySent);
+        };
+        let user = User { id, ..signup.user };
+        let user_ref = UserRef {
+            id: user.id.clone(),
+            email: user.email.clone(),
+        };
+        let event_type = match self.users.has_left_before(&user.email).await {
+            Ok(true) => EventType::Resubscribe,
+            Ok(false) => EventType::Signup,
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                EventType::Signup
+            }
+        };
+        let validation_link = format!("{}/verify?token={}", self.base_url, user.verification_token);
+        let unsubscribe_link = format!(
+            "{}/unsubscribe?id={}&token={}",
+            self.base_url,
+            user.id,
+            user.generate_unsubscribe_token(self.unsubscribe_secret)
+        );
+        let template = self
+            .users
+            .verification_template()
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Database error loading email template: {:?}", e);
+                None
+            });
+        if let Err(e) = self
+            .mailer
+            .send_verification(
+                &user,
+                &validation_link,
+                &unsubscribe_link,
+                template.as_ref(),
+            )
+            .await
+        {
+            if let Err(e) = self.users.release_verification_sent(&user.id).await {
+                eprintln!("Database error releasing verification send: {:?}", e);
+            }
+            return Err(e.into());
+        }
+        self.users
+            .record_event(
+                &user_ref,
+                event_type,
+                source,
+                signup.signup_source.as_deref(),
+            )
+            .await;
+        Ok(())
+    }
+}
+
+/// An optional channel from the signup form. Blank, or a channel that isn't
+/// `enabled` on this server, is None, and anything else must parse.
+fn optional_channel(
+    value: Option<&str>,
+    enabled: bool,
+    parse: impl Fn(&str) -> Option<String>,
+    invalid: &'static str,
+) -> Result<Option<String>, SignupError> {
+    match value.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(_) if !enabled => Ok(None),
+        Some(value) => parse(value)
+            .map(Some)
+            .ok_or(SignupError::InvalidChannel(invalid)),
+    }
+}
+
+/// Verifies signups by their emailed link or code.
+pub struct VerificationService<R> {
+    pub users: R,
+}
+
+impl VerificationService<SqliteUsers> {
+    pub fn from_state(state: &AppState) -> Self {
+        VerificationService {
+            users: SqliteUsers::new(state.pool.clone()),
+        }
+    }
+}
+
+impl<R: UserRepository> VerificationService<R> {
+    /// Verifies the user with the link's token, returning their address.
+    pub async fn verify_token(&self, token: &str) -> Result<Option<String>, sqlx::Error> {
+        let Some(user) = self.users.verify_token(token).await? else {
+            return Ok(None);
+        };
+        self.users
+            .record_event(
+                &user,
+                EventType::Verification,
+                EventSource::Web,
+                Some("link"),
+            )
+            .await;
+        Ok(Some(user.email))
+    }
+
+    /// Verifies the user with the code they entered, returning their address.
+    /// A wrong code counts against their attempts so it can't be brute forced.
+    pub async fn verify_code(
+        &self,
+        email: &str,
+        code: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        match self
+            .users
+            .verify_code(email, code, MAX_VERIFICATION_CODE_ATTEMPTS)
+            .await?
+        {
+            Some(user) => {
+                self.users
+                    .record_event(
+                        &user,
+                        EventType::Verification,
+                        EventSource::Web,
+                        Some("code"),
+                    )
+                    .await;
+                Ok(Some(user.email))
+            }
+            None => {
+                if let Err(e) = self.users.count_failed_code(email).await {
+                    eprintln!("Database error: {:?}", e);
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Removes subscribers who follow the link in their emails.
+pub struct UnsubscribeService<'a, R> {
+    pub users: R,
+    pub unsubscribe_secret: &'a str,
+}
+
+impl<'a> UnsubscribeService<'a, SqliteUsers> {
+    pub fn from_state(state: &'a AppState) -> Self {
+        UnsubscribeService {
+            users: SqliteUsers::new(state.pool.clone()),
+            unsubscribe_secret: &state.unsubscribe_secret,
+        }
+    }
+}
+
+impl<R: UserRepository> UnsubscribeService<'_, R> {
+    /// Whether `token` is the unsubscribe token for `user_id`.
+    pub fn token_is_valid(&self, user_id: &str, token: &str) -> bool {
+        User {
+            id: user_id.to_string(),
+            ..Default::default()
+        }
+        .verify_unsubscribe_token(token, self.unsubscribe_secret)
+    }
+
+    /// Whether the unsubscribe page should offer stopping just the emails.
+    pub async fn can_stop_email_only(&self, user_id: &str) -> Result<bool, sqlx::Error> {
+        self.users.has_other_channels(user_id).await
+    }
+
+    /// Stops what `scope` covers, returning what was stopped, or None if
+    /// they'd already stopped it. Stopping the emails of someone with no
+    /// other channel deletes them, as it leaves them nothing.
+    pub async fn unsubscribe(
+        &self,
+        user_id: &str,
+        scope: UnsubscribeScope,
+    ) -> Result<Option<UnsubscribeScope>, sqlx::Error> {
+        let email_only =
+            scope == UnsubscribeScope::Email && self.users.has_other_channels(user_id).await?;
+        let email = if email_only {
+            self.users.stop_email(user_id).await?
+        } else {
+            self.users.delete(user_id).await?
+        };
+        let Some(email) = email else {
+            return Ok(None);
+        };
+        let user = UserRef {
+            id: user_id.to_string(),
+            email,
+        };
+        self.users
+            .record_event(
+                &user,
+                EventType::Unsubscribe,
+                EventSource::Web,
+                email_only.then_some("email"),
+            )
+            .await;
+        Ok(Some(if email_only {
+            UnsubscribeScope::Email
+        } else {
+            UnsubscribeScope::All
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::honeypot;
+    use crate::models::is_verification_code;
+    use std::sync::Mutex;
+
+    /// Users kept in memory, with the events recorded for them.
+    #[derive(Default)]
+    struct MemoryUsers {
+        users: Mutex<Vec<(User, bool)>>,
+        events: Mutex<Vec<(String, &'static str)>>,
+        /// IDs of users with a channel besides email, and of those who stopped emails
+        other_channels: Mutex<Vec<String>>,
+        email_stopped: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl UserRepository for MemoryUsers {
+        async fn upsert_signup(
+            &self,
+            signup: &NewSignup,
+            _cooldown_start: NaiveDateTime,
+            _sent_at: NaiveDateTime,
+        ) -> Result<Option<String>, sqlx::Error> {
+            let mut users = self.users.lock().unwrap();
+            match users
+                .iter_mut()
+                .find(|(user, _)| user.email == signup.user.email)
+            {
+                Some((user, _)) if user.is_verified => Ok(None),
+                Some((_, true)) => Ok(None),
+                Some((user, sent)) => {
+                    *sent = true;
+                    Ok(Some(user.id.clone()))
+                }
+                None => {
+                    let user = User {
+                        id: signup.user.id.clone(),
+                        email: signup.user.email.clone(),
+                        verification_token: signup.user.verification_token.clone(),
+                        ..Default::default()
+                    };
+                    users.push((user, true));
+                    Ok(Some(signup.user.id.clone()))
+                }
+            }
+        }
+
+        async fn is_subscribed(&self, email: &str) -> Result<bool, sqlx::Error> {
+            Ok(self
+                .users
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(user, _)| user.email == email && user.is_verified))
+        }
+
+        async fn has_left_before(&self, _email: &str) -> Result<bool, sqlx::Error> {
+            Ok(false)
+        }
+
+        async fn release_verification_sent(&self, user_id: &str) -> Result<(), sqlx::Error> {
+            for (user, sent) in self.users.lock().unwrap().iter_mut() {
+                if user.id == user_id {
+                    *sent = false;
+                }
+            }
+            Ok(())
+        }
+
+        async fn verification_template(&self) -> Result<Option<EmailTemplate>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn verify_token(&self, token: &str) -> Result<Option<UserRef>, sqlx::Error> {
+            let mut users = self.users.lock().unwrap();
+            Ok(users
+                .iter_mut()
+                .find(|(user, _)| user.verification_token == token && !user.is_verified)
+                .map(|(user, _)| {
+                    user.is_verified = true;
+                    UserRef {
+                        id: user.id.clone(),
+                        email: user.email.clone(),
+                    }
+                }))
+        }
+
+        async fn verify_code(
+            &self,
+            _email: &str,
+            _code: &str,
+            _max_attempts: i64,
+        ) -> Result<Option<UserRef>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn count_failed_code(&self, _email: &str) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn delete(&self, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+            let mut users = self.users.lock().unwrap();
+            let index = users.iter().position(|(user, _)| user.id == user_id);
+            Ok(index.map(|index| users.remove(index).0.email))
+        }
+
+        async fn has_other_channels(&self, user_id: &str) -> Result<bool, sqlx::Error> {
+            Ok(self
+                .other_channels
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|id| id == user_id))
+        }
+
+        async fn stop_email(&self, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+            let mut stopped = self.email_stopped.lock().unwrap();
+            if stopped.iter().any(|id| id == user_id) {
+                return Ok(None);
+            }
+            let users = self.users.lock().unwrap();
+            let Some((user, _)) = users.iter().find(|(user, _)| user.id == user_id) else {
+                return Ok(None);
+            };
+            stopped.push(user_id.to_string());
+            Ok(Some(user.email.clone()))
+        }
+
+        async fn record_event(
+            &self,
+            user: &UserRef,
+            event_type: EventType,
+            _source: EventSource,
+            _detail: Option<&str>,
+        ) {
+            self.events
+                .lock()
+                .unwrap()
+                .push((user.email.clone(), event_type.as_str()));
+        }
+    }
+
+    /// Keeps the verification links it's asked to send.
+    #[derive(Default)]
+    struct MemoryMailer(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl VerificationMailer for MemoryMailer {
+        async fn send_verification(
+            &self,
+            _user: &User,
+            verification_link: &str,
+            _unsubscribe_link: &str,
+            _template: Option<&EmailTemplate>,
+        ) -> Result<(), EmailError> {
+            self.0.lock().unwrap().push(verification_link.to_string());
+            Ok(())
+        }
+    }
+
+    fn request(email: &str, website: &str) -> SignUpRequest {
+        let rendered_at = Utc::now().timestamp() - 60;
+        SignUpRequest {
+            email: email.to_string(),
+            website: website.to_string(),
+            form_token: Some(honeypot::sign(rendered_at, "secret")),
+            source: None,
+            heard_from: None,
+            reminders: false,
+            ntfy_topic: None,
+            pushover_user_key: None,
+            signal_number: None,
+            lang: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signup_and_verification() {
+        let mailer = MemoryMailer::default();
+        let signups = SignupService {
+            users: MemoryUsers::default(),
+            mailer: &mailer,
+            base_url: "http://localhost",
+            unsubscribe_secret: "secret",
+            mx_validator: None,
+        };
+
+        assert!(matches!(
+            signups
+                .sign_up(request("not-an-email", ""), EventSource::Web)
+                .await,
+            Err(SignupError::InvalidEmail)
+        ));
+        // Filling in the honeypot looks like success but sends nothing
+        signups
+            .sign_up(request("bot@example.com", "spam"), EventSource::Web)
+            .await
+            .unwrap();
+        assert!(mailer.0.lock().unwrap().is_empty());
+
+        // A form left open for days is told to reload
+        let stale = SignUpRequest {
+            form_token: Some(honeypot::sign(
+                Utc::now().timestamp() - 2 * 86_400,
+                "secret",
+            )),
+            ..request("a@example.com", "")
+        };
+        assert!(matches!(
+            signups.sign_up(stale, EventSource::Web).await,
+            Err(SignupError::FormExpired)
+        ));
+
+        signups
+            .sign_up(request("a@example.com", ""), EventSource::Web)
+            .await
+            .unwrap();
+        assert_eq!(mailer.0.lock().unwrap().len(), 1);
+        assert!(matches!(
+            signups
+                .sign_up(request("A@Example.com", ""), EventSource::Web)
+                .await,
+            Err(SignupError::RecentlySent)
+        ));
+
+        let link = mailer.0.lock().unwrap()[0].clone();
+        let token = link.split("token=").nth(1).unwrap();
+        let verification = VerificationService {
+            users: signups.users,
+        };
+        assert_eq!(
+            verification.verify_token(token).await.unwrap().as_deref(),
+            Some("a@example.com")
+        );
+        assert_eq!(verification.verify_token(token).await.unwrap(), None);
+        assert_eq!(
+            *verification.users.events.lock().unwrap(),
+            [
+                ("a@example.com".to_string(), "signup"),
+                ("a@example.com".to_string(), "verification"),
+            ]
+        );
+
+        // Verified inside the cooldown is a conflict, not too many requests
+        let signups = SignupService {
+            users: verification.users,
+            ..signups
+        };
+        assert!(matches!(
+            signups
+                .sign_up(request("a@example.com", ""), EventSource::Web)
+                .await,
+            Err(SignupError::AlreadyVerified)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_api_signup_without_form_token() {
+        let mailer = MemoryMailer::default();
+        let signups = SignupService {
+            users: MemoryUsers::default(),
+            mailer: &mailer,
+            base_url: "http://localhost",
+            unsubscribe_secret: "secret",
+            mx_validator: None,
+        };
+        let payload = || SignUpRequest {
+            form_token: None,
+            ..request("a@example.com", "")
+        };
+
+        // The same request from the web form looks like a bot
+        signups.sign_up(payload(), EventSource::Web).await.unwrap();
+        assert!(signups.users.users.lock().unwrap().is_empty());
+
+        signups.sign_up(payload(), EventSource::Api).await.unwrap();
+        assert_eq!(signups.users.users.lock().unwrap().len(), 1);
+        assert_eq!(mailer.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_signup_claims_the_send() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let users = SqliteUsers::new(pool.clone());
+        let signup = || NewSignup {
+            user: User::new("a@example.com".to_string()),
+            signup_source: None,
+            heard_from: None,
+            reminders: false,
+            ntfy_topic: None,
+            pushover_user_key: None,
+            signal_number: None,
+        };
+        let now = Utc::now().naive_utc();
+        let cooldown_start = now - Duration::minutes(15);
+
+        assert!(
+            users
+                .upsert_signup(&signup(), cooldown_start, now)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        // A second signup at the same time finds the send already claimed
+        assert!(
+            users
+                .upsert_signup(&signup(), cooldown_start, now)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(!users.is_subscribed("a@example.com").await.unwrap());
+        let later = now + Duration::minutes(20);
+        assert!(
+            users
+                .upsert_signup(&signup(), later - Duration::minutes(15), later)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        sqlx::query("UPDATE users SET is_verified = 1, is_subscribed = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let much_later = later + Duration::hours(1);
+        assert!(
+            users
+                .upsert_signup(&signup(), much_later - Duration::minutes(15), much_later)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(users.is_subscribed("a@example.com").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_code_skips_empty_codes() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        // Signed up before codes, so the column default is all it has
+        sqlx::query(
+            "INSERT INTO users (id, email, email_key, verification_token) VALUES ('1', 'a@example.com', 'a@example.com', 't')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let users = SqliteUsers::new(pool);
+        assert!(
+            users
+                .verify_code("a@example.com", "", MAX_VERIFICATION_CODE_ATTEMPTS)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(!is_verification_code(""));
+        assert!(!is_verification_code("123"));
+        assert!(is_verification_code("042317"));
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe() {
+        let users = MemoryUsers::default();
+        let user = User::new("a@example.com".to_string());
+        let token = user.generate_unsubscribe_token("secret");
+        let id = user.id.clone();
+        users.users.lock().unwrap().push((user, true));
+        let pushed = User::new("b@example.com".to_string());
+        let pushed_id = pushed.id.clone();
+        users.users.lock().unwrap().push((pushed, true));
+        users.other_channels.lock().unwrap().push(pushed_id.clone());
+        let service = UnsubscribeService {
+            users,
+            unsubscribe_secret: "secret",
+        };
+
+        assert!(service.token_is_valid(&id, &token));
+        assert!(!service.token_is_valid(&id, "forged"));
+        // With nothing but email, stopping the emails stops everything
+        assert!(!service.can_stop_email_only(&id).await.unwrap());
+        assert_eq!(
+            service
+                .unsubscribe(&id, UnsubscribeScope::Email)
+                .await
+                .unwrap(),
+            Some(UnsubscribeScope::All)
+        );
+        assert_eq!(
+            service
+                .unsubscribe(&id, UnsubscribeScope::All)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert!(service.can_stop_email_only(&pushed_id).await.unwrap());
+        let email_only = service
+            .unsubscribe(&pushed_id, UnsubscribeScope::Email)
+            .await
+            .unwrap();
+        assert_eq!(email_only, Some(UnsubscribeScope::Email));
+        assert_eq!(service.users.users.lock().unwrap().len(), 1);
+        assert_eq!(
+            service
+                .unsubscribe(&pushed_id, UnsubscribeScope::Email)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            service
+                .unsubscribe(&pushed_id, UnsubscribeScope::All)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(service.users.users.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use std::env;
+use tower_sessions::cookie::SameSite;
+use tower_sessions::cookie::time::Duration;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, ExpiredDeletion, SessionStore};
+use tower_sessions::{Expiry, SessionManagerLayer};
+use tower_sessions_sqlx_store::SqliteStore;
+
+use crate::AppState;
+#[cfg(feature = "redis")]
+use crate::shared::RedisSessionStore;
+
+/// Default number of idle hours before a session expires.
+const DEFAULT_SESSION_IDLE_HOURS: i64 = 12;
+const TABLE_NAME: &str = "sessions";
+const COOKIE_NAME: &str = "session";
+
+fn idle_hours() -> i64 {
+    env::var("SESSION_IDLE_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_SESSION_IDLE_HOURS)
+}
+
+/// Where sessions are kept: SQLite, or Redis when it's shared between
+/// instances.
+#[derive(Debug, Clone)]
+pub enum Store {
+    Sqlite(SqliteStore),
+    #[cfg(feature = "redis")]
+    Redis(RedisSessionStore),
+}
+
+#[async_trait]
+impl SessionStore for Store {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            Store::Sqlite(store) => store.create(record).await,
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            Store::Sqlite(store) => store.save(record).await,
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            Store::Sqlite(store) => store.load(id).await,
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.load(id).await,
+        }
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        match self {
+            Store::Sqlite(store) => store.delete(id).await,
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.delete(id).await,
+        }
+    }
+}
+
+fn sqlite_store(state: &AppState) -> SqliteStore {
+    let store = SqliteStore::new(state.pool.clone())
+        .with_table_name(TABLE_NAME)
+        .expect("Invalid sessions table name");
+
+    let cleanup = store.clone();
+    tokio::spawn(async move {
+        if let Err(e) = cleanup
+            .continuously_delete_expired(tokio::time::Duration::from_secs(60 * 60))
+            .await
+        {
+            eprintln!("Expired session cleanup stopped: {}", e);
+        }
+    });
+    store
+}
+
+/// Server side sessions, for any page that needs someone to be logged in.
+/// Only a random session id is kept in the cookie.
+pub fn layer(state: &AppState) -> SessionManagerLayer<Store> {
+    #[cfg(feature = "redis")]
+    let store = match &state.redis {
+        Some(redis) => Store::Redis(RedisSessionStore::new(redis.clone())),
+        None => Store::Sqlite(sqlite_store(state)),
+    };
+    #[cfg(not(feature = "redis"))]
+    let store = Store::Sqlite(sqlite_store(state));
+
+    // Lax rather than Strict so the session survives the redirect back from
+    // an OIDC provider
+    SessionManagerLayer::new(store)
+        .with_name(COOKIE_NAME)
+        .with_http_only(true)
+        .with_secure(state.base_url.starts_with("https://"))
+        .with_same_site(SameSite::Lax)
+        .with_expiry(Expiry::OnInactivity(Duration::hours(idle_hours())))
+}
@@ -0,0 +1,201 @@
+use async_trait::async_trait;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, Client, RedisError, Script, SetExpiry, SetOptions};
+use std::env;
+use std::net::IpAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, SessionStore};
+
+/// Prepended to every key, so the Redis can be shared with other apps.
+const KEY_PREFIX: &str = "flood-alert:";
+const SNAPSHOT_KEY: &str = "tide-snapshot";
+
+/// A generic cell rate limit like governor's, with the theoretical arrival
+/// time kept in Redis. Returns the milliseconds to wait, or 0 to go ahead.
+static LIMIT_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local time = redis.call('TIME')
+        local now = time[1] * 1000 + math.floor(time[2] / 1000)
+        local period = tonumber(ARGV[1])
+        local burst = tonumber(ARGV[2])
+        local tat = math.max(tonumber(redis.call('GET', KEYS[1]) or now), now)
+        local next_tat = tat + period
+        local wait = next_tat - now - period * burst
+        if wait > 0 then
+            return wait
+        end
+        redis.call('SET', KEYS[1], next_tat, 'PX', next_tat - now)
+        return 0
+        ",
+    )
+});
+
+fn key(parts: &[&str]) -> String {
+    format!("{}{}", KEY_PREFIX, parts.join(":"))
+}
+
+fn redis_url() -> Option<String> {
+    env::var("REDIS_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+}
+
+/// A connection to the Redis at `REDIS_URL`, made on first use and remade
+/// whenever it drops. None without one, keeping state in this instance.
+pub fn from_env() -> Option<ConnectionManager> {
+    let url = redis_url()?;
+    let client = Client::open(url).expect("REDIS_URL must be a valid Redis URL");
+    let manager = ConnectionManager::new_lazy_with_config(client, ConnectionManagerConfig::new())
+        .expect("Error setting up the Redis connection");
+    Some(manager)
+}
+
+/// Checks `REDIS_URL` answers, for the startup checks.
+pub async fn check() -> Option<String> {
+    let url = redis_url()?;
+    let result = async {
+        let client = Client::open(url)?;
+        let mut connection = client.get_multiplexed_async_connection().await?;
+        redis::cmd("PING")
+            .query_async::<String>(&mut connection)
+            .await
+    }
+    .await;
+    result
+        .err()
+        .map(|e| format!("Redis at REDIS_URL can't be reached: {}", e))
+}
+
+/// A rate limit counted across every instance.
+#[derive(Clone)]
+pub struct Limit {
+    pub redis: ConnectionManager,
+    pub name: &'static str,
+    pub period: Duration,
+    pub burst: u32,
+}
+
+impl Limit {
+    /// How long `ip` has to wait, or None when its request can go ahead.
+    pub async fn check(&self, ip: IpAddr) -> Result<Option<Duration>, RedisError> {
+        let mut redis = self.redis.clone();
+        let wait: u64 = LIMIT_SCRIPT
+            .key(key(&["limit", self.name, &ip.to_string()]))
+            .arg(self.period.as_millis() as u64)
+            .arg(self.burst)
+            .invoke_async(&mut redis)
+            .await?;
+        Ok((wait > 0).then(|| Duration::from_millis(wait)))
+    }
+}
+
+/// The current site's snapshot, as each site has its own tides.
+fn snapshot_key() -> String {
+    match crate::site::current() {
+        0 => key(&[SNAPSHOT_KEY]),
+        _ => key(&[SNAPSHOT_KEY, &crate::site::site().id]),
+    }
+}
+
+/// The last tide snapshot any instance took, as JSON.
+pub async fn load_snapshot(redis: &ConnectionManager) -> Result<Option<String>, RedisError> {
+    redis.clone().get(snapshot_key()).await
+}
+
+pub async fn save_snapshot(redis: &ConnectionManager, json: &str) -> Result<(), RedisError> {
+    redis.clone().set(snapshot_key(), json).await
+}
+
+/// Sessions kept in Redis, so a login works on whichever instance serves
+/// the next request. Each expires with its session.
+#[derive(Clone)]
+pub struct RedisSessionStore {
+    redis: ConnectionManager,
+}
+
+impl RedisSessionStore {
+    pub fn new(redis: ConnectionManager) -> Self {
+        RedisSessionStore { redis }
+    }
+
+    /// Saves `record`, only if no session has its id when `only_new`.
+    /// Returns whether it was saved.
+    async fn set(&self, record: &Record, only_new: bool) -> session_store::Result<bool> {
+        let json = serde_json::to_string(record)
+            .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        let expiry = SetExpiry::EXAT(record.expiry_date.unix_timestamp().max(1) as u64);
+        let mut options = SetOptions::default().with_expiration(expiry);
+        if only_new {
+            options = options.conditional_set(redis::ExistenceCheck::NX);
+        }
+        let saved: Option<String> = self
+            .redis
+            .clone()
+            .set_options(key(&["session", &record.id.to_string()]), json, options)
+            .await
+            .map_err(backend)?;
+        Ok(saved.is_some())
+    }
+}
+
+// By hand since `ConnectionManager` isn't `Debug`
+impl std::fmt::Debug for RedisSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisSessionStore").finish_non_exhaustive()
+    }
+}
+
+fn backend(e: RedisError) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        while !self.set(record, true).await? {
+            record.id = Id::default();
+        }
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.set(record, false).await.map(|_| ())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let json: Option<String> = self
+            .redis
+            .clone()
+            .get(key(&["session", &id.to_string()]))
+            .await
+            .map_err(backend)?;
+        json.map(|json| serde_json::from_str(&json))
+            .transpose()
+            .map_err(|e| session_store::Error::Decode(e.to_string()))
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        self.redis
+            .clone()
+            .del::<_, ()>(key(&["session", &id.to_string()]))
+            .await
+            .map_err(backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(
+            key(&["limit", "signup", "203.0.113.7"]),
+            "flood-alert:limit:signup:203.0.113.7"
+        );
+        assert_eq!(snapshot_key(), "flood-alert:tide-snapshot");
+    }
+}
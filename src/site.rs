@@ -0,0 +1,450 @@
+use axum::Router;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::header::HOST;
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::convert::Infallible;
+use std::env;
+use std::sync::LazyLock;
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+use crate::assets;
+use crate::i18n::Strings;
+use crate::station_settings;
+use crate::tides::{self, DEFAULT_STATION_ID};
+
+const DEFAULT_SITE_ID: &str = "default";
+const DEFAULT_SITE_NAME: &str = "Mill Valley-Sausalito Bike Path";
+const DEFAULT_STATION_NAME: &str = "Sausalito Corps of Engineers Dock";
+const DEFAULT_BASE_URL: &str = "http://localhost:3000";
+const DEFAULT_PRIVACY_UPDATED: &str = "January 2026";
+
+/// What an extra site in `SITES` has to set for itself, rather than
+/// falling back to the default site's settings.
+pub const SITE_VARS: [&str; 3] = ["HOSTS", "SITE_NAME", "BASE_URL"];
+/// Also required of an extra site with its own `<ID>_DATABASE_URL`. One
+/// sharing the default site's database uses its station.
+pub const OWN_DATABASE_VARS: [&str; 2] = ["STATION_ID", "STATION_NAME"];
+
+/// The default site, then the extra ones from `SITES`.
+static SITES: LazyLock<Vec<Site>> = LazyLock::new(|| {
+    let var = |name: &str| env::var(name).ok();
+    let mut sites = vec![Site::from_vars(None, var)];
+    sites.extend(
+        site_ids(var("SITES").as_deref())
+            .iter()
+            .map(|id| Site::from_vars(Some(id), var)),
+    );
+    sites
+});
+
+tokio::task_local! {
+    static CURRENT: usize;
+}
+
+/// Details of the deployment shown across the pages and emails, so the
+/// templates don't hardcode a location.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Site {
+    /// `default`, or the extra site's id in `SITES`
+    pub id: String,
+    /// Lowercase hostnames this site is served on, from `<ID>_HOSTS`
+    pub hosts: Vec<String>,
+    /// Its own database, so it has its own subscribers, tides and email
+    /// overrides, unless it shares the default site's
+    pub database_url: Option<String>,
+    /// An extra site without its own database, e.g. another hostname and
+    /// brand for the same path. The default site's worker sends its emails.
+    pub shares_database: bool,
+    /// What's being forecast, e.g. "Mill Valley-Sausalito Bike Path"
+    pub name: String,
+    /// Who runs this deployment, named in the privacy policy when set
+    pub operator: Option<String>,
+    /// Where visitors can write with questions, shown in the footer when set
+    pub contact_email: Option<String>,
+    /// The NOAA station whose predictions are used
+    pub station_name: String,
+    pub station_id: String,
+    /// See `tides::flood_threshold`, without any calibration
+    pub configured_threshold: f64,
+    /// Without a trailing slash
+    pub base_url: String,
+    /// When the privacy policy last changed, e.g. "January 2026"
+    pub privacy_updated: String,
+    /// Replaces the homepage heading, e.g. "Manzanita Tide Watch"
+    pub title: Option<String>,
+    /// An image under `assets/`, e.g. `img/logo.png`, or a full URL
+    pub logo: Option<String>,
+    /// `#rgb` or `#rrggbb` used for links and buttons instead of Pico's blue
+    pub accent_color: Option<String>,
+    /// A line added to the footer of pages and emails
+    pub footer_text: Option<String>,
+}
+
+/// Whether `value` is a `#rgb` or `#rrggbb` color, the only kind put into
+/// the pages' CSS.
+pub fn is_hex_color(value: &str) -> bool {
+    value
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// The extra site ids in `SITES`, e.g. `manzanita,stinson`.
+pub fn site_ids(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|id| id.trim().to_lowercase())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// The environment variable `key` for the extra site `id`, e.g.
+/// `MANZANITA_STATION_ID`.
+pub fn prefixed(id: &str, key: &str) -> String {
+    format!("{}_{}", id.to_uppercase().replace('-', "_"), key)
+}
+
+impl Site {
+    /// Reads `SITE_NAME`, `OPERATOR_NAME`, `CONTACT_EMAIL`, `STATION_NAME`,
+    /// `STATION_ID`, `BASE_URL`, `PRIVACY_POLICY_UPDATED` and the flood
+    /// thresholds. An extra site reads them prefixed with its id first,
+    /// and its hosts and database only that way.
+    fn from_vars(id: Option<&str>, var: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+        let own = |name: &str| match id {
+            Some(id) => var(&prefixed(id, name)),
+            None => var(name),
+        };
+        let shares_database = id.is_some() && own("DATABASE_URL").is_none();
+        let station = |name: &str| {
+            if shares_database {
+                var(name)
+            } else {
+                own(name).or_else(|| var(name))
+            }
+        };
+        let station_id = station("STATION_ID").unwrap_or_else(|| DEFAULT_STATION_ID.to_string());
+        let station_name =
+            station("STATION_NAME").unwrap_or_else(|| DEFAULT_STATION_NAME.to_string());
+        let var = |name: &str| own(name).or_else(|| var(name));
+        Site {
+            id: id.unwrap_or(DEFAULT_SITE_ID).to_string(),
+            hosts: own("HOSTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            database_url: var("DATABASE_URL"),
+            shares_database,
+            name: var("SITE_NAME").unwrap_or_else(|| DEFAULT_SITE_NAME.to_string()),
+            operator: var("OPERATOR_NAME"),
+            contact_email: var("CONTACT_EMAIL"),
+            station_name,
+            configured_threshold: tides::threshold_from_vars(&station_id, var),
+            station_id,
+            base_url: var("BASE_URL")
+                .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            privacy_updated: var("PRIVACY_POLICY_UPDATED")
+                .unwrap_or_else(|| DEFAULT_PRIVACY_UPDATED.to_string()),
+            title: var("SITE_TITLE"),
+            logo: var("LOGO_PATH").map(|path| path.trim().to_string()),
+            accent_color: var("ACCENT_COLOR")
+                .map(|color| color.trim().to_string())
+                .filter(|color| is_hex_color(color)),
+            footer_text: var("FOOTER_TEXT"),
+        }
+    }
+
+    /// What the setting `key` is called for this site, e.g.
+    /// `MANZANITA_DATABASE_URL`.
+    pub fn var_name(&self, key: &str) -> String {
+        match self.id.as_str() {
+            DEFAULT_SITE_ID => key.to_string(),
+            id => prefixed(id, key),
+        }
+    }
+
+    /// The homepage heading, `SITE_TITLE` or the site name in `t`'s language.
+    pub fn heading(&self, t: &Strings) -> String {
+        self.title.clone().unwrap_or_else(|| t.heading(&self.name))
+    }
+
+    /// Where the logo is served from, fingerprinted when it's an asset.
+    pub fn logo_url(&self) -> Option<String> {
+        let logo = self.logo.as_ref()?;
+        if logo.starts_with("https://") || logo.starts_with("http://") {
+            return Some(logo.clone());
+        }
+        let path = logo.trim_start_matches('/');
+        Some(assets::asset(path.strip_prefix("assets/").unwrap_or(path)))
+    }
+
+    /// The logo's full URL, for emails.
+    pub fn email_logo_url(&self) -> Option<String> {
+        let url = self.logo_url()?;
+        if url.starts_with('/') {
+            Some(format!("{}{}", self.base_url, url))
+        } else {
+            Some(url)
+        }
+    }
+
+    /// The accent color, or `default` for emails that style their own.
+    pub fn accent_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.accent_color.as_deref().unwrap_or(default)
+    }
+
+    /// NOAA's page for the station.
+    pub fn station_url(&self) -> String {
+        format!(
+            "https://tidesandcurrents.noaa.gov/stationhome.html?id={}",
+            self.station_id
+        )
+    }
+
+    /// The height at which the path floods: a calibrated one if applied,
+    /// then the station settings', then the environment's.
+    pub fn flood_threshold(&self) -> f64 {
+        tides::calibrated_threshold(&self.id)
+            .or_else(|| station_settings::threshold(&self.id))
+            .unwrap_or(self.configured_threshold)
+    }
+}
+
+/// The site being served or run for, read from the environment once:
+/// `{% let site = crate::site::site() %}`.
+pub fn site() -> &'static Site {
+    &SITES[current()]
+}
+
+/// Every site, the default first.
+pub fn sites() -> &'static [Site] {
+    &SITES
+}
+
+/// The index in `sites()` of the site being served or run for, the default
+/// outside of `scope`.
+pub fn current() -> usize {
+    CURRENT.try_with(|index| *index).unwrap_or(0)
+}
+
+/// The index of the site with `id`.
+pub fn find(id: &str) -> Option<usize> {
+    SITES
+        .iter()
+        .position(|site| site.id == id.trim().to_lowercase())
+}
+
+/// Runs `future` for the site at `index`.
+pub async fn scope<F: Future>(index: usize, future: F) -> F::Output {
+    CURRENT.scope(index, future).await
+}
+
+/// `tokio::spawn` for background work that keeps the current site.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(scope(current(), future))
+}
+
+/// A setting for the current site: `<ID>_<KEY>` for an extra site, falling
+/// back to `<KEY>` like the default site.
+pub fn var(key: &str) -> Option<String> {
+    let var = |key: &str| env::var(key).ok().filter(|value| !value.trim().is_empty());
+    var(&site().var_name(key)).or_else(|| var(key))
+}
+
+/// The index of the site serving `host`, ignoring any port, and the default
+/// site for hosts no site lists.
+fn index_for_host(sites: &[Site], host: &str) -> usize {
+    let host = host.trim().to_lowercase();
+    let host = match host.strip_prefix('[') {
+        Some(address) => address.split(']').next(),
+        None => host.split(':').next(),
+    }
+    .unwrap_or_default();
+    sites
+        .iter()
+        .position(|site| site.hosts.iter().any(|name| name == host))
+        .unwrap_or(0)
+}
+
+/// The index of the site a request is for, from its `Host`.
+pub fn for_request(req: &Request) -> usize {
+    let host = req
+        .uri()
+        .host()
+        .or_else(|| {
+            req.headers()
+                .get(HOST)
+                .and_then(|value| value.to_str().ok())
+        })
+        .unwrap_or_default();
+    index_for_host(&SITES, host)
+}
+
+/// The site a request is for, for handlers to take as an argument.
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentSite(pub &'static Site);
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentSite {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<CurrentSite>()
+            .copied()
+            .unwrap_or(CurrentSite(site())))
+    }
+}
+
+/// Middleware handling requests for the site at `index`, which handlers
+/// can take as `CurrentSite` or read with `site()`.
+pub async fn middleware(State(index): State<usize>, mut req: Request, next: Next) -> Response {
+    req.extensions_mut().insert(CurrentSite(&SITES[index]));
+    scope(index, next.run(req)).await
+}
+
+/// Hands each request to the router of the site its host is for, given by
+/// index in `sites()`, or to the first router for hosts none of them serve.
+pub fn dispatch(mut routers: Vec<(usize, Router)>) -> Router {
+    if routers.len() == 1 {
+        return routers.remove(0).1;
+    }
+    Router::new().fallback(move |req: Request| {
+        let index = for_request(&req);
+        let router = routers
+            .iter()
+            .find(|(site, _)| *site == index)
+            .unwrap_or(&routers[0])
+            .1
+            .clone();
+        async move { router.oneshot(req).await }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Lang;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_vars() {
+        let site = Site::from_vars(None, |_| None);
+        assert_eq!(site.id, DEFAULT_SITE_ID);
+        assert_eq!(site.name, DEFAULT_SITE_NAME);
+        assert_eq!(site.operator, None);
+        assert_eq!(site.contact_email, None);
+        assert_eq!(site.station_id, DEFAULT_STATION_ID);
+        assert_eq!(site.base_url, DEFAULT_BASE_URL);
+        assert_eq!(
+            site.station_url(),
+            "https://tidesandcurrents.noaa.gov/stationhome.html?id=9414819"
+        );
+
+        let site = Site::from_vars(None, |name| match name {
+            "SITE_NAME" => Some("Larkspur Path".to_string()),
+            "OPERATOR_NAME" => Some("Larkspur Bike Club".to_string()),
+            "CONTACT_EMAIL" => Some("help@example.com".to_string()),
+            "STATION_NAME" => Some(" ".to_string()),
+            "BASE_URL" => Some("https://floods.example.com/".to_string()),
+            _ => None,
+        });
+        assert_eq!(site.name, "Larkspur Path");
+        assert_eq!(site.operator.as_deref(), Some("Larkspur Bike Club"));
+        assert_eq!(site.contact_email.as_deref(), Some("help@example.com"));
+        assert_eq!(site.station_name, DEFAULT_STATION_NAME);
+        assert_eq!(site.base_url, "https://floods.example.com");
+    }
+
+    #[test]
+    fn test_theme() {
+        let site = Site::from_vars(None, |name| match name {
+            "SITE_TITLE" => Some("Manzanita Tide Watch".to_string()),
+            "LOGO_PATH" => Some("/assets/img/logo.png".to_string()),
+            "ACCENT_COLOR" => Some("red; } body { display: none".to_string()),
+            "BASE_URL" => Some("https://floods.example.com".to_string()),
+            _ => None,
+        });
+        assert_eq!(site.heading(Lang::En.strings()), "Manzanita Tide Watch");
+        assert_eq!(site.logo_url().as_deref(), Some("/assets/img/logo.png"));
+        assert_eq!(
+            site.email_logo_url().as_deref(),
+            Some("https://floods.example.com/assets/img/logo.png")
+        );
+        assert_eq!(site.accent_color, None);
+        assert_eq!(site.accent_or("#0056b3"), "#0056b3");
+
+        assert!(is_hex_color("#2a9d8f"));
+        assert!(is_hex_color("#fff"));
+        assert!(!is_hex_color("2a9d8f"));
+        assert!(!is_hex_color("#2a9d8"));
+    }
+
+    #[test]
+    fn test_extra_site() {
+        let vars = HashMap::from([
+            ("SITES", " Manzanita, ,highway-1"),
+            ("DATABASE_URL", "sqlite:data/alerts.db"),
+            ("OPERATOR_NAME", "Marin Bike Club"),
+            ("FLOOD_THRESHOLD_FT", "6.8"),
+            ("FLOOD_THRESHOLDS", "9437540=9.2"),
+            (
+                "MANZANITA_HOSTS",
+                "Manzanita.example.com, floods.example.org",
+            ),
+            ("MANZANITA_DATABASE_URL", "sqlite:data/manzanita.db"),
+            ("MANZANITA_SITE_NAME", "Manzanita Highway 1"),
+            ("MANZANITA_STATION_ID", "9437540"),
+            ("HIGHWAY_1_STATION_ID", "9437540"),
+        ]);
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+        assert_eq!(
+            site_ids(var("SITES").as_deref()),
+            ["manzanita", "highway-1"]
+        );
+        assert_eq!(prefixed("highway-1", "HOSTS"), "HIGHWAY_1_HOSTS");
+
+        let site = Site::from_vars(Some("manzanita"), var);
+        assert!(!site.shares_database);
+        assert_eq!(site.id, "manzanita");
+        assert_eq!(site.hosts, ["manzanita.example.com", "floods.example.org"]);
+        assert_eq!(
+            site.database_url.as_deref(),
+            Some("sqlite:data/manzanita.db")
+        );
+        assert_eq!(site.name, "Manzanita Highway 1");
+        assert_eq!(site.operator.as_deref(), Some("Marin Bike Club"));
+        assert_eq!(site.configured_threshold, 9.2);
+
+        // Without a database of its own it shares the default site's, and so
+        // its station
+        let site = Site::from_vars(Some("highway-1"), var);
+        assert!(site.shares_database);
+        assert_eq!(site.database_url.as_deref(), Some("sqlite:data/alerts.db"));
+        assert_eq!(site.station_id, DEFAULT_STATION_ID);
+        assert_eq!(site.configured_threshold, 6.8);
+    }
+
+    #[test]
+    fn test_index_for_host() {
+        let mut manzanita = Site::from_vars(None, |_| None);
+        manzanita.hosts = vec!["manzanita.example.com".to_string(), "::1".to_string()];
+        let sites = [Site::from_vars(None, |_| None), manzanita];
+        assert_eq!(index_for_host(&sites, "manzanita.example.com"), 1);
+        assert_eq!(index_for_host(&sites, "Manzanita.Example.com:3000"), 1);
+        assert_eq!(index_for_host(&sites, "[::1]:3000"), 1);
+        assert_eq!(index_for_host(&sites, "floods.example.com"), 0);
+        assert_eq!(index_for_host(&sites, ""), 0);
+    }
+}
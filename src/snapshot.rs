@@ -0,0 +1,407 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::sync::{Arc, RwLock};
+use tracing::instrument;
+
+use crate::AppState;
+use crate::error::AppError;
+use crate::floods::FLOOD_MARGIN_MINUTES;
+use crate::jobs::{self, Job};
+#[cfg(feature = "redis")]
+use crate::shared;
+use crate::site;
+use crate::station_settings;
+use crate::sync_runs::{self, SyncRun};
+use crate::tides::{
+    FORECAST_DAYS, FloodTide, Tide, flood_threshold, get_flood_tides_between, get_tides_between,
+};
+
+/// How often the snapshot is reloaded from the database.
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Days past the forecast the snapshot covers, so it still fills the
+/// forecast after a day without the database.
+const EXTRA_DAYS: i64 = 2;
+
+/// The tides last read from the database, kept in memory so the homepage and
+/// API can still answer from them while the database is locked or
+/// unavailable, which is most likely during a storm when traffic peaks.
+#[derive(Default)]
+pub struct TideSnapshot {
+    inner: RwLock<Option<Snapshot>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    taken_at: DateTime<Utc>,
+    /// Start of the tides loaded
+    covers_from: DateTime<Utc>,
+    /// When the last successful sync finished
+    synced_at: Option<DateTime<Utc>>,
+    /// When NOAA sent the predictions of the last successful sync
+    fetched_at: Option<DateTime<Utc>>,
+    /// The latest recorded sync, whether or not it succeeded
+    last_sync: Option<SyncRun>,
+    tides: Vec<Tide>,
+}
+
+/// Served from the snapshot taken at this time instead of the database.
+#[derive(Debug, Clone, Copy)]
+pub struct Stale(pub DateTime<Utc>);
+
+/// `ETag` and `Last-Modified` for the forecast endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Stale {
+    /// Value for the `Data-Stale-Since` header on API responses.
+    pub fn header_value(&self) -> String {
+        self.0.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    }
+}
+
+impl TideSnapshot {
+    /// Reloads the tides from shortly before now to past the forecast.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn refresh(&self, pool: &SqlitePool) -> Result<(), AppError> {
+        let now = Utc::now();
+        let covers_from = now - Duration::minutes(FLOOD_MARGIN_MINUTES);
+        let tides = get_tides_between(
+            pool,
+            covers_from,
+            now + Duration::days(FORECAST_DAYS + EXTRA_DAYS),
+        )
+        .await?;
+        let synced_at = jobs::last_succeeded(pool, Job::Sync)
+            .await?
+            .map(|finished_at| finished_at.and_utc());
+        let fetched_at = sync_runs::last_fetched(pool)
+            .await?
+            .map(|fetched_at| fetched_at.and_utc());
+        let last_sync = sync_runs::recent(pool, 1).await?.pop();
+        *self.inner.write().unwrap() = Some(Snapshot {
+            taken_at: Utc::now(),
+            covers_from,
+            synced_at,
+            fetched_at,
+            last_sync,
+            tides,
+        });
+        Ok(())
+    }
+
+    /// When NOAA sent the predictions being served, if a sync recorded it.
+    pub fn fetched_at(&self) -> Option<DateTime<Utc>> {
+        self.inner.read().unwrap().as_ref()?.fetched_at
+    }
+
+    pub fn last_sync(&self) -> Option<SyncRun> {
+        self.inner.read().unwrap().as_ref()?.last_sync.clone()
+    }
+
+    /// When the predictions being served were fetched, if that's long
+    /// enough ago at `now` that they may be missing NOAA's latest.
+    pub fn outdated_since(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.fetched_at()
+            .filter(|fetched_at| sync_runs::is_outdated(*fetched_at, now))
+    }
+
+    /// Validators for the forecast at `now`, if a sync has been recorded.
+    /// The forecast changes when a sync stores new tides, and as time moves
+    /// a tide out of or into the window, so both go into them.
+    pub fn validators(&self, now: DateTime<Utc>) -> Option<Validators> {
+        let inner = self.inner.read().unwrap();
+        let snapshot = inner.as_ref()?;
+        let synced_at = snapshot.synced_at?;
+        let end = now + Duration::days(FORECAST_DAYS);
+
+        let first = snapshot
+            .tides
+            .iter()
+            .find(|tide| tide.prediction_time >= now)
+            .map(|tide| tide.prediction_time);
+        let last = snapshot
+            .tides
+            .iter()
+            .rev()
+            .find(|tide| tide.prediction_time <= end)
+            .map(|tide| tide.prediction_time);
+        // The window last changed when the latest tide before it passed, or
+        // when its last tide came within the forecast days. Tides before the
+        // snapshot passed before it started, so that's late enough.
+        let passed = snapshot
+            .tides
+            .iter()
+            .rev()
+            .find(|tide| tide.prediction_time < now)
+            .map_or(snapshot.covers_from, |tide| tide.prediction_time);
+        let entered = last.map(|last| last - Duration::days(FORECAST_DAYS));
+        let window_changed = passed.max(entered.unwrap_or(passed));
+
+        let minute = |time: Option<DateTime<Utc>>| {
+            time.map_or("none".to_string(), |time| {
+                time.format("%Y%m%d%H%M").to_string()
+            })
+        };
+        Some(Validators {
+            etag: format!(
+                "\"{}-{}-{}\"",
+                synced_at.timestamp(),
+                minute(first),
+                minute(last)
+            ),
+            last_modified: synced_at.max(window_changed),
+        })
+    }
+
+    /// Saves a fresh snapshot to Redis for the other instances, or after
+    /// a failed refresh takes theirs if it's newer, e.g. when this instance
+    /// started while the database was down.
+    #[cfg(feature = "redis")]
+    async fn share(&self, redis: &redis::aio::ConnectionManager, refreshed: bool) {
+        if refreshed {
+            let json = match self.inner.read().unwrap().as_ref() {
+                Some(snapshot) => serde_json::to_string(snapshot),
+                None => return,
+            };
+            let saved = match json {
+                Ok(json) => shared::save_snapshot(redis, &json)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = saved {
+                eprintln!("Error sharing the tide snapshot: {}", e);
+            }
+            return;
+        }
+
+        let loaded = match shared::load_snapshot(redis).await {
+            Ok(json) => json.map(|json| serde_json::from_str::<Snapshot>(&json)),
+            Err(e) => {
+                eprintln!("Error loading the shared tide snapshot: {}", e);
+                return;
+            }
+        };
+        match loaded {
+            Some(Ok(shared)) => {
+                let mut inner = self.inner.write().unwrap();
+                if inner
+                    .as_ref()
+                    .is_none_or(|snapshot| snapshot.taken_at < shared.taken_at)
+                {
+                    println!("Using the shared tide snapshot of {}", shared.taken_at);
+                    *inner = Some(shared);
+                }
+            }
+            Some(Err(e)) => eprintln!("Error reading the shared tide snapshot: {}", e),
+            None => {}
+        }
+    }
+
+    fn tides_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<(Vec<Tide>, Stale)> {
+        let inner = self.inner.read().unwrap();
+        let snapshot = inner.as_ref()?;
+        let tides = snapshot
+            .tides
+            .iter()
+            .filter(|tide| tide.prediction_time >= start && tide.prediction_time <= end)
+            .cloned()
+            .collect();
+        Some((tides, Stale(snapshot.taken_at)))
+    }
+
+    fn flood_tides_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<(Vec<FloodTide>, Stale)> {
+        let (tides, stale) = self.tides_between(start, end)?;
+        let floods = tides
+            .into_iter()
+            .filter(|tide| tide.height_ft >= flood_threshold())
+            .map(|tide| FloodTide {
+                prediction_time: tide.prediction_time,
+                height_ft: tide.height_ft,
+            })
+            .collect();
+        Some((floods, stale))
+    }
+}
+
+/// Flood tides between two times from the database, or from the
+/// snapshot if the database can't be read.
+pub async fn flood_tides_between(
+    state: &AppState,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<(Vec<FloodTide>, Option<Stale>), AppError> {
+    match get_flood_tides_between(&state.pool, start, end).await {
+        Ok(floods) => Ok((floods, None)),
+        Err(e) => {
+            let Some((floods, stale)) = state.tide_snapshot.flood_tides_between(start, end) else {
+                return Err(e);
+            };
+            eprintln!("Serving floods from the snapshot of {}: {}", stale.0, e);
+            Ok((floods, Some(stale)))
+        }
+    }
+}
+
+/// Flood tides for the next `forecast_days`, falling back to the snapshot.
+pub async fn flood_tides(
+    state: &AppState,
+    forecast_days: i64,
+) -> Result<(Vec<FloodTide>, Option<Stale>), AppError> {
+    let now = Utc::now();
+    flood_tides_between(state, now, now + Duration::days(forecast_days)).await
+}
+
+/// High and low tides for the next `forecast_days`, falling back to the
+/// snapshot.
+pub async fn tides(
+    state: &AppState,
+    forecast_days: i64,
+) -> Result<(Vec<Tide>, Option<Stale>), AppError> {
+    let now = Utc::now();
+    let end = now + Duration::days(forecast_days);
+    match get_tides_between(&state.pool, now, end).await {
+        Ok(tides) => Ok((tides, None)),
+        Err(e) => {
+            let Some((tides, stale)) = state.tide_snapshot.tides_between(now, end) else {
+                return Err(e);
+            };
+            eprintln!("Serving tides from the snapshot of {}: {}", stale.0, e);
+            Ok((tides, Some(stale)))
+        }
+    }
+}
+
+/// Keeps the snapshot fresh, keeping the last good one when a reload fails.
+pub fn spawn_refresh(state: Arc<AppState>) {
+    site::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            station_settings::refresh(&state.pool).await;
+            #[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+            let refreshed = match state.tide_snapshot.refresh(&state.pool).await {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Error refreshing the tide snapshot: {}", e);
+                    false
+                }
+            };
+            #[cfg(feature = "redis")]
+            if let Some(redis) = &state.redis {
+                state.tide_snapshot.share(redis, refreshed).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_snapshot_outlives_database() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let now = Utc::now();
+        for (hours, height) in [(5, 7.1), (11, 2.0), (24 * 60, 7.5)] {
+            sqlx::query(
+                "INSERT INTO tides (prediction_time, height_ft, tide_type) VALUES (?, ?, 'High')",
+            )
+            .bind((now + Duration::hours(hours)).naive_utc())
+            .bind(height)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let snapshot = TideSnapshot::default();
+        let end = now + Duration::days(FORECAST_DAYS);
+        assert!(snapshot.tides_between(now, end).is_none());
+
+        snapshot.refresh(&pool).await.unwrap();
+        pool.close().await;
+        assert!(get_flood_tides_between(&pool, now, end).await.is_err());
+        let (tides, stale) = snapshot.tides_between(now, end).unwrap();
+        assert_eq!(tides.len(), 2);
+        assert!(stale.0 <= Utc::now());
+        let (floods, _) = snapshot.flood_tides_between(now, end).unwrap();
+        assert_eq!(floods.len(), 1);
+        assert_eq!(floods[0].height_ft, 7.1);
+    }
+
+    #[tokio::test]
+    async fn test_validators() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let now = Utc::now();
+        for hours in [-30, 5, 11] {
+            sqlx::query(
+                "INSERT INTO tides (prediction_time, height_ft, tide_type) VALUES (?, 7.0, 'High')",
+            )
+            .bind((now + Duration::minutes(hours)).naive_utc())
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let snapshot = TideSnapshot::default();
+        snapshot.refresh(&pool).await.unwrap();
+        assert!(snapshot.validators(Utc::now()).is_none());
+
+        sqlx::query(
+            "INSERT INTO job_runs (id, job, status, source, attempts, finished_at)
+            VALUES ('run', 'sync', 'succeeded', 'cli', 1, '2025-10-09 08:53:20')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        snapshot.refresh(&pool).await.unwrap();
+        let now = Utc::now();
+        let validators = snapshot.validators(now).unwrap();
+        assert!(validators.etag.starts_with("\"1760000000-"));
+        // The tide half an hour ago is the last change to the window
+        assert!(validators.last_modified > now - Duration::minutes(31));
+        assert!(validators.last_modified < now - Duration::minutes(29));
+        assert_eq!(snapshot.validators(now), Some(validators.clone()));
+
+        // Once the next tide passes the window and the ETag move on
+        let later = now + Duration::minutes(6);
+        let moved = snapshot.validators(later).unwrap();
+        assert_ne!(moved.etag, validators.etag);
+        assert!(moved.last_modified > now);
+    }
+
+    #[tokio::test]
+    async fn test_outdated_since() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let snapshot = TideSnapshot::default();
+        snapshot.refresh(&pool).await.unwrap();
+        let now = Utc::now();
+        assert!(snapshot.outdated_since(now).is_none());
+
+        let fetched_at = now - Duration::hours(40);
+        let mut run = sync_runs::Recorder::start(now.naive_utc(), now.naive_utc());
+        run.source = Some(sync_runs::Source::Cache);
+        run.fetched_at = Some(fetched_at.naive_utc());
+        run.finish(&pool, Ok(())).await.unwrap();
+        snapshot.refresh(&pool).await.unwrap();
+        assert_eq!(snapshot.outdated_since(now), Some(fetched_at));
+        assert!(snapshot.outdated_since(now - Duration::hours(20)).is_none());
+        assert_eq!(
+            snapshot.last_sync().unwrap().source.as_deref(),
+            Some("cache")
+        );
+    }
+}
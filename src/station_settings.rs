@@ -0,0 +1,247 @@
+use sqlx::sqlite::SqlitePool;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use crate::error::AppError;
+use crate::site::site;
+use crate::tides::station_id;
+
+/// The station's settings from `station_settings`, by site id.
+static SETTINGS: RwLock<BTreeMap<String, StationSettings>> = RwLock::new(BTreeMap::new());
+
+/// Location specific settings stored for a station, so they can change
+/// without a deploy. Those left unset fall back to the environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StationSettings {
+    pub threshold_ft: Option<f64>,
+    pub reminder_window_hours: Option<i64>,
+    /// Where the station and path are, shown on the homepage
+    pub location_description: Option<String>,
+    /// A way around the flooded path, shown on the homepage and in emails
+    pub alternate_route: Option<String>,
+}
+
+/// The changes `station-settings set` makes, an empty value clearing one.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub threshold_ft: Option<f64>,
+    pub reminder_window_hours: Option<i64>,
+    pub location_description: Option<String>,
+    pub alternate_route: Option<String>,
+}
+
+impl StationSettings {
+    fn apply(mut self, changes: Changes) -> Result<Self, String> {
+        let text = |value: String| Some(value.trim().to_string()).filter(|value| !value.is_empty());
+        if let Some(threshold) = changes.threshold_ft {
+            if !threshold.is_finite() || threshold <= 0.0 {
+                return Err(format!("The threshold must be above 0 ft: {}", threshold));
+            }
+            self.threshold_ft = Some(threshold);
+        }
+        if let Some(hours) = changes.reminder_window_hours {
+            if hours <= 0 {
+                return Err(format!(
+                    "The reminder window must be at least an hour: {}",
+                    hours
+                ));
+            }
+            self.reminder_window_hours = Some(hours);
+        }
+        if let Some(description) = changes.location_description {
+            self.location_description = text(description);
+        }
+        if let Some(route) = changes.alternate_route {
+            self.alternate_route = text(route);
+        }
+        Ok(self)
+    }
+}
+
+/// The current site's station settings.
+pub fn current() -> StationSettings {
+    SETTINGS
+        .read()
+        .unwrap()
+        .get(&site().id)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// The stored threshold for the site `id`, used over the environment's.
+pub fn threshold(id: &str) -> Option<f64> {
+    SETTINGS
+        .read()
+        .unwrap()
+        .get(id)
+        .and_then(|settings| settings.threshold_ft)
+}
+
+/// The alternate route for emails, empty when there's none.
+pub fn alternate_route() -> String {
+    current().alternate_route.unwrap_or_default()
+}
+
+async fn fetch(pool: &SqlitePool) -> Result<StationSettings, sqlx::Error> {
+    let station_id = station_id();
+    let settings = sqlx::query_as!(
+        StationSettings,
+        r#"
+        SELECT threshold_ft, reminder_window_hours, location_description, alternate_route
+        FROM station_settings WHERE station_id = ?
+        "#,
+        station_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(settings.unwrap_or_default())
+}
+
+/// Reads the current site's station settings from the database.
+pub async fn load(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let settings = fetch(pool).await?;
+    SETTINGS
+        .write()
+        .unwrap()
+        .insert(site().id.clone(), settings);
+    Ok(())
+}
+
+/// Reloads the settings, so changes reach a running server. Failures keep
+/// the settings already loaded.
+pub async fn refresh(pool: &SqlitePool) {
+    if let Err(e) = load(pool).await {
+        eprintln!("Error loading the station settings: {}", e);
+    }
+}
+
+pub async fn print(pool: &SqlitePool) -> Result<(), AppError> {
+    let settings = fetch(pool).await?;
+    let or_env =
+        |value: Option<String>| value.unwrap_or_else(|| "(from the environment)".to_string());
+    println!("Station {}", station_id());
+    println!(
+        "Flood threshold: {}",
+        or_env(
+            settings
+                .threshold_ft
+                .map(|threshold| format!("{} ft", threshold))
+        )
+    );
+    println!(
+        "Reminder window: {}",
+        or_env(
+            settings
+                .reminder_window_hours
+                .map(|hours| format!("{} hours", hours))
+        )
+    );
+    println!(
+        "Location description: {}",
+        settings.location_description.as_deref().unwrap_or("(none)")
+    );
+    println!(
+        "Alternate route: {}",
+        settings.alternate_route.as_deref().unwrap_or("(none)")
+    );
+    Ok(())
+}
+
+/// Saves `changes` to the current site's station settings.
+pub async fn set(pool: &SqlitePool, changes: Changes) -> Result<(), AppError> {
+    let station_id = station_id();
+    let settings = fetch(pool)
+        .await?
+        .apply(changes)
+        .map_err(AppError::BadRequest)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO station_settings
+            (station_id, threshold_ft, reminder_window_hours, location_description, alternate_route)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(station_id) DO UPDATE SET
+            threshold_ft = excluded.threshold_ft,
+            reminder_window_hours = excluded.reminder_window_hours,
+            location_description = excluded.location_description,
+            alternate_route = excluded.alternate_route,
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+        station_id,
+        settings.threshold_ft,
+        settings.reminder_window_hours,
+        settings.location_description,
+        settings.alternate_route
+    )
+    .execute(pool)
+    .await?;
+    println!("Saved the settings for station {}", station_id);
+    print(pool).await
+}
+
+/// Removes the current site's station settings, going back to the
+/// environment's.
+pub async fn reset(pool: &SqlitePool) -> Result<(), AppError> {
+    let station_id = station_id();
+    let result = sqlx::query!(
+        "DELETE FROM station_settings WHERE station_id = ?",
+        station_id
+    )
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        println!("Station {} has no settings", station_id);
+    } else {
+        println!("Removed the settings for station {}", station_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_set_and_reset() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        assert_eq!(fetch(&pool).await.unwrap(), StationSettings::default());
+
+        set(
+            &pool,
+            Changes {
+                threshold_ft: Some(6.7),
+                alternate_route: Some("Take Shoreline Highway ".to_string()),
+                ..Changes::default()
+            },
+        )
+        .await
+        .unwrap();
+        set(
+            &pool,
+            Changes {
+                reminder_window_hours: Some(12),
+                alternate_route: Some(" ".to_string()),
+                ..Changes::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch(&pool).await.unwrap(),
+            StationSettings {
+                threshold_ft: Some(6.7),
+                reminder_window_hours: Some(12),
+                location_description: None,
+                alternate_route: None,
+            }
+        );
+
+        let invalid = Changes {
+            threshold_ft: Some(-1.0),
+            ..Changes::default()
+        };
+        assert!(set(&pool, invalid).await.is_err());
+        reset(&pool).await.unwrap();
+        assert_eq!(fetch(&pool).await.unwrap(), StationSettings::default());
+    }
+}
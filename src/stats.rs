@@ -0,0 +1,210 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::error::AppError;
+use crate::models::FloodDisplay;
+use crate::tides::{self, FloodTide, get_flood_tides_between};
+
+/// Periods send volumes are counted over, in hours.
+const SEND_PERIODS: [(&str, i64); 3] = [("24 hours", 24), ("7 days", 7 * 24), ("30 days", 30 * 24)];
+
+/// Emails sent in one period.
+struct Sends {
+    period: &'static str,
+    notifications: i64,
+    reminders: i64,
+    bounces: i64,
+}
+
+struct Stats {
+    subscribed: i64,
+    /// Subscribed on other channels after stopping the emails
+    email_stopped: i64,
+    unverified: i64,
+    /// Addresses that left and haven't signed up again
+    unsubscribed: i64,
+    suppressed: i64,
+    stored_tides: i64,
+    first_tide: Option<DateTime<Utc>>,
+    last_tide: Option<DateTime<Utc>>,
+    next_flood: Option<FloodTide>,
+    sends: Vec<Sends>,
+}
+
+async fn gather(pool: &SqlitePool, now: DateTime<Utc>) -> Result<Stats, AppError> {
+    let users = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(is_verified = 1 AND is_subscribed = 1), 0) AS "subscribed!: i64",
+            COALESCE(SUM(is_verified = 1 AND is_subscribed = 1 AND wants_email = 0), 0)
+                AS "email_stopped!: i64",
+            COALESCE(SUM(is_verified = 0), 0) AS "unverified!: i64"
+        FROM users
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    let left = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(DISTINCT CASE WHEN event_type = 'unsubscribe' THEN email_key END)
+                AS "unsubscribed!: i64",
+            COUNT(DISTINCT CASE WHEN event_type = 'suppression' THEN email_key END)
+                AS "suppressed!: i64"
+        FROM events
+        WHERE email_key NOT IN (SELECT email_key FROM users WHERE email_key IS NOT NULL)
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    let coverage = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!: i64",
+            MIN(prediction_time) AS "first: DateTime<Utc>",
+            MAX(prediction_time) AS "last: DateTime<Utc>"
+        FROM tides
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    let next_flood = match coverage.last {
+        Some(last) if last > now => get_flood_tides_between(pool, now, last)
+            .await?
+            .into_iter()
+            .next(),
+        _ => None,
+    };
+
+    let mut sends = Vec::new();
+    for (period, hours) in SEND_PERIODS {
+        let since = (now - Duration::hours(hours)).naive_utc();
+        let counts = sqlx::query!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM notification_log WHERE sent_at >= ?1) AS "notifications!: i64",
+                (SELECT COUNT(*) FROM reminder_log WHERE sent_at >= ?1) AS "reminders!: i64",
+                (SELECT COUNT(*) FROM events WHERE event_type = 'bounce' AND created_at >= ?1)
+                    AS "bounces!: i64"
+            "#,
+            since
+        )
+        .fetch_one(pool)
+        .await?;
+        sends.push(Sends {
+            period,
+            notifications: counts.notifications,
+            reminders: counts.reminders,
+            bounces: counts.bounces,
+        });
+    }
+
+    Ok(Stats {
+        subscribed: users.subscribed,
+        email_stopped: users.email_stopped,
+        unverified: users.unverified,
+        unsubscribed: left.unsubscribed,
+        suppressed: left.suppressed,
+        stored_tides: coverage.count,
+        first_tide: coverage.first,
+        last_tide: coverage.last,
+        next_flood,
+        sends,
+    })
+}
+
+/// Prints subscriber totals, the predictions stored and recent sends.
+pub async fn print_report(pool: &SqlitePool) -> Result<(), AppError> {
+    let stats = gather(pool, Utc::now()).await?;
+    println!(
+        "Subscribers: {} verified ({} with emails stopped), {} unverified",
+        stats.subscribed, stats.email_stopped, stats.unverified
+    );
+    println!(
+        "Left: {} unsubscribed, {} suppressed",
+        stats.unsubscribed, stats.suppressed
+    );
+    match (stats.first_tide, stats.last_tide) {
+        (Some(first), Some(last)) => println!(
+            "Predictions: {} stored, {} to {}",
+            stats.stored_tides,
+            tides::local(first).format("%Y-%m-%d"),
+            tides::local(last).format("%Y-%m-%d")
+        ),
+        _ => println!("Predictions: none stored, run sync"),
+    }
+    match &stats.next_flood {
+        Some(flood) => {
+            let display = FloodDisplay::new(flood.prediction_time, flood.height_ft);
+            println!("Next flood: {}, {} ft", display.datetime, display.height);
+        }
+        None => println!("Next flood: none in the stored predictions"),
+    }
+    for sends in &stats.sends {
+        println!(
+            "Last {:<8}  notifications {:>5}  reminders {:>5}  bounces {:>4}",
+            sends.period, sends.notifications, sends.reminders, sends.bounces
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_gather() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let now = "2026-10-15T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        sqlx::query(
+            "INSERT INTO users (id, email, email_key, verification_token, is_verified, is_subscribed, wants_email)
+             VALUES ('1', 'a@x.com', 'a@x.com', '', 1, 1, 1), ('2', 'b@x.com', 'b@x.com', '', 1, 1, 0),
+                 ('3', 'c@x.com', 'c@x.com', '', 0, 0, 1)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO events (user_id, email, email_key, event_type, source, created_at)
+             VALUES ('4', 'd@x.com', 'd@x.com', 'unsubscribe', 'web', '2026-10-01 00:00:00'),
+                 ('2', 'b@x.com', 'b@x.com', 'unsubscribe', 'web', '2026-10-14 00:00:00'),
+                 ('5', 'e@x.com', 'e@x.com', 'bounce', 'cli', '2026-10-15 06:00:00'),
+                 ('5', 'e@x.com', 'e@x.com', 'suppression', 'cli', '2026-10-15 06:00:00')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO tides (prediction_time, height_ft, tide_type)
+             VALUES ('2026-10-14 10:00:00+00:00', 7.5, 'High'), ('2026-10-17 16:50:00+00:00', 8.1, 'High'),
+                 ('2026-10-18 17:30:00+00:00', 2.0, 'High')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO notification_log (id, user_id, email, subject, sent_at)
+             VALUES ('m1', '1', 'a@x.com', 'Floods', '2026-10-15 08:00:00'),
+                 ('m2', '1', 'a@x.com', 'Floods', '2026-10-10 08:00:00')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let stats = gather(&pool, now).await.unwrap();
+        assert_eq!(
+            (stats.subscribed, stats.email_stopped, stats.unverified),
+            (2, 1, 1)
+        );
+        assert_eq!((stats.unsubscribed, stats.suppressed), (1, 1));
+        assert_eq!(stats.stored_tides, 3);
+        assert_eq!(
+            stats.next_flood.map(|flood| flood.prediction_time),
+            "2026-10-17T16:50:00Z".parse().ok()
+        );
+        let notifications: Vec<i64> = stats.sends.iter().map(|s| s.notifications).collect();
+        assert_eq!(notifications, [1, 2, 2]);
+        assert_eq!(stats.sends[0].bounces, 1);
+    }
+}
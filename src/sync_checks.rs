@@ -0,0 +1,128 @@
+use chrono::{Duration, NaiveDateTime};
+use noaa_tides::products::predictions::Prediction;
+
+/// The longest NOAA's highs and lows should ever be apart.
+const MAX_GAP_HOURS: i64 = 14;
+/// Mixed semidiurnal tides give 3 or 4 highs and lows a day, with a little
+/// slack for a range that cuts a day short.
+const MIN_TIDES_PER_DAY: i64 = 3;
+const MAX_TIDES_PER_DAY: i64 = 5;
+/// Feet above MLLW the station's predictions stay well within.
+const MIN_HEIGHT_FT: f64 = -4.0;
+const MAX_HEIGHT_FT: f64 = 10.0;
+
+/// What's wrong with predictions fetched for `begin` to `end`, in UTC, that
+/// means they shouldn't replace the stored ones. Empty when they look sound.
+pub fn problems(
+    predictions: &[&Prediction],
+    begin: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let days = (end.date() - begin.date()).num_days() + 1;
+    let (min, max) = (days * MIN_TIDES_PER_DAY, days * MAX_TIDES_PER_DAY);
+    let count = predictions.len() as i64;
+    if count < min || count > max {
+        problems.push(format!(
+            "Expected {} to {} highs and lows over {} days, got {}",
+            min, max, days, count
+        ));
+    }
+
+    let outside: Vec<_> = predictions
+        .iter()
+        .filter(|p| !(MIN_HEIGHT_FT..=MAX_HEIGHT_FT).contains(&(p.height as f64)))
+        .collect();
+    if let Some(first) = outside.first() {
+        problems.push(format!(
+            "{} heights are outside {} to {} ft, the first {} ft at {}",
+            outside.len(),
+            MIN_HEIGHT_FT,
+            MAX_HEIGHT_FT,
+            first.height,
+            first.datetime
+        ));
+    }
+
+    let mut times: Vec<_> = predictions.iter().map(|p| p.datetime).collect();
+    times.sort();
+    let out_of_range = times
+        .iter()
+        .filter(|time| **time < begin || **time > end)
+        .count();
+    if out_of_range > 0 {
+        problems.push(format!(
+            "{} highs and lows fall outside {} to {}",
+            out_of_range, begin, end
+        ));
+    }
+    // The range's ends count, so a response cut short shows up as a gap
+    let bounds: Vec<_> = [begin]
+        .into_iter()
+        .chain(
+            times
+                .iter()
+                .copied()
+                .filter(|time| (begin..=end).contains(time)),
+        )
+        .chain([end])
+        .collect();
+    for pair in bounds.windows(2) {
+        if pair[1] - pair[0] > Duration::hours(MAX_GAP_HOURS) {
+            problems.push(format!(
+                "No highs or lows for {} hours from {} to {}",
+                (pair[1] - pair[0]).num_hours(),
+                pair[0],
+                pair[1]
+            ));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use noaa_tides::products::predictions::TideType;
+
+    fn predictions(begin: NaiveDateTime, count: i32) -> Vec<Prediction> {
+        (0..count)
+            .map(|i| Prediction {
+                datetime: begin + Duration::minutes(3 * 60 + 372 * i as i64),
+                height: if i % 2 == 0 { 5.5 } else { 0.5 },
+                tide_type: Some(if i % 2 == 0 {
+                    TideType::High
+                } else {
+                    TideType::Low
+                }),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_problems() {
+        let begin = NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let end = NaiveDate::from_ymd_opt(2026, 1, 3)
+            .unwrap()
+            .and_hms_opt(23, 59, 59)
+            .unwrap();
+        let sound = predictions(begin, 11);
+        assert!(problems(&sound.iter().collect::<Vec<_>>(), begin, end).is_empty());
+
+        // Cut short a day early, with one height out of bounds
+        let mut broken = predictions(begin, 7);
+        broken[2].height = 42.0;
+        let found = problems(&broken.iter().collect::<Vec<_>>(), begin, end);
+        assert_eq!(found.len(), 3, "{:?}", found);
+        assert!(found[0].starts_with("Expected 9 to 15 highs and lows over 3 days, got 7"));
+        assert!(found[1].starts_with("1 heights are outside"));
+        assert!(found[2].starts_with("No highs or lows for"));
+
+        assert_eq!(problems(&[], begin, end).len(), 2);
+    }
+}
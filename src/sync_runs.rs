@@ -0,0 +1,226 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use mill_valley_sausalito_bikepath_flood_alert::api_types::SyncRunEntry;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use std::time::Instant;
+use tracing::instrument;
+
+use crate::error::AppError;
+use crate::ops_webhook::{self, OpsEvent};
+use crate::tides::station_id;
+
+/// Predictions fetched longer ago than this get a banner saying the forecast
+/// may be missing NOAA's latest, as syncs run a few times a day.
+pub const OUTDATED_AFTER_HOURS: i64 = 36;
+
+/// Where a sync's predictions came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Api,
+    /// NOAA's latest cached response, when it couldn't be reached
+    Cache,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Api => "api",
+            Source::Cache => "cache",
+        }
+    }
+}
+
+/// One recorded sync of the station's predictions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRun {
+    pub station_id: String,
+    pub window_start: NaiveDateTime,
+    pub window_end: NaiveDateTime,
+    pub inserted: i64,
+    pub deleted: i64,
+    pub duration_ms: i64,
+    pub source: Option<String>,
+    pub fetched_at: Option<NaiveDateTime>,
+    pub error: Option<String>,
+    pub finished_at: NaiveDateTime,
+}
+
+impl SyncRun {
+    pub fn entry(&self) -> SyncRunEntry {
+        SyncRunEntry {
+            finished_at: self.finished_at,
+            window_start: self.window_start,
+            window_end: self.window_end,
+            inserted: self.inserted,
+            deleted: self.deleted,
+            duration_ms: self.duration_ms,
+            source: self.source.clone(),
+            fetched_at: self.fetched_at,
+            error: self.error.clone(),
+        }
+    }
+}
+
+/// A sync in progress, filled in as it goes and recorded by `finish`.
+pub struct Recorder {
+    started: Instant,
+    window_start: NaiveDateTime,
+    window_end: NaiveDateTime,
+    pub inserted: usize,
+    pub deleted: u64,
+    pub source: Option<Source>,
+    pub fetched_at: Option<NaiveDateTime>,
+}
+
+impl Recorder {
+    pub fn start(window_start: NaiveDateTime, window_end: NaiveDateTime) -> Self {
+        Recorder {
+            started: Instant::now(),
+            window_start,
+            window_end,
+            inserted: 0,
+            deleted: 0,
+            source: None,
+            fetched_at: None,
+        }
+    }
+
+    /// Records the sync with how it ended, posting failures to the ops
+    /// webhook, and passes `result` through. Failing to record it is logged,
+    /// the sync itself is what matters.
+    pub async fn finish<T>(
+        self,
+        pool: &SqlitePool,
+        result: Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let run = SyncRun {
+            station_id: station_id().to_string(),
+            window_start: self.window_start,
+            window_end: self.window_end,
+            inserted: self.inserted as i64,
+            deleted: self.deleted as i64,
+            duration_ms: self.started.elapsed().as_millis() as i64,
+            source: self.source.map(|source| source.as_str().to_string()),
+            fetched_at: self.fetched_at,
+            error: result.as_ref().err().map(|e| e.to_string()),
+            finished_at: Utc::now().naive_utc(),
+        };
+        if let Err(e) = record(pool, &run).await {
+            eprintln!("Error recording the sync run: {}", e);
+        }
+        if let Some(error) = run.error {
+            ops_webhook::post(OpsEvent::SyncFailed { error }).await;
+        }
+        result
+    }
+}
+
+async fn record(pool: &SqlitePool, run: &SyncRun) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO sync_runs (
+            station_id, window_start, window_end, inserted, deleted, duration_ms, source,
+            fetched_at, error, finished_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        run.station_id,
+        run.window_start,
+        run.window_end,
+        run.inserted,
+        run.deleted,
+        run.duration_ms,
+        run.source,
+        run.fetched_at,
+        run.error,
+        run.finished_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The station's latest sync runs, newest first.
+#[instrument(level = "debug", skip(pool))]
+pub async fn recent(pool: &SqlitePool, limit: i64) -> Result<Vec<SyncRun>, sqlx::Error> {
+    let station_id = station_id();
+    sqlx::query_as!(
+        SyncRun,
+        r#"
+        SELECT
+            station_id,
+            window_start AS "window_start: NaiveDateTime",
+            window_end AS "window_end: NaiveDateTime",
+            inserted,
+            deleted,
+            duration_ms,
+            source,
+            fetched_at AS "fetched_at: NaiveDateTime",
+            error,
+            finished_at AS "finished_at: NaiveDateTime"
+        FROM sync_runs
+        WHERE station_id = ?
+        ORDER BY finished_at DESC, id DESC
+        LIMIT ?
+        "#,
+        station_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// When NOAA sent the predictions of the station's latest successful sync.
+pub async fn last_fetched(pool: &SqlitePool) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    let station_id = station_id();
+    sqlx::query_scalar!(
+        r#"
+        SELECT MAX(fetched_at) AS "fetched_at: NaiveDateTime" FROM sync_runs
+        WHERE station_id = ? AND error IS NULL
+        "#,
+        station_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Whether predictions fetched at `fetched_at` are too old to trust at `now`.
+pub fn is_outdated(fetched_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    now - fetched_at > Duration::hours(OUTDATED_AFTER_HOURS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_and_read() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let now = Utc::now().naive_utc();
+
+        let mut recorder = Recorder::start(now, now + Duration::days(31));
+        recorder.inserted = 120;
+        recorder.deleted = 118;
+        recorder.source = Some(Source::Cache);
+        recorder.fetched_at = Some(now - Duration::hours(40));
+        recorder.finish(&pool, Ok(())).await.unwrap();
+        let failed: Result<(), AppError> = Err(AppError::Noaa("unreachable".to_string()));
+        assert!(
+            Recorder::start(now, now + Duration::days(31))
+                .finish(&pool, failed)
+                .await
+                .is_err()
+        );
+
+        let runs = recent(&pool, 10).await.unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].error.as_deref(), Some("NOAA error: unreachable"));
+        assert_eq!(runs[1].source.as_deref(), Some("cache"));
+        assert_eq!(runs[1].inserted, 120);
+        let fetched_at = last_fetched(&pool).await.unwrap().unwrap();
+        assert_eq!(fetched_at, now - Duration::hours(40));
+        assert!(is_outdated(fetched_at.and_utc(), now.and_utc()));
+        assert!(!is_outdated(now.and_utc(), now.and_utc()));
+    }
+}
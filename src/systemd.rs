@@ -0,0 +1,125 @@
+use sqlx::sqlite::SqlitePool;
+use std::env;
+use std::io;
+use std::time::Duration;
+
+/// Sends `state` to the `NOTIFY_SOCKET` systemd gives a `Type=notify`
+/// service. Does nothing when it isn't set, e.g. outside systemd.
+fn notify(state: &str) {
+    let Some(socket) = env::var("NOTIFY_SOCKET")
+        .ok()
+        .filter(|socket| !socket.is_empty())
+    else {
+        return;
+    };
+    if let Err(e) = send(&socket, state) {
+        eprintln!("Error notifying systemd of {}: {}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    // A leading @ is an abstract socket, which only Linux has
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let addr = SocketAddr::from_abstract_name(name)?;
+        datagram.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+    datagram.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &str, _state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Tells systemd the server is up, once it's migrated, checked the database
+/// answers and bound its listeners.
+pub fn ready() {
+    notify("READY=1\nSTATUS=Serving");
+}
+
+/// How often to pet the watchdog for `WatchdogSec=`: half the timeout, so a
+/// late tick doesn't get the process killed. None when the watchdog is off
+/// or meant for another process.
+fn watchdog_interval(usec: Option<String>, pid: Option<String>) -> Option<Duration> {
+    if let Some(pid) = pid
+        && pid.trim().parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = usec?.trim().parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Pets systemd's watchdog while the database answers, so a process that's
+/// wedged, or stuck on a database that stopped responding, gets restarted.
+/// Its own loop rather than the job worker's, which waits on a whole notify
+/// run.
+pub fn spawn_watchdog(pool: SqlitePool) {
+    let Some(interval) = watchdog_interval(
+        env::var("WATCHDOG_USEC").ok(),
+        env::var("WATCHDOG_PID").ok(),
+    ) else {
+        return;
+    };
+    println!("Petting the systemd watchdog every {:?}", interval);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            let check = sqlx::query("SELECT 1").execute(&pool);
+            match tokio::time::timeout(interval, check).await {
+                Ok(Ok(_)) => notify("WATCHDOG=1"),
+                Ok(Err(e)) => eprintln!("Not petting the watchdog, the database failed: {}", e),
+                Err(_) => eprintln!("Not petting the watchdog, the database didn't answer"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        let pid = Some(std::process::id().to_string());
+        assert_eq!(
+            watchdog_interval(Some("30000000".to_string()), None),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000".to_string()), pid),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000".to_string()), Some("1".to_string())),
+            None
+        );
+        assert_eq!(watchdog_interval(None, None), None);
+        assert_eq!(watchdog_interval(Some("0".to_string()), None), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = env::temp_dir().join(format!("notify-test-{}.sock", uuid::Uuid::new_v4()));
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,114 @@
+use opentelemetry::global;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{MetricExporter, SpanExporter};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use std::env;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::error::AppError;
+use crate::metrics;
+
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// The layer sending spans to the OTLP exporter.
+pub type OtelLayer<S> = OpenTelemetryLayer<S, Tracer>;
+
+/// Exports spans and the counters over OTLP while it's kept, flushing what's
+/// left when it's dropped.
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            eprintln!("Error flushing spans to OTLP: {}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            eprintln!("Error flushing metrics to OTLP: {}", e);
+        }
+    }
+}
+
+/// Whether the standard OpenTelemetry variables turn the export on: it needs
+/// `OTEL_EXPORTER_OTLP_ENDPOINT`, and `OTEL_SDK_DISABLED=true` turns it off.
+fn enabled(var: impl Fn(&str) -> Option<String>) -> bool {
+    let var = |key: &str| var(key).filter(|value| !value.trim().is_empty());
+    let disabled = var("OTEL_SDK_DISABLED").is_some_and(|value| value.trim() == "true");
+    !disabled && var("OTEL_EXPORTER_OTLP_ENDPOINT").is_some()
+}
+
+/// The OTLP exporters and the layer sending spans to them, when enabled.
+/// The exporters read the endpoint, headers and timeout from the usual
+/// `OTEL_EXPORTER_OTLP_*` variables, and the resource `OTEL_SERVICE_NAME`
+/// and `OTEL_RESOURCE_ATTRIBUTES`.
+pub fn from_env<S>() -> Result<Option<(Telemetry, OtelLayer<S>)>, AppError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !enabled(|key| env::var(key).ok()) {
+        return Ok(None);
+    }
+    let config_error = |e| AppError::Config(format!("Can't export to OTLP: {}", e));
+
+    let mut resource = Resource::builder();
+    if !env::var("OTEL_SERVICE_NAME").is_ok_and(|name| !name.trim().is_empty()) {
+        resource = resource.with_service_name(SERVICE_NAME);
+    }
+    let resource = resource.build();
+
+    let span_exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(config_error)?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_resource(resource.clone())
+        .with_batch_exporter(span_exporter)
+        .build();
+
+    let metric_exporter = MetricExporter::builder()
+        .with_http()
+        .build()
+        .map_err(config_error)?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_resource(resource)
+        .with_periodic_exporter(metric_exporter)
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+    metrics::register(&global::meter(SERVICE_NAME));
+
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(SERVICE_NAME));
+    Ok(Some((
+        Telemetry {
+            tracer_provider,
+            meter_provider,
+        },
+        layer,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_enabled() {
+        let enabled = |vars: &[(&str, &str)]| {
+            let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+            enabled(|key| vars.get(key).map(|value| value.to_string()))
+        };
+        let endpoint = ("OTEL_EXPORTER_OTLP_ENDPOINT", "https://api.honeycomb.io");
+
+        assert!(!enabled(&[]));
+        assert!(!enabled(&[("OTEL_EXPORTER_OTLP_ENDPOINT", " ")]));
+        assert!(enabled(&[endpoint]));
+        assert!(enabled(&[endpoint, ("OTEL_SDK_DISABLED", "false")]));
+        assert!(!enabled(&[endpoint, ("OTEL_SDK_DISABLED", "true")]));
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Duration, Utc};
+use std::f64::consts::PI;
+
+use crate::tides::{self, Tide};
+
+/// Size of the chart in pixels, as wide as the email's content.
+pub const WIDTH: u32 = 540;
+pub const HEIGHT: u32 = 140;
+/// What the notification's HTML refers to the chart by.
+pub const CONTENT_ID: &str = "tide-chart";
+
+/// The chart's 2 bit palette, indexed by the colors below.
+const PALETTE: [[u8; 3]; 4] = [
+    [0xff, 0xff, 0xff],
+    [0x9e, 0xc5, 0xe6],
+    [0xd9, 0x53, 0x4f],
+    [0x1a, 0x3a, 0x5a],
+];
+const WATER: u8 = 1;
+/// Water over the flooding threshold
+const FLOODED: u8 = 2;
+/// The threshold and the start of each day
+const LINE: u8 = 3;
+/// Feet of headroom above the highest tide or threshold.
+const HEADROOM_FT: f64 = 0.5;
+
+/// The height at `time`, following the half cosine the water roughly takes
+/// from one predicted high or low to the next. None outside the predictions.
+fn height_at(tides: &[Tide], time: DateTime<Utc>) -> Option<f64> {
+    let next = tides.iter().position(|tide| tide.prediction_time >= time)?;
+    if next == 0 {
+        return (tides[0].prediction_time == time).then_some(tides[0].height_ft);
+    }
+    let (from, to) = (&tides[next - 1], &tides[next]);
+    let span = (to.prediction_time - from.prediction_time).num_seconds() as f64;
+    let progress = (time - from.prediction_time).num_seconds() as f64 / span;
+    Some(from.height_ft + (to.height_ft - from.height_ft) * (1.0 - (PI * progress).cos()) / 2.0)
+}
+
+/// Draws the water level from `start` to `end` from the highs and lows in
+/// `tides`, red where it's over `threshold`, as a PNG.
+pub fn render(tides: &[Tide], start: DateTime<Utc>, end: DateTime<Utc>, threshold: f64) -> Vec<u8> {
+    let (width, height) = (WIDTH as usize, HEIGHT as usize);
+    let low = tides
+        .iter()
+        .map(|tide| tide.height_ft)
+        .fold(0.0_f64, f64::min);
+    let high = tides
+        .iter()
+        .map(|tide| tide.height_ft)
+        .fold(threshold, f64::max)
+        + HEADROOM_FT;
+    let row = |height_ft: f64| {
+        let above_bottom = (height_ft - low) / (high - low) * (height - 1) as f64;
+        (height - 1).saturating_sub(above_bottom.round().max(0.0) as usize)
+    };
+    let threshold_row = row(threshold);
+    let step = (end - start).num_seconds() as f64 / width as f64;
+
+    let mut pixels = vec![0; width * height];
+    let mut previous_day = None;
+    for x in 0..width {
+        let time = start + Duration::seconds((step * x as f64) as i64);
+        if let Some(height_ft) = height_at(tides, time) {
+            for y in row(height_ft)..height {
+                pixels[y * width + x] = if y < threshold_row { FLOODED } else { WATER };
+            }
+        }
+        let day = tides::local(time).date();
+        if previous_day.is_some_and(|previous| previous != day) {
+            for y in (0..height).step_by(3) {
+                pixels[y * width + x] = LINE;
+            }
+        }
+        previous_day = Some(day);
+        if x % 8 < 5 {
+            pixels[threshold_row * width + x] = LINE;
+        }
+    }
+    encode_png(WIDTH, HEIGHT, &pixels)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// A PNG of 2 bit `pixels` in the palette. The image data is stored rather
+/// than compressed, which at this size and depth is still only ~20 KB.
+fn encode_png(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let row_bytes = (width as usize * 2).div_ceil(8);
+    let mut raw = Vec::with_capacity((row_bytes + 1) * height as usize);
+    for row in pixels.chunks(width as usize) {
+        // No filter
+        raw.push(0);
+        for four in row.chunks(4) {
+            let byte = four
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, pixel)| byte | pixel << (6 - 2 * i));
+            raw.push(byte);
+        }
+    }
+
+    // A zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(u16::MAX as usize).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push(u8::from(i == blocks.len() - 1));
+        let len = block.len() as u16;
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(*block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 2 bit depth, palette color, then the standard compression, filter and
+    // interlace methods
+    header.extend([2, 3, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"PLTE", &PALETTE.concat());
+    chunk(&mut png, b"IDAT", &zlib);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tide(hours: i64, height_ft: f64) -> Tide {
+        let start = "2026-11-03T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        Tide {
+            prediction_time: start + Duration::hours(hours),
+            height_ft,
+            tide_type: None,
+        }
+    }
+
+    #[test]
+    fn test_height_at() {
+        let tides = [tide(0, 0.0), tide(6, 6.0), tide(12, 1.0)];
+        let at = |minutes| {
+            height_at(
+                &tides,
+                tides[0].prediction_time + Duration::minutes(minutes),
+            )
+        };
+        assert_eq!(at(0), Some(0.0));
+        assert!((at(180).unwrap() - 3.0).abs() < 1e-9);
+        assert_eq!(at(360), Some(6.0));
+        assert!(at(400).unwrap() < 6.0);
+        assert_eq!(at(-1), None);
+        assert_eq!(at(721), None);
+    }
+
+    #[test]
+    fn test_render_png() {
+        let tides = [tide(0, 1.0), tide(6, 7.0), tide(12, 0.5), tide(18, 6.0)];
+        let png = render(
+            &tides,
+            tides[0].prediction_time,
+            tides[3].prediction_time,
+            6.5,
+        );
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"));
+        assert_eq!(&png[16..24], &[0, 0, 2, 28, 0, 0, 0, 140]);
+        assert_eq!(&png[24..26], &[2, 3]);
+        // The IEND chunk and its well known CRC
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
@@ -1,39 +1,269 @@
-use crate::models::FloodDisplay;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Months, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
 use chrono_tz::US::Pacific;
-use noaa_tides::products::predictions::TideType;
-use noaa_tides::{NoaaTideClient, PredictionsRequest, params};
+use noaa_tides::products::predictions::{Prediction, TideType};
+use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use tracing::{Span, instrument};
 
-const STATION_ID: &str = "9414819";
-pub const FLOOD_THRESHOLD_FT: f64 = 6.4;
+use crate::error::AppError;
+use crate::site::site;
+use crate::sync_checks;
+use crate::sync_runs;
+
+/// The station used when `STATION_ID` isn't set.
+pub const DEFAULT_STATION_ID: &str = "9414819";
+/// Height in feet above MLLW at which the path floods, when neither
+/// `FLOOD_THRESHOLDS` nor `FLOOD_THRESHOLD_FT` is set.
+pub const DEFAULT_FLOOD_THRESHOLD_FT: f64 = 6.4;
 pub const FORECAST_DAYS: i64 = 30;
+/// The years NOAA publishes predictions for.
+const MIN_SYNC_YEAR: i32 = 1900;
+const MAX_SYNC_YEAR: i32 = 2100;
+
+/// Used instead of the configured threshold once a calibration is applied,
+/// by site id.
+static CALIBRATED_THRESHOLD: RwLock<BTreeMap<String, f64>> = RwLock::new(BTreeMap::new());
+
+/// Added to predicted heights once a bias correction is applied, by site id.
+static PREDICTION_OFFSET: RwLock<BTreeMap<String, f64>> = RwLock::new(BTreeMap::new());
+
+/// Per station thresholds like `9414819=6.6,9414290=7.1`, from
+/// `FLOOD_THRESHOLDS`.
+pub fn parse_thresholds(value: &str) -> Result<HashMap<String, f64>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (station, feet) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("{} isn't station=feet", entry))?;
+            let feet = feet
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|feet| feet.is_finite())
+                .ok_or_else(|| format!("{} isn't a height in feet", feet.trim()))?;
+            Ok((station.trim().to_string(), feet))
+        })
+        .collect()
+}
+
+pub fn threshold_from_vars(station: &str, var: impl Fn(&str) -> Option<String>) -> f64 {
+    let var = |name| var(name).filter(|value: &String| !value.trim().is_empty());
+    let overrides = var("FLOOD_THRESHOLDS").map(|value| {
+        parse_thresholds(&value).unwrap_or_else(|e| panic!("FLOOD_THRESHOLDS is invalid: {}", e))
+    });
+    overrides
+        .and_then(|overrides| overrides.get(station).copied())
+        .or_else(|| {
+            var("FLOOD_THRESHOLD_FT").map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .unwrap_or_else(|_| panic!("FLOOD_THRESHOLD_FT must be a number: {}", value))
+            })
+        })
+        .unwrap_or(DEFAULT_FLOOD_THRESHOLD_FT)
+}
+
+/// Tide times are stored and compared in UTC, and shown in the station's
+/// time zone.
+pub const LOCAL_TZ: Tz = Pacific;
+
+/// `time` as a Pacific wall clock time, for display and grouping by day.
+pub fn local(time: DateTime<Utc>) -> NaiveDateTime {
+    time.with_timezone(&LOCAL_TZ).naive_local()
+}
+
+/// A Pacific wall clock time in UTC. The repeated hour in November is taken
+/// as the first, and the hour skipped in March as standard time.
+#[cfg(test)]
+pub fn from_local(time: NaiveDateTime) -> DateTime<Utc> {
+    time.and_local_timezone(LOCAL_TZ)
+        .earliest()
+        .map(|time| time.to_utc())
+        .unwrap_or_else(|| (time + Duration::hours(8)).and_utc())
+}
+
+/// The NOAA station of the current site.
+pub fn station_id() -> &'static str {
+    &site().station_id
+}
+
+/// The height at which tides at the current site's station flood the path,
+/// read once from the station's `FLOOD_THRESHOLDS` entry, else
+/// `FLOOD_THRESHOLD_FT`. A threshold applied from `calibrate` takes their
+/// place.
+pub fn flood_threshold() -> f64 {
+    site().flood_threshold()
+}
+
+/// The threshold from the station settings or the environment, ignoring any
+/// calibration.
+pub fn configured_flood_threshold() -> f64 {
+    crate::station_settings::threshold(&site().id).unwrap_or(site().configured_threshold)
+}
 
-pub async fn update_tide_predictions(pool: SqlitePool) -> Result<(), Box<dyn std::error::Error>> {
-    let client = NoaaTideClient::new();
-    let begin_date = Utc::now().with_timezone(&Pacific).date_naive();
-    let end_date = begin_date + Duration::days(FORECAST_DAYS);
-
-    let request = PredictionsRequest {
-        station: STATION_ID.into(),
-        date_range: params::DateRange {
-            begin_date,
-            end_date,
-        },
-        datum: params::Datum::MLLW,
-        time_zone: params::Timezone::LST_LDT,
-        interval: params::Interval::HighLow,
-        units: params::Units::English,
+/// The threshold applied from `calibrate` for the site `id`, if any.
+pub fn calibrated_threshold(id: &str) -> Option<f64> {
+    CALIBRATED_THRESHOLD.read().unwrap().get(id).copied()
+}
+
+fn apply(applied: &RwLock<BTreeMap<String, f64>>, value: Option<f64>) {
+    let mut applied = applied.write().unwrap();
+    match value {
+        Some(value) => applied.insert(site().id.clone(), value),
+        None => applied.remove(&site().id),
     };
+}
 
-    let predictions = client.fetch_predictions(&request).await?.predictions;
+pub fn apply_calibrated_threshold(threshold: Option<f64>) {
+    apply(&CALIBRATED_THRESHOLD, threshold);
+}
 
-    // Drop existing predictions in case of updates
+/// The offset from `bias` added to every height read from the database, if
+/// one is applied, for labelling them as adjusted.
+pub fn prediction_offset() -> Option<f64> {
+    PREDICTION_OFFSET.read().unwrap().get(&site().id).copied()
+}
+
+pub fn apply_prediction_offset(offset: Option<f64>) {
+    apply(&PREDICTION_OFFSET, offset);
+}
+
+/// Replaces the stored predictions with NOAA's latest, or its latest cached
+/// response when it can't be reached, recording the run in `sync_runs`.
+/// Returns how many were stored.
+#[instrument(skip_all, fields(stored))]
+pub async fn update_tide_predictions(pool: SqlitePool) -> Result<usize, AppError> {
+    // In UTC, with a day extra so the last Pacific day is covered
+    let begin_date = Utc::now().date_naive();
+    let end_date = begin_date + Duration::days(FORECAST_DAYS + 1);
     let begin_time = begin_date.and_hms_opt(0, 0, 0).unwrap();
     let end_time = end_date.and_hms_opt(23, 59, 59).unwrap();
 
+    let mut run = sync_runs::Recorder::start(begin_time, end_time);
+    let result: Result<Vec<FloodTide>, AppError> = async {
+        let fetched = crate::noaa_cache::fetch_predictions(&pool, begin_date).await?;
+        run.source = Some(fetched.source);
+        run.fetched_at = Some(fetched.fetched_at);
+        let floods_before = get_flood_tides(&pool, FORECAST_DAYS).await?;
+
+        let stored: Vec<_> = fetched
+            .predictions
+            .iter()
+            .filter(|p| p.tide_type.is_some())
+            .filter(|p| (begin_time..=end_time).contains(&p.datetime))
+            .collect();
+        let problems = sync_checks::problems(&stored, begin_time, end_time);
+        if !problems.is_empty() {
+            for problem in &problems {
+                eprintln!("Sync check failed: {}", problem);
+            }
+            return Err(AppError::NoaaData(format!(
+                "Kept the stored predictions, NOAA's failed {} checks: {}",
+                problems.len(),
+                problems.join("; ")
+            )));
+        }
+
+        run.deleted = replace_predictions(&pool, &stored, begin_time, end_time).await?;
+        run.inserted = stored.len();
+        Ok(floods_before)
+    }
+    .await;
+    let stored = run.inserted;
+    let floods_before = run.finish(&pool, result).await?;
+
+    Span::current().record("stored", stored);
+    println!("Successfully updated {} rows.", stored);
+    crate::bias::refresh(&pool).await;
+    crate::calibration::recalibrate(&pool).await;
+    crate::forecast_changes::report(&pool, &floods_before).await;
+    crate::mqtt::publish_forecast(&pool).await;
+    crate::matrix::post_new_events(&pool, floods_before).await;
+    Ok(stored)
+}
+
+/// The first and last day of each month of `year`.
+fn months(year: i32) -> Vec<(NaiveDate, NaiveDate)> {
+    (1..=12)
+        .filter_map(|month| {
+            let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+            let last = first.checked_add_months(Months::new(1))?.pred_opt()?;
+            Some((first, last))
+        })
+        .collect()
+}
+
+/// Stores every high and low of `year`, fetched a month at a time so each
+/// request stays small, for planning past the forecast and riding out short
+/// NOAA outages. Each month is checked like a sync, and one that fails stops
+/// the run with the months before it kept. Returns how many were stored.
+pub async fn sync_year(pool: &SqlitePool, year: i32) -> Result<usize, AppError> {
+    if !(MIN_SYNC_YEAR..=MAX_SYNC_YEAR).contains(&year) {
+        return Err(AppError::BadRequest(format!(
+            "NOAA predicts tides from {} to {}, not {}",
+            MIN_SYNC_YEAR, MAX_SYNC_YEAR, year
+        )));
+    }
+    let mut total = 0;
+    for (first, last) in months(year) {
+        let begin_time = first.and_hms_opt(0, 0, 0).unwrap();
+        let end_time = last.and_hms_opt(23, 59, 59).unwrap();
+        let mut run = sync_runs::Recorder::start(begin_time, end_time);
+        let result: Result<(), AppError> = async {
+            let predictions = crate::noaa_cache::fetch_range(first, last).await?;
+            run.source = Some(sync_runs::Source::Api);
+            run.fetched_at = Some(Utc::now().naive_utc());
+            let stored: Vec<_> = predictions
+                .iter()
+                .filter(|p| p.tide_type.is_some())
+                .filter(|p| (begin_time..=end_time).contains(&p.datetime))
+                .collect();
+            let problems = sync_checks::problems(&stored, begin_time, end_time);
+            if !problems.is_empty() {
+                return Err(AppError::NoaaData(format!(
+                    "Stopped at {}, NOAA's predictions failed {} checks: {}",
+                    first.format("%B %Y"),
+                    problems.len(),
+                    problems.join("; ")
+                )));
+            }
+            run.deleted = replace_predictions(pool, &stored, begin_time, end_time).await?;
+            run.inserted = stored.len();
+            Ok(())
+        }
+        .await;
+        let stored = run.inserted;
+        run.finish(pool, result).await?;
+        println!(
+            "Stored {} highs and lows for {}",
+            stored,
+            first.format("%B %Y")
+        );
+        total += stored;
+    }
+    println!("Successfully stored {} highs and lows for {}.", total, year);
+    Ok(total)
+}
+
+/// Replaces the highs and lows stored from `begin_time` to `end_time`, in
+/// UTC, with `predictions`. Returns how many were replaced.
+#[instrument(level = "debug", skip(pool, predictions))]
+pub async fn replace_predictions(
+    pool: &SqlitePool,
+    predictions: &[&Prediction],
+    begin_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+) -> Result<u64, AppError> {
+    // Drop existing predictions in case of updates
     let mut tx = pool.begin().await?;
-    sqlx::query!(
+    let deleted = sqlx::query!(
         r#"
         DELETE FROM tides
         WHERE prediction_time >= ? AND prediction_time <= ?;
@@ -42,58 +272,191 @@ pub async fn update_tide_predictions(pool: SqlitePool) -> Result<(), Box<dyn std
         end_time,
     )
     .execute(&mut *tx)
-    .await?;
+    .await?
+    .rows_affected();
     let mut query_builder =
         sqlx::QueryBuilder::new("INSERT INTO tides (prediction_time, height_ft, tide_type) ");
-
-    query_builder.push_values(
-        predictions.iter().filter(|p| p.tide_type.is_some()),
-        |mut b, prediction| {
-            let tide_type = match prediction.tide_type {
-                Some(TideType::High) => "High",
-                Some(TideType::Low) => "Low",
-                _ => unreachable!(),
-            };
-            b.push_bind(prediction.datetime)
-                .push_bind(prediction.height)
-                .push_bind(tide_type);
-        },
-    );
+    query_builder.push_values(predictions, |mut b, prediction| {
+        let tide_type = match prediction.tide_type {
+            Some(TideType::High) => "High",
+            Some(TideType::Low) => "Low",
+            _ => unreachable!(),
+        };
+        b.push_bind(prediction.datetime)
+            .push_bind(prediction.height)
+            .push_bind(tide_type);
+    });
 
     query_builder.build().execute(&mut *tx).await?;
     tx.commit().await?;
+    Ok(deleted)
+}
 
-    println!("Successfully updated {} rows.", predictions.len());
-    Ok(())
+/// A predicted high tide at or above the flood threshold
+#[derive(Clone)]
+pub struct FloodTide {
+    pub prediction_time: DateTime<Utc>,
+    pub height_ft: f64,
 }
 
-/// Gets flood predictions for the next forecast_days
-pub async fn get_flood_predictions(
+/// Gets the raw flood tides for the next forecast_days
+pub async fn get_flood_tides(
     pool: &SqlitePool,
     forecast_days: i64,
-) -> Result<Vec<FloodDisplay>, Box<dyn std::error::Error>> {
-    let local_time_start = chrono::Utc::now().with_timezone(&Pacific).naive_local();
-    let local_time_end = local_time_start + Duration::days(forecast_days);
+) -> Result<Vec<FloodTide>, AppError> {
+    get_flood_tides_within(pool, Duration::days(forecast_days)).await
+}
 
+/// Gets the raw flood tides from now until `window` from now
+pub async fn get_flood_tides_within(
+    pool: &SqlitePool,
+    window: Duration,
+) -> Result<Vec<FloodTide>, AppError> {
+    let start = Utc::now();
+    get_flood_tides_between(pool, start, start + window).await
+}
+
+/// Gets the flood tides between two times, adjusted by any applied offset
+#[instrument(level = "debug", skip(pool), fields(rows))]
+pub async fn get_flood_tides_between(
+    pool: &SqlitePool,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<FloodTide>, AppError> {
+    let offset = prediction_offset().unwrap_or(0.0);
+    let threshold = flood_threshold() - offset;
+    // Bound as naive UTC to match the stored format, which sorts as text
+    let (start, end) = (start.naive_utc(), end.naive_utc());
     let predictions = sqlx::query!(
         r#"
-        SELECT prediction_time, height_ft
+        SELECT prediction_time AS "prediction_time: DateTime<Utc>", height_ft
         FROM tides
         WHERE prediction_time >= ? AND prediction_time <= ?
             AND height_ft >= ?
         ORDER BY prediction_time ASC
         "#,
-        local_time_start,
-        local_time_end,
-        FLOOD_THRESHOLD_FT,
+        start,
+        end,
+        threshold,
     )
     .fetch_all(pool)
     .await?;
 
-    let results = predictions
+    let results: Vec<_> = predictions
         .into_iter()
-        .map(|record| FloodDisplay::new(record.prediction_time, record.height_ft))
+        .map(|record| FloodTide {
+            prediction_time: record.prediction_time,
+            height_ft: record.height_ft + offset,
+        })
         .collect();
 
+    Span::current().record("rows", results.len());
     Ok(results)
 }
+
+/// A predicted high or low tide
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tide {
+    pub prediction_time: DateTime<Utc>,
+    pub height_ft: f64,
+    pub tide_type: Option<String>,
+}
+
+/// Gets all high and low tides between two times, adjusted by any applied
+/// offset
+#[instrument(level = "debug", skip(pool), fields(rows))]
+pub async fn get_tides_between(
+    pool: &SqlitePool,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<Tide>, AppError> {
+    let (start, end) = (start.naive_utc(), end.naive_utc());
+    let mut tides = sqlx::query_as!(
+        Tide,
+        r#"
+        SELECT prediction_time AS "prediction_time: DateTime<Utc>", height_ft, tide_type
+        FROM tides
+        WHERE prediction_time >= ? AND prediction_time <= ?
+        ORDER BY prediction_time ASC
+        "#,
+        start,
+        end,
+    )
+    .fetch_all(pool)
+    .await?;
+    if let Some(offset) = prediction_offset() {
+        for tide in &mut tides {
+            tide.height_ft += offset;
+        }
+    }
+
+    Span::current().record("rows", tides.len());
+    Ok(tides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_months() {
+        let months = months(2028);
+        assert_eq!(months.len(), 12);
+        let date = |month, day| NaiveDate::from_ymd_opt(2028, month, day).unwrap();
+        assert_eq!(months[0], (date(1, 1), date(1, 31)));
+        assert_eq!(months[1], (date(2, 1), date(2, 29)));
+        assert_eq!(months[11], (date(12, 1), date(12, 31)));
+    }
+
+    #[test]
+    fn test_flood_threshold() {
+        assert_eq!(
+            threshold_from_vars(DEFAULT_STATION_ID, |_| None),
+            DEFAULT_FLOOD_THRESHOLD_FT
+        );
+
+        let vars = |name: &str| match name {
+            "FLOOD_THRESHOLD_FT" => Some("6.8".to_string()),
+            "FLOOD_THRESHOLDS" => Some("9414819 = 6.6, 9414290=7.1".to_string()),
+            _ => None,
+        };
+        assert_eq!(threshold_from_vars("9414819", vars), 6.6);
+        assert_eq!(threshold_from_vars("9415020", vars), 6.8);
+
+        assert!(parse_thresholds("9414819").is_err());
+        assert!(parse_thresholds("9414819=high").is_err());
+        assert!(parse_thresholds("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_local_times_across_dst() {
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let utc = |s| time(s).and_utc();
+
+        assert_eq!(
+            from_local(time("2026-03-08 01:30")),
+            utc("2026-03-08 09:30")
+        );
+        assert_eq!(
+            from_local(time("2026-03-08 03:30")),
+            utc("2026-03-08 10:30")
+        );
+        // Skipped in March, taken as standard time
+        assert_eq!(
+            from_local(time("2026-03-08 02:30")),
+            utc("2026-03-08 10:30")
+        );
+        // Repeated in November
+        assert_eq!(
+            from_local(time("2026-11-01 01:30")),
+            utc("2026-11-01 08:30")
+        );
+        assert_eq!(local(utc("2026-11-01 09:30")), time("2026-11-01 01:30"));
+
+        // An hour apart in UTC across the change, though the same clock time
+        let before = utc("2026-11-01 08:45");
+        let after = utc("2026-11-01 09:15");
+        assert!(before < after);
+        assert!(local(before) > local(after));
+    }
+}
@@ -0,0 +1,169 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::response::{IntoResponse, Redirect, Response};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+use std::sync::Arc;
+
+use crate::AppState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Whether notifications carry an open tracking pixel. Off unless
+/// `OPEN_TRACKING` is enabled, and the privacy policy says so when it is.
+pub fn open_tracking_enabled() -> bool {
+    env::var("OPEN_TRACKING")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+pub fn pixel_url(base_url: &str, message_id: &str) -> String {
+    format!("{}/o/{}", base_url.trim_end_matches('/'), message_id)
+}
+
+/// Adds the pixel at the end of the body, so it's fetched when the email is
+/// displayed with images.
+pub fn with_pixel(html_body: &str, pixel_url: &str) -> String {
+    let img = format!(
+        r#"<img src="{}" width="1" height="1" alt="" style="display:block;border:0;">"#,
+        pixel_url
+    );
+    match html_body.rfind("</body>") {
+        Some(end) => format!("{}{}{}", &html_body[..end], img, &html_body[end..]),
+        None => format!("{}{}", html_body, img),
+    }
+}
+
+/// Serves the pixel, recording the first open of the message. Image proxies
+/// such as Apple Mail Privacy Protection fetch it without the email being
+/// read, so opens are an estimate.
+pub async fn pixel_handler(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+) -> impl IntoResponse {
+    if state.open_tracking
+        && let Err(e) = sqlx::query!(
+            r#"
+            UPDATE notification_log SET opened_at = CURRENT_TIMESTAMP
+            WHERE id = ? AND opened_at IS NULL;
+            "#,
+            message_id
+        )
+        .execute(&state.pool)
+        .await
+    {
+        eprintln!("Database error recording open: {:?}", e);
+    }
+
+    (
+        [
+            (CONTENT_TYPE, "image/gif"),
+            (CACHE_CONTROL, "no-store, private"),
+        ],
+        PIXEL,
+    )
+}
+
+fn click_signature(secret: &str, payload: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(format!("click:{}", secret).as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// A `/r/<token>` link that records a click on the message before
+/// redirecting to `path` on this site. The token is signed so it can't be
+/// turned into an open redirect or used to fake clicks.
+pub fn click_url(base_url: &str, secret: &str, message_id: &str, path: &str) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(format!("{}|{}", message_id, path));
+    format!(
+        "{}/r/{}.{}",
+        base_url.trim_end_matches('/'),
+        payload,
+        click_signature(secret, &payload)
+    )
+}
+
+/// Returns the message id and path of a valid click token.
+fn parse_click_token(secret: &str, token: &str) -> Option<(String, String)> {
+    let (payload, signature) = token.split_once('.')?;
+    if click_signature(secret, payload) != signature {
+        return None;
+    }
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    let (message_id, path) = decoded.split_once('|')?;
+    // Only ever redirect within the site
+    if !path.starts_with('/') || path.starts_with("//") {
+        return None;
+    }
+    Some((message_id.to_string(), path.to_string()))
+}
+
+/// Records the first click on a message's links and redirects to the link.
+pub async fn click_handler(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> Response {
+    let Some((message_id, path)) = parse_click_token(&state.unsubscribe_secret, &token) else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    if let Err(e) = sqlx::query!(
+        r#"
+        UPDATE notification_log SET clicked_at = CURRENT_TIMESTAMP
+        WHERE id = ? AND clicked_at IS NULL;
+        "#,
+        message_id
+    )
+    .execute(&state.pool)
+    .await
+    {
+        eprintln!("Database error recording click: {:?}", e);
+    }
+
+    Redirect::to(&path).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_pixel() {
+        let url = pixel_url("http://example.com/", "abc");
+        assert_eq!(url, "http://example.com/o/abc");
+
+        let html = with_pixel("<html><body><p>Hi</p></body></html>", &url);
+        assert!(html.ends_with(r#"border:0;"></body></html>"#));
+        assert!(html.contains(r#"<p>Hi</p><img src="http://example.com/o/abc""#));
+
+        assert!(with_pixel("<p>Hi</p>", &url).starts_with("<p>Hi</p><img"));
+    }
+
+    #[test]
+    fn test_click_token() {
+        let url = click_url("http://example.com", "secret", "msg-1", "/?from=email");
+        let token = url.strip_prefix("http://example.com/r/").unwrap();
+        assert_eq!(
+            parse_click_token("secret", token),
+            Some(("msg-1".to_string(), "/?from=email".to_string()))
+        );
+        assert_eq!(parse_click_token("other-secret", token), None);
+
+        // A re-signed payload pointing off the site is still refused
+        let payload = URL_SAFE_NO_PAD.encode("msg-1|//evil.example");
+        let forged = format!("{}.{}", payload, click_signature("secret", &payload));
+        assert_eq!(parse_click_token("secret", &forged), None);
+        assert_eq!(parse_click_token("secret", "garbage"), None);
+    }
+}
@@ -0,0 +1,100 @@
+use axum::http::HeaderValue;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Redirect, Response};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use std::env;
+
+use crate::site::{CurrentSite, Site};
+
+/// How far ahead security.txt's `Expires` is, RFC 9116 asks for under a year.
+const EXPIRES_DAYS: i64 = 180;
+
+/// `/.well-known/security.txt` (RFC 9116) for `site`. `SECURITY_CONTACT`
+/// overrides the contact, which is otherwise `CONTACT_EMAIL` or the contact
+/// page, and `SECURITY_POLICY_URL` adds a policy.
+fn security_txt(
+    site: &Site,
+    contact: Option<String>,
+    policy: Option<String>,
+    now: DateTime<Utc>,
+) -> String {
+    let contact = contact
+        .or_else(|| {
+            site.contact_email
+                .as_ref()
+                .map(|email| format!("mailto:{}", email))
+        })
+        .unwrap_or_else(|| format!("{}/contact", site.base_url));
+    let expires = (now + Duration::days(EXPIRES_DAYS)).to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut text = format!("Contact: {}\nExpires: {}\n", contact, expires);
+    if let Some(policy) = policy {
+        text.push_str(&format!("Policy: {}\n", policy));
+    }
+    text.push_str(&format!(
+        "Preferred-Languages: en, es\nCanonical: {}/.well-known/security.txt\n",
+        site.base_url
+    ));
+    text
+}
+
+pub async fn security_txt_handler(CurrentSite(site): CurrentSite) -> Response {
+    let var = |key| env::var(key).ok().filter(|value| !value.trim().is_empty());
+    let text = security_txt(
+        site,
+        var("SECURITY_CONTACT"),
+        var("SECURITY_POLICY_URL"),
+        Utc::now(),
+    );
+    (
+        [(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        )],
+        text,
+    )
+        .into_response()
+}
+
+/// Where password managers look to change a password. Subscribers don't have
+/// one, so they're sent to the notification preferences, which explain that
+/// the page is reached from the link in their emails.
+pub async fn change_password_handler() -> Redirect {
+    Redirect::to("/reminders")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_security_txt() {
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 8, 0, 0).unwrap();
+        let mut site = crate::site::site().clone();
+        site.base_url = "https://example.com".to_string();
+        site.contact_email = None;
+        assert_eq!(
+            security_txt(&site, None, None, now),
+            "Contact: https://example.com/contact\nExpires: 2026-06-30T08:00:00Z\n\
+             Preferred-Languages: en, es\nCanonical: https://example.com/.well-known/security.txt\n"
+        );
+
+        site.contact_email = Some("help@example.com".to_string());
+        let text = security_txt(
+            &site,
+            None,
+            Some("https://example.com/pages/security".to_string()),
+            now,
+        );
+        assert!(text.starts_with("Contact: mailto:help@example.com\n"));
+        assert!(text.contains("\nPolicy: https://example.com/pages/security\n"));
+        let text = security_txt(
+            &site,
+            Some("mailto:security@example.com".to_string()),
+            None,
+            now,
+        );
+        assert!(text.starts_with("Contact: mailto:security@example.com\n"));
+    }
+}